@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use crate::genesis::Genesis;
+use crate::genesis::{Genesis, GenesisCommittee};
 
 #[derive(Serialize, Deserialize, Default)]
 pub enum Mode {
@@ -14,4 +14,9 @@ pub enum Mode {
 pub struct Config {
     pub genesis: Option<Genesis>,
     pub mode: Mode,
+    /// When set, the application starts from a minimal genesis in which the given node, which
+    /// should be the local node, is the only committee member. Useful for running a development
+    /// network without any other nodes.
+    #[serde(default)]
+    pub dev_single_node: Option<GenesisCommittee>,
 }
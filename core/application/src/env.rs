@@ -6,13 +6,14 @@
 use hp_fixed::unsigned::HpUfixed;
 use lightning_interfaces::types::{
     AccountInfo, Block, BlockExecutionResponse, Committee, CommodityTypes, Epoch, ExecutionData,
-    Metadata, NodeInfo, NodeServed, ProtocolParams, ReportedReputationMeasurements, Service,
+    ExecutionError, Metadata, NodeIndex, NodeInfo, NodeServed, Participation, Proposal, ProposalId,
+    ProtocolParams, Receipt, ReportedReputationMeasurements, ReputationMeasurements, Service,
     ServiceId, ServiceRevenue, TotalServed, TransactionResponse, Value,
 };
 
 use crate::{
     config::{Config, Mode},
-    genesis::{Genesis, GenesisPrices},
+    genesis::{Genesis, GenesisPrices, GenesisRewardWeight},
     query_runner::QueryRunner,
     state::State,
     table::StateTables,
@@ -29,25 +30,33 @@ pub fn new() -> Self {
             .with_table::<EthAddress, AccountInfo>("account")
             .with_table::<ClientPublicKey, EthAddress>("client_keys")
             .with_table::<NodePublicKey, NodeInfo>("node")
-            .with_table::<NodePublicKey, u32>("pubkey_to_index")
-            .with_table::<u32, NodePublicKey>("index_to_pubkey")
-            .with_table::<(u32, u32), Duration>("latencies")
+            .with_table::<NodePublicKey, NodeIndex>("pubkey_to_index")
+            .with_table::<NodeIndex, NodePublicKey>("index_to_pubkey")
+            .with_table::<(NodeIndex, NodeIndex), Duration>("latencies")
             .with_table::<Epoch, Committee>("committee")
             .with_table::<ServiceId, Service>("service")
             .with_table::<ProtocolParams, u128>("parameter")
             .with_table::<NodePublicKey, Vec<ReportedReputationMeasurements>>("rep_measurements")
             .with_table::<NodePublicKey, u8>("rep_scores")
+            .with_table::<NodePublicKey, ReputationMeasurements>("rep_consensus")
             .with_table::<NodePublicKey, NodeServed>("current_epoch_served")
+            .with_table::<NodePublicKey, Participation>("participation")
             .with_table::<NodePublicKey, NodeServed>("last_epoch_served")
             .with_table::<Epoch, TotalServed>("total_served")
             .with_table::<CommodityTypes, HpUfixed<6>>("commodity_prices")
+            .with_table::<CommodityTypes, HpUfixed<6>>("commodity_reward_weights")
             .with_table::<ServiceId, ServiceRevenue>("service_revenue")
+            .with_table::<(NodePublicKey, [u8; 32]), Epoch>("slashed_offences")
+            .with_table::<[u8; 32], Receipt>("receipts")
+            .with_table::<u64, Vec<[u8; 32]>>("block_receipts")
+            .with_table::<ProposalId, Proposal>("proposals")
             .enable_iter("current_epoch_served")
             .enable_iter("rep_measurements")
             .enable_iter("rep_scores")
             .enable_iter("latencies")
             .enable_iter("node")
-            .enable_iter("service_revenue");
+            .enable_iter("service_revenue")
+            .enable_iter("proposals");
 
         #[cfg(debug_assertions)]
         {
@@ -63,9 +72,7 @@ pub fn new() -> Self {
     fn run(&mut self, block: Block) -> BlockExecutionResponse {
         self.inner.run(move |ctx| {
             // Create the app/execution enviroment
-            let backend = StateTables {
-                table_selector: ctx,
-            };
+            let backend = StateTables::new(ctx);
             let app = State::new(backend);
 
             // Create block response
@@ -76,12 +83,40 @@ fn run(&mut self, block: Block) -> BlockExecutionResponse {
                 txn_receipts: Vec::with_capacity(block.transactions.len()),
             };
 
+            let block_number = app.next_block_number();
+            let mut receipt_keys = Vec::with_capacity(block.transactions.len());
+
+            let max_block_weight = app.max_block_weight();
+            let mut block_weight = 0;
+            let mut deferred = false;
+
             // Execute each transaction and add the results to the block response
             for txn in &block.transactions {
-                let receipt = match app.verify_transaction(txn) {
-                    Ok(_) => app.execute_txn(txn.clone()),
-                    Err(err) => TransactionResponse::Revert(err),
-                };
+                receipt_keys.push(txn.receipt_key());
+
+                // A transaction that is heavier than an entire block can never be executed, it is
+                // reverted without touching the state and the transactions after it still run.
+                let weight = txn.payload.method.weight() as u128;
+                if weight > max_block_weight {
+                    response.txn_receipts.push(TransactionResponse::Revert(
+                        ExecutionError::BlockWeightExceeded,
+                    ));
+                    continue;
+                }
+
+                // Once a transaction doesn't fit in the block weight budget, it and all the
+                // transactions after it are deferred without touching the state, so they can be
+                // resubmitted in order in a later block.
+                block_weight += weight;
+                deferred |= block_weight > max_block_weight;
+                if deferred {
+                    response.txn_receipts.push(TransactionResponse::Revert(
+                        ExecutionError::BlockWeightExceeded,
+                    ));
+                    continue;
+                }
+
+                let receipt = app.process_txn(txn);
 
                 // If the transaction moved the epoch forward, aknowledge that in the block response
                 if let TransactionResponse::Success(ExecutionData::EpochChange) = receipt {
@@ -93,6 +128,8 @@ fn run(&mut self, block: Block) -> BlockExecutionResponse {
                 response.txn_receipts.push(receipt);
             }
 
+            app.store_receipts(block_number, &receipt_keys, &response.txn_receipts);
+
             // Return the response
             response
         })
@@ -130,6 +167,11 @@ pub fn genesis(&mut self, config: Config) {
                 Mode::Prod => (),
             }
 
+            if let Some(node) = &config.dev_single_node {
+                genesis = Genesis::dev_single_node(node.clone())
+                    .expect("Failed to build the single node genesis.");
+            }
+
             let mut node_table = ctx.get_table::<NodePublicKey, NodeInfo>("node");
             let mut account_table = ctx.get_table::<EthAddress, AccountInfo>("account");
             let mut service_table = ctx.get_table::<ServiceId, Service>("service");
@@ -138,14 +180,18 @@ pub fn genesis(&mut self, config: Config) {
             let mut metadata_table = ctx.get_table::<Metadata, Value>("metadata");
             let mut commodity_prices_table =
                 ctx.get_table::<CommodityTypes, HpUfixed<6>>("commodity_prices");
+            let mut commodity_reward_weights_table =
+                ctx.get_table::<CommodityTypes, HpUfixed<6>>("commodity_reward_weights");
             let mut rep_scores_table = ctx.get_table::<NodePublicKey, u8>("rep_scores");
             let mut total_served_table = ctx.get_table::<Epoch, TotalServed>("total_served");
             let mut current_epoch_served_table =
                 ctx.get_table::<NodePublicKey, NodeServed>("current_epoch_served");
             let mut latencies_table =
-                ctx.get_table::<(u32, u32), Duration>("latencies");
-            let mut pubkey_to_index_table = ctx.get_table::<NodePublicKey, u32>("pubkey_to_index");
-            let mut index_to_pubkey_table = ctx.get_table::<u32, NodePublicKey>("index_to_pubkey");
+                ctx.get_table::<(NodeIndex, NodeIndex), Duration>("latencies");
+            let mut pubkey_to_index_table =
+                ctx.get_table::<NodePublicKey, NodeIndex>("pubkey_to_index");
+            let mut index_to_pubkey_table =
+                ctx.get_table::<NodeIndex, NodePublicKey>("index_to_pubkey");
 
             let protocol_fund_address =
                 AccountOwnerPublicKey::from_base64(&genesis.protocol_fund_address).unwrap();
@@ -178,6 +224,18 @@ pub fn genesis(&mut self, config: Config) {
             );
             param_table.insert(ProtocolParams::MaxBoost, genesis.max_boost as u128);
             param_table.insert(ProtocolParams::MaxStakeLockTime, genesis.max_lock_time as u128);
+            param_table.insert(
+                ProtocolParams::MaxBlockWeight,
+                genesis.max_block_weight as u128,
+            );
+            param_table.insert(
+                ProtocolParams::MaxMeasurementDeviation,
+                genesis.max_measurement_deviation as u128,
+            );
+            param_table.insert(
+                ProtocolParams::SlashingPercentage,
+                genesis.slashing_percentage as u128,
+            );
             param_table.insert(ProtocolParams::EpochTime, genesis.epoch_time as u128);
             param_table.insert(ProtocolParams::MinimumNodeStake, genesis.min_stake as u128);
             param_table.insert(ProtocolParams::LockTime, genesis.lock_time as u128);
@@ -210,14 +268,14 @@ pub fn genesis(&mut self, config: Config) {
 
                 let node_index = match metadata_table.get(Metadata::NextNodeIndex) {
                     Some(Value::NextNodeIndex(index)) => index,
-                    _ => 0,
+                    _ => NodeIndex::default(),
                 };
                 pubkey_to_index_table.insert(node_info.public_key, node_index);
                 index_to_pubkey_table.insert(node_index, node_info.public_key);
                 node_table.insert(node_info.public_key, node_info);
                 metadata_table.insert(
                     Metadata::NextNodeIndex,
-                    Value::NextNodeIndex(node_index + 1),
+                    Value::NextNodeIndex(node_index.next()),
                 );
             }
             committee_table.insert(
@@ -228,6 +286,10 @@ pub fn genesis(&mut self, config: Config) {
                     epoch_end_timestamp: epoch_end,
                 },
             );
+            metadata_table.insert(
+                Metadata::EpochStart,
+                Value::Timestamp(genesis.epoch_start),
+            );
 
             for service in &genesis.service {
                 let owner_public_key = AccountOwnerPublicKey::from_base64(&service.owner).unwrap();
@@ -259,6 +321,13 @@ pub fn genesis(&mut self, config: Config) {
                 commodity_prices_table.insert(commodity, big_price);
             }
 
+            // add commodity reward weights
+            for reward_weight in genesis.commodity_reward_weights {
+                let GenesisRewardWeight { commodity, weight } = reward_weight;
+                let big_weight: HpUfixed<6> = weight.into();
+                commodity_reward_weights_table.insert(commodity, big_weight);
+            }
+
             // add reputation scores
             for (node_public_key_b64, rep_score) in genesis.rep_scores {
                 let node_public_key = NodePublicKey::from_base64(&node_public_key_b64)
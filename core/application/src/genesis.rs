@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::SystemTime};
 
 use anyhow::{Context, Result};
 use fleek_crypto::{AccountOwnerPublicKey, NodeNetworkingPublicKey, NodePublicKey, PublicKey};
@@ -23,10 +23,17 @@ pub struct Genesis {
     pub consumer_rebate: u64,
     pub max_boost: u16,
     pub max_lock_time: u64,
+    pub max_block_weight: u64,
+    pub max_measurement_deviation: u16,
+    pub slashing_percentage: u16,
     pub committee: Vec<GenesisCommittee>,
     pub service: Vec<GenesisService>,
     pub account: Vec<GenesisAccount>,
     pub commodity_prices: Vec<GenesisPrices>,
+    /// The weight of each commodity in the node rewards, commodities without a weight count their
+    /// revenue as is.
+    #[serde(default)]
+    pub commodity_reward_weights: Vec<GenesisRewardWeight>,
     pub supply_at_genesis: u64,
     pub protocol_fund_address: String,
     pub governance_address: String,
@@ -70,6 +77,12 @@ pub struct GenesisPrices {
     pub price: f64,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GenesisRewardWeight {
+    pub commodity: CommodityTypes,
+    pub weight: f64,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct GenesisLatency {
     pub node_public_key_lhs: String,
@@ -83,6 +96,26 @@ pub fn load() -> Result<Genesis> {
         let raw = include_str!("../genesis.toml");
         toml::from_str(raw).context("Failed to parse genesis file")
     }
+
+    /// Build a minimal genesis for a network bootstrapped by a single node. The protocol
+    /// parameters, services, accounts and prices are taken from the default genesis file, while
+    /// the committee only contains the provided node and any state that refers to the default
+    /// committee is dropped.
+    pub fn dev_single_node(node: GenesisCommittee) -> Result<Genesis> {
+        let mut genesis = Self::load()?;
+        genesis.epoch_start = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .context("System time is before the unix epoch")?
+            .as_millis() as u64;
+        genesis.committee_size = 1;
+        genesis.committee = vec![node];
+        genesis.rep_scores.clear();
+        genesis.node_info.clear();
+        genesis.total_served.clear();
+        genesis.current_epoch_served.clear();
+        genesis.latencies = None;
+        Ok(genesis)
+    }
 }
 
 #[test]
@@ -3,6 +3,8 @@
 pub mod env;
 pub mod genesis;
 pub mod query_runner;
+pub mod reward;
+pub mod selection;
 pub mod state;
 pub mod table;
 #[cfg(test)]
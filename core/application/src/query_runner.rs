@@ -1,18 +1,26 @@
 use std::{collections::HashMap, time::Duration};
 
 use atomo::{Atomo, QueryPerm, ResolvedTableReference};
-use fleek_crypto::{ClientPublicKey, EthAddress, NodePublicKey};
+use fleek_crypto::{
+    ClientPublicKey, EthAddress, NodePublicKey, NodeSignature, PublicKey, TransactionSender,
+};
 use hp_fixed::unsigned::HpUfixed;
 use lightning_interfaces::{
     application::SyncQueryRunnerInterface,
     types::{
-        AccountInfo, Committee, CommodityTypes, Epoch, EpochInfo, Metadata, NodeInfo, NodeServed,
-        ProtocolParams, ReportedReputationMeasurements, Service, ServiceId, ServiceRevenue,
-        TotalServed, TransactionResponse, UpdateRequest, Value,
+        receipt_key, AccountInfo, Committee, CommodityTypes, Epoch, EpochInfo, EpochProgress,
+        ExecutionError, Metadata, NodeIndex, NodeInfo, NodeServed, Participation, Proposal,
+        ProposalId, ProtocolParams, Receipt, ReportedReputationMeasurements,
+        ReputationMeasurements, Service, ServiceId, ServiceRevenue, SimulatedOutcome,
+        SupplySnapshot, TotalServed, TransactionResponse, UpdateRequest, Value,
     },
 };
 
-use crate::{state::State, table::StateTables};
+use crate::{
+    selection::rank_candidates,
+    state::{epoch_change_threshold_met, State},
+    table::{RecordingTables, StateTables},
+};
 
 #[derive(Clone)]
 pub struct QueryRunner {
@@ -21,19 +29,23 @@ pub struct QueryRunner {
     account_table: ResolvedTableReference<EthAddress, AccountInfo>,
     client_table: ResolvedTableReference<ClientPublicKey, EthAddress>,
     node_table: ResolvedTableReference<NodePublicKey, NodeInfo>,
-    pubkey_to_index: ResolvedTableReference<NodePublicKey, u32>,
-    index_to_pubkey: ResolvedTableReference<u32, NodePublicKey>,
+    pubkey_to_index: ResolvedTableReference<NodePublicKey, NodeIndex>,
+    index_to_pubkey: ResolvedTableReference<NodeIndex, NodePublicKey>,
     committee_table: ResolvedTableReference<Epoch, Committee>,
     services_table: ResolvedTableReference<ServiceId, Service>,
     param_table: ResolvedTableReference<ProtocolParams, u128>,
     current_epoch_served: ResolvedTableReference<NodePublicKey, NodeServed>,
     rep_measurements: ResolvedTableReference<NodePublicKey, Vec<ReportedReputationMeasurements>>,
-    latencies: ResolvedTableReference<(u32, u32), Duration>,
+    latencies: ResolvedTableReference<(NodeIndex, NodeIndex), Duration>,
     rep_scores: ResolvedTableReference<NodePublicKey, u8>,
+    rep_consensus: ResolvedTableReference<NodePublicKey, ReputationMeasurements>,
     _last_epoch_served: ResolvedTableReference<NodePublicKey, NodeServed>,
     total_served_table: ResolvedTableReference<Epoch, TotalServed>,
     _service_revenue: ResolvedTableReference<ServiceId, ServiceRevenue>,
     _commodity_price: ResolvedTableReference<CommodityTypes, HpUfixed<6>>,
+    receipts: ResolvedTableReference<[u8; 32], Receipt>,
+    participation: ResolvedTableReference<NodePublicKey, Participation>,
+    proposals: ResolvedTableReference<ProposalId, Proposal>,
 }
 
 impl QueryRunner {
@@ -43,8 +55,8 @@ pub fn init(atomo: Atomo<QueryPerm>) -> Self {
             account_table: atomo.resolve::<EthAddress, AccountInfo>("account"),
             client_table: atomo.resolve::<ClientPublicKey, EthAddress>("client_keys"),
             node_table: atomo.resolve::<NodePublicKey, NodeInfo>("node"),
-            pubkey_to_index: atomo.resolve::<NodePublicKey, u32>("pubkey_to_index"),
-            index_to_pubkey: atomo.resolve::<u32, NodePublicKey>("index_to_pubkey"),
+            pubkey_to_index: atomo.resolve::<NodePublicKey, NodeIndex>("pubkey_to_index"),
+            index_to_pubkey: atomo.resolve::<NodeIndex, NodePublicKey>("index_to_pubkey"),
             committee_table: atomo.resolve::<Epoch, Committee>("committee"),
             services_table: atomo.resolve::<ServiceId, Service>("service"),
             param_table: atomo.resolve::<ProtocolParams, u128>("parameter"),
@@ -52,12 +64,16 @@ pub fn init(atomo: Atomo<QueryPerm>) -> Self {
                 .resolve::<NodePublicKey, NodeServed>("current_epoch_served"),
             rep_measurements: atomo
                 .resolve::<NodePublicKey, Vec<ReportedReputationMeasurements>>("rep_measurements"),
-            latencies: atomo.resolve::<(u32, u32), Duration>("latencies"),
+            latencies: atomo.resolve::<(NodeIndex, NodeIndex), Duration>("latencies"),
             rep_scores: atomo.resolve::<NodePublicKey, u8>("rep_scores"),
+            rep_consensus: atomo.resolve::<NodePublicKey, ReputationMeasurements>("rep_consensus"),
             _last_epoch_served: atomo.resolve::<NodePublicKey, NodeServed>("last_epoch_served"),
             total_served_table: atomo.resolve::<Epoch, TotalServed>("total_served"),
             _commodity_price: atomo.resolve::<CommodityTypes, HpUfixed<6>>("commodity_prices"),
             _service_revenue: atomo.resolve::<ServiceId, ServiceRevenue>("service_revenue"),
+            receipts: atomo.resolve::<[u8; 32], Receipt>("receipts"),
+            participation: atomo.resolve::<NodePublicKey, Participation>("participation"),
+            proposals: atomo.resolve::<ProposalId, Proposal>("proposals"),
             inner: atomo,
         }
     }
@@ -156,6 +172,10 @@ fn get_reputation(&self, node: &NodePublicKey) -> Option<u8> {
         self.inner.run(|ctx| self.rep_scores.get(ctx).get(node))
     }
 
+    fn get_reputation_consensus(&self, node: &NodePublicKey) -> Option<ReputationMeasurements> {
+        self.inner.run(|ctx| self.rep_consensus.get(ctx).get(node))
+    }
+
     fn get_relative_score(&self, _n1: &NodePublicKey, _n2: &NodePublicKey) -> u128 {
         todo!()
     }
@@ -164,16 +184,56 @@ fn get_node_info(&self, id: &NodePublicKey) -> Option<NodeInfo> {
         self.inner.run(|ctx| self.node_table.get(ctx).get(id))
     }
 
+    fn verify_node_signature(
+        &self,
+        node: &NodePublicKey,
+        digest: &[u8; 32],
+        signature: &NodeSignature,
+    ) -> bool {
+        self.get_node_info(node)
+            .map(|info| info.public_key.verify(signature, digest))
+            .unwrap_or(false)
+    }
+
     fn get_node_registry(&self) -> Vec<NodeInfo> {
-        let public_keys: Vec<NodePublicKey> = self
-            .inner
-            .run(|ctx| self.node_table.get(ctx).keys())
-            .collect();
-        public_keys
-            .into_iter()
-            .filter(|node| self.is_valid_node(node))
-            .filter_map(|node| self.inner.run(|ctx| self.node_table.get(ctx).get(node)))
-            .collect()
+        self.get_node_registry_page(0, usize::MAX)
+    }
+
+    fn get_node_registry_page(&self, offset: usize, limit: usize) -> Vec<NodeInfo> {
+        self.inner.run(|ctx| {
+            let minimum_stake: HpUfixed<18> = self
+                .param_table
+                .get(ctx)
+                .get(&ProtocolParams::MinimumNodeStake)
+                .unwrap_or(0)
+                .into();
+            let node_table = self.node_table.get(ctx);
+            // The keys are walked lazily, so only the nodes up to the end of the page are read.
+            node_table
+                .keys()
+                .filter_map(|node| node_table.get(node))
+                .filter(|node| node.stake.staked >= minimum_stake)
+                .skip(offset)
+                .take(limit)
+                .collect()
+        })
+    }
+
+    fn node_registry_len(&self) -> usize {
+        self.inner.run(|ctx| {
+            let minimum_stake: HpUfixed<18> = self
+                .param_table
+                .get(ctx)
+                .get(&ProtocolParams::MinimumNodeStake)
+                .unwrap_or(0)
+                .into();
+            let node_table = self.node_table.get(ctx);
+            node_table
+                .keys()
+                .filter_map(|node| node_table.get(node))
+                .filter(|node| node.stake.staked >= minimum_stake)
+                .count()
+        })
     }
 
     fn is_valid_node(&self, id: &NodePublicKey) -> bool {
@@ -215,6 +275,67 @@ fn get_committee_members(&self) -> Vec<NodePublicKey> {
         })
     }
 
+    fn epoch_change_readiness(&self) -> (usize, usize, bool) {
+        let committee = self.inner.run(|ctx| {
+            let epoch = match self.metadata_table.get(ctx).get(&Metadata::Epoch) {
+                Some(Value::Epoch(epoch)) => epoch,
+                _ => 0,
+            };
+            self.committee_table.get(ctx).get(epoch).unwrap_or_default()
+        });
+        let ready = committee.ready_to_change.len();
+        let total = committee.members.len();
+        (ready, total, epoch_change_threshold_met(ready, total))
+    }
+
+    fn committee_position(&self, node: &NodePublicKey) -> Option<usize> {
+        self.get_committee_members()
+            .iter()
+            .position(|member| member == node)
+    }
+
+    fn get_node_selection_rank(&self, node: &NodePublicKey) -> Option<(usize, u128)> {
+        rank_candidates(&self.get_node_registry())
+            .into_iter()
+            .enumerate()
+            .find(|(_, (candidate, _))| candidate == node)
+            .map(|(rank, (_, score))| (rank, score))
+    }
+
+    fn committee_cutoff_score(&self) -> Option<u128> {
+        let committee_size = self.inner.run(|ctx| {
+            self.param_table
+                .get(ctx)
+                .get(&ProtocolParams::CommitteeSize)
+                .unwrap_or(0)
+        });
+        let ranking = rank_candidates(&self.get_node_registry());
+        if (ranking.len() as u128) <= committee_size {
+            return None;
+        }
+        // The cutoff is the score of the candidate on the last seat.
+        committee_size
+            .checked_sub(1)
+            .and_then(|last_seat| ranking.get(last_seat as usize))
+            .map(|(_, score)| *score)
+    }
+
+    fn get_next_committee_members(&self) -> Vec<NodePublicKey> {
+        self.inner.run(|ctx| {
+            let epoch = match self.metadata_table.get(ctx).get(&Metadata::Epoch) {
+                Some(Value::Epoch(epoch)) => epoch,
+                _ => 0,
+            };
+
+            // The committee of an epoch is only written to the committee table once it is chosen.
+            self.committee_table
+                .get(ctx)
+                .get(epoch + 1)
+                .map(|c| c.members)
+                .unwrap_or_default()
+        })
+    }
+
     fn get_epoch(&self) -> Epoch {
         self.inner.run(
             |ctx| match self.metadata_table.get(ctx).get(&Metadata::Epoch) {
@@ -249,6 +370,62 @@ fn get_epoch_info(&self) -> EpochInfo {
         })
     }
 
+    fn get_epoch_info_at(&self, epoch: Epoch) -> Option<EpochInfo> {
+        self.inner.run(|ctx| {
+            let node_table = self.node_table.get(ctx);
+
+            // The committee of every epoch is kept in the committee table, keyed by the epoch.
+            let committee = self.committee_table.get(ctx).get(epoch)?;
+
+            Some(EpochInfo {
+                committee: committee
+                    .members
+                    .iter()
+                    .filter_map(|member| node_table.get(member))
+                    .collect(),
+                epoch,
+                epoch_end: committee.epoch_end_timestamp,
+            })
+        })
+    }
+
+    fn epoch_progress(&self, now: u64) -> EpochProgress {
+        let (epoch_start, epoch_end) = self.inner.run(|ctx| {
+            let metadata_table = self.metadata_table.get(ctx);
+            let epoch = match metadata_table.get(&Metadata::Epoch) {
+                Some(Value::Epoch(epoch)) => epoch,
+                _ => 0,
+            };
+            let epoch_end = self
+                .committee_table
+                .get(ctx)
+                .get(epoch)
+                .map(|committee| committee.epoch_end_timestamp)
+                .unwrap_or(0);
+            // The start of the epoch is recorded when the epoch changes.
+            let epoch_start = match metadata_table.get(&Metadata::EpochStart) {
+                Some(Value::Timestamp(start)) => start,
+                _ => epoch_end,
+            };
+            (epoch_start, epoch_end)
+        });
+
+        let epoch_time = epoch_end.saturating_sub(epoch_start);
+        let elapsed = now.saturating_sub(epoch_start).min(epoch_time);
+        let remaining = epoch_end.saturating_sub(now).min(epoch_time);
+        let fraction = if epoch_time == 0 {
+            1.0
+        } else {
+            elapsed as f64 / epoch_time as f64
+        };
+
+        EpochProgress {
+            elapsed: Duration::from_millis(elapsed),
+            remaining: Duration::from_millis(remaining),
+            fraction,
+        }
+    }
+
     fn get_total_served(&self, epoch: Epoch) -> TotalServed {
         self.inner.run(|ctx| {
             self.total_served_table
@@ -300,6 +477,51 @@ fn get_protocol_fund_address(&self) -> EthAddress {
         })
     }
 
+    fn get_supply_snapshot(&self) -> SupplySnapshot {
+        self.inner.run(|ctx| {
+            let metadata_table = self.metadata_table.get(ctx);
+            let total_supply = match metadata_table.get(&Metadata::TotalSupply) {
+                Some(Value::HpUfixed(s)) => s,
+                _ => panic!("TotalSupply is set genesis and should never be empty"),
+            };
+            let year_start_supply = match metadata_table.get(&Metadata::SupplyYearStart) {
+                Some(Value::HpUfixed(s)) => s,
+                _ => panic!("SupplyYearStart is set genesis and should never be empty"),
+            };
+            let protocol_fund_address = match metadata_table.get(&Metadata::ProtocolFundAddress) {
+                Some(Value::AccountPublicKey(s)) => s,
+                _ => panic!("AccountPublicKey is set genesis and should never be empty"),
+            };
+            let protocol_fund_balance = self
+                .account_table
+                .get(ctx)
+                .get(protocol_fund_address)
+                .map(|account| account.flk_balance)
+                .unwrap_or(HpUfixed::<18>::zero());
+
+            let node_table = self.node_table.get(ctx);
+            let total_staked = node_table
+                .keys()
+                .filter_map(|node| node_table.get(node))
+                .fold(HpUfixed::<18>::zero(), |total, node| {
+                    total + node.stake.staked + node.stake.locked
+                });
+            let circulating = if total_supply > total_staked {
+                &total_supply - &total_staked
+            } else {
+                HpUfixed::<18>::zero()
+            };
+
+            SupplySnapshot {
+                total_supply,
+                year_start_supply,
+                total_staked,
+                circulating,
+                protocol_fund_balance,
+            }
+        })
+    }
+
     fn get_protocol_params(&self, param: ProtocolParams) -> u128 {
         self.inner.run(|ctx| {
             let param = &param;
@@ -310,16 +532,72 @@ fn get_protocol_params(&self, param: ProtocolParams) -> u128 {
     fn validate_txn(&self, txn: UpdateRequest) -> TransactionResponse {
         self.inner.run(|ctx| {
             // Create the app/execution enviroment
-            let backend = StateTables {
-                table_selector: ctx,
-            };
+            let backend = StateTables::new(ctx);
+            let app = State::new(backend);
+
+            // A transaction that is heavier than an entire block can never be executed.
+            if txn.payload.method.weight() as u128 > app.max_block_weight() {
+                return TransactionResponse::Revert(ExecutionError::BlockWeightExceeded);
+            }
+
+            app.process_txn(&txn)
+        })
+    }
+
+    fn simulate_txn(&self, txn: UpdateRequest) -> SimulatedOutcome {
+        // Running with query permissions, the writes are dropped once the closure returns.
+        self.inner.run(|ctx| {
+            let backend = RecordingTables::new(StateTables::new(ctx));
+            let writes = backend.writes();
             let app = State::new(backend);
-            app.execute_txn(txn.clone())
+
+            let response = if txn.payload.method.weight() as u128 > app.max_block_weight() {
+                TransactionResponse::Revert(ExecutionError::BlockWeightExceeded)
+            } else {
+                app.process_txn(&txn)
+            };
+
+            SimulatedOutcome {
+                response,
+                deltas: writes.deltas(),
+            }
+        })
+    }
+
+    fn get_receipt(&self, sender: &TransactionSender, digest: [u8; 32]) -> Option<Receipt> {
+        let key = receipt_key(sender, &digest);
+        self.inner.run(|ctx| self.receipts.get(ctx).get(key))
+    }
+
+    fn get_participation(&self, node: &NodePublicKey) -> Participation {
+        self.inner
+            .run(|ctx| self.participation.get(ctx).get(node))
+            .unwrap_or_default()
+    }
+
+    fn get_open_proposals(&self) -> Vec<Proposal> {
+        self.inner.run(|ctx| {
+            let epoch = match self.metadata_table.get(ctx).get(&Metadata::Epoch) {
+                Some(Value::Epoch(epoch)) => epoch,
+                _ => 0,
+            };
+            let proposals = self.proposals.get(ctx);
+            let mut open: Vec<Proposal> = proposals
+                .keys()
+                .filter_map(|id| proposals.get(id))
+                .filter(|proposal| proposal.is_open(epoch))
+                .collect();
+            open.sort_unstable_by_key(|proposal| proposal.id);
+            open
         })
     }
 
+    fn get_proposal(&self, id: ProposalId) -> Option<Proposal> {
+        self.inner.run(|ctx| self.proposals.get(ctx).get(id))
+    }
+
     fn get_latencies(&self) -> HashMap<(NodePublicKey, NodePublicKey), Duration> {
-        let keys: Vec<(u32, u32)> = self
+        let keys: Vec<(NodeIndex, NodeIndex)> = self
             .inner
             .run(|ctx| self.latencies.get(ctx).keys())
             .collect();
@@ -348,12 +626,12 @@ fn get_service_info(&self, service_id: ServiceId) -> Service {
             .run(|ctx| self.services_table.get(ctx).get(service_id).unwrap())
     }
 
-    fn pubkey_to_index(&self, node: NodePublicKey) -> Option<u32> {
+    fn pubkey_to_index(&self, node: NodePublicKey) -> Option<NodeIndex> {
         self.inner
             .run(|ctx| self.pubkey_to_index.get(ctx).get(node))
     }
 
-    fn index_to_pubkey(&self, node_index: u32) -> Option<NodePublicKey> {
+    fn index_to_pubkey(&self, node_index: NodeIndex) -> Option<NodePublicKey> {
         self.inner
             .run(|ctx| self.index_to_pubkey.get(ctx).get(node_index))
     }
@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+
+use hp_fixed::unsigned::HpUfixed;
+use lightning_interfaces::types::CommodityTypes;
+use num_traits::FromPrimitive;
+
+/// Decides how much the commodities served by a node count towards its share of the node rewards
+/// of an epoch.
+///
+/// Every served amount is valued at the price of its commodity and scaled by the weight of the
+/// commodity. A commodity with a weight of 2 earns twice the rewards of the same revenue in a
+/// commodity with a weight of 1, which is the weight of the commodities that were not configured.
+pub struct RewardPolicy {
+    prices: HashMap<CommodityTypes, HpUfixed<6>>,
+    weights: HashMap<CommodityTypes, HpUfixed<6>>,
+}
+
+impl RewardPolicy {
+    pub fn new(
+        prices: HashMap<CommodityTypes, HpUfixed<6>>,
+        weights: HashMap<CommodityTypes, HpUfixed<6>>,
+    ) -> Self {
+        Self { prices, weights }
+    }
+
+    /// Returns the weight of the given commodity.
+    pub fn weight(&self, commodity: CommodityTypes) -> HpUfixed<6> {
+        self.weights
+            .get(&commodity)
+            .cloned()
+            .unwrap_or_else(|| 1_u64.into())
+    }
+
+    /// Returns the reward weight of the served amounts, which are indexed by commodity type.
+    pub fn reward_weight(&self, served: &[u128]) -> HpUfixed<18> {
+        let mut total = HpUfixed::<18>::zero();
+        for (index, amount) in served.iter().enumerate() {
+            let Some(commodity) = CommodityTypes::from_usize(index) else {
+                continue;
+            };
+            let Some(price) = self.prices.get(&commodity) else {
+                continue;
+            };
+            let amount: HpUfixed<6> = (*amount).into();
+            let weighted = &(&amount * price) * &self.weight(commodity);
+            total = total + &weighted.convert_precision::<18>();
+        }
+        total
+    }
+}
@@ -0,0 +1,25 @@
+//! The ordering of the candidates for the committee.
+//!
+//! Both the queries that report where a node stands and the committee selection itself must go
+//! through these functions, so the rank a node operator sees is the rank that is used.
+
+use fleek_crypto::NodePublicKey;
+use lightning_interfaces::types::NodeInfo;
+
+/// Returns the score of a candidate for the committee, which is its stake in whole FLK.
+pub fn selection_score(node: &NodeInfo) -> u128 {
+    u128::try_from(node.stake.staked.clone()).unwrap_or(u128::MAX)
+}
+
+/// Orders the candidates by descending score. Ties are broken by the public key, so that every
+/// node derives the same ordering.
+pub fn rank_candidates<'a>(
+    candidates: impl IntoIterator<Item = &'a NodeInfo>,
+) -> Vec<(NodePublicKey, u128)> {
+    let mut ranking = candidates
+        .into_iter()
+        .map(|node| (node.public_key, selection_score(node)))
+        .collect::<Vec<_>>();
+    ranking.sort_by(|(a, a_score), (b, b_score)| b_score.cmp(a_score).then_with(|| a.cmp(b)));
+    ranking
+}
@@ -1,6 +1,6 @@
 use std::{
     cmp::Ordering,
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, HashMap, HashSet},
     time::Duration,
 };
 
@@ -13,8 +13,9 @@
 use lightning_interfaces::{
     types::{
         AccountInfo, Committee, CommodityTypes, DeliveryAcknowledgment, Epoch, ExecutionData,
-        ExecutionError, Metadata, NodeInfo, NodeServed, ProofOfConsensus, ProofOfMisbehavior,
-        ProtocolParams, ReportedReputationMeasurements, ReputationMeasurements, Service, ServiceId,
+        ExecutionError, Metadata, MisbehaviorEvidence, NodeIndex, NodeInfo, NodeServed,
+        Participation, ProofOfConsensus, ProofOfMisbehavior, Proposal, ProposalId, ProtocolParams,
+        Receipt, ReportedReputationMeasurements, ReputationMeasurements, Service, ServiceId,
         ServiceRevenue, Staking, Tokens, TotalServed, TransactionResponse, UpdateMethod,
         UpdateRequest, Value, Worker,
     },
@@ -23,7 +24,10 @@
 use lightning_reputation::{statistics, types::WeightedReputationMeasurements};
 use multiaddr::Multiaddr;
 
-use crate::table::{Backend, TableRef};
+use crate::{
+    reward::RewardPolicy,
+    table::{Backend, TableRef},
+};
 
 /// Minimum number of reported measurements that have to be available for a node.
 /// If less measurements have been reported, no reputation score will be computed in that epoch.
@@ -45,6 +49,16 @@
 /// epochs and 30% is based on the current epoch.
 const REP_EWMA_WEIGHT: f64 = 0.7;
 
+/// The number of most recent blocks for which the receipts of the transactions are kept. The
+/// receipts of older blocks are removed from the state.
+pub const RECEIPT_RETENTION_BLOCKS: u64 = 1024;
+
+/// Returns true if enough members of a committee of the given size signaled that they are ready
+/// to change the epoch, which takes more than two thirds of the committee.
+pub fn epoch_change_threshold_met(ready: usize, committee_size: usize) -> bool {
+    ready > 2 * committee_size / 3
+}
+
 lazy_static! {
     static ref BIG_HUNDRED: HpUfixed<18> = HpUfixed::<18>::from(100_u64);
 }
@@ -58,19 +72,26 @@ pub struct State<B: Backend> {
     pub account_info: B::Ref<EthAddress, AccountInfo>,
     pub client_keys: B::Ref<ClientPublicKey, EthAddress>,
     pub node_info: B::Ref<NodePublicKey, NodeInfo>,
-    pub pubkey_to_index: B::Ref<NodePublicKey, u32>,
-    pub index_to_pubkey: B::Ref<u32, NodePublicKey>,
-    pub latencies: B::Ref<(u32, u32), Duration>,
+    pub pubkey_to_index: B::Ref<NodePublicKey, NodeIndex>,
+    pub index_to_pubkey: B::Ref<NodeIndex, NodePublicKey>,
+    pub latencies: B::Ref<(NodeIndex, NodeIndex), Duration>,
     pub committee_info: B::Ref<Epoch, Committee>,
     pub services: B::Ref<ServiceId, Service>,
     pub parameters: B::Ref<ProtocolParams, u128>,
     pub rep_measurements: B::Ref<NodePublicKey, Vec<ReportedReputationMeasurements>>,
     pub rep_scores: B::Ref<NodePublicKey, u8>,
+    pub rep_consensus: B::Ref<NodePublicKey, ReputationMeasurements>,
     pub current_epoch_served: B::Ref<NodePublicKey, NodeServed>,
     pub last_epoch_served: B::Ref<NodePublicKey, NodeServed>,
+    pub participation: B::Ref<NodePublicKey, Participation>,
     pub total_served: B::Ref<Epoch, TotalServed>,
     pub service_revenue: B::Ref<ServiceId, ServiceRevenue>,
     pub commodity_prices: B::Ref<CommodityTypes, HpUfixed<6>>,
+    pub commodity_reward_weights: B::Ref<CommodityTypes, HpUfixed<6>>,
+    pub slashed_offences: B::Ref<(NodePublicKey, [u8; 32]), Epoch>,
+    pub receipts: B::Ref<[u8; 32], Receipt>,
+    pub block_receipts: B::Ref<u64, Vec<[u8; 32]>>,
+    pub proposals: B::Ref<ProposalId, Proposal>,
     pub backend: B,
 }
 
@@ -89,37 +110,150 @@ pub fn new(backend: B) -> Self {
             rep_measurements: backend.get_table_reference("rep_measurements"),
             latencies: backend.get_table_reference("latencies"),
             rep_scores: backend.get_table_reference("rep_scores"),
+            rep_consensus: backend.get_table_reference("rep_consensus"),
             last_epoch_served: backend.get_table_reference("last_epoch_served"),
             current_epoch_served: backend.get_table_reference("current_epoch_served"),
+            participation: backend.get_table_reference("participation"),
             total_served: backend.get_table_reference("total_served"),
             commodity_prices: backend.get_table_reference("commodity_prices"),
+            commodity_reward_weights: backend.get_table_reference("commodity_reward_weights"),
             service_revenue: backend.get_table_reference("service_revenue"),
+            slashed_offences: backend.get_table_reference("slashed_offences"),
+            receipts: backend.get_table_reference("receipts"),
+            block_receipts: backend.get_table_reference("block_receipts"),
+            proposals: backend.get_table_reference("proposals"),
             backend,
         }
     }
 
+    /// Verify a transaction and execute it if it is valid.
+    ///
+    /// This is the only place that decides whether a transaction succeeds or reverts. Block
+    /// execution and `validate_txn` both go through it, so for the same state they always agree on
+    /// the outcome.
+    pub fn process_txn(&self, txn: &UpdateRequest) -> TransactionResponse {
+        match self.verify_transaction(txn) {
+            Ok(_) => self.execute_txn(txn.clone()),
+            Err(err) => TransactionResponse::Revert(err),
+        }
+    }
+
+    /// Returns the maximum total weight of the transactions executed in a single block.
+    pub fn max_block_weight(&self) -> u128 {
+        self.parameters
+            .get(&ProtocolParams::MaxBlockWeight)
+            .unwrap_or(u128::MAX)
+    }
+
+    /// Increment the block number and return the number of the block that is being executed. The
+    /// first block has number 1.
+    pub fn next_block_number(&self) -> u64 {
+        let block_number = match self.metadata.get(&Metadata::BlockNumber) {
+            Some(Value::BlockNumber(number)) => number + 1,
+            _ => 1,
+        };
+        self.metadata
+            .set(Metadata::BlockNumber, Value::BlockNumber(block_number));
+        block_number
+    }
+
+    /// Store the receipts of the transactions of a block under their receipt keys, and remove the
+    /// receipts of the block that fell out of the retention window.
+    ///
+    /// The receipt of a transaction that succeeded is never replaced, so replaying a transaction
+    /// that was already executed does not hide its outcome behind the revert of the replay.
+    pub fn store_receipts(
+        &self,
+        block_number: u64,
+        keys: &[[u8; 32]],
+        responses: &[TransactionResponse],
+    ) {
+        let epoch = match self.metadata.get(&Metadata::Epoch) {
+            Some(Value::Epoch(epoch)) => epoch,
+            _ => 0,
+        };
+        for (index, (key, response)) in keys.iter().zip(responses).enumerate() {
+            if let Some(Receipt {
+                response: TransactionResponse::Success(_),
+                ..
+            }) = self.receipts.get(key)
+            {
+                continue;
+            }
+            self.receipts.set(
+                *key,
+                Receipt {
+                    response: response.clone(),
+                    epoch,
+                    block_number,
+                    index: index as u32,
+                },
+            );
+        }
+        self.block_receipts.set(block_number, keys.to_vec());
+
+        if block_number > RECEIPT_RETENTION_BLOCKS {
+            let expired = block_number - RECEIPT_RETENTION_BLOCKS;
+            for key in self.block_receipts.get(&expired).unwrap_or_default() {
+                // The key may have been stored again by a later block.
+                let expired_receipt = self
+                    .receipts
+                    .get(&key)
+                    .map_or(false, |receipt| receipt.block_number == expired);
+                if expired_receipt {
+                    self.receipts.remove(&key);
+                }
+            }
+            self.block_receipts.remove(&expired);
+        }
+    }
+
     /// This function is the entry point of a transaction
     pub fn execute_txn(&self, txn: UpdateRequest) -> TransactionResponse {
         // Execute transaction
-        let response = match txn.payload.method {
+        let response = self.execute_method(txn.sender, txn.payload.method);
+
+        #[cfg(debug_assertions)]
+        {
+            let node_info_len = self.node_info.keys().count();
+            let index_to_pubkey_len = self.index_to_pubkey.keys().count();
+            let pubkey_to_index_len = self.pubkey_to_index.keys().count();
+            assert_eq!(node_info_len, index_to_pubkey_len);
+            assert_eq!(node_info_len, pubkey_to_index_len);
+            assert_eq!(index_to_pubkey_len, pubkey_to_index_len);
+        }
+
+        // Increment nonce of the sender
+        self.increment_nonce(txn.sender);
+        // Return the response
+        response
+    }
+
+    /// Execute a single method on behalf of the sender, without touching the nonce.
+    fn execute_method(
+        &self,
+        sender: TransactionSender,
+        method: UpdateMethod,
+    ) -> TransactionResponse {
+        match method {
             UpdateMethod::SubmitDeliveryAcknowledgmentAggregation {
                 commodity,
                 service_id,
                 proofs,
                 metadata: _,
-            } => self.submit_pod(txn.sender, commodity, service_id, proofs),
+            } => self.submit_pod(sender, commodity, service_id, proofs),
 
             UpdateMethod::Withdraw {
                 amount,
                 token,
                 receiving_address,
-            } => self.withdraw(txn.sender, receiving_address, amount, token),
+            } => self.withdraw(sender, receiving_address, amount, token),
 
             UpdateMethod::Deposit {
                 proof,
                 token,
                 amount,
-            } => self.deposit(txn.sender, proof, amount, token),
+            } => self.deposit(sender, proof, amount, token),
 
             UpdateMethod::Stake {
                 amount,
@@ -130,7 +264,7 @@ pub fn execute_txn(&self, txn: UpdateRequest) -> TransactionResponse {
                 worker_domain,
                 worker_mempool_address,
             } => self.stake(
-                txn.sender,
+                sender,
                 amount,
                 node_public_key,
                 node_network_key,
@@ -140,53 +274,78 @@ pub fn execute_txn(&self, txn: UpdateRequest) -> TransactionResponse {
                 worker_mempool_address,
             ),
             UpdateMethod::StakeLock { node, locked_for } => {
-                self.stake_lock(txn.sender, node, locked_for)
+                self.stake_lock(sender, node, locked_for)
             },
 
-            UpdateMethod::Unstake { amount, node } => self.unstake(txn.sender, amount, node),
+            UpdateMethod::Unstake { amount, node } => self.unstake(sender, amount, node),
 
             UpdateMethod::WithdrawUnstaked { node, recipient } => {
-                self.withdrawl_unstaked(txn.sender, node, recipient)
+                self.withdrawl_unstaked(sender, node, recipient)
             },
 
-            UpdateMethod::ChangeEpoch { epoch } => self.change_epoch(txn.sender, epoch),
+            UpdateMethod::ChangeEpoch { epoch } => self.change_epoch(sender, epoch),
 
             UpdateMethod::AddService {
                 service,
                 service_id,
-            } => self.add_service(txn.sender, service, service_id),
+            } => self.add_service(sender, service, service_id),
 
-            UpdateMethod::RemoveService { service_id } => {
-                self.remove_service(txn.sender, service_id)
-            },
+            UpdateMethod::RemoveService { service_id } => self.remove_service(sender, service_id),
 
             UpdateMethod::Slash {
                 service_id,
                 node,
                 proof_of_misbehavior,
-            } => self.slash(txn.sender, proof_of_misbehavior, service_id, node),
+            } => self.slash(sender, proof_of_misbehavior, service_id, node),
 
             UpdateMethod::SubmitReputationMeasurements { measurements } => {
-                self.submit_reputation_measurements(txn.sender, measurements)
+                self.submit_reputation_measurements(sender, measurements)
             },
             UpdateMethod::ChangeProtocolParam { param, value } => {
-                self.change_protocol_param(txn.sender, param, value)
+                self.change_protocol_param(sender, param, value)
             },
-        };
+            UpdateMethod::Bundle { methods } => self.execute_bundle(sender, methods),
+            UpdateMethod::SubmitMisbehaviorEvidence { node, evidence } => {
+                self.submit_misbehavior_evidence(sender, node, evidence)
+            },
+            UpdateMethod::SubmitProposal { method, deadline } => {
+                self.submit_proposal(sender, *method, deadline)
+            },
+            UpdateMethod::VoteOnProposal {
+                proposal_id,
+                approve,
+            } => self.vote_on_proposal(sender, proposal_id, approve),
+        }
+    }
 
-        #[cfg(debug_assertions)]
+    /// Execute the methods of a bundle in order, reverting the changes of all of them if any one
+    /// of them reverts. A bundle that contains another bundle reverts without executing anything,
+    /// since the checkpoints of the bundles would be nested.
+    fn execute_bundle(
+        &self,
+        sender: TransactionSender,
+        methods: Vec<UpdateMethod>,
+    ) -> TransactionResponse {
+        if methods
+            .iter()
+            .any(|method| matches!(method, UpdateMethod::Bundle { .. }))
         {
-            let node_info_len = self.node_info.keys().count();
-            let index_to_pubkey_len = self.index_to_pubkey.keys().count();
-            let pubkey_to_index_len = self.pubkey_to_index.keys().count();
-            assert_eq!(node_info_len, index_to_pubkey_len);
-            assert_eq!(node_info_len, pubkey_to_index_len);
-            assert_eq!(index_to_pubkey_len, pubkey_to_index_len);
+            return TransactionResponse::Revert(ExecutionError::NestedBundle);
         }
-
-        // Increment nonce of the sender
-        self.increment_nonce(txn.sender);
-        // Return the response
+        let checkpoint = self.backend.checkpoint();
+        let mut response = TransactionResponse::Success(ExecutionData::None);
+        for method in methods {
+            match self.execute_method(sender, method) {
+                TransactionResponse::Revert(error) => {
+                    self.backend.rollback(checkpoint);
+                    return TransactionResponse::Revert(error);
+                },
+                // The epoch change has to be reported even if it is not the last method.
+                _ if response == TransactionResponse::Success(ExecutionData::EpochChange) => {},
+                method_response => response = method_response,
+            }
+        }
+        self.backend.release(checkpoint);
         response
     }
 
@@ -634,15 +793,21 @@ fn change_epoch(&self, sender: TransactionSender, epoch: Epoch) -> TransactionRe
         current_committee.ready_to_change.push(sender);
 
         // If more than 2/3rds of the committee have signaled, start the epoch change process
-        if current_committee.ready_to_change.len() > (2 * current_committee.members.len() / 3) {
+        if epoch_change_threshold_met(
+            current_committee.ready_to_change.len(),
+            current_committee.members.len(),
+        ) {
             // Todo: Reward nodes, calculate rep?, choose new committee, increment epoch.
+            // This has to happen before the rewards are distributed, which clears what was served.
+            self.record_participation(&current_committee);
             self.calculate_reputation_scores();
             self.distribute_rewards();
 
             // calculate the next epoch endstamp
             let epoch_duration = self.parameters.get(&ProtocolParams::EpochTime).unwrap_or(1);
 
-            let new_epoch_end = current_committee.epoch_end_timestamp + epoch_duration as u64;
+            let new_epoch_start = current_committee.epoch_end_timestamp;
+            let new_epoch_end = new_epoch_start + epoch_duration as u64;
 
             // Save the old committee so we can see who signaled
             self.committee_info.set(current_epoch, current_committee);
@@ -662,6 +827,8 @@ fn change_epoch(&self, sender: TransactionSender, epoch: Epoch) -> TransactionRe
 
             self.metadata
                 .set(Metadata::Epoch, Value::Epoch(current_epoch));
+            self.metadata
+                .set(Metadata::EpochStart, Value::Timestamp(new_epoch_start));
             TransactionResponse::Success(ExecutionData::EpochChange)
         } else {
             self.committee_info.set(current_epoch, current_committee);
@@ -669,6 +836,39 @@ fn change_epoch(&self, sender: TransactionSender, epoch: Epoch) -> TransactionRe
         }
     }
 
+    /// Update the participation of the nodes with the epoch that is ending, given its committee.
+    ///
+    /// A node is active if it signaled the epoch change or served commodities during the epoch,
+    /// and the committee members that were not active missed the epoch.
+    fn record_participation(&self, committee: &Committee) {
+        let active: HashSet<NodePublicKey> = committee
+            .ready_to_change
+            .iter()
+            .copied()
+            .chain(self.current_epoch_served.keys())
+            .collect();
+
+        let mut nodes: HashMap<NodePublicKey, Participation> = HashMap::new();
+        for node in &active {
+            nodes.entry(*node).or_default().epochs_active = 1;
+        }
+        for node in &committee.members {
+            let participation = nodes.entry(*node).or_default();
+            participation.epochs_in_committee += 1;
+            if !active.contains(node) {
+                participation.missed_epochs += 1;
+            }
+        }
+
+        for (node, epoch) in nodes {
+            let mut participation = self.participation.get(&node).unwrap_or_default();
+            participation.epochs_active += epoch.epochs_active;
+            participation.epochs_in_committee += epoch.epochs_in_committee;
+            participation.missed_epochs += epoch.missed_epochs;
+            self.participation.set(node, participation);
+        }
+    }
+
     fn calculate_reputation_scores(&self) {
         let mut rep_scores = HashMap::new();
         self.rep_scores.keys().for_each(|node| {
@@ -692,8 +892,9 @@ fn calculate_reputation_scores(&self) {
                         .into_iter()
                         .map(|m| {
                             let weight = self
-                                .rep_scores
+                                .index_to_pubkey
                                 .get(&m.reporting_node)
+                                .and_then(|reporter| self.rep_scores.get(&reporter))
                                 .unwrap_or(default_score);
                             WeightedReputationMeasurements {
                                 measurements: m.measurements,
@@ -726,12 +927,67 @@ fn calculate_reputation_scores(&self) {
         }
 
         self.update_latencies();
+        self.update_measurement_consensus();
 
         // Remove measurements from this epoch once we ccalculated the rep scores.
         let nodes = self.rep_measurements.keys();
         nodes.for_each(|node| self.rep_measurements.remove(&node));
     }
 
+    /// Store the stake-weighted consensus of the measurements reported for each node in this
+    /// epoch, replacing the consensus from the previous epoch.
+    fn update_measurement_consensus(&self) {
+        let max_deviation = self
+            .parameters
+            .get(&ProtocolParams::MaxMeasurementDeviation)
+            .map_or(f64::INFINITY, |percent| percent as f64 / 100.0);
+
+        let nodes = self.rep_consensus.keys();
+        nodes.for_each(|node| self.rep_consensus.remove(&node));
+
+        for node in self.rep_measurements.keys() {
+            let Some(reported_measurements) = self.rep_measurements.get(&node) else {
+                continue;
+            };
+            // Reporters are weighted by their own stake.
+            let reports: Vec<_> = reported_measurements
+                .into_iter()
+                .filter_map(|m| {
+                    let reporter = self.index_to_pubkey.get(&m.reporting_node)?;
+                    let stake = self.node_info.get(&reporter)?.stake.staked;
+                    let weight = f64::try_from(stake).ok()?;
+                    Some((m.measurements, weight))
+                })
+                .collect();
+            let consensus = |field: fn(&ReputationMeasurements) -> Option<f64>| {
+                let values = reports
+                    .iter()
+                    .filter_map(|(m, weight)| Some((field(m)?, *weight)))
+                    .collect();
+                statistics::weighted_consensus(values, max_deviation)
+            };
+
+            let measurements = ReputationMeasurements {
+                latency: consensus(|m| m.latency.map(|l| l.as_secs_f64()))
+                    .map(Duration::from_secs_f64),
+                interactions: consensus(|m| m.interactions.map(|v| v as f64))
+                    .map(|v| v.round() as i64),
+                inbound_bandwidth: consensus(|m| m.inbound_bandwidth.map(|v| v as f64))
+                    .map(|v| v.round() as u128),
+                outbound_bandwidth: consensus(|m| m.outbound_bandwidth.map(|v| v as f64))
+                    .map(|v| v.round() as u128),
+                bytes_received: consensus(|m| m.bytes_received.map(|v| v as f64))
+                    .map(|v| v.round() as u128),
+                bytes_sent: consensus(|m| m.bytes_sent.map(|v| v as f64))
+                    .map(|v| v.round() as u128),
+                hops: consensus(|m| m.hops.map(|v| v as f64)).map(|v| v.round() as u8),
+            };
+            if measurements != ReputationMeasurements::default() {
+                self.rep_consensus.set(node, measurements);
+            }
+        }
+    }
+
     fn update_latencies(&self) {
         // Remove latency measurements from invalid nodes.
         let node_registry = self.get_node_registry();
@@ -749,11 +1005,15 @@ fn update_latencies(&self) {
         for node in self.rep_measurements.keys() {
             if let Some(reported_measurements) = self.rep_measurements.get(&node) {
                 for measurement in reported_measurements {
+                    let Some(reporter) = self.index_to_pubkey.get(&measurement.reporting_node)
+                    else {
+                        continue;
+                    };
                     if let Some(latency) = measurement.measurements.latency {
-                        let (node_lhs, node_rhs) = if node < measurement.reporting_node {
-                            (node, measurement.reporting_node)
+                        let (node_lhs, node_rhs) = if node < reporter {
+                            (node, reporter)
                         } else {
-                            (measurement.reporting_node, node)
+                            (reporter, node)
                         };
                         let latency =
                             if let Some(opp_latency) = latency_map.get(&(node_lhs, node_rhs)) {
@@ -803,6 +1063,91 @@ fn change_protocol_param(
         TransactionResponse::Success(ExecutionData::None)
     }
 
+    // This method can panic if the governance address wasn't previously stored in the application
+    // state. The governance address should be seeded though the genesis.
+    fn submit_proposal(
+        &self,
+        sender: TransactionSender,
+        method: UpdateMethod,
+        deadline: Epoch,
+    ) -> TransactionResponse {
+        let sender = match self.only_account_owner(sender) {
+            Ok(account) => account,
+            Err(e) => return e,
+        };
+        let governance_address = match self.metadata.get(&Metadata::GovernanceAddress) {
+            Some(Value::AccountPublicKey(address)) => address,
+            _ => panic!("Governance address is missing from state."),
+        };
+        if sender != governance_address {
+            return TransactionResponse::Revert(ExecutionError::OnlyGovernance);
+        }
+
+        let current_epoch = match self.metadata.get(&Metadata::Epoch) {
+            Some(Value::Epoch(epoch)) => epoch,
+            _ => 0,
+        };
+        // A proposal that could never be voted on is rejected right away.
+        if deadline <= current_epoch {
+            return TransactionResponse::Revert(ExecutionError::ProposalExpired);
+        }
+
+        let id = match self.metadata.get(&Metadata::NextProposalId) {
+            Some(Value::NextProposalId(id)) => id,
+            _ => 0,
+        };
+        self.proposals.set(
+            id,
+            Proposal {
+                id,
+                method,
+                votes: BTreeMap::new(),
+                deadline,
+            },
+        );
+        self.metadata
+            .set(Metadata::NextProposalId, Value::NextProposalId(id + 1));
+        TransactionResponse::Success(ExecutionData::UInt(id as u128))
+    }
+
+    fn vote_on_proposal(
+        &self,
+        sender: TransactionSender,
+        proposal_id: ProposalId,
+        approve: bool,
+    ) -> TransactionResponse {
+        // Only Nodes can call this function
+        let sender = match self.only_node(sender) {
+            Ok(node) => node,
+            Err(e) => return e,
+        };
+        let mut proposal = match self.proposals.get(&proposal_id) {
+            Some(proposal) => proposal,
+            None => return TransactionResponse::Revert(ExecutionError::ProposalDoesNotExist),
+        };
+
+        let current_epoch = match self.metadata.get(&Metadata::Epoch) {
+            Some(Value::Epoch(epoch)) => epoch,
+            _ => 0,
+        };
+        if !proposal.is_open(current_epoch) {
+            return TransactionResponse::Revert(ExecutionError::ProposalExpired);
+        }
+
+        // The members of the current committee vote on the proposals.
+        let committee = self.committee_info.get(&current_epoch).unwrap_or_default();
+        if !committee.members.contains(&sender) {
+            return TransactionResponse::Revert(ExecutionError::NotCommitteeMember);
+        }
+        if proposal.votes.contains_key(&sender) {
+            return TransactionResponse::Revert(ExecutionError::AlreadyVoted);
+        }
+
+        proposal.votes.insert(sender, approve);
+        self.proposals.set(proposal_id, proposal);
+        TransactionResponse::Success(ExecutionData::None)
+    }
+
     fn get_node_registry(&self) -> HashMap<NodePublicKey, NodeInfo> {
         let minimum_stake = self
             .parameters
@@ -842,6 +1187,56 @@ fn slash(
         todo!()
     }
 
+    fn submit_misbehavior_evidence(
+        &self,
+        _sender: TransactionSender,
+        node_public_key: NodePublicKey,
+        evidence: MisbehaviorEvidence,
+    ) -> TransactionResponse {
+        // Anyone can submit evidence, since it is verified against the key of the node alone.
+        let mut node = match self.node_info.get(&node_public_key) {
+            Some(node) => node,
+            None => return TransactionResponse::Revert(ExecutionError::NodeDoesNotExist),
+        };
+
+        if !evidence.verify(&node_public_key) {
+            return TransactionResponse::Revert(ExecutionError::InvalidProof);
+        }
+
+        // A node is only slashed once per offence, no matter how often it is reported.
+        let offence = (node_public_key, evidence.offence_id());
+        if self.slashed_offences.get(&offence).is_some() {
+            return TransactionResponse::Revert(ExecutionError::AlreadySlashed);
+        }
+
+        let current_epoch = match self.metadata.get(&Metadata::Epoch) {
+            Some(Value::Epoch(epoch)) => epoch,
+            _ => 0,
+        };
+
+        let slashing_percentage: HpUfixed<18> = self
+            .parameters
+            .get(&ProtocolParams::SlashingPercentage)
+            .unwrap_or(0)
+            .min(100)
+            .into();
+        let slashed = &(&node.stake.staked * &slashing_percentage) / &(*BIG_HUNDRED);
+        node.stake.staked -= slashed.clone();
+
+        // The slashed stake goes to the protocol fund, so the total supply stays the same.
+        let protocol_owner = match self.metadata.get(&Metadata::ProtocolFundAddress) {
+            Some(Value::AccountPublicKey(owner)) => owner,
+            _ => panic!("ProtocolFundAddress is added at Genesis and should exist"),
+        };
+        let mut protocol_account = self.account_info.get(&protocol_owner).unwrap_or_default();
+        protocol_account.flk_balance += slashed;
+
+        self.account_info.set(protocol_owner, protocol_account);
+        self.node_info.set(node_public_key, node);
+        self.slashed_offences.set(offence, current_epoch);
+        TransactionResponse::Success(ExecutionData::None)
+    }
+
     fn submit_reputation_measurements(
         &self,
         sender: TransactionSender,
@@ -851,6 +1246,11 @@ fn submit_reputation_measurements(
             Ok(account) => account,
             Err(e) => return e,
         };
+        // Every node that exists has an index.
+        let reporting_node = match self.pubkey_to_index.get(&reporting_node) {
+            Some(index) => index,
+            None => return TransactionResponse::Revert(ExecutionError::NodeDoesNotExist),
+        };
         measurements.into_iter().for_each(|(peer, measurements)| {
             let mut node_measurements = match self.rep_measurements.get(&peer) {
                 Some(node_measurements) => node_measurements,
@@ -882,7 +1282,8 @@ fn submit_reputation_measurements(
     /// This function calculates the rewards for each node that has served commodities in the
     /// current epoch. The rewards are given in two forms: 1) A `stable` currency that is
     /// proportional to the revenue earned by selling the commodities. 2) `FLK` token that is
-    /// proportional to the `stable` currency rewards, but also depends on a `boost` factor.
+    /// proportional to the reward weight of the served commodities given by the [`RewardPolicy`],
+    /// but also depends on a `boost` factor.
     ///
     /// `FLk` total emission is given by:
     /// `emission = (inflation * supply) / (daysInYear=365.0)`
@@ -892,11 +1293,8 @@ fn distribute_rewards(&self) {
             _ => 0,
         };
 
-        let reward_pool = self
-            .total_served
-            .get(&epoch)
-            .unwrap_or_default()
-            .reward_pool;
+        let total_served = self.total_served.get(&epoch).unwrap_or_default();
+        let reward_pool = total_served.reward_pool;
 
         // if reward is 0, no commodity under any service was served
         if reward_pool == HpUfixed::zero() {
@@ -911,6 +1309,9 @@ fn distribute_rewards(&self) {
         let emissions = self.calculate_emissions();
         let emissions_for_node = &emissions * &node_share;
 
+        let reward_policy = self.reward_policy();
+        let total_reward_weight = reward_policy.reward_weight(&total_served.served);
+
         let mut total_reward_share: HpUfixed<18> = HpUfixed::from(0_u64);
         let mut local_shares_map: HashMap<NodePublicKey, HpUfixed<18>> = HashMap::new();
         let mut node_info_map: HashMap<NodePublicKey, NodeInfo> = HashMap::new();
@@ -921,14 +1322,15 @@ fn distribute_rewards(&self) {
             let node_info = self.node_info.get(&node).unwrap();
             node_info_map.insert(node, node_info.clone());
 
-            let stables_revenue: HpUfixed<6> = self
-                .current_epoch_served
-                .get(&node)
-                .unwrap_or_default()
-                .stables_revenue;
+            let node_served = self.current_epoch_served.get(&node).unwrap_or_default();
+            let stables_revenue = node_served.stables_revenue;
 
-            let node_service_proportion =
-                &stables_revenue.convert_precision::<18>() / &reward_pool.convert_precision::<18>();
+            // All of the commodities could have a weight of zero.
+            let node_service_proportion = if total_reward_weight == HpUfixed::zero() {
+                HpUfixed::zero()
+            } else {
+                &reward_policy.reward_weight(&node_served.served) / &total_reward_weight
+            };
             self.mint_and_transfer_stables(
                 stables_revenue * &node_share.convert_precision(),
                 node_info.owner,
@@ -941,14 +1343,14 @@ fn distribute_rewards(&self) {
             local_shares_map.insert(node, local_share);
         }
 
-        let base_reward = &emissions_for_node / &total_reward_share;
-
         for (node, node_info) in node_info_map.iter() {
-            let local_share = local_shares_map.get(node).unwrap();
-            let flk_rewards = &base_reward * local_share;
+            if total_reward_share != HpUfixed::zero() {
+                let local_share = local_shares_map.get(node).unwrap();
+                let flk_rewards = &(&emissions_for_node / &total_reward_share) * local_share;
 
-            // todo: add service builders and protocols share in stables too
-            self.mint_and_transfer_flk(flk_rewards, node_info.owner);
+                // todo: add service builders and protocols share in stables too
+                self.mint_and_transfer_flk(flk_rewards, node_info.owner);
+            }
             self.current_epoch_served.remove(node);
         }
 
@@ -994,6 +1396,31 @@ fn distribute_rewards(&self) {
             protocol_owner,
         );
         self.mint_and_transfer_flk(&emissions * &protocol_share, protocol_owner);
+
+        // If none of the served commodities has a reward weight, no node has a share of the
+        // emissions for the nodes, so they go to the protocol fund instead.
+        if total_reward_share == HpUfixed::zero() {
+            self.mint_and_transfer_flk(emissions_for_node, protocol_owner);
+        }
+    }
+
+    /// Returns the policy that weighs the commodities served by the nodes in their rewards.
+    fn reward_policy(&self) -> RewardPolicy {
+        let mut prices = HashMap::new();
+        let mut weights = HashMap::new();
+        for commodity in [
+            CommodityTypes::Bandwidth,
+            CommodityTypes::Compute,
+            CommodityTypes::Gpu,
+        ] {
+            if let Some(price) = self.commodity_prices.get(&commodity) {
+                prices.insert(commodity, price);
+            }
+            if let Some(weight) = self.commodity_reward_weights.get(&commodity) {
+                weights.insert(commodity, weight);
+            }
+        }
+        RewardPolicy::new(prices, weights)
     }
 
     fn calculate_emissions(&self) -> HpUfixed<18> {
@@ -1091,7 +1518,8 @@ fn get_boost(&self, locked_until: u64, current_epoch: &Epoch) -> HpUfixed<3> {
     fn choose_new_committee(&self) -> Vec<NodePublicKey> {
         // Todo: function not done
         // we need true randomness here, for now we will return the same committee as before to be
-        // able to run tests
+        // able to run tests. The candidates must be considered in the order given by
+        // `selection::rank_candidates`, which is the rank reported to node operators.
         let epoch = match self.metadata.get(&Metadata::Epoch) {
             Some(Value::Epoch(epoch)) => epoch,
             _ => 0,
@@ -1172,14 +1600,14 @@ fn create_node(&self, node: NodeInfo) -> bool {
         }
         let node_index = match self.metadata.get(&Metadata::NextNodeIndex) {
             Some(Value::NextNodeIndex(index)) => index,
-            _ => 0,
+            _ => NodeIndex::default(),
         };
         self.pubkey_to_index.set(node.public_key, node_index);
         self.index_to_pubkey.set(node_index, node.public_key);
         self.node_info.set(node.public_key, node);
         self.metadata.set(
             Metadata::NextNodeIndex,
-            Value::NextNodeIndex(node_index + 1),
+            Value::NextNodeIndex(node_index.next()),
         );
         true
     }
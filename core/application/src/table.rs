@@ -1,6 +1,7 @@
-use std::{any::Any, cell::RefCell, hash::Hash};
+use std::{any::Any, cell::RefCell, collections::BTreeMap, hash::Hash, rc::Rc};
 
 use atomo::{KeyIterator, SerdeBackend, TableRef as AtomoTableRef, TableSelector};
+use lightning_interfaces::types::StateDelta;
 use serde::{de::DeserializeOwned, Serialize};
 
 pub trait Backend {
@@ -14,6 +15,17 @@ fn get_table_reference<
         &self,
         id: &str,
     ) -> Self::Ref<K, V>;
+
+    /// Start keeping track of the writes made through the tables of this backend, and return a
+    /// checkpoint that can be passed to [`Backend::rollback`] to undo them. Checkpoints can be
+    /// nested, every checkpoint must be closed with either a rollback or a release.
+    fn checkpoint(&self) -> usize;
+
+    /// Undo every write made since the given checkpoint, and close it.
+    fn rollback(&self, checkpoint: usize);
+
+    /// Keep the writes made since the given checkpoint, and close it.
+    fn release(&self, checkpoint: usize);
 }
 
 pub trait TableRef<K, V> {
@@ -23,8 +35,64 @@ pub trait TableRef<K, V> {
     fn remove(&self, key: &K);
 }
 
+/// The actions that restore the previous state of every write made since the outermost open
+/// checkpoint, `None` if there is no open checkpoint.
+type UndoActions<'selector> = Option<Vec<Box<dyn FnOnce() + 'selector>>>;
+
+/// A log of the actions to undo the writes made through a backend, shared by all of its tables.
+#[derive(Clone, Default)]
+struct UndoLog<'selector>(Rc<RefCell<UndoActions<'selector>>>);
+
+impl<'selector> UndoLog<'selector> {
+    fn checkpoint(&self) -> usize {
+        self.0.borrow_mut().get_or_insert_with(Vec::new).len()
+    }
+
+    fn rollback(&self, checkpoint: usize) {
+        loop {
+            // The borrow must be released before running the action.
+            let action = match self.0.borrow_mut().as_mut() {
+                Some(actions) if actions.len() > checkpoint => actions.pop(),
+                _ => None,
+            };
+            match action {
+                Some(action) => action(),
+                None => break,
+            }
+        }
+        self.release(checkpoint);
+    }
+
+    fn release(&self, checkpoint: usize) {
+        // Closing the outermost checkpoint keeps all of the writes.
+        if checkpoint == 0 {
+            *self.0.borrow_mut() = None;
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.0.borrow().is_some()
+    }
+
+    fn push(&self, action: impl FnOnce() + 'selector) {
+        if let Some(actions) = self.0.borrow_mut().as_mut() {
+            actions.push(Box::new(action));
+        }
+    }
+}
+
 pub struct StateTables<'selector, S: SerdeBackend> {
     pub table_selector: &'selector TableSelector<S>,
+    undo: UndoLog<'selector>,
+}
+
+impl<'selector, S: SerdeBackend> StateTables<'selector, S> {
+    pub fn new(table_selector: &'selector TableSelector<S>) -> Self {
+        Self {
+            table_selector,
+            undo: UndoLog::default(),
+        }
+    }
 }
 
 impl<'selector, S: SerdeBackend> Backend for StateTables<'selector, S> {
@@ -40,7 +108,22 @@ fn get_table_reference<
         &self,
         id: &str,
     ) -> Self::Ref<K, V> {
-        AtomoTable(RefCell::new(self.table_selector.get_table(id)))
+        AtomoTable {
+            table: Rc::new(RefCell::new(self.table_selector.get_table(id))),
+            undo: self.undo.clone(),
+        }
+    }
+
+    fn checkpoint(&self) -> usize {
+        self.undo.checkpoint()
+    }
+
+    fn rollback(&self, checkpoint: usize) {
+        self.undo.rollback(checkpoint)
+    }
+
+    fn release(&self, checkpoint: usize) {
+        self.undo.release(checkpoint)
     }
 }
 
@@ -49,7 +132,36 @@ pub struct AtomoTable<
     K: Hash + Eq + Serialize + DeserializeOwned + 'static,
     V: Serialize + DeserializeOwned + 'static,
     S: SerdeBackend,
->(RefCell<AtomoTableRef<'selector, K, V, S>>);
+> {
+    table: Rc<RefCell<AtomoTableRef<'selector, K, V, S>>>,
+    undo: UndoLog<'selector>,
+}
+
+impl<
+    'selector,
+    K: Hash + Eq + Serialize + DeserializeOwned + Any,
+    V: Serialize + DeserializeOwned + Any + Clone,
+    S: SerdeBackend,
+> AtomoTable<'selector, K, V, S>
+{
+    /// Record how to restore the current value of the key, if there is an open checkpoint.
+    fn record_undo(&self, key: &K) {
+        if !self.undo.is_open() {
+            return;
+        }
+        // The undo action needs its own copy of the key.
+        let key = S::deserialize::<K>(&S::serialize(key));
+        let previous = self.table.borrow().get(&key);
+        let table = self.table.clone();
+        self.undo.push(move || {
+            let mut table = table.borrow_mut();
+            match previous {
+                Some(value) => table.insert(key, value),
+                None => table.remove(key),
+            }
+        });
+    }
+}
 
 impl<
     'selector,
@@ -59,18 +171,157 @@ impl<
 > TableRef<K, V> for AtomoTable<'selector, K, V, S>
 {
     fn set(&self, key: K, value: V) {
-        self.0.borrow_mut().insert(key, value);
+        self.record_undo(&key);
+        self.table.borrow_mut().insert(key, value);
+    }
+
+    fn get(&self, key: &K) -> Option<V> {
+        self.table.borrow_mut().get(key)
+    }
+
+    fn keys(&self) -> KeyIterator<K> {
+        self.table.borrow_mut().keys()
+    }
+
+    fn remove(&self, key: &K) {
+        self.record_undo(key);
+        self.table.borrow_mut().remove(key)
+    }
+}
+
+type WriteLog = Rc<RefCell<BTreeMap<(String, Vec<u8>), Option<Vec<u8>>>>>;
+
+/// A backend that records the writes made through it, used to report the effect of a
+/// transaction that is not committed.
+pub struct RecordingTables<'selector, S: SerdeBackend> {
+    tables: StateTables<'selector, S>,
+    log: WriteLog,
+}
+
+impl<'selector, S: SerdeBackend> RecordingTables<'selector, S> {
+    pub fn new(tables: StateTables<'selector, S>) -> Self {
+        Self {
+            tables,
+            log: Default::default(),
+        }
+    }
+
+    /// Returns a handle to the writes that will be made through this backend.
+    pub fn writes(&self) -> RecordedWrites {
+        RecordedWrites(self.log.clone())
+    }
+}
+
+impl<'selector, S: SerdeBackend> Backend for RecordingTables<'selector, S> {
+    type Ref<
+        K: Eq + Hash + Send + Serialize + DeserializeOwned + 'static,
+        V: Clone + Send + Serialize + DeserializeOwned + 'static,
+    > = RecordingTable<'selector, K, V, S>;
+
+    fn get_table_reference<
+        K: Eq + Hash + Send + Serialize + DeserializeOwned,
+        V: Clone + Send + Serialize + DeserializeOwned,
+    >(
+        &self,
+        id: &str,
+    ) -> Self::Ref<K, V> {
+        RecordingTable {
+            inner: self.tables.get_table_reference(id),
+            table: id.to_string(),
+            log: self.log.clone(),
+            undo: self.tables.undo.clone(),
+        }
+    }
+
+    fn checkpoint(&self) -> usize {
+        self.tables.checkpoint()
+    }
+
+    fn rollback(&self, checkpoint: usize) {
+        self.tables.rollback(checkpoint)
+    }
+
+    fn release(&self, checkpoint: usize) {
+        self.tables.release(checkpoint)
+    }
+}
+
+/// The writes recorded by a [`RecordingTables`] backend.
+pub struct RecordedWrites(WriteLog);
+
+impl RecordedWrites {
+    /// Returns the final value of every written entry, ordered by table and key.
+    pub fn deltas(&self) -> Vec<StateDelta> {
+        self.0
+            .borrow()
+            .iter()
+            .map(|((table, key), value)| StateDelta {
+                table: table.clone(),
+                key: key.clone(),
+                value: value.clone(),
+            })
+            .collect()
+    }
+}
+
+pub struct RecordingTable<
+    'selector,
+    K: Hash + Eq + Serialize + DeserializeOwned + 'static,
+    V: Serialize + DeserializeOwned + 'static,
+    S: SerdeBackend,
+> {
+    inner: AtomoTable<'selector, K, V, S>,
+    table: String,
+    log: WriteLog,
+    undo: UndoLog<'selector>,
+}
+
+impl<
+    'selector,
+    K: Hash + Eq + Serialize + DeserializeOwned + Any,
+    V: Serialize + DeserializeOwned + Any + Clone,
+    S: SerdeBackend,
+> RecordingTable<'selector, K, V, S>
+{
+    fn record_write(&self, key: &K, value: Option<Vec<u8>>) {
+        let entry = (self.table.clone(), S::serialize(key));
+        let previous = self.log.borrow_mut().insert(entry.clone(), value);
+        if self.undo.is_open() {
+            // Writes that are undone must not be reported either.
+            let log = self.log.clone();
+            self.undo.push(move || {
+                let mut log = log.borrow_mut();
+                match previous {
+                    Some(value) => log.insert(entry, value),
+                    None => log.remove(&entry),
+                };
+            });
+        }
+    }
+}
+
+impl<
+    'selector,
+    K: Hash + Eq + Serialize + DeserializeOwned + Any,
+    V: Serialize + DeserializeOwned + Any + Clone,
+    S: SerdeBackend,
+> TableRef<K, V> for RecordingTable<'selector, K, V, S>
+{
+    fn set(&self, key: K, value: V) {
+        self.record_write(&key, Some(S::serialize(&value)));
+        self.inner.set(key, value)
     }
 
     fn get(&self, key: &K) -> Option<V> {
-        self.0.borrow_mut().get(key)
+        self.inner.get(key)
     }
 
     fn keys(&self) -> KeyIterator<K> {
-        self.0.borrow_mut().keys()
+        self.inner.keys()
     }
 
     fn remove(&self, key: &K) {
-        self.0.borrow_mut().remove(key)
+        self.record_write(key, None);
+        self.inner.remove(key)
     }
 }
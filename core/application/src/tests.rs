@@ -1,18 +1,24 @@
-use std::{collections::BTreeMap, time::SystemTime, vec};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::{Duration, SystemTime},
+    vec,
+};
 
 use affair::Socket;
 use anyhow::{anyhow, Result};
+use atomo::{DefaultSerdeBackend, SerdeBackend};
 use fleek_crypto::{
-    AccountOwnerSecretKey, NodeNetworkingSecretKey, NodePublicKey, NodeSecretKey, PublicKey,
-    SecretKey,
+    AccountOwnerSecretKey, EthAddress, NodeNetworkingSecretKey, NodePublicKey, NodeSecretKey,
+    NodeSignature, PublicKey, SecretKey,
 };
 use hp_fixed::unsigned::HpUfixed;
 use lightning_interfaces::{
     application::ExecutionEngineSocket,
     types::{
-        Block, BlockExecutionResponse, DeliveryAcknowledgment, Epoch, ExecutionError, NodeInfo,
-        ProofOfConsensus, ProtocolParams, Tokens, TotalServed, TransactionResponse, UpdateMethod,
-        UpdatePayload, UpdateRequest,
+        AccountInfo, Block, BlockExecutionResponse, CommodityTypes, DeliveryAcknowledgment, Epoch,
+        ExecutionData, ExecutionError, MisbehaviorEvidence, NodeIndex, NodeInfo, Participation,
+        ProofOfConsensus, ProtocolParams, ReputationMeasurements, SignedVote, Tokens, TotalServed,
+        TransactionResponse, UpdateMethod, UpdateRequest, UpdateRequestBuilder,
     },
     ApplicationInterface, SyncQueryRunnerInterface, ToDigest,
 };
@@ -22,8 +28,9 @@
 use crate::{
     app::Application,
     config::{Config, Mode},
-    genesis::{Genesis, GenesisCommittee},
+    genesis::{Genesis, GenesisCommittee, GenesisRewardWeight},
     query_runner::QueryRunner,
+    state::epoch_change_threshold_met,
 };
 
 pub struct Params {
@@ -37,7 +44,7 @@ pub struct Params {
 }
 
 struct GenesisCommitteeKeystore {
-    _owner_secret_key: AccountOwnerSecretKey,
+    owner_secret_key: AccountOwnerSecretKey,
     node_secret_key: NodeSecretKey,
     _network_secret_key: NodeNetworkingSecretKey,
     _worker_secret_key: NodeNetworkingSecretKey,
@@ -66,7 +73,7 @@ fn get_genesis_committee(
             None,
         ));
         keystore.push(GenesisCommitteeKeystore {
-            _owner_secret_key: owner_secret_key,
+            owner_secret_key,
             node_secret_key,
             _network_secret_key: network_secret_key,
             _worker_secret_key: network_secret_key,
@@ -128,6 +135,7 @@ async fn init_app_with_params(
     let config = Config {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     };
 
     init_app(Some(config)).await
@@ -165,14 +173,8 @@ fn get_update_request_node(
     secret_key: NodeSecretKey,
     nonce: u64,
 ) -> UpdateRequest {
-    let payload = UpdatePayload { nonce, method };
-    let digest = payload.to_digest();
-    let signature = secret_key.sign(&digest);
-    UpdateRequest {
-        sender: secret_key.to_pk().into(),
-        signature: signature.into(),
-        payload,
-    }
+    UpdateRequestBuilder::new(method, nonce)
+        .build(secret_key.to_pk(), |digest| secret_key.sign(digest))
 }
 // Passing the private key around like this should only be done for
 // testing.
@@ -181,14 +183,8 @@ fn get_update_request_account(
     secret_key: AccountOwnerSecretKey,
     nonce: u64,
 ) -> UpdateRequest {
-    let payload = UpdatePayload { nonce, method };
-    let digest = payload.to_digest();
-    let signature = secret_key.sign(&digest);
-    UpdateRequest {
-        sender: secret_key.to_pk().into(),
-        signature: signature.into(),
-        payload,
-    }
+    UpdateRequestBuilder::new(method, nonce)
+        .build(secret_key.to_pk(), |digest| secret_key.sign(digest))
 }
 
 fn get_genesis() -> (Genesis, Vec<NodeInfo>) {
@@ -322,6 +318,7 @@ async fn test_epoch_change() {
     let (update_socket, query_runner) = init_app(Some(Config {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     }))
     .await;
 
@@ -356,6 +353,260 @@ async fn test_epoch_change() {
     assert_eq!(query_runner.get_epoch_info().epoch, 1);
 }
 
+#[test]
+async fn test_epoch_change_readiness() {
+    let (committee, keystore) = get_genesis_committee(4);
+    let mut genesis = Genesis::load().unwrap();
+    let committee_size = committee.len();
+    genesis.committee = committee;
+    let (update_socket, query_runner) = init_app(Some(Config {
+        genesis: Some(genesis),
+        mode: Mode::Test,
+        dev_single_node: None,
+    }))
+    .await;
+
+    assert_eq!(
+        query_runner.epoch_change_readiness(),
+        (0, committee_size, false)
+    );
+
+    // Readiness goes up with every member that signals, until the threshold is met.
+    let required_signals = 2 * committee_size / 3 + 1;
+    for (signaled, node) in keystore.iter().take(required_signals - 1).enumerate() {
+        let req = get_update_request_node(
+            UpdateMethod::ChangeEpoch { epoch: 0 },
+            node.node_secret_key,
+            1,
+        );
+        run_transaction(vec![req], &update_socket).await.unwrap();
+        assert_eq!(
+            query_runner.epoch_change_readiness(),
+            (signaled + 1, committee_size, false)
+        );
+    }
+    assert!(!epoch_change_threshold_met(
+        required_signals - 1,
+        committee_size
+    ));
+    assert!(epoch_change_threshold_met(required_signals, committee_size));
+
+    // The signal that meets the threshold changes the epoch, and the new committee starts over.
+    let req = get_update_request_node(
+        UpdateMethod::ChangeEpoch { epoch: 0 },
+        keystore[required_signals - 1].node_secret_key,
+        1,
+    );
+    let res = run_transaction(vec![req], &update_socket).await.unwrap();
+    assert!(res.change_epoch);
+    assert_eq!(query_runner.get_epoch_info().epoch, 1);
+    let (ready, total, threshold_met) = query_runner.epoch_change_readiness();
+    assert_eq!(ready, 0);
+    assert_eq!(total, query_runner.get_committee_members().len());
+    assert!(!threshold_met);
+}
+
+#[test]
+async fn test_verify_node_signature() {
+    let (committee, keystore) = get_genesis_committee(4);
+    let mut genesis = Genesis::load().unwrap();
+    genesis.committee = committee;
+    let (_, query_runner) = init_app(Some(Config {
+        genesis: Some(genesis),
+        mode: Mode::Test,
+        dev_single_node: None,
+    }))
+    .await;
+
+    let secret_key = keystore[0].node_secret_key;
+    let node = secret_key.to_pk();
+    let digest = [7; 32];
+    let signature = secret_key.sign(&digest);
+
+    // A valid signature from a registered node.
+    assert!(query_runner.verify_node_signature(&node, &digest, &signature));
+
+    // The signature does not match the digest, or was made by another node.
+    assert!(!query_runner.verify_node_signature(&node, &[8; 32], &signature));
+    let other = keystore[1].node_secret_key.sign(&digest);
+    assert!(!query_runner.verify_node_signature(&node, &digest, &other));
+
+    // A node that is not registered, even with a valid signature.
+    let unknown = NodeSecretKey::generate();
+    let signature = unknown.sign(&digest);
+    assert!(!query_runner.verify_node_signature(&unknown.to_pk(), &digest, &signature));
+}
+
+#[test]
+async fn test_get_participation() {
+    let (committee, keystore) = get_genesis_committee(4);
+    let mut genesis = Genesis::load().unwrap();
+    genesis.committee = committee;
+    let (update_socket, query_runner) = init_app(Some(Config {
+        genesis: Some(genesis),
+        mode: Mode::Test,
+        dev_single_node: None,
+    }))
+    .await;
+
+    // Three of the four members are enough to change the epoch. Node 2 misses epoch 1 and node 3
+    // only signals in epoch 1, but it also serves commodities in epoch 0.
+    let signals: [&[usize]; 3] = [&[0, 1, 2], &[0, 1, 3], &[0, 1, 2]];
+    let mut nonces = [0; 4];
+    nonces[3] += 1;
+    let pod = pod_request(keystore[3].node_secret_key, 1000, 0, nonces[3]);
+    run_transaction(vec![pod], &update_socket).await.unwrap();
+    for (epoch, nodes) in signals.into_iter().enumerate() {
+        for &node in nodes {
+            nonces[node] += 1;
+            let req = get_update_request_node(
+                UpdateMethod::ChangeEpoch {
+                    epoch: epoch as Epoch,
+                },
+                keystore[node].node_secret_key,
+                nonces[node],
+            );
+            run_transaction(vec![req], &update_socket).await.unwrap();
+        }
+        assert_eq!(query_runner.get_epoch_info().epoch, epoch as Epoch + 1);
+    }
+
+    let participation =
+        |node: usize| query_runner.get_participation(&keystore[node].node_secret_key.to_pk());
+    let expected = |epochs_active, missed_epochs| Participation {
+        epochs_active,
+        epochs_in_committee: 3,
+        missed_epochs,
+    };
+    assert_eq!(participation(0), expected(3, 0));
+    assert_eq!(participation(1), expected(3, 0));
+    assert_eq!(participation(2), expected(2, 1));
+    assert_eq!(participation(3), expected(2, 1));
+
+    // An epoch is never counted as both active and missed for the same node.
+    for node in 0..4 {
+        let participation = participation(node);
+        assert_eq!(
+            participation.epochs_active + participation.missed_epochs,
+            participation.epochs_in_committee
+        );
+    }
+
+    // A node that never participated has no record.
+    assert_eq!(
+        query_runner.get_participation(&NodeSecretKey::generate().to_pk()),
+        Participation::default()
+    );
+}
+
+#[test]
+async fn test_epoch_info_at() {
+    let (committee, keystore) = get_genesis_committee(4);
+    let mut genesis = Genesis::load().unwrap();
+    let committee_size = committee.len();
+    genesis.committee = committee;
+    let (update_socket, query_runner) = init_app(Some(Config {
+        genesis: Some(genesis),
+        mode: Mode::Test,
+        dev_single_node: None,
+    }))
+    .await;
+
+    let required_signals = 2 * committee_size / 3 + 1;
+    let genesis_info = query_runner.get_epoch_info();
+
+    // Go through two epoch changes.
+    for epoch in 0..2 {
+        let requests = keystore
+            .iter()
+            .take(required_signals)
+            .map(|node| {
+                get_update_request_node(
+                    UpdateMethod::ChangeEpoch { epoch },
+                    node.node_secret_key,
+                    epoch + 1,
+                )
+            })
+            .collect();
+        let res = run_transaction(requests, &update_socket).await.unwrap();
+        assert!(res.change_epoch);
+    }
+    assert_eq!(query_runner.get_epoch(), 2);
+
+    // The committee of the prior epochs is still retrievable.
+    let epoch_info = query_runner.get_epoch_info_at(0).unwrap();
+    assert_eq!(epoch_info.epoch, 0);
+    assert_eq!(epoch_info.committee, genesis_info.committee);
+    assert_eq!(epoch_info.epoch_end, genesis_info.epoch_end);
+
+    let epoch_info = query_runner.get_epoch_info_at(1).unwrap();
+    assert_eq!(epoch_info.epoch, 1);
+    assert_eq!(epoch_info.committee.len(), committee_size);
+    assert!(epoch_info.epoch_end > genesis_info.epoch_end);
+
+    assert_eq!(
+        query_runner.get_epoch_info_at(2),
+        Some(query_runner.get_epoch_info())
+    );
+    assert_eq!(query_runner.get_epoch_info_at(3), None);
+}
+
+#[test]
+async fn test_committee_position() {
+    let (committee, keystore) = get_genesis_committee(4);
+    let mut genesis = Genesis::load().unwrap();
+    genesis.committee = committee;
+    let (_, query_runner) = init_app(Some(Config {
+        genesis: Some(genesis),
+        mode: Mode::Test,
+        dev_single_node: None,
+    }))
+    .await;
+
+    let members = query_runner.get_committee_members();
+    assert_eq!(members.len(), keystore.len());
+    for (index, member) in members.iter().enumerate() {
+        assert_eq!(query_runner.committee_position(member), Some(index));
+        // The position does not change between queries.
+        assert_eq!(query_runner.committee_position(member), Some(index));
+    }
+    assert_eq!(
+        query_runner.committee_position(&NodePublicKey([9; 96])),
+        None
+    );
+
+    // The committee of the next epoch is only chosen on the epoch change.
+    assert!(query_runner.get_next_committee_members().is_empty());
+}
+
+#[test]
+async fn test_node_index_round_trip() {
+    let (committee, keystore) = get_genesis_committee(4);
+    let mut genesis = Genesis::load().unwrap();
+    genesis.committee = committee;
+    let (_, query_runner) = init_app(Some(Config {
+        genesis: Some(genesis),
+        mode: Mode::Test,
+        dev_single_node: None,
+    }))
+    .await;
+
+    for node in &keystore {
+        let public_key = node.node_secret_key.to_pk();
+        let index = query_runner.pubkey_to_index(public_key).unwrap();
+        assert_eq!(query_runner.index_to_pubkey(index), Some(public_key));
+    }
+    // Every node was assigned its own index.
+    let indices: BTreeSet<NodeIndex> = keystore
+        .iter()
+        .filter_map(|node| query_runner.pubkey_to_index(node.node_secret_key.to_pk()))
+        .collect();
+    assert_eq!(indices.len(), keystore.len());
+
+    assert_eq!(query_runner.pubkey_to_index(NodePublicKey([9; 96])), None);
+    assert_eq!(query_runner.index_to_pubkey(NodeIndex(u32::MAX)), None);
+}
+
 #[test]
 async fn test_stake() {
     let (update_socket, query_runner) = init_app(None).await;
@@ -598,6 +849,87 @@ async fn test_stake_lock() {
     );
 }
 
+#[test]
+async fn test_block_weight_budget() {
+    let (committee, keystore) = get_genesis_committee(4);
+    let mut genesis = Genesis::load().unwrap();
+    genesis.committee = committee;
+    let secret_key = keystore[0].node_secret_key;
+
+    // Only two delivery acknowledgments fit in a block.
+    let weight = pod_request(secret_key, 1000, 0, 1).payload.method.weight();
+    genesis.max_block_weight = 2 * weight + 1;
+    let (update_socket, query_runner) = init_app(Some(Config {
+        genesis: Some(genesis),
+        mode: Mode::Test,
+        dev_single_node: None,
+    }))
+    .await;
+
+    let requests = (1..=4)
+        .map(|nonce| pod_request(secret_key, 1000, 0, nonce))
+        .collect();
+    let res = run_transaction(requests, &update_socket).await.unwrap();
+    assert_eq!(
+        res.txn_receipts[..2],
+        vec![TransactionResponse::Success(ExecutionData::None); 2]
+    );
+    assert_eq!(
+        res.txn_receipts[2..],
+        vec![TransactionResponse::Revert(ExecutionError::BlockWeightExceeded); 2]
+    );
+    assert_eq!(
+        query_runner.get_node_served(&secret_key.to_pk()).served,
+        vec![2000]
+    );
+
+    // The deferred transactions did not touch the state and can be resubmitted.
+    let requests = (3..=4)
+        .map(|nonce| pod_request(secret_key, 1000, 0, nonce))
+        .collect();
+    let res = run_transaction(requests, &update_socket).await.unwrap();
+    assert!(
+        res.txn_receipts
+            .iter()
+            .all(|receipt| matches!(receipt, TransactionResponse::Success(_)))
+    );
+    assert_eq!(
+        query_runner.get_node_served(&secret_key.to_pk()).served,
+        vec![4000]
+    );
+
+    // A transaction heavier than an entire block is rejected upfront.
+    let req = get_update_request_node(
+        UpdateMethod::SubmitDeliveryAcknowledgmentAggregation {
+            commodity: 1000,
+            service_id: 0,
+            proofs: vec![DeliveryAcknowledgment::default(); 2 * weight as usize],
+            metadata: None,
+        },
+        secret_key,
+        5,
+    );
+    assert_eq!(
+        query_runner.validate_txn(req.clone()),
+        TransactionResponse::Revert(ExecutionError::BlockWeightExceeded)
+    );
+
+    // In a block it is reverted on its own, and the transactions after it that fit still run.
+    let requests = vec![req, pod_request(secret_key, 1000, 0, 5)];
+    let res = run_transaction(requests, &update_socket).await.unwrap();
+    assert_eq!(
+        res.txn_receipts,
+        vec![
+            TransactionResponse::Revert(ExecutionError::BlockWeightExceeded),
+            TransactionResponse::Success(ExecutionData::None),
+        ]
+    );
+    assert_eq!(
+        query_runner.get_node_served(&secret_key.to_pk()).served,
+        vec![5000]
+    );
+}
+
 #[test]
 async fn test_pod_without_proof() {
     let (committee, keystore) = get_genesis_committee(4);
@@ -606,6 +938,7 @@ async fn test_pod_without_proof() {
     let (update_socket, query_runner) = init_app(Some(Config {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     }))
     .await;
 
@@ -798,6 +1131,159 @@ async fn test_distribute_rewards() {
     }
 }
 
+#[test]
+async fn test_distribute_rewards_with_commodity_weights() {
+    let (committee, keystore) = get_genesis_committee(4);
+    let mut genesis = Genesis::load().unwrap();
+    genesis.committee = committee;
+    genesis.epoch_start = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    // Compute counts three times as much as bandwidth.
+    genesis.commodity_reward_weights = vec![
+        GenesisRewardWeight {
+            commodity: CommodityTypes::Bandwidth,
+            weight: 1.0,
+        },
+        GenesisRewardWeight {
+            commodity: CommodityTypes::Compute,
+            weight: 3.0,
+        },
+    ];
+    let percentage_divisor: HpUfixed<18> = 100_u16.into();
+    let supply_at_year_start: HpUfixed<18> = genesis.supply_at_genesis.into();
+    let inflation: HpUfixed<18> = HpUfixed::from(genesis.max_inflation) / &percentage_divisor;
+    let node_share = HpUfixed::from(genesis.node_share) / &percentage_divisor;
+    let (update_socket, query_runner) = init_app(Some(Config {
+        genesis: Some(genesis),
+        mode: Mode::Test,
+        dev_single_node: None,
+    }))
+    .await;
+
+    let owner_secret_key1 = AccountOwnerSecretKey::generate();
+    let node_secret_key1 = NodeSecretKey::generate();
+    let owner_secret_key2 = AccountOwnerSecretKey::generate();
+    let node_secret_key2 = NodeSecretKey::generate();
+    for (owner_secret_key, node_secret_key) in [
+        (owner_secret_key1, node_secret_key1),
+        (owner_secret_key2, node_secret_key2),
+    ] {
+        deposit(
+            10_000_u64.into(),
+            Tokens::FLK,
+            owner_secret_key,
+            &update_socket,
+            1,
+        )
+        .await;
+        stake(
+            10_000_u64.into(),
+            node_secret_key.to_pk(),
+            owner_secret_key,
+            &update_socket,
+            2,
+        )
+        .await;
+    }
+
+    // Both nodes earn 200 in revenue, node 1 serving bandwidth and node 2 serving compute.
+    let pod_1 = pod_request(node_secret_key1, 2_000, 0, 1);
+    let pod_2 = pod_request(node_secret_key2, 1_000, 1, 1);
+    if let Err(e) = run_transaction(vec![pod_1, pod_2], &update_socket).await {
+        panic!("{e}");
+    }
+    if let Err(err) = simple_epoch_change(0, &keystore, &update_socket, 1).await {
+        panic!("error while changing epoch, {err}");
+    }
+
+    // The stable rewards still follow the revenue.
+    let stables_balance1 = query_runner.get_stables_balance(&owner_secret_key1.to_pk().into());
+    let stables_balance2 = query_runner.get_stables_balance(&owner_secret_key2.to_pk().into());
+    assert_eq!(stables_balance1, stables_balance2);
+
+    // The weight of node 1 is 200 * 1 and the weight of node 2 is 200 * 3.
+    let emissions: HpUfixed<18> = (inflation * supply_at_year_start) / &365.0.into();
+    let emissions_for_node = &emissions * &node_share;
+    let node_1_proportion: HpUfixed<18> = HpUfixed::from(200_u64) / HpUfixed::from(800_u64);
+    let node_2_proportion: HpUfixed<18> = HpUfixed::from(600_u64) / HpUfixed::from(800_u64);
+    assert_eq!(
+        query_runner.get_flk_balance(&owner_secret_key1.to_pk().into()),
+        &emissions_for_node * &node_1_proportion
+    );
+    assert_eq!(
+        query_runner.get_flk_balance(&owner_secret_key2.to_pk().into()),
+        &emissions_for_node * &node_2_proportion
+    );
+}
+
+#[test]
+async fn test_distribute_rewards_without_reward_weight() {
+    let (committee, keystore) = get_genesis_committee(4);
+    let mut genesis = Genesis::load().unwrap();
+    genesis.committee = committee;
+    genesis.epoch_start = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    // Serving bandwidth earns revenue, but no share of the emissions.
+    genesis.commodity_reward_weights = vec![GenesisRewardWeight {
+        commodity: CommodityTypes::Bandwidth,
+        weight: 0.0,
+    }];
+    let percentage_divisor: HpUfixed<18> = 100_u16.into();
+    let supply_at_year_start: HpUfixed<18> = genesis.supply_at_genesis.into();
+    let inflation: HpUfixed<18> = HpUfixed::from(genesis.max_inflation) / &percentage_divisor;
+    let node_share = HpUfixed::from(genesis.node_share) / &percentage_divisor;
+    let protocol_share = HpUfixed::from(genesis.protocol_share) / &percentage_divisor;
+    let (update_socket, query_runner) = init_app(Some(Config {
+        genesis: Some(genesis),
+        mode: Mode::Test,
+        dev_single_node: None,
+    }))
+    .await;
+
+    let owner_secret_key = AccountOwnerSecretKey::generate();
+    let node_secret_key = NodeSecretKey::generate();
+    deposit(
+        10_000_u64.into(),
+        Tokens::FLK,
+        owner_secret_key,
+        &update_socket,
+        1,
+    )
+    .await;
+    stake(
+        10_000_u64.into(),
+        node_secret_key.to_pk(),
+        owner_secret_key,
+        &update_socket,
+        2,
+    )
+    .await;
+
+    let pod = pod_request(node_secret_key, 2_000, 0, 1);
+    if let Err(e) = run_transaction(vec![pod], &update_socket).await {
+        panic!("{e}");
+    }
+    if let Err(err) = simple_epoch_change(0, &keystore, &update_socket, 1).await {
+        panic!("error while changing epoch, {err}");
+    }
+
+    // The node still gets its stables, but the emissions for the nodes go to the protocol fund.
+    let owner: EthAddress = owner_secret_key.to_pk().into();
+    assert_ne!(query_runner.get_stables_balance(&owner), HpUfixed::zero());
+    assert_eq!(query_runner.get_flk_balance(&owner), HpUfixed::zero());
+
+    let emissions: HpUfixed<18> = (inflation * supply_at_year_start) / &365.0.into();
+    let protocol_account = query_runner.get_protocol_fund_address();
+    assert_eq!(
+        query_runner.get_flk_balance(&protocol_account),
+        &emissions * &protocol_share + &emissions * &node_share
+    );
+}
+
 #[test]
 async fn test_submit_rep_measurements() {
     let (committee, keystore) = get_genesis_committee(4);
@@ -806,6 +1292,7 @@ async fn test_submit_rep_measurements() {
     let (update_socket, query_runner) = init_app(Some(Config {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     }))
     .await;
 
@@ -829,20 +1316,15 @@ async fn test_submit_rep_measurements() {
         panic!("{e}");
     }
 
+    let reporting_node = query_runner.pubkey_to_index(keystore[0].node_secret_key.to_pk());
     let rep_measurements1 = query_runner.get_rep_measurements(peer1);
     assert_eq!(rep_measurements1.len(), 1);
-    assert_eq!(
-        rep_measurements1[0].reporting_node,
-        keystore[0].node_secret_key.to_pk()
-    );
+    assert_eq!(Some(rep_measurements1[0].reporting_node), reporting_node);
     assert_eq!(rep_measurements1[0].measurements, measurements1);
 
     let rep_measurements2 = query_runner.get_rep_measurements(peer2);
     assert_eq!(rep_measurements2.len(), 1);
-    assert_eq!(
-        rep_measurements2[0].reporting_node,
-        keystore[0].node_secret_key.to_pk()
-    );
+    assert_eq!(Some(rep_measurements2[0].reporting_node), reporting_node);
     assert_eq!(rep_measurements2[0].measurements, measurements2);
 }
 
@@ -855,6 +1337,7 @@ async fn test_rep_scores() {
     let (update_socket, query_runner) = init_app(Some(Config {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     }))
     .await;
     let required_signals = 2 * committee_len / 3 + 1;
@@ -916,10 +1399,78 @@ async fn test_rep_scores() {
 }
 
 #[test]
-async fn test_supply_across_epoch() {
+async fn test_reputation_consensus() {
     let (committee, keystore) = get_genesis_committee(4);
-
-    let epoch_time = 100;
+    let mut genesis = Genesis::load().unwrap();
+    genesis.committee = committee;
+    genesis.max_measurement_deviation = 50;
+    let min_stake = genesis.min_stake;
+    let (update_socket, query_runner) = init_app(Some(Config {
+        genesis: Some(genesis),
+        mode: Mode::Test,
+        dev_single_node: None,
+    }))
+    .await;
+
+    // The reporters have a stake of 1, 2, 3 and 2 times the minimum stake, and the last one
+    // reports an outlier.
+    let peer = NodePublicKey([0; 96]);
+    let reports = [(0, 100), (1, 110), (2, 120), (1, 1000)];
+    for (node, (extra_stake, latency)) in keystore.iter().zip(reports) {
+        if extra_stake > 0 {
+            let amount: HpUfixed<18> = (extra_stake * min_stake).into();
+            deposit(
+                amount.clone(),
+                Tokens::FLK,
+                node.owner_secret_key,
+                &update_socket,
+                1,
+            )
+            .await;
+            stake(
+                amount,
+                node.node_secret_key.to_pk(),
+                node.owner_secret_key,
+                &update_socket,
+                2,
+            )
+            .await;
+        }
+
+        let measurements = ReputationMeasurements {
+            latency: Some(Duration::from_millis(latency)),
+            ..Default::default()
+        };
+        let req = get_update_request_node(
+            UpdateMethod::SubmitReputationMeasurements {
+                measurements: BTreeMap::from([(peer, measurements)]),
+            },
+            node.node_secret_key,
+            1,
+        );
+        run_transaction(vec![req], &update_socket).await.unwrap();
+    }
+    // The consensus is only computed on the epoch change.
+    assert_eq!(query_runner.get_reputation_consensus(&peer), None);
+
+    simple_epoch_change(0, &keystore, &update_socket, 2)
+        .await
+        .unwrap();
+
+    // The outlier is discarded and the rest is weighted by stake:
+    // (100 * 1 + 110 * 2 + 120 * 3) / 6 = 113.33ms
+    let consensus = query_runner.get_reputation_consensus(&peer).unwrap();
+    let latency = consensus.latency.unwrap();
+    let expected = Duration::from_micros(113_333);
+    assert!(latency.abs_diff(expected) < Duration::from_micros(1));
+    assert_eq!(consensus.interactions, None);
+}
+
+#[test]
+async fn test_supply_across_epoch() {
+    let (committee, keystore) = get_genesis_committee(4);
+
+    let epoch_time = 100;
     let max_inflation = 10;
     let protocol_part = 10;
     let node_part = 80;
@@ -1010,6 +1561,7 @@ async fn test_validate_txn() {
     let (update_socket, query_runner) = init_app(Some(Config {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     }))
     .await;
 
@@ -1046,6 +1598,280 @@ async fn test_validate_txn() {
     assert_eq!(res.txn_receipts[0], query_runner.validate_txn(req));
 }
 
+#[test]
+async fn test_validate_txn_matches_execution() {
+    let (committee, keystore) = get_genesis_committee(4);
+    let mut genesis = Genesis::load().unwrap();
+    genesis.committee = committee;
+    let (update_socket, query_runner) = init_app(Some(Config {
+        genesis: Some(genesis),
+        mode: Mode::Test,
+        dev_single_node: None,
+    }))
+    .await;
+
+    let owner_secret_key = AccountOwnerSecretKey::generate();
+    let unknown_node_secret_key = NodeSecretKey::generate();
+
+    // A mix of transactions that succeed and transactions that revert during verification or
+    // execution. Each one is validated against the current state and then executed on top of
+    // that same state, so the two verdicts must be identical.
+    let requests = vec![
+        // Reverts during execution: the epoch has not started.
+        get_update_request_node(
+            UpdateMethod::ChangeEpoch { epoch: 1 },
+            keystore[0].node_secret_key,
+            1,
+        ),
+        // Reverts during verification: the nonce was already used.
+        get_update_request_node(
+            UpdateMethod::ChangeEpoch { epoch: 0 },
+            keystore[0].node_secret_key,
+            1,
+        ),
+        // Reverts during verification: signed by another node.
+        UpdateRequestBuilder::new(UpdateMethod::ChangeEpoch { epoch: 0 }, 1)
+            .build(keystore[1].node_secret_key.to_pk(), |digest| {
+                keystore[0].node_secret_key.sign(digest)
+            }),
+        // Reverts during verification: the node is not registered.
+        get_update_request_node(
+            UpdateMethod::ChangeEpoch { epoch: 0 },
+            unknown_node_secret_key,
+            1,
+        ),
+        // Succeeds.
+        get_update_request_account(
+            UpdateMethod::Deposit {
+                proof: ProofOfConsensus {},
+                token: Tokens::FLK,
+                amount: 1_000_u64.into(),
+            },
+            owner_secret_key,
+            1,
+        ),
+        // Reverts during execution: the account does not own the node.
+        get_update_request_account(
+            UpdateMethod::Unstake {
+                amount: 1_000_u64.into(),
+                node: keystore[0].node_secret_key.to_pk(),
+            },
+            owner_secret_key,
+            2,
+        ),
+        // Reverts during execution: only nodes can change the epoch.
+        get_update_request_account(UpdateMethod::ChangeEpoch { epoch: 0 }, owner_secret_key, 3),
+        // Succeeds.
+        get_update_request_node(
+            UpdateMethod::ChangeEpoch { epoch: 0 },
+            keystore[1].node_secret_key,
+            1,
+        ),
+    ];
+
+    let mut successes = 0;
+    for req in requests {
+        let validated = query_runner.validate_txn(req.clone());
+        let res = run_transaction(vec![req], &update_socket).await.unwrap();
+        assert_eq!(res.txn_receipts[0], validated);
+        if let TransactionResponse::Success(_) = validated {
+            successes += 1;
+        }
+    }
+    assert_eq!(successes, 2);
+}
+
+#[test]
+async fn test_simulate_txn() {
+    let (update_socket, query_runner) = init_app(None).await;
+
+    let owner_secret_key = AccountOwnerSecretKey::generate();
+    let node_secret_key = NodeSecretKey::generate();
+    let owner: EthAddress = owner_secret_key.to_pk().into();
+
+    deposit(
+        1_000_u64.into(),
+        Tokens::FLK,
+        owner_secret_key,
+        &update_socket,
+        1,
+    )
+    .await;
+
+    let update = get_update_request_account(
+        UpdateMethod::Stake {
+            amount: 1_000_u64.into(),
+            node_public_key: node_secret_key.to_pk(),
+            node_network_key: Some([0; 32].into()),
+            node_domain: Some("/ip4/127.0.0.1/udp/38000".to_string()),
+            worker_public_key: Some([0; 32].into()),
+            worker_domain: Some("/ip4/127.0.0.1/udp/38000".to_string()),
+            worker_mempool_address: Some("/ip4/127.0.0.1/udp/38000".to_string()),
+        },
+        owner_secret_key,
+        2,
+    );
+    let outcome = query_runner.simulate_txn(update);
+    assert!(matches!(outcome.response, TransactionResponse::Success(_)));
+
+    // The simulation reports the balance of the account after staking.
+    let key = DefaultSerdeBackend::serialize(&owner);
+    let delta = outcome
+        .deltas
+        .iter()
+        .find(|delta| delta.table == "account" && delta.key == key)
+        .expect("the account to be written");
+    let account: AccountInfo = DefaultSerdeBackend::deserialize(delta.value.as_ref().unwrap());
+    assert_eq!(account.flk_balance, HpUfixed::<18>::zero());
+
+    // But the real state is not altered.
+    assert_eq!(query_runner.get_flk_balance(&owner), 1_000_u64.into());
+    assert_eq!(
+        query_runner.get_staked(&node_secret_key.to_pk()),
+        HpUfixed::<18>::zero()
+    );
+}
+
+#[test]
+async fn test_bundle_reverts_atomically() {
+    let (update_socket, query_runner) = init_app(None).await;
+
+    let owner_secret_key = AccountOwnerSecretKey::generate();
+    let node_secret_key = NodeSecretKey::generate();
+    let owner: EthAddress = owner_secret_key.to_pk().into();
+
+    deposit(
+        1_000_u64.into(),
+        Tokens::FLK,
+        owner_secret_key,
+        &update_socket,
+        1,
+    )
+    .await;
+
+    let stake = UpdateMethod::Stake {
+        amount: 1_000_u64.into(),
+        node_public_key: node_secret_key.to_pk(),
+        node_network_key: Some([0; 32].into()),
+        node_domain: Some("/ip4/127.0.0.1/udp/38000".to_string()),
+        worker_public_key: Some([0; 32].into()),
+        worker_domain: Some("/ip4/127.0.0.1/udp/38000".to_string()),
+        worker_mempool_address: Some("/ip4/127.0.0.1/udp/38000".to_string()),
+    };
+    // The stake succeeds on its own, but locking the stake of an unknown node reverts.
+    let bundle = get_update_request_account(
+        UpdateMethod::Bundle {
+            methods: vec![
+                stake.clone(),
+                UpdateMethod::StakeLock {
+                    node: NodeSecretKey::generate().to_pk(),
+                    locked_for: 1,
+                },
+            ],
+        },
+        owner_secret_key,
+        2,
+    );
+    let res = run_transaction(vec![bundle], &update_socket).await.unwrap();
+    assert_eq!(
+        res.txn_receipts[0],
+        TransactionResponse::Revert(ExecutionError::NodeDoesNotExist)
+    );
+
+    // None of the changes of the stake are applied.
+    assert_eq!(query_runner.get_flk_balance(&owner), 1_000_u64.into());
+    assert_eq!(
+        query_runner.get_staked(&node_secret_key.to_pk()),
+        HpUfixed::<18>::zero()
+    );
+    assert!(
+        query_runner
+            .get_node_info(&node_secret_key.to_pk())
+            .is_none()
+    );
+
+    // The bundle still used up its nonce, and a bundle that succeeds applies all of its methods.
+    let bundle = get_update_request_account(
+        UpdateMethod::Bundle {
+            methods: vec![
+                stake,
+                UpdateMethod::StakeLock {
+                    node: node_secret_key.to_pk(),
+                    locked_for: 1,
+                },
+            ],
+        },
+        owner_secret_key,
+        3,
+    );
+    let res = run_transaction(vec![bundle], &update_socket).await.unwrap();
+    assert_eq!(
+        res.txn_receipts[0],
+        TransactionResponse::Success(ExecutionData::None)
+    );
+    assert_eq!(query_runner.get_flk_balance(&owner), HpUfixed::<18>::zero());
+    assert_eq!(
+        query_runner.get_staked(&node_secret_key.to_pk()),
+        1_000_u64.into()
+    );
+}
+
+#[test]
+async fn test_nested_bundle_reverts() {
+    let (update_socket, query_runner) = init_app(None).await;
+
+    let owner_secret_key = AccountOwnerSecretKey::generate();
+    let node_secret_key = NodeSecretKey::generate();
+    let owner: EthAddress = owner_secret_key.to_pk().into();
+
+    deposit(
+        1_000_u64.into(),
+        Tokens::FLK,
+        owner_secret_key,
+        &update_socket,
+        1,
+    )
+    .await;
+
+    let stake = UpdateMethod::Stake {
+        amount: 1_000_u64.into(),
+        node_public_key: node_secret_key.to_pk(),
+        node_network_key: Some([0; 32].into()),
+        node_domain: Some("/ip4/127.0.0.1/udp/38000".to_string()),
+        worker_public_key: Some([0; 32].into()),
+        worker_domain: Some("/ip4/127.0.0.1/udp/38000".to_string()),
+        worker_mempool_address: Some("/ip4/127.0.0.1/udp/38000".to_string()),
+    };
+    // Every method would succeed on its own, but the bundle contains another bundle.
+    let bundle = get_update_request_account(
+        UpdateMethod::Bundle {
+            methods: vec![
+                stake.clone(),
+                UpdateMethod::Bundle {
+                    methods: vec![UpdateMethod::StakeLock {
+                        node: node_secret_key.to_pk(),
+                        locked_for: 1,
+                    }],
+                },
+            ],
+        },
+        owner_secret_key,
+        2,
+    );
+    let res = run_transaction(vec![bundle], &update_socket).await.unwrap();
+    assert_eq!(
+        res.txn_receipts[0],
+        TransactionResponse::Revert(ExecutionError::NestedBundle)
+    );
+
+    // None of the methods are executed.
+    assert_eq!(query_runner.get_flk_balance(&owner), 1_000_u64.into());
+    assert_eq!(
+        query_runner.get_staked(&node_secret_key.to_pk()),
+        HpUfixed::<18>::zero()
+    );
+}
+
 #[test]
 async fn test_is_valid_node() {
     let (update_socket, query_runner) = init_app(None).await;
@@ -1108,6 +1934,7 @@ async fn test_get_node_registry() {
     let (update_socket, query_runner) = init_app(Some(Config {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     }))
     .await;
 
@@ -1198,6 +2025,145 @@ async fn test_get_node_registry() {
     assert!(valid_nodes.contains(&node_info3));
 }
 
+#[test]
+async fn test_get_node_registry_page() {
+    let (committee, keystore) = get_genesis_committee(5);
+    let mut genesis = Genesis::load().unwrap();
+    genesis.committee = committee;
+    let (update_socket, query_runner) = init_app(Some(Config {
+        genesis: Some(genesis),
+        mode: Mode::Test,
+        dev_single_node: None,
+    }))
+    .await;
+
+    // Add a node that does not have enough stake to be in the registry.
+    let owner_secret_key = AccountOwnerSecretKey::generate();
+    let node_secret_key = NodeSecretKey::generate();
+    let less_than_minimum_stake_amount = query_runner.get_staking_amount() / 2;
+    deposit(
+        less_than_minimum_stake_amount.into(),
+        Tokens::FLK,
+        owner_secret_key,
+        &update_socket,
+        1,
+    )
+    .await;
+    stake(
+        less_than_minimum_stake_amount.into(),
+        node_secret_key.to_pk(),
+        owner_secret_key,
+        &update_socket,
+        2,
+    )
+    .await;
+
+    let registry = query_runner.get_node_registry();
+    assert_eq!(registry.len(), keystore.len());
+    assert_eq!(query_runner.node_registry_len(), registry.len());
+
+    // Concatenating all of the pages gives back the full registry.
+    let mut pages = Vec::new();
+    let mut offset = 0;
+    while offset < query_runner.node_registry_len() {
+        let page = query_runner.get_node_registry_page(offset, 2);
+        assert!(!page.is_empty() && page.len() <= 2);
+        offset += page.len();
+        pages.extend(page);
+    }
+    assert_eq!(pages, registry);
+
+    // Offsets past the end of the registry return nothing.
+    assert!(
+        query_runner
+            .get_node_registry_page(registry.len(), 2)
+            .is_empty()
+    );
+    assert!(
+        query_runner
+            .get_node_registry_page(registry.len() + 10, 2)
+            .is_empty()
+    );
+    assert!(query_runner.get_node_registry_page(0, 0).is_empty());
+}
+
+#[test]
+async fn test_node_selection_rank() {
+    let (committee, keystore) = get_genesis_committee(4);
+    let mut genesis = Genesis::load().unwrap();
+    genesis.committee = committee;
+    genesis.committee_size = 5;
+    let (update_socket, query_runner) = init_app(Some(Config {
+        genesis: Some(genesis),
+        mode: Mode::Test,
+        dev_single_node: None,
+    }))
+    .await;
+
+    // Stake two, three and four times the minimum for three new candidates.
+    let minimum_stake_amount = query_runner.get_staking_amount();
+    let mut candidates = Vec::new();
+    for multiple in 2..=4 {
+        let owner_secret_key = AccountOwnerSecretKey::generate();
+        let node_secret_key = NodeSecretKey::generate();
+        let amount = minimum_stake_amount * multiple;
+        deposit(
+            amount.into(),
+            Tokens::FLK,
+            owner_secret_key,
+            &update_socket,
+            1,
+        )
+        .await;
+        stake(
+            amount.into(),
+            node_secret_key.to_pk(),
+            owner_secret_key,
+            &update_socket,
+            2,
+        )
+        .await;
+        candidates.push(node_secret_key.to_pk());
+    }
+
+    // The candidates with the most stake come first.
+    for (rank, candidate) in candidates.iter().rev().enumerate() {
+        let expected_score = minimum_stake_amount * (4 - rank as u128);
+        assert_eq!(
+            query_runner.get_node_selection_rank(candidate),
+            Some((rank, expected_score))
+        );
+    }
+
+    // The genesis nodes follow, and the ranks are ordered by descending score.
+    let ranks = keystore
+        .iter()
+        .map(|node| {
+            query_runner
+                .get_node_selection_rank(&node.node_secret_key.to_pk())
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let mut positions = ranks.iter().map(|(rank, _)| *rank).collect::<Vec<_>>();
+    positions.sort();
+    assert_eq!(positions, vec![3, 4, 5, 6]);
+    assert!(
+        ranks
+            .iter()
+            .all(|(_, score)| *score == minimum_stake_amount)
+    );
+
+    // The fifth seat is taken by a genesis node, which sets the cutoff.
+    assert_eq!(
+        query_runner.committee_cutoff_score(),
+        Some(minimum_stake_amount)
+    );
+    assert_eq!(
+        query_runner.get_node_selection_rank(&NodeSecretKey::generate().to_pk()),
+        None
+    );
+}
+
 #[test]
 async fn test_change_protocol_params() {
     let governance_secret_key = AccountOwnerSecretKey::generate();
@@ -1209,6 +2175,7 @@ async fn test_change_protocol_params() {
     let (update_socket, query_runner) = init_app(Some(Config {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     }))
     .await;
 
@@ -1267,3 +2234,422 @@ async fn test_change_protocol_params() {
         8
     );
 }
+
+#[test]
+async fn test_dev_single_node() {
+    let (mut committee, keystore) = get_genesis_committee(1);
+    let node_public_key = keystore[0].node_secret_key.to_pk();
+    let (update_socket, query_runner) = init_app(Some(Config {
+        genesis: None,
+        mode: Mode::Dev,
+        dev_single_node: committee.pop(),
+    }))
+    .await;
+
+    // The local node is the only committee member.
+    assert_eq!(query_runner.get_committee_members(), vec![node_public_key]);
+    assert_eq!(
+        query_runner.get_protocol_params(ProtocolParams::CommitteeSize),
+        1
+    );
+    assert!(query_runner.get_node_info(&node_public_key).is_some());
+
+    // Basic transactions can be executed.
+    let owner_secret_key = AccountOwnerSecretKey::generate();
+    deposit(
+        1_000_u64.into(),
+        Tokens::FLK,
+        owner_secret_key,
+        &update_socket,
+        1,
+    )
+    .await;
+    assert_eq!(
+        query_runner.get_flk_balance(&owner_secret_key.to_pk().into()),
+        1_000_u64.into()
+    );
+}
+
+#[test]
+async fn test_epoch_progress() {
+    let epoch_time = 3_600_000;
+    let start = 1_000_000;
+    let (committee, keystore) = get_genesis_committee(4);
+    let mut genesis = Genesis::load().unwrap();
+    genesis.committee = committee;
+    genesis.epoch_time = epoch_time;
+    genesis.epoch_start = start;
+    let (update_socket, query_runner) = init_app(Some(Config {
+        genesis: Some(genesis),
+        mode: Mode::Test,
+        dev_single_node: None,
+    }))
+    .await;
+
+    // A quarter into the epoch.
+    let progress = query_runner.epoch_progress(start + epoch_time / 4);
+    assert_eq!(progress.fraction, 0.25);
+    assert_eq!(progress.elapsed, Duration::from_millis(epoch_time / 4));
+    assert_eq!(
+        progress.remaining,
+        Duration::from_millis(3 * epoch_time / 4)
+    );
+
+    // The end of the epoch is due.
+    let progress = query_runner.epoch_progress(start + 2 * epoch_time);
+    assert_eq!(progress.fraction, 1.0);
+    assert_eq!(progress.remaining, Duration::ZERO);
+
+    // The next epoch starts where the previous one ended.
+    if let Err(err) = simple_epoch_change(0, &keystore, &update_socket, 1).await {
+        panic!("error while changing epoch, {err}");
+    }
+    let progress = query_runner.epoch_progress(start + epoch_time);
+    assert_eq!(progress.fraction, 0.0);
+    assert_eq!(progress.remaining, Duration::from_millis(epoch_time));
+}
+
+fn signed_vote(
+    secret_key: &NodeSecretKey,
+    epoch: Epoch,
+    round: u64,
+    digest: [u8; 32],
+) -> SignedVote {
+    let mut vote = SignedVote {
+        epoch,
+        round,
+        digest,
+        signature: NodeSignature([0; 48]),
+    };
+    vote.signature = secret_key.sign(&vote.to_digest());
+    vote
+}
+
+#[test]
+async fn test_submit_misbehavior_evidence() {
+    let (committee, keystore) = get_genesis_committee(4);
+    let mut genesis = Genesis::load().unwrap();
+    genesis.committee = committee;
+    genesis.slashing_percentage = 10;
+    let min_stake = genesis.min_stake;
+    let (update_socket, query_runner) = init_app(Some(Config {
+        genesis: Some(genesis),
+        mode: Mode::Test,
+        dev_single_node: None,
+    }))
+    .await;
+
+    let offender = &keystore[0].node_secret_key;
+    let reporter = AccountOwnerSecretKey::generate();
+    let staked = |node: NodePublicKey| query_runner.get_node_info(&node).unwrap().stake.staked;
+
+    // Votes for the same block are not an equivocation.
+    let evidence = MisbehaviorEvidence::Equivocation {
+        first: signed_vote(offender, 0, 1, [1; 32]),
+        second: signed_vote(offender, 0, 1, [1; 32]),
+    };
+    let req = get_update_request_account(
+        UpdateMethod::SubmitMisbehaviorEvidence {
+            node: offender.to_pk(),
+            evidence,
+        },
+        reporter,
+        1,
+    );
+    let res = run_transaction(vec![req], &update_socket).await.unwrap();
+    assert_eq!(
+        res.txn_receipts[0],
+        TransactionResponse::Revert(ExecutionError::InvalidProof)
+    );
+
+    // Votes signed by another node don't prove anything about the offender.
+    let evidence = MisbehaviorEvidence::Equivocation {
+        first: signed_vote(offender, 0, 1, [1; 32]),
+        second: signed_vote(&keystore[1].node_secret_key, 0, 1, [2; 32]),
+    };
+    let req = get_update_request_account(
+        UpdateMethod::SubmitMisbehaviorEvidence {
+            node: offender.to_pk(),
+            evidence,
+        },
+        reporter,
+        2,
+    );
+    let res = run_transaction(vec![req], &update_socket).await.unwrap();
+    assert_eq!(
+        res.txn_receipts[0],
+        TransactionResponse::Revert(ExecutionError::InvalidProof)
+    );
+    assert_eq!(staked(offender.to_pk()), min_stake.into());
+
+    // Two different blocks signed for the same round slash the offender.
+    let evidence = MisbehaviorEvidence::Equivocation {
+        first: signed_vote(offender, 0, 1, [1; 32]),
+        second: signed_vote(offender, 0, 1, [2; 32]),
+    };
+    let req = get_update_request_account(
+        UpdateMethod::SubmitMisbehaviorEvidence {
+            node: offender.to_pk(),
+            evidence: evidence.clone(),
+        },
+        reporter,
+        3,
+    );
+    let res = run_transaction(vec![req], &update_socket).await.unwrap();
+    assert_eq!(
+        res.txn_receipts[0],
+        TransactionResponse::Success(ExecutionData::None)
+    );
+    let expected: HpUfixed<18> = (min_stake * 9 / 10).into();
+    assert_eq!(staked(offender.to_pk()), expected);
+    assert_eq!(
+        staked(keystore[1].node_secret_key.to_pk()),
+        min_stake.into()
+    );
+
+    // The same offence can't be slashed twice.
+    let req = get_update_request_account(
+        UpdateMethod::SubmitMisbehaviorEvidence {
+            node: offender.to_pk(),
+            evidence,
+        },
+        reporter,
+        4,
+    );
+    let res = run_transaction(vec![req], &update_socket).await.unwrap();
+    assert_eq!(
+        res.txn_receipts[0],
+        TransactionResponse::Revert(ExecutionError::AlreadySlashed)
+    );
+    assert_eq!(staked(offender.to_pk()), expected);
+}
+
+#[test]
+async fn test_supply_snapshot() {
+    let (update_socket, query_runner) = init_app(None).await;
+    let (_, genesis_nodes) = get_genesis();
+
+    // Stake on a new node and unstake part of it, so that some of the tokens are locked.
+    let owner_secret_key = AccountOwnerSecretKey::generate();
+    let node_public_key = NodeSecretKey::generate().to_pk();
+    deposit(
+        2_000_u64.into(),
+        Tokens::FLK,
+        owner_secret_key,
+        &update_socket,
+        1,
+    )
+    .await;
+    stake(
+        1_500_u64.into(),
+        node_public_key,
+        owner_secret_key,
+        &update_socket,
+        2,
+    )
+    .await;
+    let update = get_update_request_account(
+        UpdateMethod::Unstake {
+            amount: 500_u64.into(),
+            node: node_public_key,
+        },
+        owner_secret_key,
+        3,
+    );
+    run_transaction(vec![update], &update_socket).await.unwrap();
+
+    let snapshot = query_runner.get_supply_snapshot();
+    assert_eq!(snapshot.total_supply, query_runner.get_total_supply());
+    assert_eq!(
+        snapshot.year_start_supply,
+        query_runner.get_year_start_supply()
+    );
+    assert_eq!(
+        snapshot.protocol_fund_balance,
+        query_runner.get_flk_balance(&query_runner.get_protocol_fund_address())
+    );
+
+    let total_staked = genesis_nodes
+        .iter()
+        .map(|node| node.public_key)
+        .chain(std::iter::once(node_public_key))
+        .map(|node| query_runner.get_node_info(&node).unwrap().stake)
+        .fold(HpUfixed::<18>::zero(), |total, stake| {
+            total + stake.staked + stake.locked
+        });
+    assert_eq!(snapshot.total_staked, total_staked);
+    assert_eq!(
+        snapshot.circulating,
+        &snapshot.total_supply - &snapshot.total_staked
+    );
+}
+
+#[test]
+async fn test_get_receipt() {
+    let (committee, keystore) = get_genesis_committee(4);
+    let mut genesis = Genesis::load().unwrap();
+    genesis.committee = committee;
+    let secret_key = keystore[0].node_secret_key;
+    let (update_socket, query_runner) = init_app(Some(Config {
+        genesis: Some(genesis),
+        mode: Mode::Test,
+        dev_single_node: None,
+    }))
+    .await;
+
+    let first = pod_request(secret_key, 1000, 0, 1);
+    let second = pod_request(secret_key, 1000, 0, 2);
+    let unknown = pod_request(secret_key, 1000, 0, 3);
+    let receipt = |request: &UpdateRequest| {
+        query_runner.get_receipt(&request.sender, request.payload.to_digest())
+    };
+    assert_eq!(receipt(&first), None);
+
+    run_transaction(vec![first.clone(), second.clone()], &update_socket)
+        .await
+        .unwrap();
+
+    // Each executed transaction has a receipt under its sender and the digest of its payload.
+    let second_receipt = receipt(&second).unwrap();
+    assert_eq!(
+        second_receipt.response,
+        TransactionResponse::Success(ExecutionData::None)
+    );
+    assert_eq!(second_receipt.epoch, 0);
+    assert_eq!(second_receipt.block_number, 1);
+    assert_eq!(second_receipt.index, 1);
+    assert_eq!(receipt(&unknown), None);
+
+    // Replaying a transaction reverts, but does not replace the receipt of its execution.
+    let res = run_transaction(vec![first.clone()], &update_socket)
+        .await
+        .unwrap();
+    assert_eq!(
+        res.txn_receipts[0],
+        TransactionResponse::Revert(ExecutionError::InvalidNonce)
+    );
+    let first_receipt = receipt(&first).unwrap();
+    assert_eq!(
+        first_receipt.response,
+        TransactionResponse::Success(ExecutionData::None)
+    );
+    assert_eq!((first_receipt.block_number, first_receipt.index), (1, 0));
+
+    // Another sender signing the same payload gets a receipt of its own.
+    let other = pod_request(keystore[1].node_secret_key, 1000, 0, 1);
+    assert_eq!(other.payload.to_digest(), first.payload.to_digest());
+    assert_eq!(receipt(&other), None);
+    run_transaction(vec![other.clone()], &update_socket)
+        .await
+        .unwrap();
+    assert_eq!(
+        receipt(&other).map(|receipt| (receipt.block_number, receipt.index)),
+        Some((3, 0))
+    );
+    assert_eq!(
+        receipt(&first).map(|receipt| (receipt.block_number, receipt.index)),
+        Some((1, 0))
+    );
+}
+
+#[test]
+async fn test_governance_proposals() {
+    let governance_secret_key = AccountOwnerSecretKey::generate();
+    let (committee, keystore) = get_genesis_committee(4);
+    let mut genesis = Genesis::load().unwrap();
+    genesis.committee = committee;
+    genesis.governance_address = governance_secret_key.to_pk().to_base64();
+    let (update_socket, query_runner) = init_app(Some(Config {
+        genesis: Some(genesis),
+        mode: Mode::Test,
+        dev_single_node: None,
+    }))
+    .await;
+
+    // Submit a proposal that expires at the next epoch, and one that expires after it.
+    for (nonce, deadline) in [(1, 1), (2, 2)] {
+        let method = UpdateMethod::SubmitProposal {
+            method: Box::new(UpdateMethod::ChangeProtocolParam {
+                param: ProtocolParams::LockTime,
+                value: deadline as u128,
+            }),
+            deadline,
+        };
+        let req = get_update_request_account(method, governance_secret_key, nonce);
+        let res = run_transaction(vec![req], &update_socket).await.unwrap();
+        assert_eq!(
+            res.txn_receipts[0],
+            TransactionResponse::Success(ExecutionData::UInt(nonce as u128 - 1))
+        );
+    }
+    let open = query_runner.get_open_proposals();
+    assert_eq!(open.iter().map(|p| p.id).collect::<Vec<_>>(), vec![0, 1]);
+    assert_eq!(open[0].tally(), (0, 0));
+
+    // Cast votes, every committee member only gets to vote once on a proposal.
+    let vote = |proposal_id, approve, node: usize, nonce| {
+        get_update_request_node(
+            UpdateMethod::VoteOnProposal {
+                proposal_id,
+                approve,
+            },
+            keystore[node].node_secret_key,
+            nonce,
+        )
+    };
+    let res = run_transaction(
+        vec![
+            vote(0, true, 0, 1),
+            vote(0, true, 1, 1),
+            vote(0, false, 2, 1),
+            vote(0, false, 0, 2),
+            vote(1, true, 1, 2),
+            vote(5, true, 2, 2),
+        ],
+        &update_socket,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        res.txn_receipts[3],
+        TransactionResponse::Revert(ExecutionError::AlreadyVoted)
+    );
+    assert_eq!(
+        res.txn_receipts[5],
+        TransactionResponse::Revert(ExecutionError::ProposalDoesNotExist)
+    );
+    assert_eq!(query_runner.get_proposal(0).unwrap().tally(), (2, 1));
+    assert_eq!(query_runner.get_proposal(1).unwrap().tally(), (1, 0));
+
+    // Once the epoch reaches its deadline the first proposal is closed, with its final tally.
+    simple_epoch_change(0, &keystore, &update_socket, 3)
+        .await
+        .unwrap();
+    let open = query_runner.get_open_proposals();
+    assert_eq!(open.iter().map(|p| p.id).collect::<Vec<_>>(), vec![1]);
+    let expired = query_runner.get_proposal(0).unwrap();
+    assert!(!expired.is_open(query_runner.get_epoch()));
+    assert_eq!(expired.tally(), (2, 1));
+
+    let res = run_transaction(vec![vote(0, true, 3, 1)], &update_socket)
+        .await
+        .unwrap();
+    assert_eq!(
+        res.txn_receipts[0],
+        TransactionResponse::Revert(ExecutionError::ProposalExpired)
+    );
+    assert_eq!(query_runner.get_proposal(0).unwrap().tally(), (2, 1));
+
+    // A proposal can not be submitted with a deadline that already passed.
+    let method = UpdateMethod::SubmitProposal {
+        method: Box::new(UpdateMethod::ChangeEpoch { epoch: 1 }),
+        deadline: 1,
+    };
+    let req = get_update_request_account(method, governance_secret_key, 3);
+    let res = run_transaction(vec![req], &update_socket).await.unwrap();
+    assert_eq!(
+        res.txn_receipts[0],
+        TransactionResponse::Revert(ExecutionError::ProposalExpired)
+    );
+    assert!(query_runner.get_proposal(2).is_none());
+}
@@ -0,0 +1,68 @@
+#[cfg(test)]
+use std::cell::Cell;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+use lightning_interfaces::types::{CompressionAlgoSet, CompressionAlgorithm};
+
+/// The supported algorithms, in the order in which they are preferred when a client accepts
+/// several of them.
+const PREFERENCE: [CompressionAlgorithm; 2] =
+    [CompressionAlgorithm::Gzip, CompressionAlgorithm::Snappy];
+
+#[cfg(test)]
+thread_local! {
+    /// The number of times content was compressed on this thread.
+    pub(crate) static COMPRESS_CALLS: Cell<usize> = Cell::new(0);
+}
+
+/// Returns the preferred supported algorithm out of the ones accepted by a client, falling back
+/// to the uncompressed content.
+pub fn negotiate(accepted: CompressionAlgoSet) -> CompressionAlgorithm {
+    PREFERENCE
+        .into_iter()
+        .find(|algorithm| accepted.contains(*algorithm))
+        .unwrap_or(CompressionAlgorithm::Uncompressed)
+}
+
+/// Compress the content with the given algorithm.
+pub fn compress(algorithm: CompressionAlgorithm, content: &[u8]) -> Result<Vec<u8>> {
+    #[cfg(test)]
+    COMPRESS_CALLS.with(|calls| calls.set(calls.get() + 1));
+
+    match algorithm {
+        CompressionAlgorithm::Uncompressed => Ok(content.to_vec()),
+        CompressionAlgorithm::Snappy => snap::raw::Encoder::new()
+            .compress_vec(content)
+            .map_err(|e| Error::new(ErrorKind::Other, e)),
+        CompressionAlgorithm::Gzip => {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(content)?;
+            encoder.finish()
+        },
+        algorithm => Err(unsupported(algorithm)),
+    }
+}
+
+/// Decompress content that was compressed with the given algorithm.
+pub fn decompress(algorithm: CompressionAlgorithm, content: &[u8]) -> Result<Vec<u8>> {
+    match algorithm {
+        CompressionAlgorithm::Uncompressed => Ok(content.to_vec()),
+        CompressionAlgorithm::Snappy => snap::raw::Decoder::new()
+            .decompress_vec(content)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e)),
+        CompressionAlgorithm::Gzip => {
+            let mut decompressed = Vec::new();
+            flate2::read::GzDecoder::new(content).read_to_end(&mut decompressed)?;
+            Ok(decompressed)
+        },
+        algorithm => Err(unsupported(algorithm)),
+    }
+}
+
+fn unsupported(algorithm: CompressionAlgorithm) -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        format!("unsupported compression algorithm: {algorithm:?}"),
+    )
+}
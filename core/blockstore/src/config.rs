@@ -1,4 +1,24 @@
+use std::time::Duration;
+
+use lightning_interfaces::types::CompressionAlgorithm;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Default)]
-pub struct Config {}
+#[serde(default)]
+pub struct Config {
+    /// The maximum number of bytes kept by the in-memory store before the least recently used
+    /// objects are evicted. Blocks that are still referenced by a caller are never evicted.
+    /// Unbounded if not set.
+    pub capacity: Option<usize>,
+    /// The time after which an object that was not accessed is evicted from the in-memory store,
+    /// regardless of the capacity. Pinned objects and the ones that are still referenced by a
+    /// caller are never evicted. Objects never expire if not set.
+    pub cache_ttl: Option<Duration>,
+    /// The algorithm used to compress the chunks when they are stored. The content is always
+    /// hashed uncompressed, so the choice does not affect the root hash of an object.
+    pub default_compression: CompressionAlgorithm,
+    /// The maximum number of bytes of compressed variants of the chunks kept in memory, so the
+    /// chunks that are often requested with the same compression are not compressed again on
+    /// every request. The least recently used variants are evicted first. Disabled if not set.
+    pub variant_cache_capacity: Option<usize>,
+}
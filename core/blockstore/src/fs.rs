@@ -9,10 +9,15 @@
 use tempdir::TempDir;
 use tokio::{
     fs::{self, File},
-    io::AsyncWriteExt,
+    io::{AsyncRead, AsyncWriteExt},
 };
 
-use crate::{put::IncrementalPut, store::Store, Block, BlockContent, Key};
+use crate::{
+    compression,
+    put::{self, IncrementalPut},
+    store::Store,
+    Block, BlockContent, Key,
+};
 
 const TMP_DIR_PREFIX: &str = "tmp-store";
 
@@ -68,9 +73,9 @@ async fn get(
         )
         .expect("Stored content to be serialized properly")
         {
-            BlockContent::Chunk(content) => Some(Arc::new(ContentChunk {
+            BlockContent::Chunk(compression, content) => Some(Arc::new(ContentChunk {
                 compression: CompressionAlgorithm::Uncompressed,
-                content,
+                content: compression::decompress(compression, &content).ok()?,
             })),
             _ => None,
         }
@@ -82,6 +87,14 @@ fn put(&self, root: Option<Blake3Hash>) -> Self::Put {
             None => IncrementalPut::trust(self.clone()),
         }
     }
+
+    async fn put_verified_stream<R: AsyncRead + Unpin + Send>(
+        &self,
+        root: Blake3Hash,
+        reader: R,
+    ) -> anyhow::Result<Blake3Hash> {
+        put::put_verified_stream(self.put(Some(root)), root, reader).await
+    }
 }
 
 // TODO: Add logging.
@@ -110,4 +123,9 @@ async fn insert(&mut self, key: Key, block: Block) {
             }
         }
     }
+
+    async fn contains_key(&self, key: &Key) -> bool {
+        let path = format!("{}/{:?}", self.store_dir_path, key.0);
+        fs::metadata(path).await.is_ok()
+    }
 }
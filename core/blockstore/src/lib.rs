@@ -1,17 +1,20 @@
+pub mod compression;
 pub mod config;
 mod fs;
 pub mod memory;
 pub mod put;
+pub mod read;
 mod store;
+mod variant;
 
-use lightning_interfaces::Blake3Hash;
+use lightning_interfaces::{types::CompressionAlgorithm, Blake3Hash};
 use serde::{Deserialize, Serialize};
 
 const BLAKE3_CHUNK_SIZE: usize = 256 * 1024;
 
 type Block = Vec<u8>;
 
-#[derive(Hash, Eq, PartialEq, Debug)]
+#[derive(Clone, Copy, Hash, Eq, PartialEq, Debug)]
 pub struct Key(Blake3Hash, Option<u32>);
 
 impl Key {
@@ -28,22 +31,46 @@ pub fn tree_key(hash: Blake3Hash) -> Self {
 #[derive(Serialize, Deserialize)]
 pub enum BlockContent {
     Tree(Vec<Blake3Hash>),
-    Chunk(Vec<u8>),
+    /// The content of a chunk, compressed with the given algorithm.
+    Chunk(CompressionAlgorithm, Vec<u8>),
 }
 
 #[cfg(test)]
 mod tests {
+    use std::{
+        io::Write,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::{Duration, Instant},
+    };
+
+    use async_trait::async_trait;
+    use blake3_stream::Encoder;
     use blake3_tree::{
         blake3::tree::{BlockHasher, HashTree, HashTreeBuilder},
         ProofBuf,
     };
     use lightning_interfaces::{
         types::{CompressionAlgoSet, CompressionAlgorithm},
-        Blake3Hash, BlockStoreInterface, IncrementalPutInterface,
+        Blake3Hash, BlockStoreInterface, ContentChunk, IncrementalPutInterface, PutFinalizeError,
+        PutWriteError,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        test,
     };
-    use tokio::test;
 
-    use crate::{config::Config, memory::MemoryBlockStore, BLAKE3_CHUNK_SIZE};
+    use crate::{
+        compression,
+        config::Config,
+        memory::{leaf_index, MemoryBlockStore},
+        put::IncrementalPut,
+        store::Store,
+        variant::VariantCache,
+        Block, BlockContent, Key, BLAKE3_CHUNK_SIZE,
+    };
 
     fn create_content() -> Vec<u8> {
         (0..4)
@@ -71,7 +98,7 @@ async fn test_put() {
         // Given: some content.
         let content = create_content();
         // Given: a block store.
-        let blockstore = MemoryBlockStore::init(Config {}).await.unwrap();
+        let blockstore = MemoryBlockStore::init(Config::default()).await.unwrap();
         // When: we create a putter and write some content.
         let mut putter = blockstore.put(None);
         putter
@@ -88,7 +115,7 @@ async fn test_put_verify() {
         // Given: some content.
         let content = create_content();
         // Given: a block store.
-        let blockstore = MemoryBlockStore::init(Config {}).await.unwrap();
+        let blockstore = MemoryBlockStore::init(Config::default()).await.unwrap();
         // Given: we put the content in the block store.
         let mut putter = blockstore.put(None);
         putter
@@ -116,7 +143,7 @@ async fn test_put_verify_invalid_content() {
         // Given: some content.
         let mut content = create_content();
         // Given: a block store.
-        let blockstore = MemoryBlockStore::init(Config {}).await.unwrap();
+        let blockstore = MemoryBlockStore::init(Config::default()).await.unwrap();
         // Given: we put the content in the block store and feed the proof to verify it.
         let mut putter = blockstore.put(None);
         putter
@@ -137,12 +164,50 @@ async fn test_put_verify_invalid_content() {
         assert!(write_result.is_err());
     }
 
+    #[test]
+    async fn test_put_verify_invalid_last_block_stores_nothing() {
+        // Given: some content whose last block is smaller than a Blake3 chunk.
+        let mut content = create_content();
+        content.extend_from_slice(&[7; 100]);
+        // Given: the full tree.
+        let hash_tree = hash_tree(content.as_slice());
+        // Given: a block store.
+        let blockstore = MemoryBlockStore::init(Config::default()).await.unwrap();
+        // Given: make a change to the last block.
+        let original = content.clone();
+        *content.last_mut().unwrap() = 69;
+        // When: we put the content by block and feed the proof to verify it.
+        let mut putter = blockstore.put(Some(Blake3Hash::from(hash_tree.hash)));
+        for (i, block) in content.chunks(BLAKE3_CHUNK_SIZE).enumerate() {
+            let proof = new_proof(&hash_tree.tree, i);
+            putter.feed_proof(proof.as_slice()).unwrap();
+            putter
+                .write(block, CompressionAlgorithm::Uncompressed)
+                .unwrap();
+        }
+        // Then: the putter fails to finalize.
+        assert!(putter.finalize().await.is_err());
+        // Then: none of the blocks were committed to the store.
+        for (count, chunk) in original.chunks(BLAKE3_CHUNK_SIZE).enumerate() {
+            let mut block = BlockHasher::new();
+            block.set_block(count);
+            block.update(chunk);
+            let hash = block.finalize(false);
+            assert!(
+                blockstore
+                    .get(count as u32, &hash, CompressionAlgoSet::new())
+                    .await
+                    .is_none()
+            );
+        }
+    }
+
     #[test]
     async fn test_get() {
         // Given: some content.
         let content = create_content();
         // Given: a block store.
-        let blockstore = MemoryBlockStore::init(Config {}).await.unwrap();
+        let blockstore = MemoryBlockStore::init(Config::default()).await.unwrap();
         // Given: we put the content in the block store.
         let mut putter = blockstore.put(None);
         putter
@@ -174,12 +239,66 @@ async fn test_get() {
         )
     }
 
+    #[test]
+    async fn test_read_content() {
+        // Given: some content.
+        let content = create_content();
+        // Given: a block store.
+        let blockstore = MemoryBlockStore::init(Config::default()).await.unwrap();
+        // Given: we put the content in the block store.
+        let mut putter = blockstore.put(None);
+        putter
+            .write(content.as_slice(), CompressionAlgorithm::Uncompressed)
+            .unwrap();
+        let root = putter.finalize().await.unwrap();
+        // When: we read back the content of the object.
+        let mut reader = blockstore.read_content(&root).await.unwrap();
+        let mut buffer = Vec::new();
+        reader.read_to_end(&mut buffer).await.unwrap();
+        // Then: we get the original bytes.
+        assert_eq!(buffer, content);
+        // Then: there is no content for an unknown root.
+        assert!(blockstore.read_content(&[0; 32]).await.is_none());
+    }
+
+    #[test]
+    async fn test_read_compressed_content() {
+        // Given: some content.
+        let content = create_content();
+        // Given: a block store that compresses the chunks it stores.
+        let blockstore = MemoryBlockStore::init(Config {
+            default_compression: CompressionAlgorithm::Gzip,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        // Given: we put the content in the block store.
+        let mut putter = blockstore.put(None);
+        putter
+            .write(content.as_slice(), CompressionAlgorithm::Uncompressed)
+            .unwrap();
+        let root = putter.finalize().await.unwrap();
+        // When: we read back the content of the object in small pieces.
+        let mut reader = blockstore.read_content(&root).await.unwrap();
+        let mut buffer = Vec::new();
+        let mut piece = [0; 1000];
+        loop {
+            let n = reader.read(&mut piece).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            buffer.extend_from_slice(&piece[..n]);
+        }
+        // Then: we get the original uncompressed bytes.
+        assert_eq!(buffer, content);
+    }
+
     #[test]
     async fn test_put_verify_one_chunk() {
         // Given: some content.
         let content = [0; BLAKE3_CHUNK_SIZE];
         // Given: a block store.
-        let blockstore = MemoryBlockStore::init(Config {}).await.unwrap();
+        let blockstore = MemoryBlockStore::init(Config::default()).await.unwrap();
         // Given: we put the content in the block store.
         let mut putter = blockstore.put(None);
         putter
@@ -205,7 +324,7 @@ async fn test_put_verify_one_chunk_small() {
         // Given: some content.
         let content = [0; 256];
         // Given: a block store.
-        let blockstore = MemoryBlockStore::init(Config {}).await.unwrap();
+        let blockstore = MemoryBlockStore::init(Config::default()).await.unwrap();
         // Given: we put the content in the block store.
         let mut putter = blockstore.put(None);
         putter
@@ -234,7 +353,7 @@ async fn test_put_verify_one_chunk_and_a_half() {
             .flatten()
             .collect::<Vec<_>>();
         // Given: a block store.
-        let blockstore = MemoryBlockStore::init(Config {}).await.unwrap();
+        let blockstore = MemoryBlockStore::init(Config::default()).await.unwrap();
         // Given: we put the content in the block store.
         let mut putter = blockstore.put(None);
         putter
@@ -266,4 +385,714 @@ async fn test_put_verify_one_chunk_and_a_half() {
         let root = putter.finalize().await.unwrap();
         assert_eq!(root, Blake3Hash::from(hash_tree.hash));
     }
+
+    #[test]
+    async fn test_referenced_block_is_not_evicted() {
+        // Given: a block store that can hold a single chunk.
+        let blockstore = MemoryBlockStore::init(Config {
+            capacity: Some(BLAKE3_CHUNK_SIZE + 1024),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        // Given: we put some content in the block store.
+        let content = [0; BLAKE3_CHUNK_SIZE];
+        let mut putter = blockstore.put(None);
+        putter
+            .write(&content, CompressionAlgorithm::Uncompressed)
+            .unwrap();
+        putter.finalize().await.unwrap();
+        let mut block = BlockHasher::new();
+        block.set_block(0);
+        block.update(&content);
+        let hash = block.finalize(false);
+        // Given: we hold a reference to the stored chunk.
+        let chunk = blockstore
+            .get(0, &hash, CompressionAlgoSet::new())
+            .await
+            .unwrap();
+        // When: we put other content that does not fit in the store.
+        let mut putter = blockstore.put(None);
+        putter
+            .write(&[1; BLAKE3_CHUNK_SIZE], CompressionAlgorithm::Uncompressed)
+            .unwrap();
+        putter.finalize().await.unwrap();
+        // Then: the referenced chunk is not evicted and the same instance is returned.
+        let chunk_from_store = blockstore
+            .get(0, &hash, CompressionAlgoSet::new())
+            .await
+            .unwrap();
+        assert!(Arc::ptr_eq(&chunk, &chunk_from_store));
+        assert_eq!(chunk_from_store.content, content);
+        // When: we drop the references and put other content.
+        drop(chunk);
+        drop(chunk_from_store);
+        let mut putter = blockstore.put(None);
+        putter
+            .write(&[2; BLAKE3_CHUNK_SIZE], CompressionAlgorithm::Uncompressed)
+            .unwrap();
+        putter.finalize().await.unwrap();
+        // Then: the chunk is evicted.
+        assert!(
+            blockstore
+                .get(0, &hash, CompressionAlgoSet::new())
+                .await
+                .is_none()
+        );
+    }
+
+    #[test]
+    async fn test_eviction_removes_whole_objects() {
+        // Given: a block store that can hold three chunks.
+        let blockstore = MemoryBlockStore::init(Config {
+            capacity: Some(3 * BLAKE3_CHUNK_SIZE + 1024),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        // Given: we put an object of two chunks in the block store.
+        let mut roots = Vec::new();
+        for byte in [0, 1] {
+            // When: we put another object of two chunks that does not fit next to the first.
+            let mut putter = blockstore.put(None);
+            putter
+                .write(
+                    &vec![byte; 2 * BLAKE3_CHUNK_SIZE],
+                    CompressionAlgorithm::Uncompressed,
+                )
+                .unwrap();
+            roots.push(putter.finalize().await.unwrap());
+        }
+        // Then: the first object is evicted entirely instead of leaving its tree behind.
+        assert!(!blockstore.contains(&roots[0]));
+        assert!(blockstore.incomplete_objects().is_empty());
+        // Then: the second object is stored completely.
+        assert!(blockstore.contains_complete(&roots[1]));
+    }
+
+    #[test]
+    async fn test_cache_ttl_expires_untouched_blocks() {
+        // Given: a block store that expires objects which are not accessed for 200ms, on a clock
+        // that we move manually.
+        let now = Arc::new(Mutex::new(Instant::now()));
+        let clock = now.clone();
+        let blockstore = MemoryBlockStore::init(Config {
+            cache_ttl: Some(Duration::from_millis(200)),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .with_clock(move || *clock.lock().unwrap());
+        let advance = |duration| *now.lock().unwrap() += duration;
+        // Given: we put three objects in the block store, and pin the last one.
+        let mut hashes = Vec::new();
+        let mut roots = Vec::new();
+        for byte in [0, 1, 2] {
+            let content = [byte; BLAKE3_CHUNK_SIZE];
+            let mut putter = blockstore.put(None);
+            putter
+                .write(&content, CompressionAlgorithm::Uncompressed)
+                .unwrap();
+            roots.push(putter.finalize().await.unwrap());
+            let mut block = BlockHasher::new();
+            block.set_block(0);
+            block.update(&content);
+            hashes.push(block.finalize(false));
+        }
+        blockstore.pin(&roots[2]);
+        // When: only the second object is accessed within the TTL.
+        advance(Duration::from_millis(150));
+        assert!(
+            blockstore
+                .get(0, &hashes[1], CompressionAlgoSet::new())
+                .await
+                .is_some()
+        );
+        advance(Duration::from_millis(150));
+        blockstore.sweep_expired();
+        // Then: the untouched object is gone entirely while the recently accessed one remains.
+        assert!(!blockstore.contains(&roots[0]));
+        assert!(
+            blockstore
+                .get(0, &hashes[0], CompressionAlgoSet::new())
+                .await
+                .is_none()
+        );
+        assert!(
+            blockstore
+                .get(0, &hashes[1], CompressionAlgoSet::new())
+                .await
+                .is_some()
+        );
+        // Then: the pinned object remains although it was not accessed either.
+        assert!(blockstore.contains_complete(&roots[2]));
+        // When: the pinned object is unpinned and the TTL passes without an access.
+        blockstore.unpin(&roots[2]);
+        advance(Duration::from_millis(250));
+        // Then: it expires lazily as soon as it is fetched.
+        assert!(blockstore.get_tree(&roots[2]).await.is_none());
+        assert!(!blockstore.contains(&roots[2]));
+    }
+
+    #[test]
+    async fn test_put_compressed() {
+        // Given: some content.
+        let content = create_content();
+        // Given: a block store that compresses the chunks it stores.
+        let blockstore = MemoryBlockStore::init(Config {
+            default_compression: CompressionAlgorithm::Snappy,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        // When: we put the content in the block store.
+        let mut putter = blockstore.put(None);
+        putter
+            .write(content.as_slice(), CompressionAlgorithm::Uncompressed)
+            .unwrap();
+        let root = putter.finalize().await.unwrap();
+        // Then: the root hash is computed over the uncompressed content.
+        let hash_tree = hash_tree(content.as_slice());
+        assert_eq!(root, Blake3Hash::from(hash_tree.hash));
+        // Then: the chunks are stored compressed.
+        let key = Key::chunk_key(hash_tree.tree[0], 0);
+        let block = blockstore.fetch(&key).await.unwrap();
+        match bincode::deserialize::<BlockContent>(&block).unwrap() {
+            BlockContent::Chunk(compression, stored) => {
+                assert_eq!(compression, CompressionAlgorithm::Snappy);
+                assert!(stored.len() < BLAKE3_CHUNK_SIZE);
+            },
+            BlockContent::Tree(_) => panic!("expected a chunk"),
+        }
+        // Then: we get back the identical bytes.
+        for (i, block) in content.chunks(BLAKE3_CHUNK_SIZE).enumerate() {
+            let index = i * 2 - i.count_ones() as usize;
+            let chunk = blockstore
+                .get(i as u32, &hash_tree.tree[index], CompressionAlgoSet::new())
+                .await
+                .unwrap();
+            assert_eq!(chunk.compression, CompressionAlgorithm::Uncompressed);
+            assert_eq!(chunk.content, block);
+        }
+    }
+
+    #[test]
+    async fn test_contains() {
+        // Given: some content.
+        let content = create_content();
+        // Given: a block store with the content.
+        let blockstore = MemoryBlockStore::init(Config::default()).await.unwrap();
+        let mut putter = blockstore.put(None);
+        putter
+            .write(content.as_slice(), CompressionAlgorithm::Uncompressed)
+            .unwrap();
+        let root = putter.finalize().await.unwrap();
+        // Then: the content is available.
+        assert!(blockstore.contains(&root));
+        assert!(blockstore.contains_complete(&root));
+        // Then: an unknown root is not available.
+        assert!(!blockstore.contains(&[0; 32]));
+        assert!(!blockstore.contains_complete(&[0; 32]));
+        // Given: a block store with the tree but only the first chunk of the content.
+        let mut partial = MemoryBlockStore::init(Config::default()).await.unwrap();
+        let tree_key = Key::tree_key(root);
+        partial
+            .insert(tree_key, blockstore.fetch(&tree_key).await.unwrap())
+            .await;
+        let hash_tree = hash_tree(content.as_slice());
+        let chunk_key = Key::chunk_key(hash_tree.tree[0], 0);
+        partial
+            .insert(chunk_key, blockstore.fetch(&chunk_key).await.unwrap())
+            .await;
+        // Then: the content is known but not fully available.
+        assert!(partial.contains(&root));
+        assert!(!partial.contains_complete(&root));
+    }
+
+    /// A store that counts the blocks written to the underlying in-memory store.
+    #[derive(Clone)]
+    struct CountingStore {
+        inner: MemoryBlockStore,
+        inserts: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Store for CountingStore {
+        async fn fetch(&self, key: &Key) -> Option<Block> {
+            self.inner.fetch(key).await
+        }
+
+        async fn insert(&mut self, key: Key, block: Block) {
+            self.inserts.fetch_add(1, Ordering::Relaxed);
+            self.inner.insert(key, block).await
+        }
+
+        async fn contains_key(&self, key: &Key) -> bool {
+            self.inner.contains_key(key).await
+        }
+
+        async fn add_ref(&mut self, root: Blake3Hash) {
+            self.inner.add_ref(root).await
+        }
+    }
+
+    #[test]
+    async fn test_put_identical_content_is_deduplicated() {
+        // Given: some content.
+        let content = create_content();
+        // Given: a block store that counts the writes.
+        let blockstore = MemoryBlockStore::init(Config::default()).await.unwrap();
+        let store = CountingStore {
+            inner: blockstore.clone(),
+            inserts: Default::default(),
+        };
+        // Given: we put the content in the block store.
+        let mut putter = IncrementalPut::trust(store.clone());
+        putter
+            .write(content.as_slice(), CompressionAlgorithm::Uncompressed)
+            .unwrap();
+        let root = putter.finalize().await.unwrap();
+        let inserts = store.inserts.load(Ordering::Relaxed);
+        assert_eq!(inserts, 5);
+        assert_eq!(blockstore.ref_count(&root), 1);
+        // When: we put the same content again.
+        let mut putter = IncrementalPut::trust(store.clone());
+        putter
+            .write(content.as_slice(), CompressionAlgorithm::Uncompressed)
+            .unwrap();
+        // Then: the same root is returned without writing anything and the count is incremented.
+        assert_eq!(putter.finalize().await.unwrap(), root);
+        assert_eq!(store.inserts.load(Ordering::Relaxed), inserts);
+        assert_eq!(blockstore.ref_count(&root), 2);
+        assert!(blockstore.contains_complete(&root));
+    }
+
+    #[test]
+    async fn test_put_reports_progress_per_block() {
+        // Given: some content.
+        let content = create_content();
+        // Given: a block store.
+        let blockstore = MemoryBlockStore::init(Config::default()).await.unwrap();
+        // When: we put the content with a progress callback.
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let mut putter = blockstore.put(None).with_progress({
+            let reported = reported.clone();
+            move |written, total| reported.lock().unwrap().push((written, total))
+        });
+        putter
+            .write(content.as_slice(), CompressionAlgorithm::Uncompressed)
+            .unwrap();
+        putter.finalize().await.unwrap();
+        // Then: the callback is called once per block.
+        assert_eq!(
+            *reported.lock().unwrap(),
+            vec![(1, 4), (2, 4), (3, 4), (4, 4)]
+        );
+    }
+
+    #[test]
+    async fn test_put_verify_cancelled_stores_nothing() {
+        // Given: some content and its tree.
+        let content = create_content();
+        let hash_tree = hash_tree(content.as_slice());
+        // Given: a block store.
+        let blockstore = MemoryBlockStore::init(Config::default()).await.unwrap();
+        // Given: a putter that verified all of the content.
+        let mut putter = blockstore.put(Some(Blake3Hash::from(hash_tree.hash)));
+        for (i, block) in content.chunks(BLAKE3_CHUNK_SIZE).enumerate() {
+            let proof = new_proof(&hash_tree.tree, i);
+            putter.feed_proof(proof.as_slice()).unwrap();
+            putter
+                .write(block, CompressionAlgorithm::Uncompressed)
+                .unwrap();
+        }
+        // When: the put is cancelled before it is finalized.
+        let cancellation = putter.cancellation();
+        cancellation.cancel();
+        // Then: no more content is accepted and the putter fails to finalize.
+        assert!(matches!(
+            putter.write(&[0; 10], CompressionAlgorithm::Uncompressed),
+            Err(PutWriteError::Cancelled)
+        ));
+        assert!(matches!(
+            putter.finalize().await,
+            Err(PutFinalizeError::Cancelled)
+        ));
+        // Then: none of the blocks were committed to the store.
+        for (count, chunk) in content.chunks(BLAKE3_CHUNK_SIZE).enumerate() {
+            let mut block = BlockHasher::new();
+            block.set_block(count);
+            block.update(chunk);
+            let hash = block.finalize(false);
+            assert!(
+                blockstore
+                    .get(count as u32, &hash, CompressionAlgoSet::new())
+                    .await
+                    .is_none()
+            );
+        }
+    }
+
+    #[test]
+    async fn test_availability_digest() {
+        // Given: some content.
+        let content = create_content();
+        let hash_tree = hash_tree(content.as_slice());
+        // Given: a block store with the content.
+        let blockstore = MemoryBlockStore::init(Config::default()).await.unwrap();
+        let mut putter = blockstore.put(None);
+        putter
+            .write(content.as_slice(), CompressionAlgorithm::Uncompressed)
+            .unwrap();
+        let root = putter.finalize().await.unwrap();
+        // Given: two block stores with the tree and the same chunks of the content.
+        let tree_key = Key::tree_key(root);
+        let mut first = MemoryBlockStore::init(Config::default()).await.unwrap();
+        let mut second = MemoryBlockStore::init(Config::default()).await.unwrap();
+        for store in [&mut first, &mut second] {
+            store
+                .insert(tree_key, blockstore.fetch(&tree_key).await.unwrap())
+                .await;
+            for block in [0, 2] {
+                let chunk_key = Key::chunk_key(hash_tree.tree[leaf_index(block)], block as u32);
+                store
+                    .insert(chunk_key, blockstore.fetch(&chunk_key).await.unwrap())
+                    .await;
+            }
+        }
+        // Then: both stores advertise the same blocks.
+        assert_eq!(first.availability_bitmap(&root), Some(vec![0b0101]));
+        assert_eq!(
+            first.availability_digest(&root),
+            second.availability_digest(&root)
+        );
+        // When: one of the stores gets another chunk.
+        let chunk_key = Key::chunk_key(hash_tree.tree[leaf_index(1)], 1);
+        second
+            .insert(chunk_key, blockstore.fetch(&chunk_key).await.unwrap())
+            .await;
+        // Then: the digests differ.
+        assert_eq!(second.availability_bitmap(&root), Some(vec![0b0111]));
+        assert_ne!(
+            first.availability_digest(&root),
+            second.availability_digest(&root)
+        );
+        // Then: the store with all of the content advertises every block.
+        assert_eq!(blockstore.availability_bitmap(&root), Some(vec![0b1111]));
+        // Then: nothing is advertised for an unknown root.
+        assert_eq!(blockstore.availability_digest(&[0; 32]), None);
+        assert_eq!(blockstore.availability_bitmap(&[0; 32]), None);
+    }
+
+    /// Put the content in the block store and overwrite the stored copy of its second chunk with
+    /// garbage. Returns the hash of the corrupted chunk.
+    async fn put_and_corrupt_chunk(
+        blockstore: &mut MemoryBlockStore,
+        content: &[u8],
+    ) -> Blake3Hash {
+        let mut putter = blockstore.put(None);
+        putter
+            .write(content, CompressionAlgorithm::Uncompressed)
+            .unwrap();
+        putter.finalize().await.unwrap();
+        let hash = hash_tree(content).tree[leaf_index(1)];
+        let corrupted = bincode::serialize(&BlockContent::Chunk(
+            CompressionAlgorithm::Uncompressed,
+            vec![0xff; BLAKE3_CHUNK_SIZE],
+        ))
+        .unwrap();
+        blockstore.insert(Key::chunk_key(hash, 1), corrupted).await;
+        hash
+    }
+
+    #[test]
+    async fn test_get_repairs_corrupted_chunk() {
+        // Given: some content.
+        let content = create_content();
+        let chunk = content[BLAKE3_CHUNK_SIZE..2 * BLAKE3_CHUNK_SIZE].to_vec();
+        // Given: a block store that repairs chunks with the original content.
+        let repairs = Arc::new(AtomicUsize::new(0));
+        let mut blockstore = MemoryBlockStore::init(Config::default())
+            .await
+            .unwrap()
+            .with_repair({
+                let repairs = repairs.clone();
+                move |_| {
+                    repairs.fetch_add(1, Ordering::Relaxed);
+                    Some(chunk.clone())
+                }
+            });
+        // Given: a chunk of the content is corrupted on disk.
+        let hash = put_and_corrupt_chunk(&mut blockstore, &content).await;
+        // When: we get the corrupted chunk.
+        let chunk_from_store = blockstore
+            .get(1, &hash, CompressionAlgoSet::new())
+            .await
+            .unwrap();
+        // Then: the repaired content is returned.
+        assert_eq!(
+            chunk_from_store.content,
+            &content[BLAKE3_CHUNK_SIZE..2 * BLAKE3_CHUNK_SIZE]
+        );
+        assert_eq!(repairs.load(Ordering::Relaxed), 1);
+        // Then: the repaired chunk was stored again and is not repaired a second time.
+        drop(chunk_from_store);
+        assert!(
+            blockstore
+                .get(1, &hash, CompressionAlgoSet::new())
+                .await
+                .is_some()
+        );
+        assert_eq!(repairs.load(Ordering::Relaxed), 1);
+        assert!(
+            blockstore
+                .read_content(&Blake3Hash::from(hash_tree(&content).hash))
+                .await
+                .is_some()
+        );
+    }
+
+    #[test]
+    async fn test_get_corrupted_chunk_without_repair() {
+        // Given: some content.
+        let content = create_content();
+        // Given: a block store that can not repair chunks.
+        let mut blockstore = MemoryBlockStore::init(Config::default()).await.unwrap();
+        // Given: a chunk of the content is corrupted on disk.
+        let hash = put_and_corrupt_chunk(&mut blockstore, &content).await;
+        // When: we get the corrupted chunk.
+        // Then: the corrupted bytes are not returned.
+        assert!(
+            blockstore
+                .get(1, &hash, CompressionAlgoSet::new())
+                .await
+                .is_none()
+        );
+        // Then: the other chunks are still served.
+        let hash_tree = hash_tree(&content);
+        assert!(
+            blockstore
+                .get(0, &hash_tree.tree[0], CompressionAlgoSet::new())
+                .await
+                .is_some()
+        );
+        // Then: reading the entire content fails at the corrupted chunk.
+        let mut reader = blockstore
+            .read_content(&Blake3Hash::from(hash_tree.hash))
+            .await
+            .unwrap();
+        let mut buffer = Vec::new();
+        assert!(reader.read_to_end(&mut buffer).await.is_err());
+        assert_eq!(buffer, &content[..BLAKE3_CHUNK_SIZE]);
+    }
+
+    #[test]
+    async fn test_incomplete_objects() {
+        // Given: some content.
+        let content = create_content();
+        let hash_tree = hash_tree(content.as_slice());
+        let blockstore = MemoryBlockStore::init(Config::default()).await.unwrap();
+        let mut putter = blockstore.put(None);
+        putter
+            .write(content.as_slice(), CompressionAlgorithm::Uncompressed)
+            .unwrap();
+        let root = putter.finalize().await.unwrap();
+        // Given: a block store with the tree and every chunk except the one in the middle.
+        let tree_key = Key::tree_key(root);
+        let mut partial = MemoryBlockStore::init(Config::default()).await.unwrap();
+        partial
+            .insert(tree_key, blockstore.fetch(&tree_key).await.unwrap())
+            .await;
+        for block in [0, 1, 3] {
+            let chunk_key = Key::chunk_key(hash_tree.tree[leaf_index(block)], block as u32);
+            partial
+                .insert(chunk_key, blockstore.fetch(&chunk_key).await.unwrap())
+                .await;
+        }
+        // Then: the object is reported as incomplete with the missing block.
+        assert_eq!(partial.incomplete_objects(), vec![(root, vec![2])]);
+        // Then: the store with all of the content has no incomplete objects.
+        assert!(blockstore.incomplete_objects().is_empty());
+        // When: the missing chunk is stored.
+        let chunk_key = Key::chunk_key(hash_tree.tree[leaf_index(2)], 2);
+        partial
+            .insert(chunk_key, blockstore.fetch(&chunk_key).await.unwrap())
+            .await;
+        // Then: the object is complete.
+        assert!(partial.incomplete_objects().is_empty());
+    }
+
+    #[test]
+    async fn test_variant_cache() {
+        // Given: some content.
+        let content = create_content();
+        let hash_tree = hash_tree(content.as_slice());
+        // Given: a block store with a variant cache, and one without.
+        let cached = MemoryBlockStore::init(Config {
+            variant_cache_capacity: Some(4 * BLAKE3_CHUNK_SIZE),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let uncached = MemoryBlockStore::init(Config::default()).await.unwrap();
+        // Given: we put the content in both block stores.
+        for blockstore in [&cached, &uncached] {
+            let mut putter = blockstore.put(None);
+            putter
+                .write(content.as_slice(), CompressionAlgorithm::Uncompressed)
+                .unwrap();
+            putter.finalize().await.unwrap();
+        }
+        let gzip = CompressionAlgoSet::from_algos(&[CompressionAlgorithm::Gzip]);
+        let compress_calls = || compression::COMPRESS_CALLS.with(|calls| calls.get());
+        // When: we request the first chunk compressed with gzip.
+        let before = compress_calls();
+        let first = cached.get(0, &hash_tree.tree[0], gzip).await.unwrap();
+        // Then: the chunk is compressed once.
+        assert_eq!(compress_calls(), before + 1);
+        assert_eq!(first.compression, CompressionAlgorithm::Gzip);
+        assert_eq!(
+            compression::decompress(CompressionAlgorithm::Gzip, &first.content).unwrap(),
+            content[..BLAKE3_CHUNK_SIZE]
+        );
+        // When: we request the same variant again.
+        let second = cached.get(0, &hash_tree.tree[0], gzip).await.unwrap();
+        // Then: it is served from the cache without compressing it again.
+        assert_eq!(compress_calls(), before + 1);
+        assert!(Arc::ptr_eq(&first, &second));
+        // Then: the uncompressed chunk is still served as is.
+        let chunk = cached
+            .get(0, &hash_tree.tree[0], CompressionAlgoSet::new())
+            .await
+            .unwrap();
+        assert_eq!(chunk.compression, CompressionAlgorithm::Uncompressed);
+        assert_eq!(chunk.content, content[..BLAKE3_CHUNK_SIZE]);
+        // Then: without a variant cache every request is compressed.
+        let before = compress_calls();
+        for _ in 0..2 {
+            uncached.get(0, &hash_tree.tree[0], gzip).await.unwrap();
+        }
+        assert_eq!(compress_calls(), before + 2);
+    }
+
+    fn encode_stream(content: &[u8]) -> (Blake3Hash, Vec<u8>) {
+        let hash_tree = hash_tree(content);
+        let root = Blake3Hash::from(hash_tree.hash);
+        let mut stream = Vec::new();
+        let mut encoder = Encoder::new(&mut stream, content.len(), hash_tree).unwrap();
+        encoder.write_all(content).unwrap();
+        encoder.flush().unwrap();
+        (root, stream)
+    }
+
+    #[test]
+    async fn test_variant_cache_evicts_least_recently_used() {
+        // Given: a variant cache that can hold two variants.
+        let mut cache = VariantCache::new(20);
+        let variant = || {
+            Arc::new(ContentChunk {
+                compression: CompressionAlgorithm::Gzip,
+                content: vec![0; 10],
+            })
+        };
+        let keys = [0, 1, 2].map(|byte| Key::chunk_key([byte; 32], 0));
+        cache.insert(keys[0], variant());
+        cache.insert(keys[1], variant());
+        // When: we access the first variant and then cache a third one.
+        assert!(cache.get(&keys[0], CompressionAlgorithm::Gzip).is_some());
+        cache.insert(keys[2], variant());
+        // Then: the least recently used variant is evicted.
+        assert!(cache.get(&keys[0], CompressionAlgorithm::Gzip).is_some());
+        assert!(cache.get(&keys[1], CompressionAlgorithm::Gzip).is_none());
+        assert!(cache.get(&keys[2], CompressionAlgorithm::Gzip).is_some());
+        // Then: a variant of another compression is cached separately.
+        assert!(cache.get(&keys[0], CompressionAlgorithm::Snappy).is_none());
+    }
+
+    #[test]
+    async fn test_put_verified_stream() {
+        // Given: some content whose last block is smaller than a Blake3 chunk.
+        let mut content = create_content();
+        content.extend_from_slice(&[7; 100]);
+        // Given: the content encoded as a stream.
+        let (root, stream) = encode_stream(&content);
+        // Given: a block store.
+        let blockstore = MemoryBlockStore::init(Config::default()).await.unwrap();
+        // When: we store the stream.
+        let stored = blockstore
+            .put_verified_stream(root, stream.as_slice())
+            .await
+            .unwrap();
+        // Then: the root of the content is returned.
+        assert_eq!(stored, root);
+        // Then: the tree and every chunk are stored.
+        assert_eq!(
+            blockstore.get_tree(&root).await.unwrap().0,
+            hash_tree(&content).tree
+        );
+        assert!(blockstore.contains_complete(&root));
+        // Then: we can read back the original content.
+        let mut reader = blockstore.read_content(&root).await.unwrap();
+        let mut buffer = Vec::new();
+        reader.read_to_end(&mut buffer).await.unwrap();
+        assert_eq!(buffer, content);
+    }
+
+    #[test]
+    async fn test_put_verified_stream_truncated_stores_nothing() {
+        // Given: some content encoded as a stream.
+        let content = create_content();
+        let (root, stream) = encode_stream(&content);
+        // Given: a block store.
+        let blockstore = MemoryBlockStore::init(Config::default()).await.unwrap();
+        // When: we store the stream without its last bytes.
+        let result = blockstore
+            .put_verified_stream(root, &stream[..stream.len() - 10])
+            .await;
+        // Then: the put fails.
+        assert!(result.is_err());
+        // Then: neither the tree nor any of the chunks were stored.
+        assert!(blockstore.get_tree(&root).await.is_none());
+        assert!(!blockstore.contains(&root));
+        for (count, chunk) in content.chunks(BLAKE3_CHUNK_SIZE).enumerate() {
+            let mut block = BlockHasher::new();
+            block.set_block(count);
+            block.update(chunk);
+            let hash = block.finalize(false);
+            assert!(
+                blockstore
+                    .get(count as u32, &hash, CompressionAlgoSet::new())
+                    .await
+                    .is_none()
+            );
+        }
+        // Then: a stream that ends on a frame boundary is rejected as well.
+        let result = blockstore
+            .put_verified_stream(root, &stream[..stream.len() - BLAKE3_CHUNK_SIZE])
+            .await;
+        assert!(result.is_err());
+        assert!(blockstore.get_tree(&root).await.is_none());
+    }
+
+    #[test]
+    async fn test_put_verified_stream_rejects_tampered_block() {
+        // Given: some content encoded as a stream, with a byte of the last block flipped.
+        let content = create_content();
+        let (root, mut stream) = encode_stream(&content);
+        let last = stream.len() - 1;
+        stream[last] ^= 1;
+        // Given: a block store.
+        let blockstore = MemoryBlockStore::init(Config::default()).await.unwrap();
+        // When: we store the stream from an async reader.
+        let (mut writer, reader) = tokio::io::duplex(1024);
+        let write = tokio::spawn(async move {
+            writer.write_all(&stream).await.ok();
+        });
+        let result = blockstore.put_verified_stream(root, reader).await;
+        write.await.unwrap();
+        // Then: the put fails and nothing was stored.
+        assert!(result.is_err());
+        assert!(blockstore.get_tree(&root).await.is_none());
+        assert!(!blockstore.contains(&root));
+    }
 }
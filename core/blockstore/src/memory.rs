@@ -1,17 +1,548 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
 
 use async_trait::async_trait;
+use blake3_tree::blake3::{self, tree::BlockHasher};
 use lightning_interfaces::{
     types::{CompressionAlgoSet, CompressionAlgorithm},
     Blake3Hash, Blake3Tree, BlockStoreInterface, ConfigConsumer, ContentChunk,
 };
-use parking_lot::RwLock;
+use log::error;
+use parking_lot::{Mutex, RwLock};
+use tokio::io::AsyncRead;
 
-use crate::{config::Config, put::IncrementalPut, store::Store, Block, BlockContent, Key};
+use crate::{
+    compression,
+    config::Config,
+    put::{self, IncrementalPut},
+    read::ContentReader,
+    store::Store,
+    variant::VariantCache,
+    Block, BlockContent, Key,
+};
 
+/// An in-memory block store.
+///
+/// If a capacity is configured, the least recently used objects are evicted once the stored bytes
+/// exceed it. An object is evicted along with all of its chunks, so it is never left partially
+/// stored. The chunks and trees returned from `get` and `get_tree` are shared: as long as a
+/// returned [`Arc`] is alive, the same instance is handed out to other callers and the underlying
+/// block is never evicted.
+///
+/// If a cache TTL is configured, objects that were not accessed for longer than the TTL expire
+/// independently of the capacity. Expired objects are dropped lazily when they are fetched, and
+/// swept at most once per TTL while new blocks are inserted, see
+/// [`MemoryBlockStore::sweep_expired`].
+///
+/// Pinned objects are never evicted nor expired, see [`MemoryBlockStore::pin`].
+///
+/// Chunks are verified against their hash when they are read. A corrupted chunk is fetched
+/// again through the repair function if one is set, see [`MemoryBlockStore::with_repair`].
+///
+/// A chunk requested with a compression is compressed with the preferred algorithm the caller
+/// accepts. If a variant cache is configured the compressed chunks are kept in it, so repeated
+/// requests for the same compression are not compressed again, see
+/// [`Config::variant_cache_capacity`].
 #[derive(Clone, Default)]
 pub struct MemoryBlockStore {
-    inner: Arc<RwLock<HashMap<Key, Block>>>,
+    inner: Arc<RwLock<Inner>>,
+    capacity: Option<usize>,
+    compression: CompressionAlgorithm,
+    repair: Option<RepairFn>,
+    variants: Option<Arc<Mutex<VariantCache>>>,
+}
+
+/// Fetches the uncompressed content of the chunk with the given hash from elsewhere, usually from
+/// a peer, to replace a corrupted copy.
+type RepairFn = Arc<dyn Fn(Blake3Hash) -> Option<Vec<u8>> + Send + Sync>;
+
+/// Returns the current time, used to expire the objects.
+type ClockFn = Arc<dyn Fn() -> Instant + Send + Sync>;
+
+#[derive(Default)]
+struct Inner {
+    blocks: HashMap<Key, Entry>,
+    /// The roots of the stored objects that each chunk belongs to. A chunk that does not belong
+    /// to any stored object, such as the chunk of an object that is still being put, is evicted
+    /// on its own.
+    owners: HashMap<Key, Vec<Blake3Hash>>,
+    /// The units of eviction by their last access: the tree key of a stored object, which stands
+    /// for the tree along with its chunks, or the key of a chunk without an owner.
+    lru: BTreeMap<u64, Key>,
+    /// The handles to the trees and chunks that were handed out to callers.
+    handles: HashMap<Key, Handle>,
+    /// Total number of bytes stored.
+    size: usize,
+    /// Logical clock used to track the last access of each unit of eviction.
+    tick: u64,
+    /// The number of times each root was put in the store.
+    refs: HashMap<Blake3Hash, usize>,
+    /// The roots of the objects that are never evicted.
+    pinned: HashSet<Blake3Hash>,
+    /// The time after which a unit that was not accessed expires.
+    ttl: Option<Duration>,
+    /// The time at which the expired units are swept next while blocks are inserted.
+    next_sweep: Option<Instant>,
+    clock: Option<ClockFn>,
+}
+
+struct Entry {
+    block: Block,
+    /// The keys of the chunks of the object, if the block is a tree.
+    chunks: Vec<Key>,
+    /// The last access of the block, only tracked for the units of eviction.
+    last_access: u64,
+    /// The wall clock time of the last access, used for the expiry.
+    accessed_at: Instant,
+}
+
+enum Handle {
+    Tree(Weak<Blake3Tree>),
+    Chunk(Weak<ContentChunk>),
+}
+
+impl Handle {
+    fn is_alive(&self) -> bool {
+        match self {
+            Handle::Tree(tree) => tree.strong_count() > 0,
+            Handle::Chunk(chunk) => chunk.strong_count() > 0,
+        }
+    }
+}
+
+impl Inner {
+    fn now(&self) -> Instant {
+        self.clock
+            .as_ref()
+            .map_or_else(Instant::now, |clock| clock())
+    }
+
+    fn fetch(&mut self, key: &Key) -> Option<Block> {
+        self.touch(key).then(|| self.blocks[key].block.clone())
+    }
+
+    /// Mark the block with the given key as accessed, returns false if the block is not stored
+    /// or has expired.
+    fn touch(&mut self, key: &Key) -> bool {
+        let now = self.now();
+        if !self.blocks.contains_key(key) {
+            return false;
+        }
+        for unit in self.units(key) {
+            if self.is_expired(&unit, now) && !self.is_held(&unit) {
+                self.remove_unit(&unit);
+                continue;
+            }
+            self.bump(unit, now);
+        }
+        self.blocks.contains_key(key)
+    }
+
+    fn is_expired(&self, unit: &Key, now: Instant) -> bool {
+        self.ttl.map_or(false, |ttl| {
+            now.duration_since(self.blocks[unit].accessed_at) > ttl
+        })
+    }
+
+    /// Returns the units of eviction that the block with the given key belongs to.
+    fn units(&self, key: &Key) -> Vec<Key> {
+        match self.owners.get(key) {
+            Some(roots) => roots.iter().map(|root| Key::tree_key(*root)).collect(),
+            None => vec![*key],
+        }
+    }
+
+    /// Move the unit of eviction with the given key to the back of the LRU order.
+    fn bump(&mut self, unit: Key, now: Instant) {
+        let Some(entry) = self.blocks.get_mut(&unit) else {
+            return;
+        };
+        self.lru.remove(&entry.last_access);
+        self.tick += 1;
+        entry.last_access = self.tick;
+        entry.accessed_at = now;
+        self.lru.insert(self.tick, unit);
+    }
+
+    fn insert(&mut self, key: Key, block: Block) {
+        let now = self.now();
+        self.size += block.len();
+        if let Some(entry) = self.blocks.get_mut(&key) {
+            self.size -= entry.block.len();
+            entry.block = block;
+        } else {
+            let chunks = match key.1 {
+                None => chunk_keys(&block),
+                Some(_) => Vec::new(),
+            };
+            // The chunks of a stored object are evicted along with its tree.
+            for chunk in &chunks {
+                let roots = self.owners.entry(*chunk).or_default();
+                if roots.is_empty() {
+                    if let Some(entry) = self.blocks.get(chunk) {
+                        self.lru.remove(&entry.last_access);
+                    }
+                }
+                roots.push(key.0);
+            }
+            let entry = Entry {
+                block,
+                chunks,
+                last_access: 0,
+                accessed_at: now,
+            };
+            self.blocks.insert(key, entry);
+        }
+
+        for unit in self.units(&key) {
+            self.bump(unit, now);
+        }
+    }
+
+    fn remove(&mut self, key: &Key) -> Option<Entry> {
+        let entry = self.blocks.remove(key)?;
+        self.size -= entry.block.len();
+        self.handles.remove(key);
+        Some(entry)
+    }
+
+    /// Remove the unit of eviction with the given key: a tree along with the chunks that do not
+    /// belong to any other stored object, or a single chunk.
+    fn remove_unit(&mut self, unit: &Key) {
+        let Some(entry) = self.remove(unit) else {
+            return;
+        };
+        self.lru.remove(&entry.last_access);
+        for chunk in entry.chunks {
+            let Some(roots) = self.owners.get_mut(&chunk) else {
+                continue;
+            };
+            roots.retain(|root| *root != unit.0);
+            if roots.is_empty() {
+                self.owners.remove(&chunk);
+                self.remove(&chunk);
+            }
+        }
+    }
+
+    /// Returns true if the unit of eviction with the given key must be kept, because it is a
+    /// pinned object or because a caller holds its block or any of the chunks of the object.
+    fn is_held(&self, unit: &Key) -> bool {
+        let alive = |key: &Key| self.handles.get(key).map_or(false, Handle::is_alive);
+        (unit.1.is_none() && self.pinned.contains(&unit.0))
+            || alive(unit)
+            || self
+                .blocks
+                .get(unit)
+                .map_or(false, |entry| entry.chunks.iter().any(|chunk| alive(chunk)))
+    }
+
+    /// Evict the units that are not held and were not accessed for longer than the TTL.
+    fn expire(&mut self) {
+        let now = self.now();
+        let mut cursor = 0;
+        // The units are ordered by their last access, so the walk stops at the first fresh one.
+        while let Some((&tick, &unit)) = self.lru.range(cursor..).next() {
+            if !self.is_expired(&unit, now) {
+                break;
+            }
+            cursor = tick + 1;
+            if !self.is_held(&unit) {
+                self.remove_unit(&unit);
+            }
+        }
+        self.next_sweep = self.ttl.map(|ttl| now + ttl);
+    }
+
+    /// Sweep the expired units if the TTL passed since the last sweep.
+    fn maybe_expire(&mut self) {
+        if self.ttl.is_some() && self.next_sweep.map_or(true, |next| self.now() >= next) {
+            self.expire();
+        }
+    }
+
+    /// Evict the least recently used units that are not held until the
+    /// stored bytes fit in the capacity, or there is nothing left to evict. An object is always
+    /// evicted as a whole.
+    fn evict(&mut self, capacity: usize) {
+        let mut cursor = 0;
+        while self.size > capacity {
+            let Some((&tick, &unit)) = self.lru.range(cursor..).next() else {
+                break;
+            };
+            cursor = tick + 1;
+            if !self.is_held(&unit) {
+                self.remove_unit(&unit);
+            }
+        }
+    }
+}
+
+/// Returns the keys of the chunks of the serialized tree.
+fn chunk_keys(block: &[u8]) -> Vec<Key> {
+    match bincode::deserialize::<BlockContent>(block)
+        .expect("Stored content to be serialized properly")
+    {
+        BlockContent::Tree(tree) => (0..(tree.len() + 1) / 2)
+            .map(|block| Key::chunk_key(tree[leaf_index(block)], block as u32))
+            .collect(),
+        BlockContent::Chunk(..) => Vec::new(),
+    }
+}
+
+impl MemoryBlockStore {
+    /// Set the function used to fetch the correct content of a chunk whose stored copy does not
+    /// match its hash. The repaired chunk is stored again and returned from `get`.
+    ///
+    /// Without a repair function a corrupted chunk is reported as missing.
+    pub fn with_repair(
+        mut self,
+        repair: impl Fn(Blake3Hash) -> Option<Vec<u8>> + Send + Sync + 'static,
+    ) -> Self {
+        self.repair = Some(Arc::new(repair));
+        self
+    }
+
+    /// Set the source of the current time used to expire the objects, which is the system clock
+    /// by default.
+    pub fn with_clock(self, clock: impl Fn() -> Instant + Send + Sync + 'static) -> Self {
+        self.inner.write().clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Evict the objects that were not accessed for longer than the configured cache TTL,
+    /// skipping the pinned ones and the ones that are still referenced by a caller. Does nothing
+    /// if no TTL is configured.
+    ///
+    /// This already runs at most once per TTL while blocks are inserted, it can be called
+    /// periodically to also expire objects while nothing new is stored.
+    pub fn sweep_expired(&self) {
+        self.inner.write().expire();
+    }
+
+    /// Pin the object with the given root hash, so it is never evicted nor expired until it is
+    /// unpinned. The object does not need to be stored yet.
+    pub fn pin(&self, cid: &Blake3Hash) {
+        self.inner.write().pinned.insert(*cid);
+    }
+
+    /// Unpin the object with the given root hash, so it is cached like any other object again.
+    pub fn unpin(&self, cid: &Blake3Hash) {
+        self.inner.write().pinned.remove(cid);
+    }
+
+    /// Returns true if the tree of the object with the given root hash is stored. This does not
+    /// check the chunks, see [`MemoryBlockStore::contains_complete`].
+    ///
+    /// Unlike [`BlockStoreInterface::get_tree`] this does not deserialize the tree and does not
+    /// count as an access for the eviction.
+    pub fn contains(&self, cid: &Blake3Hash) -> bool {
+        self.inner.read().blocks.contains_key(&Key::tree_key(*cid))
+    }
+
+    /// Returns the number of times the object with the given root hash was put in the store.
+    /// Putting content that is already stored only increments this count.
+    pub fn ref_count(&self, cid: &Blake3Hash) -> usize {
+        self.inner.read().refs.get(cid).copied().unwrap_or(0)
+    }
+
+    /// Returns true if the tree and every chunk of the object with the given root hash are
+    /// stored, so the entire content can be served.
+    pub fn contains_complete(&self, cid: &Blake3Hash) -> bool {
+        self.held_blocks(cid)
+            .map_or(false, |held| held.into_iter().all(|held| held))
+    }
+
+    /// Returns a bitmap of the blocks of the object with the given root hash that are stored, or
+    /// `None` if the tree of the object is not stored. Bit `i % 8` of byte `i / 8` is set if block
+    /// `i` is stored.
+    pub fn availability_bitmap(&self, cid: &Blake3Hash) -> Option<Vec<u8>> {
+        let held = self.held_blocks(cid)?;
+        let mut bitmap = vec![0; (held.len() + 7) / 8];
+        for (block, _) in held.into_iter().enumerate().filter(|(_, held)| *held) {
+            bitmap[block / 8] |= 1 << (block % 8);
+        }
+        Some(bitmap)
+    }
+
+    /// Returns a digest of the set of blocks of the object with the given root hash that are
+    /// stored, or `None` if the tree of the object is not stored.
+    ///
+    /// Two stores holding the same blocks of an object produce the same digest, so peers can
+    /// compare digests and only exchange the [`MemoryBlockStore::availability_bitmap`] when they
+    /// differ.
+    pub fn availability_digest(&self, cid: &Blake3Hash) -> Option<[u8; 32]> {
+        let held = self.held_blocks(cid)?;
+        let mut hasher = blake3::Hasher::new();
+        for (block, _) in held.into_iter().enumerate().filter(|(_, held)| *held) {
+            hasher.update(&(block as u32).to_be_bytes());
+        }
+        Some(*hasher.finalize().as_bytes())
+    }
+
+    /// Returns the objects whose tree is stored but that are missing some of their chunks, with
+    /// the indices of the missing blocks, for example the content that was partially downloaded
+    /// before a restart.
+    ///
+    /// The objects that are missing the fewest blocks come first, since they are the cheapest to
+    /// complete.
+    pub fn incomplete_objects(&self) -> Vec<(Blake3Hash, Vec<usize>)> {
+        let roots = self
+            .inner
+            .read()
+            .blocks
+            .keys()
+            .filter(|key| key.1.is_none())
+            .map(|key| key.0)
+            .collect::<Vec<_>>();
+
+        let mut incomplete = roots
+            .into_iter()
+            .filter_map(|root| {
+                let missing = self
+                    .held_blocks(&root)?
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(_, held)| !held)
+                    .map(|(block, _)| block)
+                    .collect::<Vec<_>>();
+                (!missing.is_empty()).then_some((root, missing))
+            })
+            .collect::<Vec<_>>();
+        incomplete
+            .sort_by(|(a_root, a), (b_root, b)| a.len().cmp(&b.len()).then(a_root.cmp(b_root)));
+        incomplete
+    }
+
+    /// Returns whether each block of the object with the given root hash is stored, in order, or
+    /// `None` if the tree of the object is not stored.
+    fn held_blocks(&self, cid: &Blake3Hash) -> Option<Vec<bool>> {
+        let inner = self.inner.read();
+        let entry = inner.blocks.get(&Key::tree_key(*cid))?;
+        let tree = match bincode::deserialize::<BlockContent>(entry.block.as_slice())
+            .expect("Stored content to be serialized properly")
+        {
+            BlockContent::Tree(tree) => tree,
+            _ => return None,
+        };
+
+        let num_blocks = (tree.len() + 1) / 2;
+        let held = (0..num_blocks)
+            .map(|block| {
+                inner
+                    .blocks
+                    .contains_key(&Key::chunk_key(tree[leaf_index(block)], block as u32))
+            })
+            .collect();
+        Some(held)
+    }
+
+    /// Returns a reader over the entire content of the object with the given root hash, or `None`
+    /// if the tree is not stored.
+    ///
+    /// The chunks are fetched lazily as the content is read, reading fails if one of them is
+    /// missing. The reader holds on to the chunk it is reading, so it can not be evicted meanwhile.
+    pub async fn read_content(&self, cid: &Blake3Hash) -> Option<ContentReader> {
+        let tree = self.get_tree(cid).await?;
+        Some(ContentReader::new(self.clone(), tree))
+    }
+
+    /// Fetch the correct content of a corrupted chunk through the repair function and store it
+    /// in place of the corrupted copy. Returns `None` if the chunk could not be repaired.
+    async fn repair_chunk(
+        &self,
+        key: Key,
+        block_counter: u32,
+        block_hash: &Blake3Hash,
+    ) -> Option<Vec<u8>> {
+        let Some(repair) = &self.repair else {
+            error!("Chunk {block_counter} of {block_hash:?} is corrupted and can not be repaired");
+            return None;
+        };
+        let content = match repair(*block_hash) {
+            Some(content) if verify_chunk(block_counter, block_hash, &content) => content,
+            Some(_) => {
+                error!("Repaired chunk {block_counter} of {block_hash:?} does not match its hash");
+                return None;
+            },
+            None => {
+                error!("Failed to repair corrupted chunk {block_counter} of {block_hash:?}");
+                return None;
+            },
+        };
+
+        let block = bincode::serialize(&BlockContent::Chunk(
+            self.compression,
+            compression::compress(self.compression, &content).ok()?,
+        ))
+        .expect("Serializing a chunk to succeed");
+        self.clone().insert(key, block).await;
+        Some(content)
+    }
+
+    /// Returns the uncompressed content of the chunk, shared with the other callers holding it.
+    async fn get_uncompressed(
+        &self,
+        key: Key,
+        block_counter: u32,
+        block_hash: &Blake3Hash,
+    ) -> Option<Arc<ContentChunk>> {
+        let block = {
+            let mut inner = self.inner.write();
+            let block = inner.fetch(&key)?;
+
+            if let Some(Handle::Chunk(chunk)) = inner.handles.get(&key) {
+                if let Some(chunk) = chunk.upgrade() {
+                    return Some(chunk);
+                }
+            }
+            block
+        };
+
+        let content = match bincode::deserialize::<BlockContent>(block.as_slice())
+            .expect("Stored content to be serialized properly")
+        {
+            BlockContent::Chunk(compression, content) => {
+                compression::decompress(compression, &content).ok()
+            },
+            _ => return None,
+        };
+
+        let content = match content {
+            Some(content) if verify_chunk(block_counter, block_hash, &content) => content,
+            _ => self.repair_chunk(key, block_counter, block_hash).await?,
+        };
+
+        let chunk = Arc::new(ContentChunk {
+            compression: CompressionAlgorithm::Uncompressed,
+            content,
+        });
+        self.inner
+            .write()
+            .handles
+            .insert(key, Handle::Chunk(Arc::downgrade(&chunk)));
+        Some(chunk)
+    }
+}
+
+/// Returns true if the content hashes to the given chunk hash. The chunk of an object that
+/// consists of a single chunk is hashed as the root.
+fn verify_chunk(block_counter: u32, block_hash: &Blake3Hash, content: &[u8]) -> bool {
+    let hash = |is_root| {
+        let mut hasher = BlockHasher::new();
+        hasher.set_block(block_counter as usize);
+        hasher.update(content);
+        hasher.finalize(is_root)
+    };
+    hash(false) == *block_hash || (block_counter == 0 && hash(true) == *block_hash)
+}
+
+/// Returns the index of the n-th leaf in the array representation of the tree.
+pub(crate) fn leaf_index(block: usize) -> usize {
+    block * 2 - block.count_ones() as usize
 }
 
 impl ConfigConsumer for MemoryBlockStore {
@@ -24,19 +555,42 @@ impl BlockStoreInterface for MemoryBlockStore {
     type SharedPointer<T: ?Sized + Send + Sync> = Arc<T>;
     type Put = IncrementalPut<Self>;
 
-    async fn init(_: Self::Config) -> anyhow::Result<Self> {
+    async fn init(config: Self::Config) -> anyhow::Result<Self> {
         Ok(Self {
-            inner: Default::default(),
+            inner: Arc::new(RwLock::new(Inner {
+                ttl: config.cache_ttl,
+                ..Default::default()
+            })),
+            capacity: config.capacity,
+            compression: config.default_compression,
+            repair: None,
+            variants: config
+                .variant_cache_capacity
+                .map(|capacity| Arc::new(Mutex::new(VariantCache::new(capacity)))),
         })
     }
 
     async fn get_tree(&self, cid: &Blake3Hash) -> Option<Self::SharedPointer<Blake3Tree>> {
-        match bincode::deserialize::<BlockContent>(
-            self.fetch(&Key::tree_key(*cid)).await?.as_slice(),
-        )
-        .expect("Stored content to be serialized properly")
+        let key = Key::tree_key(*cid);
+        let mut inner = self.inner.write();
+        let block = inner.fetch(&key)?;
+
+        if let Some(Handle::Tree(tree)) = inner.handles.get(&key) {
+            if let Some(tree) = tree.upgrade() {
+                return Some(tree);
+            }
+        }
+
+        match bincode::deserialize::<BlockContent>(block.as_slice())
+            .expect("Stored content to be serialized properly")
         {
-            BlockContent::Tree(tree) => Some(Arc::new(Blake3Tree(tree))),
+            BlockContent::Tree(tree) => {
+                let tree = Arc::new(Blake3Tree(tree));
+                inner
+                    .handles
+                    .insert(key, Handle::Tree(Arc::downgrade(&tree)));
+                Some(tree)
+            },
             _ => None,
         }
     }
@@ -45,21 +599,37 @@ async fn get(
         &self,
         block_counter: u32,
         block_hash: &Blake3Hash,
-        _compression: CompressionAlgoSet,
+        compression: CompressionAlgoSet,
     ) -> Option<Self::SharedPointer<ContentChunk>> {
-        match bincode::deserialize::<BlockContent>(
-            self.fetch(&Key::chunk_key(*block_hash, block_counter))
-                .await?
-                .as_slice(),
-        )
-        .expect("Stored content to be serialized properly")
-        {
-            BlockContent::Chunk(content) => Some(Arc::new(ContentChunk {
-                compression: CompressionAlgorithm::Uncompressed,
-                content,
-            })),
-            _ => None,
+        let algorithm = compression::negotiate(compression);
+        let key = Key::chunk_key(*block_hash, block_counter);
+        if algorithm != CompressionAlgorithm::Uncompressed {
+            if let Some(variants) = &self.variants {
+                let cached = variants.lock().get(&key, algorithm);
+                if let Some(chunk) = cached {
+                    // Count the access to the chunk itself for the eviction.
+                    if self.inner.write().touch(&key) {
+                        return Some(chunk);
+                    }
+                }
+            }
+        }
+
+        let chunk = self
+            .get_uncompressed(key, block_counter, block_hash)
+            .await?;
+        if algorithm == CompressionAlgorithm::Uncompressed {
+            return Some(chunk);
+        }
+
+        let chunk = Arc::new(ContentChunk {
+            compression: algorithm,
+            content: compression::compress(algorithm, &chunk.content).ok()?,
+        });
+        if let Some(variants) = &self.variants {
+            variants.lock().insert(key, chunk.clone());
         }
+        Some(chunk)
     }
 
     fn put(&self, root: Option<Blake3Hash>) -> Self::Put {
@@ -67,16 +637,38 @@ fn put(&self, root: Option<Blake3Hash>) -> Self::Put {
             Some(root) => IncrementalPut::verifier(self.clone(), root),
             None => IncrementalPut::trust(self.clone()),
         }
+        .with_compression(self.compression)
+    }
+
+    async fn put_verified_stream<R: AsyncRead + Unpin + Send>(
+        &self,
+        root: Blake3Hash,
+        reader: R,
+    ) -> anyhow::Result<Blake3Hash> {
+        put::put_verified_stream(self.put(Some(root)), root, reader).await
     }
 }
 
 #[async_trait]
 impl Store for MemoryBlockStore {
     async fn fetch(&self, key: &Key) -> Option<Block> {
-        self.inner.read().get(key).cloned()
+        self.inner.write().fetch(key)
     }
 
     async fn insert(&mut self, key: Key, block: Block) {
-        self.inner.write().insert(key, block);
+        let mut inner = self.inner.write();
+        inner.insert(key, block);
+        inner.maybe_expire();
+        if let Some(capacity) = self.capacity {
+            inner.evict(capacity);
+        }
+    }
+
+    async fn contains_key(&self, key: &Key) -> bool {
+        self.inner.write().touch(key)
+    }
+
+    async fn add_ref(&mut self, root: Blake3Hash) {
+        *self.inner.write().refs.entry(root).or_default() += 1;
     }
 }
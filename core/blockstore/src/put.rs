@@ -1,15 +1,22 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use anyhow::{ensure, Context};
 use async_trait::async_trait;
 use blake3_tree::{
-    blake3::tree::{BlockHasher, HashTreeBuilder},
-    IncrementalVerifier,
+    blake3::tree::{BlockHasher, HashTreeBuilder, IV},
+    IncrementalVerifier, ProofSizeEstimator,
 };
 use bytes::{BufMut, Bytes, BytesMut};
 use lightning_interfaces::{
     types::CompressionAlgorithm, Blake3Hash, ContentChunk, IncrementalPutInterface,
     PutFeedProofError, PutFinalizeError, PutWriteError,
 };
+use tokio::io::{AsyncRead, AsyncReadExt};
 
-use crate::{store::Store, BlockContent, Key, BLAKE3_CHUNK_SIZE};
+use crate::{compression, store::Store, BlockContent, Key, BLAKE3_CHUNK_SIZE};
 
 struct Chunk {
     hash: Blake3Hash,
@@ -23,7 +30,32 @@ pub struct IncrementalPut<S> {
     store: S,
     mode: Mode,
     compression: Option<CompressionAlgorithm>,
+    /// The algorithm the chunks are compressed with when stored.
+    store_compression: CompressionAlgorithm,
     block_count: usize,
+    /// Called with the number of blocks written to the store so far and the total number of
+    /// blocks, after each block is written.
+    progress: Option<Box<dyn Fn(usize, usize) + Send + Sync>>,
+    cancellation: PutCancellation,
+}
+
+/// A handle to cancel an [`IncrementalPut`], for example from another task.
+///
+/// A put in verifier mode is all-or-nothing: if it is cancelled before it starts to commit the
+/// content nothing is written to the store, and once it started it is no longer interrupted. A put
+/// in trust mode stops writing at the next block, and since the tree is written last the partial
+/// content can't be read.
+#[derive(Clone, Default)]
+pub struct PutCancellation(Arc<AtomicBool>);
+
+impl PutCancellation {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
 }
 
 enum Mode {
@@ -69,15 +101,55 @@ fn internal_new(store: S, mode: Mode) -> Self {
             chunks: Vec::new(),
             content_buf: BytesMut::new(),
             compression: None,
+            store_compression: CompressionAlgorithm::Uncompressed,
             block_count: 0,
+            progress: None,
+            cancellation: PutCancellation::default(),
         }
     }
+
+    /// Returns true if the tree and every chunk of the content are already in the store.
+    async fn is_stored(store: &S, root: Blake3Hash, chunks: &[Chunk]) -> bool {
+        if !store.contains_key(&Key::tree_key(root)).await {
+            return false;
+        }
+        for (count, chunk) in chunks.iter().enumerate() {
+            if !store
+                .contains_key(&Key::chunk_key(chunk.hash, count as u32))
+                .await
+            {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Compress the chunks with the given algorithm when they are stored.
+    pub fn with_compression(mut self, compression: CompressionAlgorithm) -> Self {
+        self.store_compression = compression;
+        self
+    }
+
+    /// Report the progress of writing the content to the store, the callback is called with the
+    /// number of blocks written so far and the total number of blocks after each block.
+    pub fn with_progress<F>(mut self, progress: F) -> Self
+    where
+        F: Fn(usize, usize) + Send + Sync + 'static,
+    {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Returns a handle that can be used to cancel this put.
+    pub fn cancellation(&self) -> PutCancellation {
+        self.cancellation.clone()
+    }
 }
 
 #[async_trait]
 impl<S> IncrementalPutInterface for IncrementalPut<S>
 where
-    S: Store + Send,
+    S: Store + Send + Sync,
 {
     fn feed_proof(&mut self, proof: &[u8]) -> Result<(), PutFeedProofError> {
         match &mut self.mode {
@@ -101,6 +173,10 @@ fn write(
         content: &[u8],
         compression: CompressionAlgorithm,
     ) -> Result<(), PutWriteError> {
+        if self.cancellation.is_cancelled() {
+            return Err(PutWriteError::Cancelled);
+        }
+
         self.content_buf.put(content);
 
         while self.content_buf.len() >= BLAKE3_CHUNK_SIZE {
@@ -113,14 +189,13 @@ fn write(
                 Mode::Verify {
                     proof, verifier, ..
                 } => {
-                    verifier
-                        .feed_proof(
-                            proof
-                                .as_ref()
-                                // TODO: We need a better error here.
-                                .ok_or(PutWriteError::InvalidContent)?,
-                        )
-                        .map_err(|_| PutWriteError::InvalidContent)?;
+                    // The proof is only fed once, the verifier will fail if it needs more proof
+                    // segments for this block.
+                    if let Some(proof) = proof.take() {
+                        verifier
+                            .feed_proof(&proof)
+                            .map_err(|_| PutWriteError::InvalidContent)?;
+                    }
                     verifier
                         .verify(block.clone())
                         .map_err(|_| PutWriteError::InvalidContent)?;
@@ -144,14 +219,6 @@ fn write(
             self.block_count += 1;
         }
 
-        // Remove this proof so it's ready for next block.
-        match &mut self.mode {
-            Mode::Verify { proof, .. } if self.content_buf.is_empty() => {
-                proof.take();
-            },
-            _ => {},
-        }
-
         if self.compression.is_none() {
             self.compression = Some(compression);
         }
@@ -165,6 +232,10 @@ fn is_finished(&self) -> bool {
     }
 
     async fn finalize(mut self) -> Result<Blake3Hash, PutFinalizeError> {
+        if self.cancellation.is_cancelled() {
+            return Err(PutFinalizeError::Cancelled);
+        }
+
         match self.prev_block {
             None => {
                 if self.content_buf.is_empty() {
@@ -175,7 +246,7 @@ async fn finalize(mut self) -> Result<Blake3Hash, PutFinalizeError> {
                 // If the buf is not empty, it means we have some data smaller than a
                 // Blake3 chunk size that needs to be processed so this buffered block
                 // is not the root.
-                let is_root = self.block_count == 0 && self.content_buf.is_empty();
+                let is_root = self.block_count == 1 && self.content_buf.is_empty();
                 let hash = prev_block.finalize(is_root);
                 self.chunks.push(Chunk {
                     hash,
@@ -193,6 +264,19 @@ async fn finalize(mut self) -> Result<Blake3Hash, PutFinalizeError> {
             let mut block = BlockHasher::new();
             block.set_block(self.chunks.len());
             block.update(self.content_buf.as_ref());
+            if let Mode::Verify {
+                proof, verifier, ..
+            } = &mut self.mode
+            {
+                if let Some(proof) = proof.take() {
+                    verifier
+                        .feed_proof(&proof)
+                        .map_err(|_| PutFinalizeError::InvalidCID)?;
+                }
+                verifier
+                    .verify(block.clone())
+                    .map_err(|_| PutFinalizeError::InvalidCID)?;
+            }
             let is_root = self.chunks.is_empty();
             let hash = block.finalize(is_root);
             self.chunks.push(Chunk {
@@ -204,31 +288,154 @@ async fn finalize(mut self) -> Result<Blake3Hash, PutFinalizeError> {
             });
         }
 
+        // Nothing is committed to the store unless the whole content was verified against the
+        // root, the staged chunks are dropped otherwise.
+        if let Mode::Verify { verifier, .. } = &self.mode {
+            if !verifier.is_done() {
+                return Err(PutFinalizeError::PartialContent);
+            }
+        }
+
+        let trust = matches!(self.mode, Mode::Trust { .. });
+        let (root, tree) = match self.mode {
+            Mode::Verify { root, .. } => {
+                let blocks = self
+                    .chunks
+                    .iter()
+                    .map(|chunk| chunk.hash)
+                    .collect::<Vec<_>>();
+                (root, tree_from_blocks(&blocks))
+            },
+            Mode::Trust { tree_builder } => {
+                let hash_tree = tree_builder.finalize();
+                (Blake3Hash::from(hash_tree.hash), hash_tree.tree)
+            },
+        };
+
+        // Identical content is already stored, so there is nothing to write.
+        if Self::is_stored(&self.store, root, &self.chunks).await {
+            self.store.add_ref(root).await;
+            return Ok(root);
+        }
+
+        // This is the last chance to cancel a put in verifier mode, from here on it commits all
+        // of the content.
+        if self.cancellation.is_cancelled() {
+            return Err(PutFinalizeError::Cancelled);
+        }
+
         // TODO: put methods use a non-async lock so these calls could
         // block the thread. Maybe let's use the worker pattern.
+        let total = self.chunks.len();
         for (count, chunk) in self.chunks.into_iter().enumerate() {
-            let block = bincode::serialize(&BlockContent::Chunk(chunk.content.content))
+            // Only a put in trust mode is interrupted while it writes.
+            if trust && self.cancellation.is_cancelled() {
+                return Err(PutFinalizeError::Cancelled);
+            }
+            let content = compression::compress(self.store_compression, &chunk.content.content)
+                .map_err(|_| PutFinalizeError::PartialContent)?;
+            let block = bincode::serialize(&BlockContent::Chunk(self.store_compression, content))
                 .map_err(|_| PutFinalizeError::PartialContent)?;
             self.store
                 .insert(Key::chunk_key(chunk.hash, count as u32), block)
                 .await;
+            if let Some(progress) = &self.progress {
+                progress(count + 1, total);
+            }
         }
 
-        match self.mode {
-            Mode::Verify { root, .. } => Ok(root),
-            Mode::Trust { tree_builder } => {
-                let hash_tree = tree_builder.finalize();
-                let block = bincode::serialize(&BlockContent::Tree(hash_tree.tree))
-                    .map_err(|_| PutFinalizeError::PartialContent)?;
-                self.store
-                    .insert(
-                        Key::tree_key(Blake3Hash::from(hash_tree.hash)),
-                        // TODO: We need a more descriptive error for serialization-related errors.
-                        block,
-                    )
-                    .await;
-                Ok(Blake3Hash::from(hash_tree.hash))
-            },
+        // TODO: We need a more descriptive error for serialization-related errors.
+        let block = bincode::serialize(&BlockContent::Tree(tree))
+            .map_err(|_| PutFinalizeError::PartialContent)?;
+        self.store.insert(Key::tree_key(root), block).await;
+
+        self.store.add_ref(root).await;
+        Ok(root)
+    }
+}
+
+/// Build the tree of the content from the hashes of its blocks, in the same stack ordering as
+/// the [`HashTreeBuilder`]. The blocks were already hashed when they were verified, so only the
+/// parent nodes are hashed here.
+fn tree_from_blocks(blocks: &[Blake3Hash]) -> Vec<Blake3Hash> {
+    let iv = IV::new();
+    let mut tree = Vec::with_capacity((2 * blocks.len()).saturating_sub(1));
+    let mut stack = Vec::new();
+    for (index, hash) in blocks.iter().enumerate() {
+        tree.push(*hash);
+        stack.push(*hash);
+        if index + 1 == blocks.len() {
+            break;
         }
+        // Every complete subtree is merged right away, the root is only merged in the end.
+        let mut count = index + 1;
+        while count & 1 == 0 {
+            let right = stack.pop().expect("stack to have the right child");
+            let left = stack.pop().expect("stack to have the left child");
+            let parent = iv.merge(&left, &right, false);
+            tree.push(parent);
+            stack.push(parent);
+            count >>= 1;
+        }
+    }
+    while stack.len() > 1 {
+        let right = stack.pop().expect("stack to have the right child");
+        let left = stack.pop().expect("stack to have the left child");
+        let parent = iv.merge(&left, &right, stack.is_empty());
+        tree.push(parent);
+        stack.push(parent);
     }
+    tree
+}
+
+/// Store the content of a stream encoded by a `blake3_stream::Encoder`.
+///
+/// The proofs and blocks of the stream are fed to a put in verifier mode as they are read, so
+/// every block is hashed once and verified against the root before the next one is read. Nothing
+/// is written to the store unless the entire stream was read and verified.
+pub(crate) async fn put_verified_stream<S, R>(
+    mut putter: IncrementalPut<S>,
+    root: Blake3Hash,
+    mut reader: R,
+) -> anyhow::Result<Blake3Hash>
+where
+    S: Store + Send + Sync,
+    R: AsyncRead + Unpin + Send,
+{
+    let content_len = reader
+        .read_u64()
+        .await
+        .context("failed to read the length of the stream")? as usize;
+    let num_blocks = (content_len + BLAKE3_CHUNK_SIZE - 1) / BLAKE3_CHUNK_SIZE;
+
+    let mut frame = Vec::with_capacity(BLAKE3_CHUNK_SIZE);
+    for block in 0..num_blocks {
+        let proof_len = if block == 0 {
+            ProofSizeEstimator::new(block, num_blocks).0
+        } else {
+            ProofSizeEstimator::resume(block, num_blocks).0
+        };
+        frame.resize(proof_len, 0);
+        reader
+            .read_exact(&mut frame)
+            .await
+            .context("failed to read a proof from the stream")?;
+        putter.feed_proof(&frame)?;
+
+        let block_len = if block + 1 < num_blocks {
+            BLAKE3_CHUNK_SIZE
+        } else {
+            content_len - block * BLAKE3_CHUNK_SIZE
+        };
+        frame.resize(block_len, 0);
+        reader
+            .read_exact(&mut frame)
+            .await
+            .context("failed to read a block from the stream")?;
+        putter.write(&frame, CompressionAlgorithm::Uncompressed)?;
+    }
+
+    let stored = putter.finalize().await?;
+    ensure!(stored == root, "the stored content does not match the root");
+    Ok(stored)
 }
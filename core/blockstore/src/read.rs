@@ -0,0 +1,132 @@
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+};
+
+use lightning_interfaces::{
+    types::{CompressionAlgoSet, CompressionAlgorithm},
+    Blake3Hash, Blake3Tree, BlockStoreInterface, ContentChunk,
+};
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::{
+    compression,
+    memory::{leaf_index, MemoryBlockStore},
+};
+
+type ChunkFuture = Pin<Box<dyn Future<Output = Option<Arc<ContentChunk>>> + Send>>;
+
+/// A reader over the content of a stored object, yielding the bytes of its chunks in order.
+///
+/// The chunks are fetched from the store one at a time as the content is read, and are
+/// decompressed if they are stored compressed. Reading fails if a chunk is missing.
+pub struct ContentReader {
+    store: MemoryBlockStore,
+    tree: Arc<Blake3Tree>,
+    /// The index of the next block to fetch.
+    next_block: usize,
+    num_blocks: usize,
+    /// The fetch of the next chunk, if it is in progress.
+    pending: Option<ChunkFuture>,
+    /// The content of the chunk that is being read.
+    current: Current,
+    /// Number of bytes of the current chunk that were already read.
+    offset: usize,
+}
+
+enum Current {
+    Empty,
+    Shared(Arc<ContentChunk>),
+    Decompressed(Vec<u8>),
+}
+
+impl Current {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Current::Empty => &[],
+            Current::Shared(chunk) => &chunk.content,
+            Current::Decompressed(content) => content,
+        }
+    }
+}
+
+impl ContentReader {
+    pub(crate) fn new(store: MemoryBlockStore, tree: Arc<Blake3Tree>) -> Self {
+        let num_blocks = (tree.0.len() + 1) / 2;
+        Self {
+            store,
+            tree,
+            next_block: 0,
+            num_blocks,
+            pending: None,
+            current: Current::Empty,
+            offset: 0,
+        }
+    }
+
+    fn fetch_next(&mut self) -> ChunkFuture {
+        let store = self.store.clone();
+        let block = self.next_block;
+        let hash: Blake3Hash = self.tree.0[leaf_index(block)];
+        Box::pin(async move {
+            store
+                .get(block as u32, &hash, CompressionAlgoSet::new())
+                .await
+        })
+    }
+}
+
+impl AsyncRead for ContentReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            let content = &this.current.as_slice()[this.offset..];
+            if !content.is_empty() {
+                let n = content.len().min(buf.remaining());
+                buf.put_slice(&content[..n]);
+                this.offset += n;
+                return Poll::Ready(Ok(()));
+            }
+
+            // Reading nothing into the buffer signals the end of the content.
+            if this.pending.is_none() && this.next_block == this.num_blocks {
+                this.current = Current::Empty;
+                return Poll::Ready(Ok(()));
+            }
+
+            if this.pending.is_none() {
+                this.pending = Some(this.fetch_next());
+            }
+            let chunk = ready!(
+                this.pending
+                    .as_mut()
+                    .expect("fetch to be pending")
+                    .as_mut()
+                    .poll(cx)
+            );
+            this.pending = None;
+
+            let Some(chunk) = chunk else {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("missing chunk {} of the content", this.next_block),
+                )));
+            };
+            this.next_block += 1;
+            this.offset = 0;
+            this.current = match chunk.compression {
+                CompressionAlgorithm::Uncompressed => Current::Shared(chunk),
+                algorithm => {
+                    Current::Decompressed(compression::decompress(algorithm, &chunk.content)?)
+                },
+            };
+        }
+    }
+}
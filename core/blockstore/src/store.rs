@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use lightning_interfaces::Blake3Hash;
 
 use crate::{Block, Key};
 
@@ -7,4 +8,9 @@
 pub trait Store {
     async fn fetch(&self, key: &Key) -> Option<Block>;
     async fn insert(&mut self, key: Key, block: Block);
+    /// Returns true if a block is stored under the given key.
+    async fn contains_key(&self, key: &Key) -> bool;
+    /// Record that the content with the given root was put in the store once more. Stores that
+    /// do not keep track of the references ignore this.
+    async fn add_ref(&mut self, _root: Blake3Hash) {}
 }
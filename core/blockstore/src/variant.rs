@@ -0,0 +1,77 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use lightning_interfaces::{types::CompressionAlgorithm, ContentChunk};
+
+use crate::Key;
+
+/// A cache of the compressed variants of chunks, bounded by the number of bytes of compressed
+/// content it holds. Once it is full the least recently used variant is evicted.
+pub struct VariantCache {
+    variants: HashMap<(Key, CompressionAlgorithm), Variant>,
+    /// The cached variants by their last access.
+    lru: BTreeMap<u64, (Key, CompressionAlgorithm)>,
+    capacity: usize,
+    size: usize,
+    /// Logical clock used to track the last access of each variant.
+    tick: u64,
+}
+
+struct Variant {
+    chunk: Arc<ContentChunk>,
+    last_access: u64,
+}
+
+impl VariantCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            variants: HashMap::new(),
+            lru: BTreeMap::new(),
+            capacity,
+            size: 0,
+            tick: 0,
+        }
+    }
+
+    /// Returns the chunk with the given key compressed with the algorithm, if it is cached.
+    pub fn get(&mut self, key: &Key, algorithm: CompressionAlgorithm) -> Option<Arc<ContentChunk>> {
+        let variant = self.variants.get_mut(&(*key, algorithm))?;
+        self.lru.remove(&variant.last_access);
+        self.tick += 1;
+        variant.last_access = self.tick;
+        self.lru.insert(self.tick, (*key, algorithm));
+        Some(variant.chunk.clone())
+    }
+
+    /// Cache the compressed variant of the chunk with the given key, evicting the least recently
+    /// used variants until it fits. A variant larger than the capacity is not cached.
+    pub fn insert(&mut self, key: Key, chunk: Arc<ContentChunk>) {
+        let len = chunk.content.len();
+        if len > self.capacity {
+            return;
+        }
+
+        self.tick += 1;
+        let id = (key, chunk.compression);
+        let variant = Variant {
+            chunk,
+            last_access: self.tick,
+        };
+        if let Some(old) = self.variants.insert(id, variant) {
+            self.lru.remove(&old.last_access);
+            self.size -= old.chunk.content.len();
+        }
+        self.lru.insert(self.tick, id);
+        self.size += len;
+
+        while self.size > self.capacity {
+            let Some((_, lru)) = self.lru.pop_first() else {
+                break;
+            };
+            let evicted = self.variants.remove(&lru).expect("variant to exist");
+            self.size -= evicted.chunk.content.len();
+        }
+    }
+}
@@ -47,7 +47,13 @@ async fn submit_batch(&self, batch: Vec<Batch>) {
                 transactions: batch
                     .transactions()
                     .iter()
-                    .filter_map(|txn| bincode::deserialize::<UpdateRequest>(txn).ok())
+                    .filter_map(|txn| {
+                        // Transactions forwarded by nodes that predate the wire encoding are
+                        // still accepted in their serde layout.
+                        UpdateRequest::decode(txn)
+                            .ok()
+                            .or_else(|| bincode::deserialize::<UpdateRequest>(txn).ok())
+                    })
                     .collect(),
             };
             info!("Consensus submitted new block to application");
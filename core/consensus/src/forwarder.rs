@@ -74,7 +74,7 @@ async fn handle_forward(&mut self, req: &UpdateRequest) -> Result<()> {
         }
 
         // serialize transaction
-        let txn_bytes = bincode::serialize(&req)?;
+        let txn_bytes = req.encode();
 
         let request = TransactionProto {
             transaction: txn_bytes.into(),
@@ -2,6 +2,7 @@
 
 use clap::{Parser, Subcommand};
 use fleek_crypto::NodeNetworkingPublicKey;
+use lightning_blockstore::memory::MemoryBlockStore;
 use lightning_dht::dht::{Builder, Dht};
 use lightning_interfaces::Blake3Hash;
 
@@ -61,7 +62,7 @@ async fn main() {
     }
 }
 
-async fn start_node(bootstrapper: Option<SocketAddr>) -> Dht {
+async fn start_node(bootstrapper: Option<SocketAddr>) -> Dht<MemoryBlockStore> {
     let mut builder = Builder::new();
 
     let public_key = NodeNetworkingPublicKey(rand::random());
@@ -73,7 +74,7 @@ async fn start_node(bootstrapper: Option<SocketAddr>) -> Dht {
 
     builder.set_node_key(public_key);
 
-    let dht = builder.build().await.unwrap();
+    let dht = builder.build(MemoryBlockStore::default()).await.unwrap();
 
     tracing::info!("start bootstrap");
     dht.bootstrap().await;
@@ -99,6 +99,15 @@ async fn bootstrap(
 
     closest_nodes(local_key, handler_tx.clone(), table_tx.clone()).await?;
 
+    refresh_buckets(handler_tx, table_tx).await
+}
+
+/// Look up a random key in every bucket starting from the first non-empty one, to fill the
+/// routing table with the nodes closest to those keys.
+pub async fn refresh_buckets(
+    handler_tx: Sender<HandlerCommand>,
+    table_tx: Sender<TableCommand>,
+) -> Result<()> {
     let (tx, rx) = oneshot::channel();
     table_tx
         .send(TableCommand::FirstNonEmptyBucket { tx })
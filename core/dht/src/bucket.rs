@@ -1,3 +1,5 @@
+use fleek_crypto::NodeNetworkingPublicKey;
+
 use crate::query::NodeInfo;
 
 pub const MAX_BUCKET_SIZE: usize = 6;
@@ -34,4 +36,11 @@ pub fn add_node(&mut self, node: &Node) -> bool {
         self.inner.push(node.clone());
         true
     }
+
+    /// Remove the node with the given key, returning true if it was in the bucket.
+    pub fn remove_node(&mut self, key: &NodeNetworkingPublicKey) -> bool {
+        let len = self.inner.len();
+        self.inner.retain(|node| node.info.key != *key);
+        self.inner.len() != len
+    }
 }
@@ -0,0 +1,184 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use lightning_interfaces::dht::{KeyPrefix, TableEntry};
+
+/// Default time a resolved value is kept in the cache.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60);
+/// Default maximum number of values kept in the cache.
+pub const DEFAULT_MAX_ENTRIES: usize = 1024;
+
+/// A local cache of recently resolved values.
+///
+/// Entries expire after the TTL, and once the cache is full the least recently used entry is
+/// evicted. A cache with a maximum of zero entries never stores anything.
+pub struct ValueCache {
+    entries: HashMap<(KeyPrefix, Vec<u8>), CacheEntry>,
+    ttl: Duration,
+    max_entries: usize,
+    /// Logical clock used to track the last access of each entry.
+    tick: u64,
+}
+
+struct CacheEntry {
+    entry: TableEntry,
+    expires_at: Instant,
+    last_access: u64,
+}
+
+impl Default for ValueCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_TTL, DEFAULT_MAX_ENTRIES)
+    }
+}
+
+impl ValueCache {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            ttl,
+            max_entries,
+            tick: 0,
+        }
+    }
+
+    /// Returns the cached entry for the key if it has not expired yet.
+    pub fn get(&mut self, prefix: KeyPrefix, key: &[u8]) -> Option<TableEntry> {
+        let cache_key = (prefix, key.to_vec());
+        let cached = self.entries.get_mut(&cache_key)?;
+
+        if cached.expires_at <= Instant::now() {
+            self.entries.remove(&cache_key);
+            return None;
+        }
+
+        self.tick += 1;
+        cached.last_access = self.tick;
+        Some(cached.entry.clone())
+    }
+
+    /// Cache the entry resolved for the requested key, evicting the least recently used entry if
+    /// the cache is full. The entry is cached under the requested key rather than the key of the
+    /// entry, so it is found and invalidated by the same key it was requested with.
+    pub fn insert(&mut self, prefix: KeyPrefix, key: &[u8], entry: TableEntry) {
+        if self.max_entries == 0 {
+            return;
+        }
+
+        let cache_key = (prefix, key.to_vec());
+        if !self.entries.contains_key(&cache_key) && self.entries.len() >= self.max_entries {
+            self.evict();
+        }
+
+        self.tick += 1;
+        self.entries.insert(
+            cache_key,
+            CacheEntry {
+                entry,
+                expires_at: Instant::now() + self.ttl,
+                last_access: self.tick,
+            },
+        );
+    }
+
+    /// Remove the entry for the key, if any.
+    pub fn invalidate(&mut self, prefix: KeyPrefix, key: &[u8]) {
+        self.entries.remove(&(prefix, key.to_vec()));
+    }
+
+    /// Remove every expired entry.
+    pub fn remove_expired(&mut self) {
+        let now = Instant::now();
+        self.entries.retain(|_, cached| cached.expires_at > now);
+    }
+
+    fn evict(&mut self) {
+        // Prefer dropping expired entries before evicting live ones.
+        self.remove_expired();
+        if self.entries.len() < self.max_entries {
+            return;
+        }
+
+        if let Some(key) = self
+            .entries
+            .iter()
+            .min_by_key(|(_, cached)| cached.last_access)
+            .map(|(key, _)| key.clone())
+        {
+            self.entries.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fleek_crypto::NodeNetworkingPublicKey;
+
+    use super::*;
+
+    fn entry(key: &[u8], value: &[u8]) -> TableEntry {
+        TableEntry {
+            prefix: KeyPrefix::ContentRegistry,
+            key: key.to_vec(),
+            value: value.to_vec(),
+            source: NodeNetworkingPublicKey([0; 32]),
+            signature: None,
+        }
+    }
+
+    #[test]
+    fn hit_within_ttl() {
+        let mut cache = ValueCache::new(Duration::from_secs(60), 8);
+        cache.insert(KeyPrefix::ContentRegistry, b"key", entry(b"key", b"value"));
+
+        let cached = cache.get(KeyPrefix::ContentRegistry, b"key").unwrap();
+        assert_eq!(cached.value, b"value");
+        assert!(cache.get(KeyPrefix::ContentRegistry, b"other").is_none());
+    }
+
+    #[test]
+    fn expires_after_ttl() {
+        let mut cache = ValueCache::new(Duration::from_millis(20), 8);
+        cache.insert(KeyPrefix::ContentRegistry, b"key", entry(b"key", b"value"));
+        assert!(cache.get(KeyPrefix::ContentRegistry, b"key").is_some());
+
+        std::thread::sleep(Duration::from_millis(40));
+        assert!(cache.get(KeyPrefix::ContentRegistry, b"key").is_none());
+    }
+
+    #[test]
+    fn invalidate_on_store() {
+        let mut cache = ValueCache::new(Duration::from_secs(60), 8);
+        cache.insert(KeyPrefix::ContentRegistry, b"key", entry(b"key", b"value"));
+        cache.invalidate(KeyPrefix::ContentRegistry, b"key");
+        assert!(cache.get(KeyPrefix::ContentRegistry, b"key").is_none());
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = ValueCache::new(Duration::from_secs(60), 2);
+        cache.insert(KeyPrefix::ContentRegistry, b"a", entry(b"a", b"1"));
+        cache.insert(KeyPrefix::ContentRegistry, b"b", entry(b"b", b"2"));
+        // Touch `a` so `b` becomes the least recently used entry.
+        assert!(cache.get(KeyPrefix::ContentRegistry, b"a").is_some());
+        cache.insert(KeyPrefix::ContentRegistry, b"c", entry(b"c", b"3"));
+
+        assert!(cache.get(KeyPrefix::ContentRegistry, b"a").is_some());
+        assert!(cache.get(KeyPrefix::ContentRegistry, b"b").is_none());
+        assert!(cache.get(KeyPrefix::ContentRegistry, b"c").is_some());
+    }
+
+    #[test]
+    fn keyed_by_requested_key() {
+        let mut cache = ValueCache::new(Duration::from_secs(60), 8);
+        // The resolved entry does not carry the prefix the key was requested with.
+        cache.insert(KeyPrefix::NodeRegistry, b"key", entry(b"key", b"value"));
+
+        assert!(cache.get(KeyPrefix::NodeRegistry, b"key").is_some());
+        assert!(cache.get(KeyPrefix::ContentRegistry, b"key").is_none());
+        cache.invalidate(KeyPrefix::NodeRegistry, b"key");
+        assert!(cache.get(KeyPrefix::NodeRegistry, b"key").is_none());
+    }
+}
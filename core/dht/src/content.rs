@@ -0,0 +1,236 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, ensure, Context, Result};
+use blake3_tree::ProofBuf;
+use lightning_interfaces::{
+    types::{CompressionAlgoSet, CompressionAlgorithm},
+    Blake3Hash, Blake3Tree, BlockStoreInterface, ContentChunk, IncrementalPutInterface,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{maintenance::Published, query::Value};
+
+/// Values larger than this are not sent in a datagram, only the root of their content is.
+pub const MAX_INLINE_VALUE_SIZE: usize = 1024;
+
+/// Returns the value to send over the network for the given value.
+///
+/// Values that would not fit in one datagram are put in the block store, from which their
+/// content is served to the nodes that get them, and only their root is sent.
+pub async fn encode_value<B: BlockStoreInterface>(blockstore: &B, value: &[u8]) -> Result<Value> {
+    if value.len() <= MAX_INLINE_VALUE_SIZE {
+        return Ok(Value::Inline(value.to_vec()));
+    }
+
+    let mut putter = blockstore.put(None);
+    putter
+        .write(value, CompressionAlgorithm::Uncompressed)
+        .map_err(|e| anyhow!("failed to write value to the block store: {e:?}"))?;
+    let root = putter
+        .finalize()
+        .await
+        .map_err(|e| anyhow!("failed to finalize value in the block store: {e:?}"))?;
+    Ok(Value::Content(root))
+}
+
+/// Returns the content with the given root from the block store, or [`None`] if any of it is
+/// missing.
+pub async fn read_content<B: BlockStoreInterface>(
+    blockstore: &B,
+    root: &Blake3Hash,
+) -> Option<Vec<u8>> {
+    let tree = blockstore.get_tree(root).await?;
+    let chunks = get_chunks(blockstore, &tree).await?;
+    Some(
+        chunks
+            .iter()
+            .flat_map(|chunk| &chunk.content)
+            .copied()
+            .collect(),
+    )
+}
+
+/// Returns the decompressed chunks of the content with the given tree.
+async fn get_chunks<B: BlockStoreInterface>(
+    blockstore: &B,
+    tree: &Blake3Tree,
+) -> Option<Vec<B::SharedPointer<ContentChunk>>> {
+    let num_blocks = (tree.0.len() + 1) / 2;
+    let mut chunks = Vec::with_capacity(num_blocks);
+    for block in 0..num_blocks {
+        // The hash of a block is at its leaf in the flattened tree.
+        let hash = tree.0[block * 2 - block.count_ones() as usize];
+        let chunk = blockstore
+            .get(block as u32, &hash, CompressionAlgoSet::new())
+            .await?;
+        chunks.push(chunk);
+    }
+    Some(chunks)
+}
+
+/// Fetch the content with the given root from the node at the address, unless it is already in
+/// the block store.
+///
+/// The content is received as a `blake3-stream` encoded stream, which is verified against the
+/// root as it is put in the block store.
+pub async fn fetch_content<B: BlockStoreInterface>(
+    blockstore: &B,
+    root: Blake3Hash,
+    address: SocketAddr,
+) -> Result<Vec<u8>> {
+    if let Some(content) = read_content(blockstore, &root).await {
+        return Ok(content);
+    }
+
+    let mut stream = TcpStream::connect(address)
+        .await
+        .with_context(|| format!("failed to connect to {address}"))?;
+    stream.write_all(&root).await?;
+    blockstore.put_verified_stream(root, stream).await?;
+
+    read_content(blockstore, &root)
+        .await
+        .ok_or_else(|| anyhow!("missing content for root {root:?}"))
+}
+
+/// Serve the content of the values published by this node to the nodes that get them.
+///
+/// Every connection requests the content of a single root, which is sent as a `blake3-stream`
+/// encoded stream. Only the roots of the values that this node still publishes are served, the
+/// rest of the block store can not be read through this server.
+pub async fn start_server<B: BlockStoreInterface + 'static>(
+    listener: TcpListener,
+    blockstore: B,
+    published: Arc<Mutex<Published>>,
+) {
+    loop {
+        let (stream, address) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                tracing::error!("failed to accept connection: {e:?}");
+                continue;
+            },
+        };
+        let blockstore = blockstore.clone();
+        let published = published.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve(stream, blockstore, published).await {
+                tracing::trace!("failed to serve content to {address}: {e:?}");
+            }
+        });
+    }
+}
+
+async fn serve<B: BlockStoreInterface>(
+    mut stream: TcpStream,
+    blockstore: B,
+    published: Arc<Mutex<Published>>,
+) -> Result<()> {
+    let mut root = [0; 32];
+    stream.read_exact(&mut root).await?;
+    ensure!(
+        published.lock().unwrap().has_content(&root),
+        "root {root:?} is not published by this node"
+    );
+
+    let tree = blockstore
+        .get_tree(&root)
+        .await
+        .ok_or_else(|| anyhow!("missing tree for root {root:?}"))?;
+    let chunks = get_chunks(&blockstore, &tree)
+        .await
+        .ok_or_else(|| anyhow!("missing content for root {root:?}"))?;
+
+    // The stream is the length of the content, followed by every block preceded by its proof.
+    let content_len: usize = chunks.iter().map(|chunk| chunk.content.len()).sum();
+    stream
+        .write_all(&(content_len as u64).to_be_bytes())
+        .await?;
+    for (block, chunk) in chunks.iter().enumerate() {
+        let proof = if block == 0 {
+            ProofBuf::new(&tree.0, 0)
+        } else {
+            ProofBuf::resume(&tree.0, block)
+        };
+        stream.write_all(proof.as_slice()).await?;
+        stream.write_all(&chunk.content).await?;
+    }
+    stream.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use lightning_blockstore::memory::MemoryBlockStore;
+    use lightning_interfaces::dht::KeyPrefix;
+
+    use super::*;
+    use crate::maintenance::SystemClock;
+
+    #[tokio::test]
+    async fn small_value_is_inline() {
+        let blockstore = MemoryBlockStore::default();
+
+        let value = encode_value(&blockstore, b"value").await.unwrap();
+        assert_eq!(value, Value::Inline(b"value".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn large_value_is_fetched_by_its_root() {
+        let server_store = MemoryBlockStore::default();
+        let value: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+
+        let encoded = encode_value(&server_store, &value).await.unwrap();
+        let Value::Content(root) = encoded else {
+            panic!("expected the value to be content addressed");
+        };
+        let mut published = Published::new(Arc::new(SystemClock), Duration::from_secs(60), 8);
+        published.insert(KeyPrefix::ContentRegistry, b"key".to_vec(), encoded);
+
+        // Content that is in the block store but was not published.
+        let unpublished: Vec<u8> = value.iter().rev().copied().collect();
+        let Value::Content(unpublished_root) =
+            encode_value(&server_store, &unpublished).await.unwrap()
+        else {
+            panic!("expected the value to be content addressed");
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(start_server(
+            listener,
+            server_store,
+            Arc::new(Mutex::new(published)),
+        ));
+
+        // The content is not in the local store, so it is fetched from the server.
+        let client_store = MemoryBlockStore::default();
+        assert!(read_content(&client_store, &root).await.is_none());
+        let content = fetch_content(&client_store, root, address).await.unwrap();
+        assert_eq!(content, value);
+        assert_eq!(read_content(&client_store, &root).await.unwrap(), value);
+
+        // Content the server does not have can not be fetched.
+        assert!(
+            fetch_content(&client_store, [0; 32], address)
+                .await
+                .is_err()
+        );
+        // Neither can content that is stored but was not published.
+        assert!(
+            fetch_content(&client_store, unpublished_root, address)
+                .await
+                .is_err()
+        );
+
+        server.abort();
+    }
+}
@@ -1,21 +1,41 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use anyhow::Result;
 use async_trait::async_trait;
 use fleek_crypto::NodeNetworkingPublicKey;
+use futures::{stream, StreamExt};
 use lightning_application::query_runner::QueryRunner;
 use lightning_interfaces::{
     dht::{DhtInterface, KeyPrefix, TableEntry},
-    SignerInterface, WithStartAndShutdown,
+    BlockStoreInterface, SignerInterface, WithStartAndShutdown,
 };
 use lightning_topology::Topology;
 use tokio::{
-    net::UdpSocket,
+    net::{TcpListener, UdpSocket},
     sync::{mpsc, oneshot},
+    task::JoinHandle,
 };
 
 use crate::{
-    bootstrap, bootstrap::BootstrapCommand, handler, handler::HandlerCommand, query::NodeInfo,
+    bootstrap,
+    bootstrap::BootstrapCommand,
+    cache,
+    cache::ValueCache,
+    content, handler,
+    handler::HandlerCommand,
+    lookup::ALPHA,
+    maintenance,
+    maintenance::{
+        Clock, MaintenanceCommand, MaintenanceConfig, MaintenanceScheduler, Published, SystemClock,
+    },
+    query::{NodeInfo, Value, ValueRecord},
+    retry::RetryPolicy,
+    store,
+    store::{MultiValueStore, StoreError, ValueStore},
     table,
 };
 
@@ -26,6 +46,14 @@ pub struct Builder {
     node_key: Option<NodeNetworkingPublicKey>,
     address: Option<SocketAddr>,
     buffer_size: Option<usize>,
+    cache_ttl: Option<Duration>,
+    cache_size: Option<usize>,
+    retry_policy: Option<RetryPolicy>,
+    maintenance_config: Option<MaintenanceConfig>,
+    clock: Option<Arc<dyn Clock>>,
+    max_value_size: Option<usize>,
+    max_total_store_bytes: Option<usize>,
+    max_published: Option<usize>,
 }
 
 impl Builder {
@@ -53,8 +81,52 @@ pub fn set_buffer_size(&mut self, size: usize) {
         self.buffer_size = Some(size);
     }
 
-    /// Build and initiates the DHT.
-    pub async fn build(self) -> Result<Dht> {
+    /// Set how long resolved values are cached locally.
+    pub fn set_cache_ttl(&mut self, ttl: Duration) {
+        self.cache_ttl = Some(ttl);
+    }
+
+    /// Set the maximum number of resolved values cached locally. Zero disables the cache.
+    pub fn set_cache_size(&mut self, size: usize) {
+        self.cache_size = Some(size);
+    }
+
+    /// Set the policy for retrying queries that do not get a response in time.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = Some(policy);
+    }
+
+    /// Set the intervals of the periodic maintenance of the DHT.
+    pub fn set_maintenance_config(&mut self, config: MaintenanceConfig) {
+        self.maintenance_config = Some(config);
+    }
+
+    /// Set the clock driving the periodic maintenance. Defaults to the system clock.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = Some(clock);
+    }
+
+    /// Set the maximum size of a value in the local store. Larger values are rejected.
+    pub fn set_max_value_size(&mut self, size: usize) {
+        self.max_value_size = Some(size);
+    }
+
+    /// Set the maximum number of bytes held by each of the local stores, the one for values and
+    /// the one for entries. Once it is exceeded the values or entries that were stored or
+    /// republished the longest time ago are evicted.
+    pub fn set_max_total_store_bytes(&mut self, bytes: usize) {
+        self.max_total_store_bytes = Some(bytes);
+    }
+
+    /// Set the maximum number of values put by this node that are republished. Once it is
+    /// exceeded the values that were put the longest time ago are no longer republished.
+    pub fn set_max_published(&mut self, max_published: usize) {
+        self.max_published = Some(max_published);
+    }
+
+    /// Build and initiates the DHT. The content of the values that are too large to be sent in a
+    /// datagram is put in the given block store, and served from it to the other nodes.
+    pub async fn build<B: BlockStoreInterface + 'static>(self, blockstore: B) -> Result<Dht<B>> {
         let buffer_size = self.buffer_size.unwrap_or(10_000);
 
         let node_key = self.node_key.unwrap_or_else(|| {
@@ -66,43 +138,158 @@ pub async fn build(self) -> Result<Dht> {
 
         let address = self.address.unwrap_or_else(|| "0.0.0.0:0".parse().unwrap());
         let socket = UdpSocket::bind(address).await.map(Arc::new)?;
+        let address = socket.local_addr()?;
+        let max_value_size = self.max_value_size.unwrap_or(store::DEFAULT_MAX_VALUE_SIZE);
+        let max_total_store_bytes = self
+            .max_total_store_bytes
+            .unwrap_or(store::DEFAULT_MAX_TOTAL_STORE_BYTES);
+        let store = Arc::new(Mutex::new(MultiValueStore::with_limits(
+            max_value_size,
+            max_total_store_bytes,
+        )));
         let (handler_tx, handler_rx) = mpsc::channel(buffer_size);
         tokio::spawn(handler::start_worker(
             handler_rx,
             table_tx.clone(),
             socket,
             node_key,
+            self.retry_policy.unwrap_or_default(),
+            Arc::new(Mutex::new(ValueStore::with_limits(
+                max_value_size,
+                max_total_store_bytes,
+            ))),
+            store.clone(),
+        ));
+
+        let clock = self.clock.unwrap_or_else(|| Arc::new(SystemClock));
+        let maintenance_config = self.maintenance_config.unwrap_or_default();
+        let published = Arc::new(Mutex::new(Published::new(
+            clock.clone(),
+            maintenance_config.publish_ttl,
+            self.max_published
+                .unwrap_or(maintenance::DEFAULT_MAX_PUBLISHED),
+        )));
+
+        // The nodes that get a large value only know the address from which it was put, so the
+        // content is served on the same port as the datagrams.
+        let listener = TcpListener::bind(address).await?;
+        let content_server = tokio::spawn(content::start_server(
+            listener,
+            blockstore.clone(),
+            published.clone(),
         ));
 
         let (bootstrap_tx, bootstrap_rx) = mpsc::channel(buffer_size);
         tokio::spawn(bootstrap::start_worker(
             bootstrap_rx,
-            table_tx,
+            table_tx.clone(),
             handler_tx.clone(),
             node_key,
             self.nodes,
         ));
 
+        let cache = Arc::new(Mutex::new(ValueCache::new(
+            self.cache_ttl.unwrap_or(cache::DEFAULT_TTL),
+            self.cache_size.unwrap_or(cache::DEFAULT_MAX_ENTRIES),
+        )));
+
+        // All the periodic maintenance shares a single scheduler.
+        let scheduler = MaintenanceScheduler::new(clock, maintenance_config);
+        let (maintenance_tx, maintenance_rx) = mpsc::channel(buffer_size);
+        tokio::spawn(maintenance::start_worker(
+            maintenance_rx,
+            scheduler,
+            table_tx,
+            handler_tx.clone(),
+            cache.clone(),
+            published.clone(),
+        ));
+
         Ok(Dht {
+            address,
             handler_tx,
             bootstrap_tx,
+            maintenance_tx,
+            cache,
+            published,
+            store,
+            blockstore,
+            content_server,
         })
     }
 }
 
 /// Maintains the DHT.
-pub struct Dht {
+pub struct Dht<B: BlockStoreInterface> {
+    address: SocketAddr,
     handler_tx: mpsc::Sender<HandlerCommand>,
     bootstrap_tx: mpsc::Sender<BootstrapCommand>,
+    maintenance_tx: mpsc::Sender<MaintenanceCommand>,
+    cache: Arc<Mutex<ValueCache>>,
+    /// The values put by this node, which are republished periodically.
+    published: Arc<Mutex<Published>>,
+    /// The signed entries stored on this node, by this node or by others.
+    store: Arc<Mutex<MultiValueStore>>,
+    blockstore: B,
+    /// Serves the content of the large values put by this node.
+    content_server: JoinHandle<()>,
 }
 
-impl Dht {
+impl<B: BlockStoreInterface + 'static> Dht<B> {
+    /// Returns the address that the node is bound to.
+    pub fn local_address(&self) -> SocketAddr {
+        self.address
+    }
+
     /// Return one value associated with the given key.
     pub async fn get(&self, key: &[u8]) -> Option<TableEntry> {
+        self.get_with_prefix(KeyPrefix::ContentRegistry, key).await
+    }
+
+    /// Return one value for each of the given keys, in the same order as the keys.
+    pub async fn get_batch(&self, keys: &[Vec<u8>]) -> Vec<Option<TableEntry>> {
+        self.get_batch_with_prefix(KeyPrefix::ContentRegistry, keys)
+            .await
+    }
+
+    /// Put a key-value pair into the DHT.
+    pub fn put(&self, key: &[u8], value: &[u8]) {
+        self.put_with_prefix(KeyPrefix::ContentRegistry, key, value)
+    }
+
+    /// Add a signed entry to the values stored under its key on this node and on the nodes
+    /// closest to the key, replacing the previous entry from the same source. Entries with an
+    /// invalid signature or a value that is too large are rejected.
+    pub fn put_entry(&self, entry: TableEntry) -> Result<(), StoreError> {
+        self.store.lock().unwrap().insert(entry.clone())?;
+
+        let handler_tx = self.handler_tx.clone();
+        tokio::spawn(async move {
+            if handler_tx
+                .send(HandlerCommand::PutEntry { entry })
+                .await
+                .is_err()
+            {
+                tracing::error!("failed to send to handler command");
+            }
+        });
+        Ok(())
+    }
+
+    /// Return all the values associated with the given key, at most one per source.
+    pub async fn get_all(&self, key: &[u8]) -> Vec<TableEntry> {
+        self.get_all_with_prefix(KeyPrefix::ContentRegistry, key)
+            .await
+    }
+
+    async fn get_all_with_prefix(&self, prefix: KeyPrefix, key: &[u8]) -> Vec<TableEntry> {
+        let mut entries = self.store.lock().unwrap().get_all(prefix, key);
+
         let (tx, rx) = oneshot::channel();
         if self
             .handler_tx
-            .send(HandlerCommand::Get {
+            .send(HandlerCommand::GetAll {
+                prefix,
                 key: key.to_vec(),
                 tx,
             })
@@ -111,22 +298,89 @@ pub async fn get(&self, key: &[u8]) -> Option<TableEntry> {
         {
             tracing::error!("failed to send to handler command");
         }
-        rx.await
+        let found = rx
+            .await
             .expect("handler worker to not drop the channel")
             .unwrap_or_else(|e| {
                 tracing::trace!("unexpected error when attempting to get {key:?}: {e:?}");
-                None
-            })
+                Vec::new()
+            });
+
+        // The entries stored on this node come first, the other nodes only add new sources.
+        for entry in found {
+            if !entries.iter().any(|known| known.source == entry.source) {
+                entries.push(entry);
+            }
+        }
+        entries
     }
 
-    /// Put a key-value pair into the DHT.
-    pub fn put(&self, key: &[u8], value: &[u8]) {
+    async fn get_with_prefix(&self, prefix: KeyPrefix, key: &[u8]) -> Option<TableEntry> {
+        if let Some(entry) = self.cache.lock().unwrap().get(prefix, key) {
+            return Some(entry);
+        }
+
+        let record = self.lookup_value(prefix, key).await?;
+        let value = match record.value {
+            Value::Inline(value) => value,
+            // The content of large values is fetched from the node that put them.
+            Value::Content(root) => {
+                content::fetch_content(&self.blockstore, root, record.source.address)
+                    .await
+                    .map_err(|e| tracing::trace!("failed to fetch value of {key:?}: {e:?}"))
+                    .ok()?
+            },
+        };
+        let entry = TableEntry {
+            prefix,
+            key: key.to_vec(),
+            value,
+            source: record.source.key,
+            signature: None,
+        };
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(prefix, key, entry.clone());
+        Some(entry)
+    }
+
+    async fn get_batch_with_prefix(
+        &self,
+        prefix: KeyPrefix,
+        keys: &[Vec<u8>],
+    ) -> Vec<Option<TableEntry>> {
+        // At most alpha lookups are in flight at once, `buffered` yields them in input order.
+        stream::iter(keys)
+            .map(|key| self.get_with_prefix(prefix, key))
+            .buffered(ALPHA)
+            .collect()
+            .await
+    }
+
+    fn put_with_prefix(&self, prefix: KeyPrefix, key: &[u8], value: &[u8]) {
+        // The cached value for this key is now stale.
+        self.cache.lock().unwrap().invalidate(prefix, key);
+
         let handler_tx = self.handler_tx.clone();
+        let blockstore = self.blockstore.clone();
+        let published = self.published.clone();
         let key = key.to_vec();
         let value = value.to_vec();
         tokio::spawn(async move {
+            let value = match content::encode_value(&blockstore, &value).await {
+                Ok(value) => value,
+                Err(e) => {
+                    tracing::error!("failed to store value: {e:?}");
+                    return;
+                },
+            };
+            published
+                .lock()
+                .unwrap()
+                .insert(prefix, key.clone(), value.clone());
             if handler_tx
-                .send(HandlerCommand::Put { key, value })
+                .send(HandlerCommand::Put { prefix, key, value })
                 .await
                 .is_err()
             {
@@ -135,6 +389,29 @@ pub fn put(&self, key: &[u8], value: &[u8]) {
         });
     }
 
+    /// Resolve the value of the key from the network.
+    async fn lookup_value(&self, prefix: KeyPrefix, key: &[u8]) -> Option<ValueRecord> {
+        let (tx, rx) = oneshot::channel();
+        if self
+            .handler_tx
+            .send(HandlerCommand::Get {
+                prefix,
+                key: key.to_vec(),
+                tx,
+            })
+            .await
+            .is_err()
+        {
+            tracing::error!("failed to send to handler command");
+        }
+        rx.await
+            .expect("handler worker to not drop the channel")
+            .unwrap_or_else(|e| {
+                tracing::trace!("unexpected error when attempting to get {key:?}: {e:?}");
+                None
+            })
+    }
+
     /// Start bootstrap task.
     /// If bootstrapping is in process, this command will be ignored.
     pub async fn bootstrap(&self) {
@@ -164,9 +441,12 @@ pub async fn is_bootstrapped(&self) -> bool {
 }
 
 #[async_trait]
-impl WithStartAndShutdown for Dht {
+impl<B: BlockStoreInterface + 'static> WithStartAndShutdown for Dht<B> {
     fn is_running(&self) -> bool {
-        !self.handler_tx.is_closed() && !self.bootstrap_tx.is_closed()
+        !self.handler_tx.is_closed()
+            && !self.bootstrap_tx.is_closed()
+            && !self.maintenance_tx.is_closed()
+            && !self.content_server.is_finished()
     }
 
     async fn start(&self) {}
@@ -179,26 +459,225 @@ async fn shutdown(&self) {
             .send(BootstrapCommand::Shutdown)
             .await
             .expect("bootstrap worker to not drop channel");
+        // The maintenance worker may also be communicating with the handler worker.
+        self.maintenance_tx
+            .send(MaintenanceCommand::Shutdown)
+            .await
+            .expect("maintenance worker to not drop channel");
         self.handler_tx
             .send(HandlerCommand::Shutdown)
             .await
             .expect("handler worker to not drop channel");
+        self.content_server.abort();
     }
 }
 
 #[async_trait]
-impl DhtInterface for Dht {
+impl<B: BlockStoreInterface + 'static> DhtInterface for Dht<B> {
     type Topology = Topology<QueryRunner>;
+    type BlockStore = B;
 
-    async fn init<S: SignerInterface>(_: &S, _: Arc<Self::Topology>) -> Result<Self> {
-        Builder::new().build().await
+    async fn init<S: SignerInterface>(
+        _: &S,
+        _: Arc<Self::Topology>,
+        blockstore: Self::BlockStore,
+    ) -> Result<Self> {
+        Builder::new().build(blockstore).await
     }
 
-    fn put(&self, _: KeyPrefix, key: &[u8], value: &[u8]) {
-        self.put(key, value)
+    fn put(&self, prefix: KeyPrefix, key: &[u8], value: &[u8]) {
+        self.put_with_prefix(prefix, key, value)
     }
 
-    async fn get(&self, _: KeyPrefix, key: &[u8]) -> Option<TableEntry> {
-        self.get(key).await
+    async fn get(&self, prefix: KeyPrefix, key: &[u8]) -> Option<TableEntry> {
+        self.get_with_prefix(prefix, key).await
+    }
+
+    async fn get_batch(&self, prefix: KeyPrefix, keys: &[Vec<u8>]) -> Vec<Option<TableEntry>> {
+        self.get_batch_with_prefix(prefix, keys).await
+    }
+
+    async fn get_all(&self, prefix: KeyPrefix, key: &[u8]) -> Vec<TableEntry> {
+        self.get_all_with_prefix(prefix, key).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fleek_crypto::{NodeNetworkingSecretKey, SecretKey};
+    use lightning_blockstore::memory::MemoryBlockStore;
+    use lightning_interfaces::ToDigest;
+
+    use super::*;
+
+    /// Start a node on the loopback interface and wait until it is bootstrapped from the given
+    /// node. Every node has its own block store.
+    async fn start_node(bootstrapper: Option<&NodeInfo>) -> (Dht<MemoryBlockStore>, NodeInfo) {
+        let key = NodeNetworkingPublicKey(rand::random());
+        let mut builder = Builder::new();
+        builder.set_node_key(key);
+        builder.set_address("127.0.0.1:0".parse().unwrap());
+        if let Some(bootstrapper) = bootstrapper {
+            builder.add_node(bootstrapper.clone());
+        }
+        let dht = builder.build(MemoryBlockStore::default()).await.unwrap();
+
+        dht.bootstrap().await;
+        while !dht.is_bootstrapped().await {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let info = NodeInfo {
+            address: dht.local_address(),
+            key,
+        };
+        (dht, info)
+    }
+
+    #[tokio::test]
+    async fn get_batch_returns_values_in_input_order() {
+        let dht = Builder::new()
+            .build(MemoryBlockStore::default())
+            .await
+            .unwrap();
+
+        // Seed the cache so these keys resolve without a network. Nothing else is in the routing
+        // table, so the lookups of the other keys find nothing.
+        for key in [b"a", b"c"] {
+            dht.cache.lock().unwrap().insert(
+                KeyPrefix::ContentRegistry,
+                key,
+                TableEntry {
+                    prefix: KeyPrefix::ContentRegistry,
+                    key: key.to_vec(),
+                    value: vec![key[0]; 8],
+                    source: NodeNetworkingPublicKey([0; 32]),
+                    signature: None,
+                },
+            );
+        }
+
+        let keys = [b"a", b"b", b"c", b"d"].map(|key| key.to_vec());
+        let entries = dht.get_batch(&keys).await;
+
+        assert_eq!(entries.len(), keys.len());
+        assert_eq!(entries[0].as_ref().unwrap().value, [b'a'; 8]);
+        assert!(entries[1].is_none());
+        assert_eq!(entries[2].as_ref().unwrap().value, [b'c'; 8]);
+        assert!(entries[3].is_none());
+    }
+
+    #[tokio::test]
+    async fn put_invalidates_cached_get() {
+        let dht = Builder::new()
+            .build(MemoryBlockStore::default())
+            .await
+            .unwrap();
+        let prefix = KeyPrefix::NodeRegistry;
+
+        // Seed the cache with a value resolved earlier, so the get does not need a network.
+        dht.cache.lock().unwrap().insert(
+            prefix,
+            b"key",
+            TableEntry {
+                prefix,
+                key: b"key".to_vec(),
+                value: b"old".to_vec(),
+                source: NodeNetworkingPublicKey([0; 32]),
+                signature: None,
+            },
+        );
+        let entry = DhtInterface::get(&dht, prefix, b"key").await.unwrap();
+        assert_eq!(entry.value, b"old");
+
+        // After a put, the old value is no longer served from the cache.
+        DhtInterface::put(&dht, prefix, b"key", b"new");
+        let entry = DhtInterface::get(&dht, prefix, b"key").await;
+        assert_ne!(entry.map(|entry| entry.value), Some(b"old".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn large_value_is_fetched_from_the_node_that_put_it() {
+        let (bootstrapper, info) = start_node(None).await;
+        let (publisher, publisher_info) = start_node(Some(&info)).await;
+        let (getter, _) = start_node(Some(&info)).await;
+
+        let value: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+        publisher.put(b"key", &value);
+
+        // The put reaches the bootstrapper asynchronously.
+        let entry = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if let Some(entry) = getter.get(b"key").await {
+                    break entry;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("value to be found");
+        assert_eq!(entry.value, value);
+        assert_eq!(entry.source, publisher_info.key);
+
+        // Only the root went through the DHT, the content was fetched from the publisher.
+        let Value::Content(root) = content::encode_value(&MemoryBlockStore::default(), &value)
+            .await
+            .unwrap()
+        else {
+            panic!("expected the value to be content addressed");
+        };
+        assert!(bootstrapper.blockstore.get_tree(&root).await.is_none());
+        assert_eq!(
+            content::read_content(&getter.blockstore, &root).await,
+            Some(value)
+        );
+    }
+
+    #[tokio::test]
+    async fn get_all_returns_the_providers_stored_on_another_node() {
+        let (bootstrapper, info) = start_node(None).await;
+        let (publisher, _) = start_node(Some(&info)).await;
+        let (getter, _) = start_node(Some(&info)).await;
+
+        let providers = (0..3)
+            .map(|_| NodeNetworkingSecretKey::generate())
+            .collect::<Vec<_>>();
+        for provider in &providers {
+            let mut entry = TableEntry {
+                prefix: KeyPrefix::ContentRegistry,
+                key: b"content".to_vec(),
+                value: provider.to_pk().0.to_vec(),
+                source: provider.to_pk(),
+                signature: None,
+            };
+            entry.signature = Some(provider.sign(&entry.to_digest()));
+            publisher.put_entry(entry).unwrap();
+        }
+
+        // The entries reach the bootstrapper asynchronously.
+        let entries = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let entries = getter.get_all(b"content").await;
+                if entries.len() == providers.len() {
+                    break entries;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("entries to be found");
+        for provider in &providers {
+            assert!(entries.iter().any(|entry| entry.source == provider.to_pk()));
+        }
+
+        // The getter has none of the entries itself, they were retrieved from the bootstrapper.
+        let stored = |dht: &Dht<MemoryBlockStore>| {
+            dht.store
+                .lock()
+                .unwrap()
+                .get_all(KeyPrefix::ContentRegistry, b"content")
+                .len()
+        };
+        assert_eq!(stored(&getter), 0);
+        assert_eq!(stored(&bootstrapper), providers.len());
     }
 }
@@ -1,7 +1,7 @@
 use std::{
     collections::{hash_map::Entry, HashMap},
     net::SocketAddr,
-    sync::Arc,
+    sync::{Arc, Mutex},
 };
 
 use anyhow::Result;
@@ -19,10 +19,12 @@
 
 use crate::{
     lookup,
-    lookup::{LookupResult, LookupTask, ResponseEvent},
-    query::{Message, MessageType, NodeInfo, Query, Response},
+    lookup::{LookupKind, LookupResult, LookupTask, ResponseEvent},
+    query::{Message, MessageType, NodeInfo, Query, Response, Value, ValueRecord},
+    retry::RetryPolicy,
     socket,
-    table::{TableCommand, TableKey},
+    store::{MultiValueStore, ValueStore},
+    table::{DeriveTableKey, TableCommand, TableKey},
 };
 
 pub const NO_REPLY_CHANNEL_ID: u64 = 0;
@@ -32,12 +34,18 @@ pub async fn start_worker(
     table_tx: Sender<TableCommand>,
     socket: Arc<UdpSocket>,
     local_key: NodeNetworkingPublicKey,
+    retry: RetryPolicy,
+    values: Arc<Mutex<ValueStore>>,
+    entries: Arc<Mutex<MultiValueStore>>,
 ) {
     let mut handler = Handler {
         pending: HashMap::new(),
         local_key,
+        retry,
         table_tx: table_tx.clone(),
         socket: socket.clone(),
+        values,
+        entries,
         received_shutdown: false,
     };
     loop {
@@ -74,6 +82,8 @@ async fn handle_query(
     table_tx: Sender<TableCommand>,
     socket: Arc<UdpSocket>,
     local_key: NodeNetworkingPublicKey,
+    values: Arc<Mutex<ValueStore>>,
+    entries: Arc<Mutex<MultiValueStore>>,
     message: Message,
     address: SocketAddr,
 ) -> Result<()> {
@@ -86,12 +96,18 @@ async fn handle_query(
                 .await
                 .expect("table worker to not drop the channel");
             let nodes = rx.await.expect("table worker to not drop the channel")?;
-            let value = match find_value {
-                // Todo: Check from local store.
-                true => todo!(),
-                false => None,
+            let (value, entries) = match find_value {
+                true => (
+                    values.lock().unwrap().get(&target),
+                    entries.lock().unwrap().get_all_by_table_key(&target),
+                ),
+                false => (None, Vec::new()),
             };
-            let payload = bincode::serialize(&Response { nodes, value })?;
+            let payload = bincode::serialize(&Response {
+                nodes,
+                value,
+                entries,
+            })?;
             let response = Message {
                 ty: MessageType::Response,
                 token: message.token,
@@ -102,14 +118,31 @@ async fn handle_query(
             let bytes = bincode::serialize(&response)?;
             socket::send_to(&socket, bytes.as_slice(), address).await?;
         },
-        Query::Store { .. } => {
-            // Todo: How do we avoid someone sending tons of Store queries.
-            todo!()
+        Query::Store { key, value } => {
+            // The store is bounded, so it can not be filled up by someone sending tons of Store
+            // queries. The content of large values is served by the node that put them.
+            let record = ValueRecord {
+                source: NodeInfo {
+                    address,
+                    key: message.sender_key,
+                },
+                value,
+            };
+            if let Err(e) = values.lock().unwrap().insert(key, record) {
+                tracing::trace!("rejected value from {address}: {e:?}");
+            }
+        },
+        Query::StoreEntry { entry } => {
+            // Only entries signed by their source are accepted, and the store is bounded.
+            if let Err(e) = entries.lock().unwrap().insert(entry) {
+                tracing::trace!("rejected entry from {address}: {e:?}");
+            }
         },
         Query::Ping => {
             let payload = bincode::serialize(&Response {
                 nodes: Vec::new(),
                 value: None,
+                entries: Vec::new(),
             })?;
             let response = Message {
                 ty: MessageType::Response,
@@ -125,15 +158,59 @@ async fn handle_query(
     Ok(())
 }
 
+/// Send the store query to the nodes closest to the target of the lookup.
+async fn store(
+    task: LookupTask,
+    socket: Arc<UdpSocket>,
+    sender_key: NodeNetworkingPublicKey,
+    query: Query,
+) {
+    let nodes = match lookup::lookup(task).await {
+        Ok(lookup_result) => match lookup_result {
+            LookupResult::Nodes(nodes) => nodes,
+            _ => panic!("we did not request for a value"),
+        },
+        Err(e) => {
+            tracing::error!("failed to handle PUT command: {e:?}");
+            return;
+        },
+    };
+
+    let payload = bincode::serialize(&query).expect("query to be valid");
+    let message = Message {
+        ty: MessageType::Query,
+        id: NO_REPLY_CHANNEL_ID,
+        token: rand::random(),
+        sender_key,
+        payload,
+    };
+    let bytes = bincode::serialize(&message).expect("Serialization to succeed");
+    for node in nodes {
+        if let Err(e) = socket::send_to(&socket, &bytes, node.address).await {
+            tracing::error!("failed to send datagram {e:?}");
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum HandlerCommand {
     Get {
+        prefix: KeyPrefix,
         key: Vec<u8>,
-        tx: oneshot::Sender<Result<Option<TableEntry>>>,
+        tx: oneshot::Sender<Result<Option<ValueRecord>>>,
     },
     Put {
+        prefix: KeyPrefix,
         key: Vec<u8>,
-        value: Vec<u8>,
+        value: Value,
+    },
+    GetAll {
+        prefix: KeyPrefix,
+        key: Vec<u8>,
+        tx: oneshot::Sender<Result<Vec<TableEntry>>>,
+    },
+    PutEntry {
+        entry: TableEntry,
     },
     FindNode {
         target: NodeNetworkingPublicKey,
@@ -147,7 +224,12 @@ struct Handler {
     local_key: NodeNetworkingPublicKey,
     table_tx: Sender<TableCommand>,
     socket: Arc<UdpSocket>,
+    /// The values put on this node by other nodes.
+    values: Arc<Mutex<ValueStore>>,
+    /// The signed entries stored on this node, shared with the DHT.
+    entries: Arc<Mutex<MultiValueStore>>,
     received_shutdown: bool,
+    retry: RetryPolicy,
 }
 
 impl Handler {
@@ -157,16 +239,17 @@ fn handle_command(&mut self, command: HandlerCommand) -> Result<()> {
         self.pending.insert(task_id, event_tx);
 
         match command {
-            HandlerCommand::Get { key, tx } => {
-                let target = TableKey::try_from(key.as_slice())?;
+            HandlerCommand::Get { prefix, key, tx } => {
+                let target = TableKey::derive(prefix, &key);
                 let task = LookupTask::new(
                     task_id,
-                    true,
+                    LookupKind::Value,
                     self.local_key,
                     target,
                     self.table_tx.clone(),
                     event_rx,
                     self.socket.clone(),
+                    self.retry,
                 );
 
                 tokio::spawn(async move {
@@ -174,20 +257,11 @@ fn handle_command(&mut self, command: HandlerCommand) -> Result<()> {
                     match lookup::lookup(task).await {
                         Ok(lookup_result) => {
                             let value = match lookup_result {
-                                LookupResult::Nodes(_) => panic!("we did not request for a nodes"),
                                 LookupResult::Value(value) => value,
+                                _ => panic!("we did not request for a value"),
                             };
 
-                            let entry = TableEntry {
-                                prefix: KeyPrefix::ContentRegistry,
-                                key,
-                                value: value.unwrap_or_default(),
-                                // Todo: make sure we keep track of source.
-                                source: NodeNetworkingPublicKey(rand::random()),
-                                signature: None,
-                            };
-
-                            if tx.send(Ok(Some(entry))).is_err() {
+                            if tx.send(Ok(value)).is_err() {
                                 tracing::error!("client dropped channel for Get respose")
                             }
                         },
@@ -200,58 +274,70 @@ fn handle_command(&mut self, command: HandlerCommand) -> Result<()> {
                     }
                 });
             },
-            HandlerCommand::Put { key, value } => {
-                let socket_clone = self.socket.clone();
-                let sender_key = self.local_key;
-                let target = TableKey::try_from(key.as_slice())?;
+            HandlerCommand::Put { prefix, key, value } => {
+                let target = TableKey::derive(prefix, &key);
                 let task = LookupTask::new(
                     task_id,
-                    false,
+                    LookupKind::Nodes,
                     self.local_key,
                     target,
                     self.table_tx.clone(),
                     event_rx,
                     self.socket.clone(),
+                    self.retry,
+                );
+                let query = Query::Store { key: target, value };
+                tokio::spawn(store(task, self.socket.clone(), self.local_key, query));
+            },
+            HandlerCommand::GetAll { prefix, key, tx } => {
+                let task = LookupTask::new(
+                    task_id,
+                    LookupKind::Entries,
+                    self.local_key,
+                    TableKey::derive(prefix, &key),
+                    self.table_tx.clone(),
+                    event_rx,
+                    self.socket.clone(),
+                    self.retry,
                 );
 
                 tokio::spawn(async move {
-                    let nodes = match lookup::lookup(task).await {
-                        Ok(lookup_result) => match lookup_result {
-                            LookupResult::Nodes(nodes) => nodes,
-                            LookupResult::Value(_) => panic!("we did not request for a nodes"),
-                        },
-                        Err(e) => {
-                            tracing::error!("failed to handle PUT command: {e:?}");
-                            return;
-                        },
-                    };
-
-                    let payload = bincode::serialize(&Query::Store { key: target, value })
-                        .expect("query to be valid");
-                    let message = Message {
-                        ty: MessageType::Query,
-                        id: NO_REPLY_CHANNEL_ID,
-                        token: rand::random(),
-                        sender_key,
-                        payload,
-                    };
-                    let bytes = bincode::serialize(&message).expect("Serialization to succeed");
-                    for node in nodes {
-                        if let Err(e) = socket::send_to(&socket_clone, &bytes, node.address).await {
-                            tracing::error!("failed to send datagram {e:?}");
-                        }
+                    let result = lookup::lookup(task).await.map(|result| match result {
+                        LookupResult::Entries(entries) => entries,
+                        _ => panic!("we did not request for entries"),
+                    });
+                    if let Err(e) = &result {
+                        tracing::error!("lookup failed: {e:?}");
+                    }
+                    if tx.send(result.map_err(Into::into)).is_err() {
+                        tracing::error!("client dropped channel for GetAll respose")
                     }
                 });
             },
+            HandlerCommand::PutEntry { entry } => {
+                let task = LookupTask::new(
+                    task_id,
+                    LookupKind::Nodes,
+                    self.local_key,
+                    TableKey::derive(entry.prefix, &entry.key),
+                    self.table_tx.clone(),
+                    event_rx,
+                    self.socket.clone(),
+                    self.retry,
+                );
+                let query = Query::StoreEntry { entry };
+                tokio::spawn(store(task, self.socket.clone(), self.local_key, query));
+            },
             HandlerCommand::FindNode { target, tx } => {
                 let task = LookupTask::new(
                     task_id,
-                    false,
+                    LookupKind::Nodes,
                     self.local_key,
                     target.0,
                     self.table_tx.clone(),
                     event_rx,
                     self.socket.clone(),
+                    self.retry,
                 );
 
                 tokio::spawn(async move {
@@ -259,7 +345,7 @@ fn handle_command(&mut self, command: HandlerCommand) -> Result<()> {
                         Ok(lookup_result) => {
                             let nodes = match lookup_result {
                                 LookupResult::Nodes(nodes) => nodes,
-                                LookupResult::Value(_) => panic!("we did not request for a nodes"),
+                                _ => panic!("we did not request for a value"),
                             };
                             tx.send(Ok(nodes))
                                 .expect("client dropped channel for FindNode respose")
@@ -285,6 +371,8 @@ fn handle_incoming(&mut self, datagram: Vec<u8>, address: SocketAddr) -> Result<
                     self.table_tx.clone(),
                     self.socket.clone(),
                     self.local_key,
+                    self.values.clone(),
+                    self.entries.clone(),
                     message,
                     address,
                 ));
@@ -297,11 +385,11 @@ fn handle_incoming(&mut self, datagram: Vec<u8>, address: SocketAddr) -> Result<
                     } else {
                         let response: Response = bincode::deserialize(&message.payload)?;
                         let event_tx = event_tx.get().clone();
-                        let sender_key = self.local_key;
+                        let sender_key = message.sender_key;
                         tokio::spawn(async move {
                             if event_tx
                                 .send(ResponseEvent {
-                                    id: message.token,
+                                    token: message.token,
                                     sender_key,
                                     response,
                                 })
@@ -1,5 +1,7 @@
 mod bootstrap;
 mod bucket;
+mod cache;
+mod content;
 mod distance;
 mod handler;
 mod lookup;
@@ -8,3 +10,6 @@
 mod table;
 
 pub mod dht;
+pub mod maintenance;
+pub mod retry;
+pub mod store;
@@ -1,10 +1,12 @@
 use std::{
     collections::{BTreeMap, HashMap},
+    net::SocketAddr,
     sync::Arc,
-    time::Duration,
+    time::Instant,
 };
 
 use fleek_crypto::NodeNetworkingPublicKey;
+use lightning_interfaces::dht::TableEntry;
 use thiserror::Error;
 use tokio::{
     net::UdpSocket,
@@ -19,11 +21,15 @@
 use crate::{
     bucket::MAX_BUCKET_SIZE,
     distance::{self, Distance},
-    query::{Message, MessageType, NodeInfo, Query, Response},
+    query::{Message, MessageType, NodeInfo, Query, Response, ValueRecord},
+    retry::RetryPolicy,
     socket,
-    table::{TableCommand, TableKey},
+    table::{DeriveTableKey, TableCommand, TableKey},
 };
 
+/// The number of queries sent concurrently, Kademlia's alpha parameter.
+pub const ALPHA: usize = 3;
+
 /// Kademlia's lookup procedure.
 pub async fn lookup(mut lookup: LookupTask) -> Result<LookupResult, LookUpError> {
     // Get initial K closest nodes from our local table.
@@ -57,127 +63,132 @@ pub async fn lookup(mut lookup: LookupTask) -> Result<LookupResult, LookUpError>
         .collect();
     lookup.closest_nodes.insert_new_entries(nodes);
 
-    // Nodes on which we are waiting for a response.
-    let mut pending = HashMap::new();
-    // Nodes that didn't send a response in time.
-    let mut late = HashMap::new();
-    // Timeout for every round.
-    let mut timeout = time::interval(Duration::from_secs(4));
+    // Queries that are waiting for a response.
+    let mut queries = PendingQueries::new(lookup.retry);
+    // Entries collected from the responses of an entries lookup, at most one per source.
+    let mut entries: Vec<TableEntry> = Vec::new();
     loop {
         // Pending is empty when a round has finished.
-        if pending.is_empty() {
+        if queries.is_empty() {
             for node in lookup
                 .closest_nodes
-                .pickout(MAX_BUCKET_SIZE, 3, |node| node.status == Status::Initial)
-            {
-                let id = rand::random();
-                let payload = bincode::serialize(&Query::Find {
-                    find_value: lookup.find_value_lookup,
-                    target: lookup.target,
+                .pickout(MAX_BUCKET_SIZE, ALPHA, |node| {
+                    node.status == Status::Initial
                 })
-                .expect("query to be valid");
-                let message = Message {
-                    ty: MessageType::Query,
-                    token: id,
-                    id: lookup.id,
-                    sender_key: lookup.local_key,
-                    payload,
-                };
-                let bytes = bincode::serialize(&message).expect("query to be valid");
-                socket::send_to(&lookup.socket, bytes.as_slice(), node.inner.address)
-                    .await
-                    .map_err(|e| LookUpError(e.to_string()))?;
-                pending.insert(
-                    node.inner.key,
-                    PendingResponse {
-                        node: node.inner,
-                        id,
-                    },
-                );
-            }
-            if !pending.is_empty() {
-                // We have found closer nodes so we start another round.
-                timeout.reset();
+            {
+                let token = rand::random();
+                lookup.send_query(node.inner.address, token).await?;
+                queries.insert(node.inner, token, Instant::now());
             }
         }
 
+        // If no query was sent, we did not find closer nodes in the last round and we are done.
+        let deadline = queries.next_deadline().unwrap_or_else(Instant::now);
+
         select! {
             // We want timeout to be polled first to control round switches
             // and avoid network channel always being ready.
             biased;
-            // Timeout for round.
-            _ = timeout.tick() => {
-                if pending.is_empty() {
+            // Timeout for the queries of the round.
+            _ = time::sleep_until(deadline.into()) => {
+                if queries.is_empty() {
                     // This can't be empty at this point if we found closer nodes in the last round
                     // because it should have been filled at the start of the loop.
                     break;
                 }
-                for (key, node) in pending.into_iter() {
-                    late.insert(key, node);
+                for expired in queries.expire(Instant::now()) {
+                    match expired {
+                        Expired::Retry { address, token } => {
+                            lookup.send_query(address, token).await?;
+                        },
+                        Expired::Unresponsive(node) => {
+                            if lookup
+                                .table_tx
+                                .send(TableCommand::RemoveNode { key: node.key })
+                                .await
+                                .is_err()
+                            {
+                                tracing::error!("failed to send to table command");
+                            }
+                        },
+                    }
                 }
-                pending = HashMap::new();
                 continue;
             }
             // Incoming K nodes from peers.
             message = lookup.main_rx.recv() => {
                 let response_event = message.expect("dispatcher worker not to drop the channel");
                 let sender_key = response_event.sender_key;
-                let response_id = response_event.id;
+                let response_token = response_event.token;
                 let response = response_event.response;
-                if pending.contains_key(&sender_key) || late.contains_key(&sender_key) {
-                    // Validate id in response.
-                    let expected_id = match pending.get(&sender_key) {
-                        Some(pending) => pending.id,
-                        // It's fine to use unwrap here because of the of condition.
-                        None => late.get(&sender_key).unwrap().id,
-                    };
-
-                    // If the id does not match, we ignore this response.
-                    if expected_id != response_id {
-                        tracing::trace!("expected id {expected_id} but received instead {response_id}");
+                match queries.check(&sender_key, response_token) {
+                    TokenCheck::Valid => {},
+                    TokenCheck::Mismatch { expected } => {
+                        // The response was not sent by the node we queried, someone could be
+                        // trying to spoof responses. We ignore it and keep waiting for the
+                        // real one.
+                        tracing::warn!(
+                            "token mismatch from {sender_key:?}: expected {expected}, got {}",
+                            response_token
+                        );
                         continue;
-                    }
+                    },
+                    TokenCheck::Unsolicited => {
+                        tracing::warn!("received unsolicited list of nodes from {sender_key:?}");
+                        continue;
+                    },
+                }
 
-                    // If this is look up is a find a value, we check if the value is in the response.
-                    if lookup.find_value_lookup && response.value.is_some() {
+                match lookup.kind {
+                    // If this is look up is a find a value, we check if the value is in the
+                    // response.
+                    LookupKind::Value if response.value.is_some() => {
                         return Ok(LookupResult::Value(response.value));
-                    }
-
-                    let nodes = response
-                        .nodes
-                        .into_iter()
-                        .map(|node| {
-                            (
-                                node.key.0,
-                                LookupNode {
-                                    inner: node,
-                                    status: Status::Initial,
-                                },
-                            )
-                        })
-                        .collect();
-
-                    // Add new nodes to closest nodes list.
-                    lookup.closest_nodes.insert_new_entries(nodes);
-
-                    // Remove sender from pending list.
-                    let node = match pending.remove(&sender_key) {
-                        Some(pending) => pending.node,
-                        // It's fine to use unwrap here because of the of condition.
-                        None => late.remove(&sender_key).unwrap().node,
-                    };
-
-                    // Put this node back to closest nodes list.
-                    assert!(lookup.closest_nodes.update(
-                        node.key.0,
-                        LookupNode {
-                            inner: node,
-                            status: Status::Responded
+                    },
+                    LookupKind::Entries => {
+                        for entry in response.entries {
+                            // A node could respond with entries it was never given, so only the
+                            // entries signed by their source for the target are kept.
+                            if entry.is_signature_valid()
+                                && TableKey::derive(entry.prefix, &entry.key) == lookup.target
+                                && !entries.iter().any(|known| known.source == entry.source)
+                            {
+                                entries.push(entry);
+                            }
                         }
-                    ).is_none());
-                } else {
-                    tracing::warn!("received unsolicited list of nodes from {sender_key:?}");
+                    },
+                    _ => {},
                 }
+
+                let nodes = response
+                    .nodes
+                    .into_iter()
+                    .map(|node| {
+                        (
+                            node.key.0,
+                            LookupNode {
+                                inner: node,
+                                status: Status::Initial,
+                            },
+                        )
+                    })
+                    .collect();
+
+                // Add new nodes to closest nodes list.
+                lookup.closest_nodes.insert_new_entries(nodes);
+
+                // Remove sender from pending list.
+                // It's fine to use unwrap here because the token was validated.
+                let node = queries.remove(&sender_key).unwrap();
+
+                // Put this node back to closest nodes list.
+                assert!(lookup.closest_nodes.update(
+                    node.key.0,
+                    LookupNode {
+                        inner: node,
+                        status: Status::Responded
+                    }
+                ).is_none());
             }
         }
     }
@@ -185,20 +196,32 @@ pub async fn lookup(mut lookup: LookupTask) -> Result<LookupResult, LookUpError>
     // Close channel to indicate to dispatcher that this task is done.
     lookup.main_rx.close();
 
-    if lookup.find_value_lookup {
-        Ok(LookupResult::Value(None))
-    } else {
-        Ok(LookupResult::Nodes(
+    match lookup.kind {
+        LookupKind::Nodes => Ok(LookupResult::Nodes(
             lookup
                 .closest_nodes
                 .into_nodes()
                 .map(|lookup_node| lookup_node.inner)
                 .take(MAX_BUCKET_SIZE)
                 .collect(),
-        ))
+        )),
+        LookupKind::Value => Ok(LookupResult::Value(None)),
+        LookupKind::Entries => Ok(LookupResult::Entries(entries)),
     }
 }
 
+/// What a lookup is looking for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LookupKind {
+    /// The nodes closest to the target.
+    Nodes,
+    /// The value stored under the target. The lookup ends at the first node that has it.
+    Value,
+    /// The entries stored under the target. The entries of every node that responds are
+    /// collected.
+    Entries,
+}
+
 #[derive(Debug, Error)]
 #[error("lookup procedure failed: {0}")]
 pub struct LookUpError(String);
@@ -206,8 +229,8 @@ pub async fn lookup(mut lookup: LookupTask) -> Result<LookupResult, LookUpError>
 pub struct LookupTask {
     // Task identifier.
     id: u64,
-    // True if this is a `find value` look up.
-    find_value_lookup: bool,
+    // What we are looking for.
+    kind: LookupKind,
     // Closest nodes.
     closest_nodes: LookupMap<LookupNode>,
     // Our node's local key.
@@ -220,29 +243,53 @@ pub struct LookupTask {
     main_rx: Receiver<ResponseEvent>,
     // Socket to send queries over the network.
     socket: Arc<UdpSocket>,
+    // Policy for retrying queries that time out.
+    retry: RetryPolicy,
 }
 
 impl LookupTask {
     pub fn new(
         task_id: u64,
-        find_value_lookup: bool,
+        kind: LookupKind,
         local_key: NodeNetworkingPublicKey,
         target: TableKey,
         table_tx: Sender<TableCommand>,
         main_rx: Receiver<ResponseEvent>,
         socket: Arc<UdpSocket>,
+        retry: RetryPolicy,
     ) -> Self {
         Self {
             id: task_id,
-            find_value_lookup,
+            kind,
             closest_nodes: LookupMap::new(target),
             local_key,
             target,
             table_tx,
             main_rx,
             socket,
+            retry,
         }
     }
+
+    /// Send the query for the target to the given address.
+    async fn send_query(&self, address: SocketAddr, token: u64) -> Result<(), LookUpError> {
+        let payload = bincode::serialize(&Query::Find {
+            find_value: self.kind != LookupKind::Nodes,
+            target: self.target,
+        })
+        .expect("query to be valid");
+        let message = Message {
+            ty: MessageType::Query,
+            token,
+            id: self.id,
+            sender_key: self.local_key,
+            payload,
+        };
+        let bytes = bincode::serialize(&message).expect("query to be valid");
+        socket::send_to(&self.socket, bytes.as_slice(), address)
+            .await
+            .map_err(|e| LookUpError(e.to_string()))
+    }
 }
 
 struct LookupMap<V> {
@@ -319,16 +366,225 @@ pub struct LookupNode {
 
 pub enum LookupResult {
     Nodes(Vec<NodeInfo>),
-    Value(Option<Vec<u8>>),
+    Value(Option<ValueRecord>),
+    Entries(Vec<TableEntry>),
 }
 
 struct PendingResponse {
     node: NodeInfo,
-    id: u64,
+    token: u64,
+    // Number of times the query was sent.
+    attempts: u32,
+    // Time at which the current attempt times out.
+    deadline: Instant,
+}
+
+/// The queries of a lookup that are waiting for a response, along with the token that each
+/// response must carry.
+///
+/// The current time is always passed in by the caller, so the retries can be driven by any clock.
+struct PendingQueries {
+    policy: RetryPolicy,
+    // Nodes on which we are waiting for a response.
+    pending: HashMap<NodeNetworkingPublicKey, PendingResponse>,
+    // Nodes that didn't send a response in time.
+    late: HashMap<NodeNetworkingPublicKey, PendingResponse>,
+}
+
+/// A query that timed out.
+#[derive(Debug)]
+enum Expired {
+    /// The query must be sent again, with the same token.
+    Retry { address: SocketAddr, token: u64 },
+    /// The node did not respond to any of the attempts.
+    Unresponsive(NodeInfo),
+}
+
+#[derive(Debug, PartialEq)]
+enum TokenCheck {
+    Valid,
+    Mismatch { expected: u64 },
+    Unsolicited,
+}
+
+impl PendingQueries {
+    fn new(policy: RetryPolicy) -> Self {
+        Self {
+            policy,
+            pending: HashMap::new(),
+            late: HashMap::new(),
+        }
+    }
+
+    /// Track a query that was just sent to the node.
+    fn insert(&mut self, node: NodeInfo, token: u64, now: Instant) {
+        let delay = self.policy.delay(0).unwrap_or(self.policy.base_delay);
+        self.pending.insert(
+            node.key,
+            PendingResponse {
+                node,
+                token,
+                attempts: 1,
+                deadline: now + delay,
+            },
+        );
+    }
+
+    /// Returns true if there are no queries left in the current round.
+    fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Returns the earliest time at which one of the queries of the current round times out.
+    fn next_deadline(&self) -> Option<Instant> {
+        self.pending.values().map(|pending| pending.deadline).min()
+    }
+
+    /// Handle the queries that timed out by `now`. The queries that have attempts left are
+    /// retried with a longer timeout, the others are marked as late.
+    fn expire(&mut self, now: Instant) -> Vec<Expired> {
+        let timed_out = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.deadline <= now)
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+
+        let mut expired = Vec::with_capacity(timed_out.len());
+        for key in timed_out {
+            let mut pending = self.pending.remove(&key).expect("key to be pending");
+            match self.policy.delay(pending.attempts) {
+                Some(delay) => {
+                    pending.attempts += 1;
+                    pending.deadline = now + delay;
+                    expired.push(Expired::Retry {
+                        address: pending.node.address,
+                        token: pending.token,
+                    });
+                    self.pending.insert(key, pending);
+                },
+                None => {
+                    expired.push(Expired::Unresponsive(pending.node.clone()));
+                    self.late.insert(key, pending);
+                },
+            }
+        }
+        expired
+    }
+
+    /// Check the token of a response against the token that was sent to the node.
+    fn check(&self, sender: &NodeNetworkingPublicKey, token: u64) -> TokenCheck {
+        match self.pending.get(sender).or_else(|| self.late.get(sender)) {
+            Some(pending) if pending.token == token => TokenCheck::Valid,
+            Some(pending) => TokenCheck::Mismatch {
+                expected: pending.token,
+            },
+            None => TokenCheck::Unsolicited,
+        }
+    }
+
+    fn remove(&mut self, sender: &NodeNetworkingPublicKey) -> Option<NodeInfo> {
+        self.pending
+            .remove(sender)
+            .or_else(|| self.late.remove(sender))
+            .map(|pending| pending.node)
+    }
 }
 
 pub struct ResponseEvent {
-    pub id: u64,
+    pub token: u64,
     pub sender_key: NodeNetworkingPublicKey,
     pub response: Response,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn node_info() -> NodeInfo {
+        NodeInfo {
+            address: "127.0.0.1:0".parse().unwrap(),
+            key: NodeNetworkingPublicKey(rand::random()),
+        }
+    }
+
+    #[test]
+    fn test_response_with_wrong_token_is_discarded() {
+        let node = node_info();
+        let key = node.key;
+        let mut queries = PendingQueries::new(RetryPolicy::default());
+        queries.insert(node, 7, Instant::now());
+
+        assert_eq!(queries.check(&key, 8), TokenCheck::Mismatch { expected: 7 });
+        // The request is still waiting for the real response.
+        assert!(!queries.is_empty());
+        assert_eq!(queries.check(&key, 7), TokenCheck::Valid);
+        assert!(queries.remove(&key).is_some());
+        assert!(queries.is_empty());
+    }
+
+    #[test]
+    fn test_late_response_token() {
+        let node = node_info();
+        let key = node.key;
+        let policy = RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        };
+        let mut queries = PendingQueries::new(policy);
+        let now = Instant::now();
+        queries.insert(node, 7, now);
+        let expired = queries.expire(now + policy.base_delay);
+
+        assert!(matches!(expired[..], [Expired::Unresponsive(_)]));
+        assert!(queries.is_empty());
+        assert_eq!(queries.check(&key, 8), TokenCheck::Mismatch { expected: 7 });
+        assert_eq!(queries.check(&key, 7), TokenCheck::Valid);
+        assert_eq!(
+            queries.check(&NodeNetworkingPublicKey(rand::random()), 7),
+            TokenCheck::Unsolicited
+        );
+    }
+
+    #[test]
+    fn test_query_retries_with_backoff() {
+        let node = node_info();
+        let address = node.address;
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(100),
+            multiplier: 2,
+            max_attempts: 3,
+        };
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        let mut queries = PendingQueries::new(policy);
+        queries.insert(node, 7, start);
+        assert_eq!(queries.next_deadline(), Some(at(100)));
+
+        // Nothing happens before the deadline.
+        assert!(queries.expire(at(99)).is_empty());
+
+        // The first retry waits twice as long.
+        let expired = queries.expire(at(100));
+        assert!(matches!(
+            expired[..],
+            [Expired::Retry { address: a, token: 7 }] if a == address
+        ));
+        assert_eq!(queries.next_deadline(), Some(at(300)));
+        assert!(queries.expire(at(299)).is_empty());
+
+        // The second retry waits twice as long again.
+        let expired = queries.expire(at(300));
+        assert!(matches!(expired[..], [Expired::Retry { token: 7, .. }]));
+        assert_eq!(queries.next_deadline(), Some(at(700)));
+
+        // And after the last attempt we give up on the node.
+        let expired = queries.expire(at(700));
+        assert!(matches!(expired[..], [Expired::Unresponsive(_)]));
+        assert!(queries.is_empty());
+        assert_eq!(queries.next_deadline(), None);
+    }
+}
@@ -0,0 +1,548 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use lightning_interfaces::{dht::KeyPrefix, Blake3Hash};
+use tokio::{
+    sync::{
+        mpsc::{Receiver, Sender},
+        watch,
+    },
+    task::JoinHandle,
+};
+
+use crate::{
+    bootstrap, cache::ValueCache, handler::HandlerCommand, query::Value, table::TableCommand,
+};
+
+/// Default interval between two refreshes of the buckets of the routing table.
+pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Default interval between two republishes of the values put by this node.
+pub const DEFAULT_REPUBLISH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Default time for which a value put by this node keeps being republished.
+pub const DEFAULT_PUBLISH_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Default maximum number of values put by this node that are republished.
+pub const DEFAULT_MAX_PUBLISHED: usize = 10_000;
+/// Default interval between two sweeps of the expired entries of the value cache.
+pub const DEFAULT_EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The source of time for the maintenance scheduler.
+#[async_trait]
+pub trait Clock: Send + Sync + 'static {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+
+    /// Wait until the clock reaches the deadline.
+    async fn sleep_until(&self, deadline: Instant);
+}
+
+/// A [`Clock`] backed by the tokio timer.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    async fn sleep_until(&self, deadline: Instant) {
+        tokio::time::sleep_until(deadline.into()).await
+    }
+}
+
+/// A [`Clock`] that only moves when it is advanced manually.
+#[derive(Debug)]
+pub struct MockClock {
+    now: watch::Sender<Instant>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new(Instant::now())
+    }
+}
+
+impl MockClock {
+    /// Create a clock that starts at the given time.
+    pub fn new(start: Instant) -> Self {
+        Self {
+            now: watch::channel(start).0,
+        }
+    }
+
+    /// Move the clock forward, waking up everyone waiting for a deadline that has passed.
+    pub fn advance(&self, duration: Duration) {
+        self.now.send_modify(|now| *now += duration);
+    }
+}
+
+#[async_trait]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now.borrow()
+    }
+
+    async fn sleep_until(&self, deadline: Instant) {
+        let mut rx = self.now.subscribe();
+        while *rx.borrow_and_update() < deadline {
+            // The sender lives as long as the clock, which is borrowed for this call.
+            let _ = rx.changed().await;
+        }
+    }
+}
+
+/// The intervals of the periodic maintenance of the DHT.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MaintenanceConfig {
+    /// How often the buckets of the routing table are refreshed with a lookup.
+    pub refresh_interval: Duration,
+    /// How often the values put by this node are sent out again.
+    pub republish_interval: Duration,
+    /// How long a value put by this node keeps being sent out again. Putting the value again
+    /// restarts its lifetime.
+    pub publish_ttl: Duration,
+    /// How often the expired entries are removed from the value cache.
+    pub expiry_interval: Duration,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            refresh_interval: DEFAULT_REFRESH_INTERVAL,
+            republish_interval: DEFAULT_REPUBLISH_INTERVAL,
+            publish_ttl: DEFAULT_PUBLISH_TTL,
+            expiry_interval: DEFAULT_EXPIRY_INTERVAL,
+        }
+    }
+}
+
+/// A periodic maintenance task.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Maintenance {
+    Refresh,
+    Republish,
+    ExpirySweep,
+}
+
+/// Keeps track of when each maintenance task is due next.
+pub struct MaintenanceScheduler {
+    clock: Arc<dyn Clock>,
+    /// The interval and the next deadline of every task.
+    tasks: Vec<(Maintenance, Duration, Instant)>,
+}
+
+impl MaintenanceScheduler {
+    /// Create a scheduler whose tasks are first due one interval from now.
+    pub fn new(clock: Arc<dyn Clock>, config: MaintenanceConfig) -> Self {
+        let now = clock.now();
+        let tasks = [
+            (Maintenance::Refresh, config.refresh_interval),
+            (Maintenance::Republish, config.republish_interval),
+            (Maintenance::ExpirySweep, config.expiry_interval),
+        ]
+        .into_iter()
+        .map(|(task, interval)| (task, interval, now + interval))
+        .collect();
+        Self { clock, tasks }
+    }
+
+    /// Returns the earliest deadline of all the tasks.
+    pub fn next_deadline(&self) -> Instant {
+        self.tasks
+            .iter()
+            .map(|(_, _, deadline)| *deadline)
+            .min()
+            .expect("there to be maintenance tasks")
+    }
+
+    /// Returns the tasks that are due and schedules each of them again one interval from now.
+    ///
+    /// A task is returned once even if the clock moved past several of its intervals, missed
+    /// cycles are not caught up on.
+    pub fn due(&mut self) -> Vec<Maintenance> {
+        let now = self.clock.now();
+        self.tasks
+            .iter_mut()
+            .filter(|(_, _, deadline)| *deadline <= now)
+            .map(|(task, interval, deadline)| {
+                *deadline = now + *interval;
+                *task
+            })
+            .collect()
+    }
+
+    /// Wait until at least one task is due and return the due tasks.
+    pub async fn tick(&mut self) -> Vec<Maintenance> {
+        loop {
+            self.clock.sleep_until(self.next_deadline()).await;
+            let due = self.due();
+            if !due.is_empty() {
+                return due;
+            }
+        }
+    }
+}
+
+/// The values put by this node, which are republished until their lifetime ends.
+///
+/// The number of values is bounded: once it is exceeded, the value that was put the longest time
+/// ago is dropped.
+pub struct Published {
+    values: HashMap<(KeyPrefix, Vec<u8>), PublishedValue>,
+    /// The keys of the values by the time they were put.
+    order: BTreeMap<u64, (KeyPrefix, Vec<u8>)>,
+    /// The key of the value that was last put for each root of content, so the content is only
+    /// served while that value is published.
+    content: HashMap<Blake3Hash, (KeyPrefix, Vec<u8>)>,
+    clock: Arc<dyn Clock>,
+    ttl: Duration,
+    max_entries: usize,
+    /// Logical clock used to order the values by the time they were put.
+    tick: u64,
+}
+
+struct PublishedValue {
+    value: Value,
+    published_at: Instant,
+    tick: u64,
+}
+
+impl Published {
+    pub fn new(clock: Arc<dyn Clock>, ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            values: HashMap::new(),
+            order: BTreeMap::new(),
+            content: HashMap::new(),
+            clock,
+            ttl,
+            max_entries,
+            tick: 0,
+        }
+    }
+
+    /// Keep republishing the value under the key, replacing the previous value.
+    pub fn insert(&mut self, prefix: KeyPrefix, key: Vec<u8>, value: Value) {
+        self.tick += 1;
+        let published = PublishedValue {
+            value,
+            published_at: self.clock.now(),
+            tick: self.tick,
+        };
+        self.order.insert(self.tick, (prefix, key.clone()));
+        let root = match &published.value {
+            Value::Content(root) => Some(*root),
+            Value::Inline(_) => None,
+        };
+        if let Some(old) = self.values.insert((prefix, key.clone()), published) {
+            self.order.remove(&old.tick);
+            self.forget_content(&old.value, &(prefix, key.clone()));
+        }
+        if let Some(root) = root {
+            self.content.insert(root, (prefix, key));
+        }
+
+        while self.values.len() > self.max_entries {
+            self.pop_oldest();
+        }
+    }
+
+    /// Drop the values whose lifetime ended and return the ones to republish.
+    pub fn live_values(&mut self) -> Vec<(KeyPrefix, Vec<u8>, Value)> {
+        let now = self.clock.now();
+        // The values are ordered by the time they were put, so the expired ones come first.
+        while let Some((_, key)) = self.order.first_key_value() {
+            if now.duration_since(self.values[key].published_at) <= self.ttl {
+                break;
+            }
+            self.pop_oldest();
+        }
+
+        self.values
+            .iter()
+            .map(|((prefix, key), published)| (*prefix, key.clone(), published.value.clone()))
+            .collect()
+    }
+
+    /// Returns true if a value that is still published has the content with the given root.
+    pub fn has_content(&self, root: &Blake3Hash) -> bool {
+        let Some(key) = self.content.get(root) else {
+            return false;
+        };
+        self.values.get(key).map_or(false, |published| {
+            published.value == Value::Content(*root)
+                && self.clock.now().duration_since(published.published_at) <= self.ttl
+        })
+    }
+
+    /// Returns the number of values that are republished.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    fn pop_oldest(&mut self) {
+        if let Some((_, key)) = self.order.pop_first() {
+            if let Some(published) = self.values.remove(&key) {
+                self.forget_content(&published.value, &key);
+            }
+        }
+    }
+
+    /// Stop serving the content of a value that is no longer published under the key.
+    fn forget_content(&mut self, value: &Value, key: &(KeyPrefix, Vec<u8>)) {
+        if let Value::Content(root) = value {
+            if self.content.get(root) == Some(key) {
+                self.content.remove(root);
+            }
+        }
+    }
+}
+
+pub enum MaintenanceCommand {
+    Shutdown,
+}
+
+pub async fn start_worker(
+    mut rx: Receiver<MaintenanceCommand>,
+    mut scheduler: MaintenanceScheduler,
+    table_tx: Sender<TableCommand>,
+    handler_tx: Sender<HandlerCommand>,
+    cache: Arc<Mutex<ValueCache>>,
+    published: Arc<Mutex<Published>>,
+) {
+    // The refresh runs lookups over the network, so it runs in its own task to not hold up the
+    // other maintenance and the shutdown.
+    let mut refresh: Option<JoinHandle<()>> = None;
+    loop {
+        tokio::select! {
+            command = rx.recv() => match command {
+                Some(MaintenanceCommand::Shutdown) | None => {
+                    tracing::trace!("shutting down maintenance worker");
+                    if let Some(refresh) = refresh {
+                        refresh.abort();
+                    }
+                    return;
+                },
+            },
+            due = scheduler.tick() => {
+                for task in due {
+                    match task {
+                        Maintenance::Refresh => {
+                            // A refresh that is still running is not started a second time.
+                            if refresh.as_ref().map_or(false, |refresh| !refresh.is_finished()) {
+                                continue;
+                            }
+                            let handler_tx = handler_tx.clone();
+                            let table_tx = table_tx.clone();
+                            refresh = Some(tokio::spawn(async move {
+                                if let Err(e) =
+                                    bootstrap::refresh_buckets(handler_tx, table_tx).await
+                                {
+                                    tracing::trace!("failed to refresh buckets: {e:?}");
+                                }
+                            }));
+                        },
+                        Maintenance::Republish => {
+                            let values = published.lock().unwrap().live_values();
+                            for (prefix, key, value) in values {
+                                if handler_tx
+                                    .send(HandlerCommand::Put { prefix, key, value })
+                                    .await
+                                    .is_err()
+                                {
+                                    tracing::error!("failed to send to handler command");
+                                }
+                            }
+                        },
+                        Maintenance::ExpirySweep => {
+                            cache.lock().unwrap().remove_expired();
+                        },
+                    }
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn advancing_past_the_interval_runs_one_cycle() {
+        let interval = Duration::from_secs(10);
+        let config = MaintenanceConfig {
+            refresh_interval: interval,
+            republish_interval: interval,
+            publish_ttl: DEFAULT_PUBLISH_TTL,
+            expiry_interval: interval,
+        };
+        let clock = Arc::new(MockClock::default());
+        let mut scheduler = MaintenanceScheduler::new(clock.clone(), config);
+
+        // Nothing is due before the interval passed.
+        clock.advance(interval - Duration::from_secs(1));
+        assert!(scheduler.tick().now_or_never().is_none());
+
+        // Moving well past the interval still triggers a single cycle.
+        clock.advance(interval * 2);
+        let due = scheduler.tick().await;
+        assert_eq!(
+            due,
+            vec![
+                Maintenance::Refresh,
+                Maintenance::Republish,
+                Maintenance::ExpirySweep
+            ]
+        );
+        assert!(scheduler.due().is_empty());
+        assert!(scheduler.tick().now_or_never().is_none());
+
+        // The next cycle is one interval after the previous one ran.
+        clock.advance(interval);
+        assert_eq!(scheduler.due().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn tasks_follow_their_own_interval() {
+        let config = MaintenanceConfig {
+            refresh_interval: Duration::from_secs(30),
+            republish_interval: Duration::from_secs(60),
+            publish_ttl: DEFAULT_PUBLISH_TTL,
+            expiry_interval: Duration::from_secs(10),
+        };
+        let clock = Arc::new(MockClock::default());
+        let mut scheduler = MaintenanceScheduler::new(clock.clone(), config);
+
+        let mut runs = HashMap::<_, usize>::new();
+        for _ in 0..6 {
+            clock.advance(Duration::from_secs(10));
+            for task in scheduler.tick().await {
+                *runs.entry(task).or_default() += 1;
+            }
+        }
+
+        assert_eq!(runs[&Maintenance::ExpirySweep], 6);
+        assert_eq!(runs[&Maintenance::Refresh], 2);
+        assert_eq!(runs[&Maintenance::Republish], 1);
+    }
+
+    #[test]
+    fn published_values_expire_and_are_bounded() {
+        let clock = Arc::new(MockClock::default());
+        let ttl = Duration::from_secs(60);
+        let mut published = Published::new(clock.clone(), ttl, 2);
+
+        published.insert(
+            KeyPrefix::ContentRegistry,
+            b"a".to_vec(),
+            Value::Inline(b"1".to_vec()),
+        );
+        clock.advance(Duration::from_secs(30));
+        published.insert(
+            KeyPrefix::ContentRegistry,
+            b"b".to_vec(),
+            Value::Inline(b"2".to_vec()),
+        );
+
+        // The value that was put first is dropped once the bound is exceeded.
+        published.insert(
+            KeyPrefix::ContentRegistry,
+            b"c".to_vec(),
+            Value::Inline(b"3".to_vec()),
+        );
+        assert_eq!(published.len(), 2);
+
+        // Putting a value again restarts its lifetime.
+        clock.advance(Duration::from_secs(40));
+        published.insert(
+            KeyPrefix::ContentRegistry,
+            b"b".to_vec(),
+            Value::Inline(b"4".to_vec()),
+        );
+        assert_eq!(published.len(), 2);
+
+        clock.advance(Duration::from_secs(40));
+        assert_eq!(
+            published.live_values(),
+            vec![(
+                KeyPrefix::ContentRegistry,
+                b"b".to_vec(),
+                Value::Inline(b"4".to_vec())
+            )]
+        );
+        assert_eq!(published.len(), 1);
+    }
+
+    #[test]
+    fn content_is_only_served_while_published() {
+        let clock = Arc::new(MockClock::default());
+        let ttl = Duration::from_secs(60);
+        let mut published = Published::new(clock.clone(), ttl, 2);
+
+        published.insert(
+            KeyPrefix::ContentRegistry,
+            b"a".to_vec(),
+            Value::Content([1; 32]),
+        );
+        assert!(published.has_content(&[1; 32]));
+        assert!(!published.has_content(&[2; 32]));
+
+        // Putting another value under the key stops serving the old content.
+        published.insert(
+            KeyPrefix::ContentRegistry,
+            b"a".to_vec(),
+            Value::Content([2; 32]),
+        );
+        assert!(!published.has_content(&[1; 32]));
+        assert!(published.has_content(&[2; 32]));
+
+        // So does the end of the lifetime of the value.
+        clock.advance(Duration::from_secs(61));
+        assert!(!published.has_content(&[2; 32]));
+    }
+
+    #[tokio::test]
+    async fn shutdown_is_not_held_up_by_a_pending_refresh() {
+        let interval = Duration::from_secs(10);
+        let config = MaintenanceConfig {
+            refresh_interval: interval,
+            republish_interval: interval,
+            publish_ttl: DEFAULT_PUBLISH_TTL,
+            expiry_interval: interval,
+        };
+        let clock = Arc::new(MockClock::default());
+        let scheduler = MaintenanceScheduler::new(clock.clone(), config);
+        let (tx, rx) = tokio::sync::mpsc::channel(8);
+        let (table_tx, mut table_rx) = tokio::sync::mpsc::channel(8);
+        let (handler_tx, _handler_rx) = tokio::sync::mpsc::channel(8);
+        let cache = Arc::new(Mutex::new(ValueCache::new(interval, 8)));
+        let published = Arc::new(Mutex::new(Published::new(
+            clock.clone(),
+            DEFAULT_PUBLISH_TTL,
+            DEFAULT_MAX_PUBLISHED,
+        )));
+        let worker = tokio::spawn(start_worker(
+            rx, scheduler, table_tx, handler_tx, cache, published,
+        ));
+
+        // The refresh asks the routing table for its buckets, which never answers.
+        clock.advance(interval);
+        let _pending = table_rx.recv().await.expect("refresh to query the table");
+
+        tx.send(MaintenanceCommand::Shutdown).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(1), worker)
+            .await
+            .expect("worker to shut down")
+            .unwrap();
+    }
+}
@@ -1,6 +1,7 @@
 use std::net::SocketAddr;
 
 use fleek_crypto::NodeNetworkingPublicKey;
+use lightning_interfaces::{dht::TableEntry, Blake3Hash};
 use serde::{Deserialize, Serialize};
 
 use crate::table::TableKey;
@@ -13,14 +14,39 @@ pub struct NodeInfo {
 
 #[derive(Debug, Deserialize, Serialize)]
 pub enum Query {
-    Find { find_value: bool, target: TableKey },
-    // Todo: This may not fit on a datagram
-    // but we will delegate this task to an
-    // encrypted channel.
-    Store { key: TableKey, value: Vec<u8> },
+    Find {
+        find_value: bool,
+        target: TableKey,
+    },
+    Store {
+        key: TableKey,
+        value: Value,
+    },
+    /// Add a signed entry to the entries stored under its key, such as a provider of some
+    /// content. The key is derived from the prefix and key of the entry.
+    StoreEntry {
+        entry: TableEntry,
+    },
     Ping,
 }
 
+/// A value as it is sent over the network.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum Value {
+    /// The raw value.
+    Inline(Vec<u8>),
+    /// The root of the content of a value that is too large to be sent in a datagram. The content
+    /// is fetched from the node that put the value.
+    Content(Blake3Hash),
+}
+
+/// A value stored on a node, along with the node that put it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ValueRecord {
+    pub source: NodeInfo,
+    pub value: Value,
+}
+
 #[repr(u8)]
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub enum MessageType {
@@ -46,5 +72,7 @@ pub struct Message {
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Response {
     pub nodes: Vec<NodeInfo>,
-    pub value: Option<Vec<u8>>,
+    pub value: Option<ValueRecord>,
+    /// The entries stored under the target, for the queries that find a value.
+    pub entries: Vec<TableEntry>,
 }
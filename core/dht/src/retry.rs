@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+/// Default time to wait for the response to the first attempt of a query.
+pub const DEFAULT_BASE_DELAY: Duration = Duration::from_secs(1);
+/// Default factor by which the time to wait grows with every attempt.
+pub const DEFAULT_MULTIPLIER: u32 = 2;
+/// Default number of times a query is sent before giving up.
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+
+/// The policy for retrying queries that did not get a response in time.
+///
+/// A query is sent at most `max_attempts` times. The time to wait for the response to each
+/// attempt starts at `base_delay` and is multiplied by `multiplier` for every attempt after the
+/// first one. Once all the attempts time out, the queried node is considered unresponsive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub base_delay: Duration,
+    pub multiplier: u32,
+    pub max_attempts: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            base_delay: DEFAULT_BASE_DELAY,
+            multiplier: DEFAULT_MULTIPLIER,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the time to wait for the response to the given attempt, counting from zero, or
+    /// `None` if the policy does not allow that many attempts.
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        let factor = self.multiplier.saturating_pow(attempt);
+        Some(self.base_delay.saturating_mul(factor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_grows_exponentially() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(100),
+            multiplier: 3,
+            max_attempts: 3,
+        };
+        assert_eq!(policy.delay(0), Some(Duration::from_millis(100)));
+        assert_eq!(policy.delay(1), Some(Duration::from_millis(300)));
+        assert_eq!(policy.delay(2), Some(Duration::from_millis(900)));
+        assert_eq!(policy.delay(3), None);
+    }
+}
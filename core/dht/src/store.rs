@@ -0,0 +1,483 @@
+use std::collections::{BTreeMap, HashMap};
+
+use fleek_crypto::NodeNetworkingPublicKey;
+use lightning_interfaces::dht::{KeyPrefix, TableEntry};
+use thiserror::Error;
+
+use crate::{
+    query::{Value, ValueRecord},
+    table::{DeriveTableKey, TableKey},
+};
+
+/// Default maximum size of a single stored value.
+pub const DEFAULT_MAX_VALUE_SIZE: usize = 4 * 1024;
+/// Default maximum number of bytes held by the store, counting the keys and the values.
+pub const DEFAULT_MAX_TOTAL_STORE_BYTES: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum StoreError {
+    #[error("the signature of the entry is not valid")]
+    InvalidSignature,
+    #[error("the value is {size} bytes, but at most {max} bytes can be stored")]
+    ValueTooLarge { size: usize, max: usize },
+}
+
+/// A local store for keys that map to several values, such as the providers of some content.
+///
+/// Every source holds at most one entry per key: a new entry from a source replaces the previous
+/// one, and only entries with a valid signature from their source are accepted, so a node can
+/// not add or overwrite entries on behalf of others.
+///
+/// The store is bounded, so it can not be filled up by someone storing large values: values
+/// larger than the maximum value size are rejected, and once the store holds more than the
+/// maximum total bytes the entries that were stored or republished the longest time ago are
+/// evicted. Sources that keep republishing their entries keep them in the store.
+pub struct MultiValueStore {
+    /// The entries by the table key derived from their prefix and key, which is what other nodes
+    /// look them up by.
+    entries: HashMap<TableKey, Vec<StoredEntry>>,
+    /// The key and the source of every entry by the time it was last stored, so the least
+    /// recently stored entry is found without scanning the store.
+    lru: BTreeMap<u64, (TableKey, NodeNetworkingPublicKey)>,
+    max_value_size: usize,
+    max_total_bytes: usize,
+    /// The number of bytes of the keys and values in the store.
+    total_bytes: usize,
+    /// Logical clock used to track when each entry was last stored.
+    tick: u64,
+}
+
+struct StoredEntry {
+    entry: TableEntry,
+    stored_at: u64,
+}
+
+impl StoredEntry {
+    fn size(&self) -> usize {
+        self.entry.key.len() + self.entry.value.len()
+    }
+}
+
+impl Default for MultiValueStore {
+    fn default() -> Self {
+        Self::with_limits(DEFAULT_MAX_VALUE_SIZE, DEFAULT_MAX_TOTAL_STORE_BYTES)
+    }
+}
+
+impl MultiValueStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limits(max_value_size: usize, max_total_bytes: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            max_value_size,
+            max_total_bytes,
+            total_bytes: 0,
+            tick: 0,
+        }
+    }
+
+    /// Add the entry under its key, evicting the least recently stored entries if the store is
+    /// full. Entries with an invalid signature or a value that is too large are rejected.
+    pub fn insert(&mut self, entry: TableEntry) -> Result<(), StoreError> {
+        if !entry.is_signature_valid() {
+            return Err(StoreError::InvalidSignature);
+        }
+        if entry.value.len() > self.max_value_size {
+            return Err(StoreError::ValueTooLarge {
+                size: entry.value.len(),
+                max: self.max_value_size,
+            });
+        }
+
+        self.tick += 1;
+        let key = TableKey::derive(entry.prefix, &entry.key);
+        let stored = StoredEntry {
+            entry,
+            stored_at: self.tick,
+        };
+        self.total_bytes += stored.size();
+        self.lru.insert(self.tick, (key, stored.entry.source));
+
+        let entries = self.entries.entry(key).or_default();
+        match entries
+            .iter_mut()
+            .find(|old| old.entry.source == stored.entry.source)
+        {
+            Some(old) => {
+                self.total_bytes -= old.size();
+                self.lru.remove(&old.stored_at);
+                *old = stored;
+            },
+            None => entries.push(stored),
+        }
+
+        while self.total_bytes > self.max_total_bytes && self.evict() {}
+        Ok(())
+    }
+
+    /// Returns every entry stored under the key, at most one per source, in the order in which
+    /// the sources were first added.
+    pub fn get_all(&self, prefix: KeyPrefix, key: &[u8]) -> Vec<TableEntry> {
+        self.get_all_by_table_key(&TableKey::derive(prefix, key))
+    }
+
+    /// Returns every entry stored under the table key, as [`MultiValueStore::get_all`] does for
+    /// the key it is derived from.
+    pub fn get_all_by_table_key(&self, key: &TableKey) -> Vec<TableEntry> {
+        self.entries
+            .get(key)
+            .map(|entries| entries.iter().map(|stored| stored.entry.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the number of bytes of the keys and values in the store.
+    pub fn total_bytes(&self) -> usize {
+        self.total_bytes
+    }
+
+    /// Remove the entry that was stored the longest time ago. Returns false if the store is
+    /// empty.
+    fn evict(&mut self) -> bool {
+        let Some((_, (key, source))) = self.lru.pop_first() else {
+            return false;
+        };
+
+        let entries = self.entries.get_mut(&key).expect("entries to exist");
+        let index = entries
+            .iter()
+            .position(|stored| stored.entry.source == source)
+            .expect("entry to exist");
+        self.total_bytes -= entries.remove(index).size();
+        if entries.is_empty() {
+            self.entries.remove(&key);
+        }
+        true
+    }
+}
+
+/// A local store for the values that other nodes put on this node.
+///
+/// A node holds one value per key: putting a value again replaces the previous one. The store is
+/// bounded: inline values larger than the maximum value size are rejected, and once the store
+/// holds more than the maximum total bytes the values that were put the longest time ago are
+/// evicted. Values that keep being republished stay in the store.
+pub struct ValueStore {
+    values: HashMap<TableKey, StoredValue>,
+    /// The keys of the values by the time they were last put.
+    order: BTreeMap<u64, TableKey>,
+    max_value_size: usize,
+    max_total_bytes: usize,
+    /// The number of bytes of the keys and values in the store.
+    total_bytes: usize,
+    /// Logical clock used to track when each value was last put.
+    tick: u64,
+}
+
+struct StoredValue {
+    record: ValueRecord,
+    stored_at: u64,
+}
+
+impl StoredValue {
+    fn size(&self) -> usize {
+        let value_size = match &self.record.value {
+            Value::Inline(value) => value.len(),
+            Value::Content(root) => root.len(),
+        };
+        std::mem::size_of::<TableKey>() + value_size
+    }
+}
+
+impl Default for ValueStore {
+    fn default() -> Self {
+        Self::with_limits(DEFAULT_MAX_VALUE_SIZE, DEFAULT_MAX_TOTAL_STORE_BYTES)
+    }
+}
+
+impl ValueStore {
+    pub fn with_limits(max_value_size: usize, max_total_bytes: usize) -> Self {
+        Self {
+            values: HashMap::new(),
+            order: BTreeMap::new(),
+            max_value_size,
+            max_total_bytes,
+            total_bytes: 0,
+            tick: 0,
+        }
+    }
+
+    /// Put the value under the key, evicting the value that was put the longest time ago if the
+    /// store is full. Inline values that are too large are rejected.
+    pub fn insert(&mut self, key: TableKey, record: ValueRecord) -> Result<(), StoreError> {
+        if let Value::Inline(value) = &record.value {
+            if value.len() > self.max_value_size {
+                return Err(StoreError::ValueTooLarge {
+                    size: value.len(),
+                    max: self.max_value_size,
+                });
+            }
+        }
+
+        self.tick += 1;
+        self.order.insert(self.tick, key);
+        let stored = StoredValue {
+            record,
+            stored_at: self.tick,
+        };
+        self.total_bytes += stored.size();
+        if let Some(old) = self.values.insert(key, stored) {
+            self.total_bytes -= old.size();
+            self.order.remove(&old.stored_at);
+        }
+
+        while self.total_bytes > self.max_total_bytes {
+            let Some((_, key)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some(old) = self.values.remove(&key) {
+                self.total_bytes -= old.size();
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the value stored under the key.
+    pub fn get(&self, key: &TableKey) -> Option<ValueRecord> {
+        self.values.get(key).map(|stored| stored.record.clone())
+    }
+
+    /// Returns the number of bytes of the keys and values in the store.
+    pub fn total_bytes(&self) -> usize {
+        self.total_bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fleek_crypto::{NodeNetworkingSecretKey, SecretKey};
+    use lightning_interfaces::ToDigest;
+
+    use super::*;
+    use crate::query::NodeInfo;
+
+    fn signed_entry(secret_key: &NodeNetworkingSecretKey, key: &[u8], value: &[u8]) -> TableEntry {
+        let mut entry = TableEntry {
+            prefix: KeyPrefix::ContentRegistry,
+            key: key.to_vec(),
+            value: value.to_vec(),
+            source: secret_key.to_pk(),
+            signature: None,
+        };
+        entry.signature = Some(secret_key.sign(&entry.to_digest()));
+        entry
+    }
+
+    #[test]
+    fn stores_one_entry_per_source() {
+        let mut store = MultiValueStore::new();
+        let providers = (0..3)
+            .map(|_| NodeNetworkingSecretKey::generate())
+            .collect::<Vec<_>>();
+
+        for (i, provider) in providers.iter().enumerate() {
+            assert!(
+                store
+                    .insert(signed_entry(provider, b"content", &[i as u8]))
+                    .is_ok()
+            );
+        }
+        // A provider announcing itself again replaces its previous entry.
+        assert!(
+            store
+                .insert(signed_entry(&providers[0], b"content", b"again"))
+                .is_ok()
+        );
+
+        let entries = store.get_all(KeyPrefix::ContentRegistry, b"content");
+        assert_eq!(entries.len(), 3);
+        for (entry, provider) in entries.iter().zip(&providers) {
+            assert_eq!(entry.source, provider.to_pk());
+        }
+        assert_eq!(entries[0].value, b"again");
+        assert_eq!(entries[1].value, [1]);
+        assert_eq!(entries[2].value, [2]);
+
+        assert!(
+            store
+                .get_all(KeyPrefix::ContentRegistry, b"other")
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_signatures() {
+        let mut store = MultiValueStore::new();
+        let provider = NodeNetworkingSecretKey::generate();
+
+        let mut unsigned = signed_entry(&provider, b"content", b"value");
+        unsigned.signature = None;
+        assert_eq!(store.insert(unsigned), Err(StoreError::InvalidSignature));
+
+        // The entry was signed for another value.
+        let mut tampered = signed_entry(&provider, b"content", b"value");
+        tampered.value = b"other".to_vec();
+        assert_eq!(store.insert(tampered), Err(StoreError::InvalidSignature));
+
+        assert!(
+            store
+                .get_all(KeyPrefix::ContentRegistry, b"content")
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn rejects_oversized_values() {
+        let mut store = MultiValueStore::with_limits(8, 1024);
+        let provider = NodeNetworkingSecretKey::generate();
+
+        assert_eq!(
+            store.insert(signed_entry(&provider, b"content", &[0; 9])),
+            Err(StoreError::ValueTooLarge { size: 9, max: 8 })
+        );
+        assert!(
+            store
+                .get_all(KeyPrefix::ContentRegistry, b"content")
+                .is_empty()
+        );
+        assert_eq!(store.total_bytes(), 0);
+
+        assert!(
+            store
+                .insert(signed_entry(&provider, b"content", &[0; 8]))
+                .is_ok()
+        );
+        assert_eq!(
+            store.get_all(KeyPrefix::ContentRegistry, b"content").len(),
+            1
+        );
+    }
+
+    #[test]
+    fn evicts_least_recently_stored_when_full() {
+        // Every entry takes 15 bytes, so the store holds three of them.
+        let mut store = MultiValueStore::with_limits(8, 45);
+        let providers = (0..4)
+            .map(|_| NodeNetworkingSecretKey::generate())
+            .collect::<Vec<_>>();
+
+        for provider in &providers[..3] {
+            assert!(
+                store
+                    .insert(signed_entry(provider, b"content", &[0; 8]))
+                    .is_ok()
+            );
+        }
+        assert_eq!(store.total_bytes(), 45);
+
+        // Republishing the first entry makes the second one the least recently stored.
+        assert!(
+            store
+                .insert(signed_entry(&providers[0], b"content", &[1; 8]))
+                .is_ok()
+        );
+        assert_eq!(store.total_bytes(), 45);
+
+        assert!(
+            store
+                .insert(signed_entry(&providers[3], b"content", &[2; 8]))
+                .is_ok()
+        );
+        assert_eq!(store.total_bytes(), 45);
+
+        let sources = store
+            .get_all(KeyPrefix::ContentRegistry, b"content")
+            .into_iter()
+            .map(|entry| entry.source)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sources,
+            vec![
+                providers[0].to_pk(),
+                providers[2].to_pk(),
+                providers[3].to_pk()
+            ]
+        );
+    }
+
+    #[test]
+    fn evicts_least_recently_stored_across_keys() {
+        // Every entry takes 9 bytes, so the store holds two of them.
+        let mut store = MultiValueStore::with_limits(8, 18);
+        let provider = NodeNetworkingSecretKey::generate();
+
+        for key in [b"a", b"b", b"a", b"c"] {
+            assert!(store.insert(signed_entry(&provider, key, &[0; 8])).is_ok());
+        }
+        assert_eq!(store.total_bytes(), 18);
+
+        // The entry under `b` was stored the longest time ago, since `a` was republished.
+        assert_eq!(store.get_all(KeyPrefix::ContentRegistry, b"a").len(), 1);
+        assert!(store.get_all(KeyPrefix::ContentRegistry, b"b").is_empty());
+        assert_eq!(store.get_all(KeyPrefix::ContentRegistry, b"c").len(), 1);
+    }
+
+    fn record(value: &[u8]) -> ValueRecord {
+        ValueRecord {
+            source: NodeInfo {
+                address: "127.0.0.1:0".parse().unwrap(),
+                key: NodeNetworkingPublicKey([0; 32]),
+            },
+            value: Value::Inline(value.to_vec()),
+        }
+    }
+
+    #[test]
+    fn value_store_evicts_the_oldest_value() {
+        // Room for two values of one byte under their 32 byte keys.
+        let mut store = ValueStore::with_limits(8, 66);
+
+        assert_eq!(
+            store.insert([0; 32], record(&[0; 9])),
+            Err(StoreError::ValueTooLarge { size: 9, max: 8 })
+        );
+        assert!(store.get(&[0; 32]).is_none());
+
+        // Putting the first value again makes the second one the oldest.
+        for key in [[1; 32], [2; 32], [1; 32], [3; 32]] {
+            assert!(store.insert(key, record(&key[..1])).is_ok());
+        }
+        assert_eq!(store.get(&[1; 32]).unwrap().value, Value::Inline(vec![1]));
+        assert!(store.get(&[2; 32]).is_none());
+        assert_eq!(store.get(&[3; 32]).unwrap().value, Value::Inline(vec![3]));
+        assert_eq!(store.total_bytes(), 66);
+
+        // Replacing a value with a larger one evicts the oldest value to make room for it.
+        assert!(store.insert([3; 32], record(&[3; 2])).is_ok());
+        assert!(store.get(&[1; 32]).is_none());
+        assert_eq!(
+            store.get(&[3; 32]).unwrap().value,
+            Value::Inline(vec![3; 2])
+        );
+        assert_eq!(store.total_bytes(), 34);
+
+        // Large values are only stored by their root.
+        assert!(
+            store
+                .insert(
+                    [4; 32],
+                    ValueRecord {
+                        value: Value::Content([4; 32]),
+                        ..record(&[])
+                    }
+                )
+                .is_ok()
+        );
+        assert!(store.get(&[4; 32]).is_some());
+        assert!(store.get(&[3; 32]).is_none());
+        assert_eq!(store.total_bytes(), 64);
+    }
+}
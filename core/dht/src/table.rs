@@ -1,8 +1,10 @@
 use std::collections::{BTreeMap, HashSet};
 
 use anyhow::Result;
+use blake3_tree::blake3::derive_key;
 use fleek_crypto::NodeNetworkingPublicKey;
-use lightning_interfaces::Blake3Hash;
+use ink_quill::TranscriptBuilder;
+use lightning_interfaces::{dht::KeyPrefix, Blake3Hash};
 use thiserror::Error;
 use tokio::sync::{mpsc::Receiver, oneshot};
 
@@ -14,6 +16,25 @@
 
 pub type TableKey = Blake3Hash;
 
+const FN_DHT_KEY_DOMAIN: &str = "FLEEK_NETWORK_DHT_KEY";
+
+/// Derivation of the table keys from the keys that are put in the table.
+pub trait DeriveTableKey {
+    /// Derive the table key for a raw key in the namespace of the given prefix. The prefix is
+    /// part of the derivation, so the same raw key is placed in unrelated parts of the keyspace
+    /// under different prefixes.
+    fn derive(prefix: KeyPrefix, raw_key: &[u8]) -> Self;
+}
+
+impl DeriveTableKey for TableKey {
+    fn derive(prefix: KeyPrefix, raw_key: &[u8]) -> Self {
+        let tb = TranscriptBuilder::empty(FN_DHT_KEY_DOMAIN)
+            .with("prefix", &(prefix as u8))
+            .with("key", &raw_key);
+        derive_key(tb.get_domain(), &tb.compile())
+    }
+}
+
 pub async fn start_worker(mut rx: Receiver<TableCommand>, local_key: NodeNetworkingPublicKey) {
     let mut table = Table::new(local_key);
     while let Some(query) = rx.recv().await {
@@ -28,6 +49,11 @@ pub async fn start_worker(mut rx: Receiver<TableCommand>, local_key: NodeNetwork
                 tx.send(nodes)
                     .expect("internal table client not to drop the channel");
             },
+            TableCommand::RemoveNode { key } => {
+                if table.remove_node(&key) {
+                    tracing::debug!("removed unresponsive node {key:?}");
+                }
+            },
             TableCommand::FirstNonEmptyBucket { tx } => {
                 let local_key = table.local_node_key;
                 let closest = table.closest_nodes(&local_key.0);
@@ -60,6 +86,10 @@ pub enum TableCommand {
         node: Node,
         tx: oneshot::Sender<Result<(), QueryError>>,
     },
+    // Removes a node that stopped responding to queries.
+    RemoveNode {
+        key: NodeNetworkingPublicKey,
+    },
     // Returns index for non-empty bucket containing closest nodes. Used for bootstrapping.
     FirstNonEmptyBucket {
         tx: oneshot::Sender<Option<usize>>,
@@ -136,6 +166,15 @@ fn add_node(&mut self, node: Node) -> Result<()> {
         Ok(())
     }
 
+    fn remove_node(&mut self, key: &NodeNetworkingPublicKey) -> bool {
+        let index = distance::leading_zero_bits(&self.local_node_key.0, &key.0);
+        if index == MAX_BUCKETS || self.buckets.is_empty() {
+            return false;
+        }
+        let bucket_index = calculate_bucket_index(self.buckets.len(), index);
+        self.buckets[bucket_index].remove_node(key)
+    }
+
     fn _add_node(&mut self, node: Node) {
         // Get index of bucket.
         let index = distance::leading_zero_bits(&self.local_node_key.0, &node.info.key.0);
@@ -173,3 +212,34 @@ fn calculate_bucket_index(bucket_count: usize, possible_index: usize) -> usize {
         possible_index
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derive_is_deterministic() {
+        let key = TableKey::derive(KeyPrefix::ContentRegistry, b"content");
+        assert_eq!(
+            key,
+            TableKey::derive(KeyPrefix::ContentRegistry, b"content")
+        );
+        assert_ne!(key, TableKey::derive(KeyPrefix::ContentRegistry, b"other"));
+    }
+
+    #[test]
+    fn test_prefixes_land_in_different_buckets() {
+        let key_a = TableKey::derive(KeyPrefix::ContentRegistry, b"content");
+        let key_b = TableKey::derive(KeyPrefix::NodeRegistry, b"content");
+        assert_ne!(key_a, key_b);
+
+        // Seen from a node that is unrelated to both keys, the same raw key falls in different
+        // buckets under the two prefixes.
+        let local_key = [0xff; 32];
+        let bucket_a =
+            calculate_bucket_index(MAX_BUCKETS, distance::leading_zero_bits(&local_key, &key_a));
+        let bucket_b =
+            calculate_bucket_index(MAX_BUCKETS, distance::leading_zero_bits(&local_key, &key_b));
+        assert_ne!(bucket_a, bucket_b);
+    }
+}
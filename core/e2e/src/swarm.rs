@@ -141,6 +141,8 @@ pub fn build(self) -> Swarm {
 
             let handshake_config = HandshakeServerConfig {
                 listen_addr: SocketAddr::from_str(&format!("0.0.0.0:{}", 6969 + i)).unwrap(),
+                rate_limit: None,
+                services: None,
             };
 
             let keys_path = directory.join("keys");
@@ -191,6 +193,7 @@ pub fn build(self) -> Swarm {
                 let app_config = AppConfig {
                     mode: Mode::Test,
                     genesis: Some(genesis.clone()),
+                    dev_single_node: None,
                 };
 
                 config.table.lock().expect("Failed to aqcuire lock").insert(
@@ -5,7 +5,7 @@
 use criterion::{measurement::Measurement, *};
 use fleek_crypto::{ClientPublicKey, ClientSignature, NodePublicKey};
 use futures::executor::block_on;
-use lightning_handshake::connection::{HandshakeConnection, HandshakeFrame, Reason};
+use lightning_handshake::connection::{FeatureFlags, HandshakeConnection, HandshakeFrame, Reason};
 use lightning_interfaces::types::CompressionAlgoSet;
 use tokio::sync::Mutex;
 
@@ -114,6 +114,7 @@ fn bench_codec_group(c: &mut Criterion) {
         supported_compression_set: CompressionAlgoSet::new(),
         pubkey: ClientPublicKey([0u8; 20]),
         resume_lane: None,
+        features: FeatureFlags::ALL,
     };
     bench_frame(&mut g, frame, "handshake_request");
 
@@ -121,6 +122,8 @@ fn bench_codec_group(c: &mut Criterion) {
         pubkey: NodePublicKey([3u8; 96]),
         nonce: 65535,
         lane: 0,
+        address_hint: None,
+        features: FeatureFlags::ALL,
     };
     bench_frame(&mut g, frame, "handshake_response");
 
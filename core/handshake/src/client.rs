@@ -1,17 +1,19 @@
 use anyhow::{anyhow, Result};
 use fleek_crypto::{ClientPublicKey, ClientSignature};
-use lightning_interfaces::types::{CompressionAlgoSet, ServiceId};
+use lightning_interfaces::types::{CompressionAlgoSet, InternetAddress, ServiceId};
 use tokio::io::{AsyncRead, AsyncWrite};
 
 use crate::connection::{
     consts::{HANDSHAKE_RES_TAG, HANDSHAKE_RES_UNLOCK_TAG},
-    HandshakeConnection, HandshakeFrame,
+    FeatureFlags, HandshakeConnection, HandshakeFrame,
 };
 
 pub struct HandshakeClient<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> {
     conn: HandshakeConnection<R, W>,
     pubkey: ClientPublicKey,
     compression_set: CompressionAlgoSet,
+    address_hint: Option<InternetAddress>,
+    features: FeatureFlags,
 }
 
 impl<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> HandshakeClient<R, W> {
@@ -26,9 +28,29 @@ pub fn new(
             conn,
             pubkey,
             compression_set,
+            address_hint: None,
+            features: FeatureFlags::ALL,
         }
     }
 
+    /// Only advertise the given optional features, instead of every feature known to the codec.
+    pub fn with_features(mut self, features: FeatureFlags) -> Self {
+        self.features = features;
+        self
+    }
+
+    /// Returns the optional features of the connection. After a handshake these are the features
+    /// the node agreed to, otherwise the features that will be advertised.
+    pub fn features(&self) -> FeatureFlags {
+        self.features
+    }
+
+    /// Returns the alternative address the node hinted during the handshake, which should be
+    /// preferred for subsequent connections.
+    pub fn address_hint(&self) -> Option<&InternetAddress> {
+        self.address_hint.as_ref()
+    }
+
     /// Handshake with a node
     pub async fn handshake(&mut self) -> Result<()> {
         // Send request
@@ -38,13 +60,21 @@ pub async fn handshake(&mut self) -> Result<()> {
                 supported_compression_set: self.compression_set,
                 pubkey: self.pubkey,
                 resume_lane: None,
+                features: self.features,
             })
             .await?;
 
         // Await response
         match self.conn.read_frame(Some(HANDSHAKE_RES_TAG)).await? {
-            Some(HandshakeFrame::HandshakeResponse { .. }) => {
+            Some(HandshakeFrame::HandshakeResponse {
+                address_hint,
+                features,
+                ..
+            }) => {
                 // TODO: Verification?
+                self.address_hint = address_hint;
+                // Never enable a feature we did not advertise.
+                self.features = self.features.intersection(features);
             },
             Some(_) => unreachable!(),
             None => return Err(anyhow!("connection disconnected")),
@@ -62,6 +92,7 @@ pub async fn handshake_unlock(&mut self, lane: u8, _signature: ClientSignature)
                 supported_compression_set: self.compression_set,
                 pubkey: self.pubkey,
                 resume_lane: Some(lane),
+                features: self.features,
             })
             .await?;
 
@@ -1,26 +1,52 @@
 #![allow(dead_code)]
 
-use std::io::{Error, ErrorKind, Write};
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{Error, ErrorKind, Read, Write},
+    ops::Range,
+};
 
 use arrayref::array_ref;
 use arrayvec::ArrayVec;
-use bytes::BytesMut;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use consts::*;
 use fleek_crypto::{ClientPublicKey, ClientSignature, NodePublicKey};
 use futures::executor::block_on;
-use lightning_interfaces::types::{CompressionAlgoSet, ServiceId};
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use lightning_interfaces::types::{
+    CompressionAlgoSet, CompressionAlgorithm, InternetAddress, ServiceId,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    time::Instant,
+};
 
 use crate::types::{BlsSignature, Nonce};
 
 /// Constant values for the codec.
 pub mod consts {
-    /// Network byte prefix in [`super::UrsaFrame::HandshakeRequest`]
+    /// Default network byte prefix in [`super::HandshakeFrame::HandshakeRequest`]
     pub const NETWORK: [u8; 9] = *b"LIGHTNING";
     /// Maximum size for a frame
     pub const MAX_FRAME_SIZE: usize = 1024;
     /// Maximum number of lanes for a single client
     pub const MAX_LANES: usize = 24;
+    /// Maximum size for a service message payload in [`super::ServiceFramer`]
+    pub const MAX_SERVICE_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+    /// Initial capacity of the [`super::ServiceFramer`] read buffer
+    pub const SERVICE_BUFFER_CAPACITY: usize = 4096;
+    /// Maximum number of bytes used by a service message length prefix (a varint encoded u32)
+    pub const MAX_VARINT_LEN: usize = 5;
+    /// Prefix of a control frame in the service messages of a [`super::ServiceFramer`]. This is a
+    /// non-canonical varint encoding of zero, so it never starts a regular message.
+    pub const SERVICE_CONTROL_PREFIX: [u8; 2] = [0x80, 0x00];
+    /// Number of bytes used by each entry of a
+    /// [`super::HandshakeFrame::BatchedDeliveryAcknowledgement`]: the start and end of the block
+    /// range and the signature.
+    pub const BATCHED_ACK_ENTRY_SIZE: usize = 8 + 8 + 96;
+    /// Maximum number of entries in a [`super::HandshakeFrame::BatchedDeliveryAcknowledgement`],
+    /// bounded by [`MAX_FRAME_SIZE`]
+    pub const MAX_BATCHED_ACKS: usize = (MAX_FRAME_SIZE - 2) / BATCHED_ACK_ENTRY_SIZE;
 
     /// [`super::HandshakeFrame::HandshakeRequest`]
     pub const HANDSHAKE_REQ_TAG: u8 = 0x01 << 0;
@@ -32,6 +58,10 @@ pub mod consts {
     pub const DELIVERY_ACK_TAG: u8 = 0x01 << 3;
     /// [`super::HandshakeFrame::ServiceRequest`]
     pub const SERVICE_REQ_TAG: u8 = 0x01 << 4;
+    /// [`super::HandshakeFrame::BatchedDeliveryAcknowledgement`]
+    pub const BATCHED_DELIVERY_ACK_TAG: u8 = 0x01 << 5;
+    /// [`super::HandshakeFrame::RenegotiateCompression`]
+    pub const RENEGOTIATE_COMPRESSION_TAG: u8 = 0x01 << 6;
 
     /// The bit flag used for termination signals, to gracefully end a connection with a reason.
     pub const TERMINATION_FLAG: u8 = 0b10000000;
@@ -42,6 +72,13 @@ pub mod consts {
     pub const GZIP: u8 = 0x01 << 2;
     /// LZ4 compression bitmap value
     pub const LZ4: u8 = 0x01 << 3;
+
+    /// No address hint in [`super::HandshakeFrame::HandshakeResponse`]
+    pub const NO_ADDRESS_HINT: u8 = 0x00;
+    /// IPv4 address hint in [`super::HandshakeFrame::HandshakeResponse`]
+    pub const IPV4_ADDRESS_HINT: u8 = 0x04;
+    /// IPv6 address hint in [`super::HandshakeFrame::HandshakeResponse`]
+    pub const IPV6_ADDRESS_HINT: u8 = 0x06;
 }
 
 fn is_termination_signal(byte: u8) -> bool {
@@ -56,6 +93,8 @@ pub enum Reason {
     OutOfLanes,
     ServiceNotFound,
     InsufficientBalance,
+    WrongNetwork,
+    RateLimited,
     Unknown = 0xFF,
 }
 
@@ -70,6 +109,8 @@ fn from_u8(byte: u8) -> Option<Self> {
             0x81 => Some(Self::OutOfLanes),
             0x82 => Some(Self::ServiceNotFound),
             0x83 => Some(Self::InsufficientBalance),
+            0x84 => Some(Self::WrongNetwork),
+            0x85 => Some(Self::RateLimited),
             _ => Some(Self::Unknown),
         }
     }
@@ -82,6 +123,55 @@ pub struct LastLaneData {
     pub signature: BlsSignature,
 }
 
+/// A bitmap of the optional features of the protocol. Both sides of a handshake advertise the
+/// features they support, and only the features in the intersection are used on the connection.
+///
+/// Bits that are not known to this version of the codec are dropped when decoding, so a newer
+/// peer can advertise features we do not know about yet.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FeatureFlags(u32);
+
+impl FeatureFlags {
+    /// Delivery acknowledgements can be sent in a
+    /// [`HandshakeFrame::BatchedDeliveryAcknowledgement`].
+    pub const BATCHED_DELIVERY_ACKS: Self = Self(0x01 << 0);
+    /// The compression of a service connection can be changed with a
+    /// [`HandshakeFrame::RenegotiateCompression`].
+    pub const COMPRESSION_RENEGOTIATION: Self = Self(0x01 << 1);
+    /// Every feature known to this version of the codec.
+    pub const ALL: Self = Self(Self::BATCHED_DELIVERY_ACKS.0 | Self::COMPRESSION_RENEGOTIATION.0);
+
+    /// Create a new empty set.
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Create a set from the raw bits, dropping the bits of unknown features.
+    pub const fn from_bits_truncate(bits: u32) -> Self {
+        Self(bits & Self::ALL.0)
+    }
+
+    /// Returns the raw bits of the set.
+    pub const fn bits(&self) -> u32 {
+        self.0
+    }
+
+    /// Returns true if every feature of the other set is present in this set.
+    pub const fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns the features present in both sets.
+    pub const fn intersection(&self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    /// Returns the features present in either set.
+    pub const fn union(&self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
 /// Frame tags
 #[repr(u8)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -91,6 +181,8 @@ pub enum FrameTag {
     HandshakeResponseUnlock = HANDSHAKE_RES_UNLOCK_TAG,
     DeliveryAcknowledgement = DELIVERY_ACK_TAG,
     ServiceRequest = SERVICE_REQ_TAG,
+    BatchedDeliveryAcknowledgement = BATCHED_DELIVERY_ACK_TAG,
+    RenegotiateCompression = RENEGOTIATE_COMPRESSION_TAG,
     TerminationSignal = TERMINATION_FLAG,
 }
 
@@ -107,18 +199,25 @@ pub fn from_u8(value: u8) -> Option<Self> {
             HANDSHAKE_RES_UNLOCK_TAG => Some(Self::HandshakeResponseUnlock),
             DELIVERY_ACK_TAG => Some(Self::DeliveryAcknowledgement),
             SERVICE_REQ_TAG => Some(Self::ServiceRequest),
+            BATCHED_DELIVERY_ACK_TAG => Some(Self::BatchedDeliveryAcknowledgement),
+            RENEGOTIATE_COMPRESSION_TAG => Some(Self::RenegotiateCompression),
             _ => None,
         }
     }
 
+    /// Returns the minimum number of bytes a frame with this tag takes. Only
+    /// [`FrameTag::HandshakeResponse`] frames can be larger, depending on the address hint, and
+    /// [`FrameTag::BatchedDeliveryAcknowledgement`] frames, depending on the number of entries.
     #[inline(always)]
     pub fn size_hint(&self) -> usize {
         match self {
-            FrameTag::HandshakeRequest => 33,
-            FrameTag::HandshakeResponse => 106,
+            FrameTag::HandshakeRequest => 37,
+            FrameTag::HandshakeResponse => 111,
             FrameTag::HandshakeResponseUnlock => 214,
             FrameTag::DeliveryAcknowledgement => 97,
             FrameTag::ServiceRequest => 5,
+            FrameTag::BatchedDeliveryAcknowledgement => 2,
+            FrameTag::RenegotiateCompression => 3,
             FrameTag::TerminationSignal => 1,
         }
     }
@@ -137,12 +236,21 @@ pub enum HandshakeFrame {
         supported_compression_set: CompressionAlgoSet,
         pubkey: ClientPublicKey,
         resume_lane: Option<u8>,
+        /// The optional features the client supports.
+        features: FeatureFlags,
     },
     /// Node response to assign an open lane.
+    ///
+    /// The node can optionally hint an alternative address (e.g. a faster IPv6 route) that the
+    /// client should prefer for subsequent connections.
     HandshakeResponse {
         lane: u8,
         pubkey: NodePublicKey,
         nonce: Nonce,
+        address_hint: Option<InternetAddress>,
+        /// The optional features enabled on the connection, the intersection of the features
+        /// supported by the client and by the node.
+        features: FeatureFlags,
     },
     /// Node response to confirm resuming a lane.
     HandshakeResponseUnlock {
@@ -158,6 +266,18 @@ pub enum HandshakeFrame {
     DeliveryAcknowledgement { signature: ClientSignature },
     /// Client request to start a service subprotocol
     ServiceRequest { service_id: ServiceId },
+    /// Client acknowledgment that many blocks were delivered, each entry acknowledges a range of
+    /// blocks with a single signature. Holds at most [`MAX_BATCHED_ACKS`] entries.
+    BatchedDeliveryAcknowledgement {
+        acks: Vec<(Range<u64>, ClientSignature)>,
+    },
+    /// Request from either side of a service connection to compress the bytes it sends with
+    /// another algorithm, or the acknowledgement of such a request with the algorithm the other
+    /// side accepted. See [`ServiceFramer::renegotiate_compression`] for when the switch happens.
+    RenegotiateCompression {
+        algorithm: CompressionAlgorithm,
+        ack: bool,
+    },
     /// Signal from the node the connection was terminated, with a reason.
     TerminationSignal(Reason),
 }
@@ -172,14 +292,34 @@ pub fn tag(&self) -> FrameTag {
             Self::HandshakeResponseUnlock { .. } => FrameTag::HandshakeResponseUnlock,
             Self::DeliveryAcknowledgement { .. } => FrameTag::DeliveryAcknowledgement,
             Self::ServiceRequest { .. } => FrameTag::ServiceRequest,
+            Self::BatchedDeliveryAcknowledgement { .. } => FrameTag::BatchedDeliveryAcknowledgement,
+            Self::RenegotiateCompression { .. } => FrameTag::RenegotiateCompression,
             Self::TerminationSignal(_) => FrameTag::TerminationSignal,
         }
     }
 
-    /// Return an estimation of the number of bytes this frame will need.
+    /// Return the number of bytes this frame will need.
     #[inline]
     pub fn size_hint(&self) -> usize {
-        self.tag().size_hint()
+        match self {
+            Self::HandshakeResponse { address_hint, .. } => {
+                self.tag().size_hint() + address_hint_len(address_hint)
+            },
+            Self::BatchedDeliveryAcknowledgement { acks } => {
+                self.tag().size_hint() + acks.len() * BATCHED_ACK_ENTRY_SIZE
+            },
+            _ => self.tag().size_hint(),
+        }
+    }
+}
+
+/// Returns the number of bytes used by the address, excluding the presence flag.
+#[inline(always)]
+fn address_hint_len(address_hint: &Option<InternetAddress>) -> usize {
+    match address_hint {
+        None => 0,
+        Some(InternetAddress::Ipv4(_)) => 4,
+        Some(InternetAddress::Ipv6(_)) => 16,
     }
 }
 
@@ -187,14 +327,48 @@ pub fn size_hint(&self) -> usize {
 pub enum HandshakeCodecError {
     InvalidNetwork,
     InvalidTag(u8),
+    DisallowedTag(u8),
     InvalidReason(u8),
+    InvalidAddressHint(u8),
+    InvalidCompression(u8),
     UnexpectedFrame(FrameTag),
     ZeroLengthBlock,
+    MessageTooLarge(usize),
     Io(std::io::Error),
     OccupiedLane,
     Unknown,
 }
 
+impl fmt::Display for HandshakeCodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidNetwork => write!(f, "handshake request is for a different network"),
+            Self::InvalidTag(tag) => write!(f, "invalid frame tag {tag:#04x}"),
+            Self::DisallowedTag(tag) => write!(f, "frame tag {tag:#04x} is not allowed here"),
+            Self::InvalidReason(reason) => write!(f, "invalid termination reason {reason:#04x}"),
+            Self::InvalidAddressHint(flag) => write!(f, "invalid address hint flag {flag:#04x}"),
+            Self::InvalidCompression(algorithm) => {
+                write!(f, "invalid compression algorithm {algorithm:#04x}")
+            },
+            Self::UnexpectedFrame(tag) => write!(f, "unexpected {tag:?} frame"),
+            Self::ZeroLengthBlock => write!(f, "received a zero length block"),
+            Self::MessageTooLarge(len) => write!(f, "message of {len} bytes is too large"),
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::OccupiedLane => write!(f, "lane is already occupied"),
+            Self::Unknown => write!(f, "unknown handshake error"),
+        }
+    }
+}
+
+impl std::error::Error for HandshakeCodecError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
 impl From<std::io::Error> for HandshakeCodecError {
     fn from(value: std::io::Error) -> Self {
         Self::Io(value)
@@ -205,16 +379,320 @@ impl From<HandshakeCodecError> for std::io::Error {
     fn from(value: HandshakeCodecError) -> Self {
         match value {
             HandshakeCodecError::Io(e) => e,
-            error => Error::new(ErrorKind::Other, format!("{error:?}")),
+            error => Error::new(ErrorKind::Other, error),
         }
     }
 }
 
+/// Encode a frame into the buffer. The network identifier is only written in
+/// [`HandshakeFrame::HandshakeRequest`] frames.
+///
+/// This is the codec used by [`HandshakeConnection::write_frame`], independent of any writer.
+///
+/// # Panics
+///
+/// If a [`HandshakeFrame::BatchedDeliveryAcknowledgement`] has more than [`MAX_BATCHED_ACKS`]
+/// entries.
+pub fn encode_frame(frame: &HandshakeFrame, network: &[u8; 9], dst: &mut BytesMut) {
+    dst.reserve(frame.size_hint());
+
+    match frame {
+        HandshakeFrame::TerminationSignal(reason) => {
+            dst.put_u8(reason.clone() as u8);
+        },
+        HandshakeFrame::HandshakeRequest {
+            version,                   // 1
+            pubkey,                    // 20
+            supported_compression_set, // 1
+            resume_lane: lane,         // 1
+            features,                  // 4
+        } => {
+            dst.put_u8(FrameTag::HandshakeRequest as u8);
+            dst.put_slice(network);
+            dst.put_u8(*version);
+            dst.put_u8((*supported_compression_set).into());
+            dst.put_u8(lane.unwrap_or(0xFF));
+            dst.put_slice(&pubkey.0);
+            dst.put_u32(features.bits());
+        },
+        HandshakeFrame::HandshakeResponse {
+            pubkey,       // 96
+            nonce,        // 8
+            lane,         // 1
+            features,     // 4
+            address_hint, // 1 + (0, 4 or 16)
+        } => {
+            dst.put_u8(FrameTag::HandshakeResponse as u8);
+            dst.put_u8(*lane);
+            dst.put_slice(&pubkey.0);
+            dst.put_u64(*nonce);
+            dst.put_u32(features.bits());
+            match address_hint {
+                None => dst.put_u8(NO_ADDRESS_HINT),
+                Some(InternetAddress::Ipv4(ip)) => {
+                    dst.put_u8(IPV4_ADDRESS_HINT);
+                    dst.put_slice(ip);
+                },
+                Some(InternetAddress::Ipv6(ip)) => {
+                    dst.put_u8(IPV6_ADDRESS_HINT);
+                    dst.put_slice(ip);
+                },
+            }
+        },
+        HandshakeFrame::HandshakeResponseUnlock {
+            pubkey,          // 96
+            nonce,           // 8
+            lane,            // 1
+            last_service_id, // 4
+            last_bytes,      // 8
+            last_signature,  // 96
+        } => {
+            dst.put_u8(FrameTag::HandshakeResponseUnlock as u8);
+            dst.put_u8(*lane);
+            dst.put_slice(&pubkey.0);
+            dst.put_u64(*nonce);
+            dst.put_u32(*last_service_id);
+            dst.put_u64(*last_bytes);
+            dst.put_slice(last_signature);
+        },
+        HandshakeFrame::DeliveryAcknowledgement { .. } => {
+            dst.put_u8(FrameTag::DeliveryAcknowledgement as u8);
+            // TODO: Get a size for client signature in fleek-crypto
+            dst.put_slice(&[0u8; 96]);
+        },
+        HandshakeFrame::ServiceRequest { service_id } => {
+            dst.put_u8(FrameTag::ServiceRequest as u8);
+            dst.put_u32(*service_id);
+        },
+        HandshakeFrame::BatchedDeliveryAcknowledgement {
+            acks, // 1 + n * (8 + 8 + 96)
+        } => {
+            assert!(
+                acks.len() <= MAX_BATCHED_ACKS,
+                "too many delivery acknowledgements in a batch"
+            );
+            dst.put_u8(FrameTag::BatchedDeliveryAcknowledgement as u8);
+            dst.put_u8(acks.len() as u8);
+            for (range, _signature) in acks {
+                dst.put_u64(range.start);
+                dst.put_u64(range.end);
+                // TODO: Get a size for client signature in fleek-crypto
+                dst.put_slice(&[0u8; 96]);
+            }
+        },
+        HandshakeFrame::RenegotiateCompression {
+            algorithm, // 1
+            ack,       // 1
+        } => {
+            dst.put_u8(FrameTag::RenegotiateCompression as u8);
+            dst.put_u8(*ack as u8);
+            dst.put_u8(*algorithm as u8);
+        },
+    }
+}
+
+/// Decode a single frame from the front of the buffer, returning `None` if the buffer does not
+/// contain an entire frame yet. The bytes of a decoded frame are removed from the buffer.
+///
+/// If a filter bitmap is given, frames with a tag outside of it are rejected as soon as the tag
+/// is available. Handshake requests for a network other than the given one are rejected.
+///
+/// This is the codec used by [`HandshakeConnection::read_frame`], independent of any reader.
+pub fn decode_frame(
+    src: &mut BytesMut,
+    network: &[u8; 9],
+    filter: Option<u8>,
+) -> Result<Option<HandshakeFrame>, HandshakeCodecError> {
+    let len = src.len();
+    if len == 0 {
+        return Ok(None);
+    }
+
+    let tag_byte = src[0];
+
+    // Even if the entire frame isn't available, we can already filter and reject invalid
+    // frames, terminating connections as soon as possible.
+    if let Some(bitmap) = filter {
+        if tag_byte & bitmap != tag_byte {
+            return Err(HandshakeCodecError::DisallowedTag(tag_byte));
+        }
+    }
+
+    // First frame byte is always the tag.
+    let tag = FrameTag::from_u8(tag_byte).ok_or(HandshakeCodecError::InvalidTag(tag_byte))?;
+    let size_hint = tag.size_hint();
+
+    // If we need more bytes for the frame, return none.
+    if len < size_hint {
+        return Ok(None);
+    }
+
+    // We're going to take the frame's length, so lets reserve the amount for the next frame.
+    src.reserve(size_hint);
+
+    match tag {
+        FrameTag::HandshakeRequest => {
+            let buf = src.split_to(size_hint);
+            if &buf[1..10] != network {
+                return Err(HandshakeCodecError::InvalidNetwork);
+            }
+
+            let version = buf[10];
+            let supported_compression_set = buf[11].into();
+            let lane = match buf[12] {
+                0xFF => None,
+                v => Some(v),
+            };
+            let pubkey = ClientPublicKey(*array_ref!(buf, 13, 20));
+            let features =
+                FeatureFlags::from_bits_truncate(u32::from_be_bytes(*array_ref!(buf, 33, 4)));
+
+            Ok(Some(HandshakeFrame::HandshakeRequest {
+                version,
+                supported_compression_set,
+                resume_lane: lane,
+                pubkey,
+                features,
+            }))
+        },
+        FrameTag::HandshakeResponse => {
+            // The frame size depends on the address hint flag.
+            let size = match src[110] {
+                NO_ADDRESS_HINT => size_hint,
+                IPV4_ADDRESS_HINT => size_hint + 4,
+                IPV6_ADDRESS_HINT => size_hint + 16,
+                flag => return Err(HandshakeCodecError::InvalidAddressHint(flag)),
+            };
+            if len < size {
+                src.reserve(size - len);
+                return Ok(None);
+            }
+
+            let buf = src.split_to(size);
+            let lane = buf[1];
+            let pubkey = NodePublicKey(*array_ref!(buf, 2, 96));
+            let nonce = u64::from_be_bytes(*array_ref!(buf, 98, 8));
+            let features =
+                FeatureFlags::from_bits_truncate(u32::from_be_bytes(*array_ref!(buf, 106, 4)));
+            let address_hint = match buf[110] {
+                IPV4_ADDRESS_HINT => Some(InternetAddress::Ipv4(*array_ref!(buf, 111, 4))),
+                IPV6_ADDRESS_HINT => Some(InternetAddress::Ipv6(*array_ref!(buf, 111, 16))),
+                _ => None,
+            };
+
+            Ok(Some(HandshakeFrame::HandshakeResponse {
+                pubkey,
+                nonce,
+                lane,
+                address_hint,
+                features,
+            }))
+        },
+        FrameTag::HandshakeResponseUnlock => {
+            let buf = src.split_to(size_hint);
+            let lane = buf[1];
+            let pubkey = NodePublicKey(*array_ref!(buf, 2, 96));
+            let nonce = u64::from_be_bytes(*array_ref!(buf, 98, 8));
+            let last_service_id = u32::from_be_bytes(*array_ref!(buf, 106, 4));
+            let last_bytes = u64::from_be_bytes(*array_ref!(buf, 110, 8));
+            let last_signature = *array_ref!(buf, 118, 96);
+
+            Ok(Some(HandshakeFrame::HandshakeResponseUnlock {
+                pubkey,
+                nonce,
+                lane,
+                last_service_id,
+                last_bytes,
+                last_signature,
+            }))
+        },
+        FrameTag::DeliveryAcknowledgement => {
+            let buf = src.split_to(size_hint);
+            let _signature = *array_ref!(buf, 1, 96);
+
+            // TODO: get size for client signature in fleek-crypto
+            Ok(Some(HandshakeFrame::DeliveryAcknowledgement {
+                signature: ClientSignature,
+            }))
+        },
+        FrameTag::ServiceRequest => {
+            let buf = src.split_to(size_hint);
+            let service_id = u32::from_be_bytes(*array_ref!(buf, 1, 4));
+
+            Ok(Some(HandshakeFrame::ServiceRequest { service_id }))
+        },
+        FrameTag::BatchedDeliveryAcknowledgement => {
+            // The frame size depends on the number of entries.
+            let count = src[1] as usize;
+            let size = size_hint + count * BATCHED_ACK_ENTRY_SIZE;
+            if count > MAX_BATCHED_ACKS {
+                return Err(HandshakeCodecError::MessageTooLarge(size));
+            }
+            if len < size {
+                src.reserve(size - len);
+                return Ok(None);
+            }
+
+            let buf = src.split_to(size);
+            let acks = buf[size_hint..]
+                .chunks_exact(BATCHED_ACK_ENTRY_SIZE)
+                .map(|entry| {
+                    let start = u64::from_be_bytes(*array_ref!(entry, 0, 8));
+                    let end = u64::from_be_bytes(*array_ref!(entry, 8, 8));
+                    let _signature = *array_ref!(entry, 16, 96);
+
+                    // TODO: get size for client signature in fleek-crypto
+                    (start..end, ClientSignature)
+                })
+                .collect();
+
+            Ok(Some(HandshakeFrame::BatchedDeliveryAcknowledgement {
+                acks,
+            }))
+        },
+        FrameTag::RenegotiateCompression => {
+            let buf = src.split_to(size_hint);
+            let ack = buf[1] != 0;
+            let algorithm = compression_from_u8(buf[2])
+                .ok_or(HandshakeCodecError::InvalidCompression(buf[2]))?;
+
+            Ok(Some(HandshakeFrame::RenegotiateCompression {
+                algorithm,
+                ack,
+            }))
+        },
+        FrameTag::TerminationSignal => {
+            let buf = src.split_to(size_hint);
+
+            match Reason::from_u8(buf[0]) {
+                Some(reason) => Ok(Some(HandshakeFrame::TerminationSignal(reason))),
+                None => Err(HandshakeCodecError::InvalidReason(buf[0])),
+            }
+        },
+    }
+}
+
+/// Returns the compression algorithm with the given wire value.
+fn compression_from_u8(value: u8) -> Option<CompressionAlgorithm> {
+    [
+        CompressionAlgorithm::Uncompressed,
+        CompressionAlgorithm::Snappy,
+        CompressionAlgorithm::Gzip,
+        CompressionAlgorithm::Brotli,
+        CompressionAlgorithm::Lz4,
+        CompressionAlgorithm::Lzma,
+    ]
+    .into_iter()
+    .find(|algorithm| *algorithm as u8 == value)
+}
+
 /// Implementation for reading and writing handshake frames on a connection.
 pub struct HandshakeConnection<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> {
     pub reader: R,
     pub writer: W,
     buffer: BytesMut,
+    network: [u8; 9],
+    deadline: Option<Instant>,
 }
 
 impl<R, W> HandshakeConnection<R, W>
@@ -229,91 +707,63 @@ pub fn new(reader: R, writer: W) -> Self {
             writer,
             // The maximum frame size is 179, so it should be enough to read into at all times
             buffer: BytesMut::with_capacity(179),
+            network: NETWORK,
+            deadline: None,
         }
     }
 
+    /// Set the network identifier sent in, and expected from, handshake requests. Defaults to
+    /// [`NETWORK`]. Requests for another network are terminated with [`Reason::WrongNetwork`].
     #[inline(always)]
-    pub async fn write_frame(&mut self, frame: HandshakeFrame) -> std::io::Result<()> {
-        match frame {
-            HandshakeFrame::TerminationSignal(reason) => {
-                self.writer.write_u8(reason as u8).await?;
-            },
-            HandshakeFrame::HandshakeRequest {
-                version,                   // 1
-                pubkey,                    // 20
-                supported_compression_set, // 1
-                resume_lane: lane,         // 1
-            } => {
-                let mut buf = ArrayVec::<u8, 34>::new_const();
-                debug_assert_eq!(NETWORK.len(), 9);
-
-                buf.push(FrameTag::HandshakeRequest as u8);
-                buf.write_all(&NETWORK).unwrap();
-                buf.push(version);
-                buf.push(supported_compression_set.into());
-                buf.push(lane.unwrap_or(0xFF));
-                buf.write_all(&pubkey.0).unwrap();
-
-                self.writer.write_all(&buf).await?;
-            },
-            HandshakeFrame::HandshakeResponse {
-                pubkey, // 96
-                nonce,  // 8
-                lane,   // 1
-            } => {
-                let mut buf = ArrayVec::<u8, 106>::new_const();
-
-                buf.push(FrameTag::HandshakeResponse as u8);
-                buf.push(lane);
-                buf.write_all(&pubkey.0).unwrap();
-                buf.write_all(&nonce.to_be_bytes()).unwrap();
-
-                self.writer.write_all(&buf).await?;
-            },
-            HandshakeFrame::HandshakeResponseUnlock {
-                pubkey,          // 96
-                nonce,           // 8
-                lane,            // 1
-                last_service_id, // 4
-                last_bytes,      // 8
-                last_signature,  // 96
-            } => {
-                let mut buf = ArrayVec::<u8, 214>::new_const();
-
-                buf.push(FrameTag::HandshakeResponseUnlock as u8);
-                buf.push(lane);
-                buf.write_all(&pubkey.0).unwrap();
-                buf.write_all(&nonce.to_be_bytes()).unwrap();
-                buf.write_all(&last_service_id.to_be_bytes()).unwrap();
-                buf.write_all(&last_bytes.to_be_bytes()).unwrap();
-                buf.write_all(&last_signature).unwrap();
+    pub fn with_network(mut self, network: [u8; 9]) -> Self {
+        self.network = network;
+        self
+    }
 
-                self.writer.write_all(&buf).await?;
-            },
-            HandshakeFrame::DeliveryAcknowledgement { .. } => {
-                let mut buf = ArrayVec::<u8, 97>::new_const();
+    /// Returns the network identifier of this connection.
+    #[inline(always)]
+    pub fn network(&self) -> &[u8; 9] {
+        &self.network
+    }
 
-                buf.push(FrameTag::DeliveryAcknowledgement as u8);
-                // TODO: Get a size for client signature in fleek-crypto
-                buf.write_all(&[0u8; 96]).unwrap();
+    /// Set a deadline for all subsequent calls to [`Self::read_frame`], until it is cleared with
+    /// [`Self::clear_deadline`]. A read that has not completed by the deadline fails with
+    /// [`ErrorKind::TimedOut`], so a single deadline can bound an entire multi-frame exchange.
+    #[inline(always)]
+    pub fn with_deadline(&mut self, deadline: Instant) {
+        self.deadline = Some(deadline);
+    }
 
-                self.writer.write_all(&buf).await?;
-            },
-            HandshakeFrame::ServiceRequest { service_id } => {
-                let mut buf = ArrayVec::<u8, 5>::new_const();
+    /// Clear the deadline set with [`Self::with_deadline`].
+    #[inline(always)]
+    pub fn clear_deadline(&mut self) {
+        self.deadline = None;
+    }
 
-                buf.push(FrameTag::ServiceRequest as u8);
-                buf.write_all(&service_id.to_be_bytes()).unwrap();
+    #[inline(always)]
+    pub async fn write_frame(&mut self, frame: HandshakeFrame) -> std::io::Result<()> {
+        let mut buf = BytesMut::with_capacity(frame.size_hint());
+        encode_frame(&frame, &self.network, &mut buf);
+        self.writer.write_all(&buf).await
+    }
 
-                self.writer.write_all(&buf).await?;
-            },
+    #[inline(always)]
+    pub async fn read_frame(
+        &mut self,
+        filter: Option<u8>,
+    ) -> std::io::Result<Option<HandshakeFrame>> {
+        match self.deadline {
+            // Reading into the buffer is cancel safe, so bytes of a partial frame are kept if the
+            // deadline passes.
+            Some(deadline) => tokio::time::timeout_at(deadline, self.read_frame_inner(filter))
+                .await
+                .map_err(|_| Error::new(ErrorKind::TimedOut, "Deadline elapsed"))?,
+            None => self.read_frame_inner(filter).await,
         }
-
-        Ok(())
     }
 
     #[inline(always)]
-    pub async fn read_frame(
+    async fn read_frame_inner(
         &mut self,
         filter: Option<u8>,
     ) -> std::io::Result<Option<HandshakeFrame>> {
@@ -341,138 +791,405 @@ pub async fn read_frame(
 
     #[inline(always)]
     fn parse_frame(&mut self, filter: Option<u8>) -> std::io::Result<Option<HandshakeFrame>> {
-        let len = self.buffer.len();
-        if len == 0 {
-            return Ok(None);
+        match decode_frame(&mut self.buffer, &self.network, filter) {
+            Ok(frame) => Ok(frame),
+            Err(error) => {
+                // Let the other side know why the connection is terminated, where we can.
+                let reason = match error {
+                    HandshakeCodecError::DisallowedTag(_) => Some(Reason::CodecViolation),
+                    HandshakeCodecError::InvalidNetwork => Some(Reason::WrongNetwork),
+                    _ => None,
+                };
+                if let Some(reason) = reason {
+                    // We dont care about this result!
+                    block_on(async { self.termination_signal(reason).await.ok() });
+                }
+                Err(Error::new(ErrorKind::InvalidData, error))
+            },
         }
+    }
 
-        let tag_byte = self.buffer[0];
+    /// Write a termination signal to the stream.
+    #[inline(always)]
+    pub async fn termination_signal(&mut self, reason: Reason) -> std::io::Result<()> {
+        self.write_frame(HandshakeFrame::TerminationSignal(reason))
+            .await
+    }
 
-        // Even if the entire frame isn't available, we can already filter and reject invalid
-        // frames, terminating connections as soon as possible.
-        if let Some(bitmap) = filter {
-            if tag_byte & bitmap != tag_byte {
-                block_on(async {
-                    self.termination_signal(Reason::CodecViolation).await.ok() // We dont care about this result!
-                });
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
-                    format!("Invalid tag: {tag_byte}",),
-                ));
+    /// Gracefully close the connection. Any buffered writes are flushed before the termination
+    /// signal is sent, and the write half is shut down afterwards, so the peer reads the reason
+    /// after all the prior frames.
+    pub async fn shutdown(&mut self, reason: Reason) -> std::io::Result<()> {
+        self.writer.flush().await?;
+        self.termination_signal(reason).await?;
+        self.writer.shutdown().await
+    }
+
+    /// Finish the connection, consuming the struct and returning the reader and writer.
+    pub fn finish(self) -> (R, W) {
+        (self.reader, self.writer)
+    }
+}
+
+/// Length-delimited framing for the service-specific bytes exchanged after a
+/// [`HandshakeFrame::ServiceRequest`].
+///
+/// Each message is a varint encoded length prefix followed by the payload. Payloads larger than
+/// the configured maximum are rejected on both ends.
+///
+/// The payloads are compressed with the algorithm chosen by their sender, which either side can
+/// change during the connection with [`ServiceFramer::renegotiate_compression`]. The frames of
+/// the renegotiation are sent between the messages, prefixed with [`SERVICE_CONTROL_PREFIX`].
+pub struct ServiceFramer<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> {
+    pub reader: R,
+    pub writer: W,
+    buffer: BytesMut,
+    max_message_size: usize,
+    /// The algorithm the payloads we send are compressed with.
+    send_compression: CompressionAlgorithm,
+    /// The algorithm the payloads we receive are compressed with.
+    recv_compression: CompressionAlgorithm,
+    /// Messages that were received while waiting for a renegotiation to be acknowledged.
+    queued: VecDeque<Bytes>,
+}
+
+/// An item read from the stream of a [`ServiceFramer`].
+enum ServiceItem {
+    Message(Bytes),
+    Control(HandshakeFrame),
+}
+
+impl<R, W> ServiceFramer<R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    #[inline(always)]
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader,
+            writer,
+            buffer: BytesMut::with_capacity(SERVICE_BUFFER_CAPACITY),
+            max_message_size: MAX_SERVICE_MESSAGE_SIZE,
+            send_compression: CompressionAlgorithm::Uncompressed,
+            recv_compression: CompressionAlgorithm::Uncompressed,
+            queued: VecDeque::new(),
+        }
+    }
+
+    /// Create a framer from the reader and writer returned by [`HandshakeConnection::finish`].
+    #[inline(always)]
+    pub fn from_parts((reader, writer): (R, W)) -> Self {
+        Self::new(reader, writer)
+    }
+
+    /// Set the maximum payload size accepted and sent by this framer.
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        assert!(
+            max_message_size <= u32::MAX as usize,
+            "Maximum message size must fit in a u32"
+        );
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// Set the compression algorithm of the payloads in both directions, as agreed on during the
+    /// handshake. Defaults to [`CompressionAlgorithm::Uncompressed`].
+    ///
+    /// # Panics
+    ///
+    /// If the algorithm is not supported, see [`ServiceFramer::supports_compression`].
+    pub fn with_compression(mut self, algorithm: CompressionAlgorithm) -> Self {
+        assert!(
+            Self::supports_compression(algorithm),
+            "Unsupported compression algorithm {algorithm:?}"
+        );
+        self.send_compression = algorithm;
+        self.recv_compression = algorithm;
+        self
+    }
+
+    /// Returns true if payloads can be compressed with the given algorithm.
+    pub fn supports_compression(algorithm: CompressionAlgorithm) -> bool {
+        matches!(
+            algorithm,
+            CompressionAlgorithm::Uncompressed | CompressionAlgorithm::Gzip
+        )
+    }
+
+    /// Returns the algorithm the payloads we send are compressed with.
+    pub fn send_compression(&self) -> CompressionAlgorithm {
+        self.send_compression
+    }
+
+    /// Returns the algorithm the payloads we receive are compressed with.
+    pub fn recv_compression(&self) -> CompressionAlgorithm {
+        self.recv_compression
+    }
+
+    /// Ask the other side to accept the payloads we send compressed with another algorithm, and
+    /// wait for its acknowledgement. Returns the algorithm the payloads are sent with from now on,
+    /// which is the previous one if the other side does not support the requested algorithm.
+    ///
+    /// The switch takes effect after the acknowledgement: nothing is written while waiting for
+    /// it, so every payload we send after the request uses the new algorithm and the other side
+    /// switches its decoding right after reading the request. The messages received while
+    /// waiting are returned by the next calls to [`ServiceFramer::read_message`].
+    pub async fn renegotiate_compression(
+        &mut self,
+        algorithm: CompressionAlgorithm,
+    ) -> std::io::Result<CompressionAlgorithm> {
+        self.write_control(HandshakeFrame::RenegotiateCompression {
+            algorithm,
+            ack: false,
+        })
+        .await?;
+
+        loop {
+            match self.read_item().await? {
+                Some(ServiceItem::Message(message)) => self.queued.push_back(message),
+                Some(ServiceItem::Control(HandshakeFrame::RenegotiateCompression {
+                    algorithm,
+                    ack: true,
+                })) => {
+                    self.send_compression = algorithm;
+                    return Ok(algorithm);
+                },
+                Some(ServiceItem::Control(frame)) => self.handle_control(frame).await?,
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::ConnectionReset,
+                        "Disconnected during renegotiation",
+                    ));
+                },
             }
         }
+    }
 
-        // First frame byte is always the tag.
-        let tag = match FrameTag::from_u8(self.buffer[0]) {
-            Some(tag) => tag,
-            None => return Err(Error::new(ErrorKind::InvalidData, "Unknown frame tag")),
-        };
-        let size_hint = tag.size_hint();
+    /// Write a single message, prefixed with its length.
+    pub async fn write_message(&mut self, payload: &[u8]) -> std::io::Result<()> {
+        if payload.len() > self.max_message_size {
+            return Err(HandshakeCodecError::MessageTooLarge(payload.len()).into());
+        }
+        let payload = compress(self.send_compression, payload)?;
+        if payload.len() > self.max_message_size {
+            return Err(HandshakeCodecError::MessageTooLarge(payload.len()).into());
+        }
 
-        // If we need more bytes for the frame, return none.
-        if len < tag.size_hint() {
-            return Ok(None);
+        let mut prefix = ArrayVec::<u8, MAX_VARINT_LEN>::new_const();
+        let mut len = payload.len() as u32;
+        while len >= 0x80 {
+            prefix.push((len as u8) | 0x80);
+            len >>= 7;
         }
+        prefix.push(len as u8);
 
-        // We're going to take the frame's length, so lets reserve the amount for the next frame.
-        self.buffer.reserve(size_hint);
+        self.writer.write_all(&prefix).await?;
+        self.writer.write_all(&payload).await?;
 
-        match tag {
-            FrameTag::HandshakeRequest => {
-                let buf = self.buffer.split_to(size_hint);
-                let network = &buf[1..10];
-                if network != NETWORK {
-                    return Err(HandshakeCodecError::InvalidNetwork.into());
-                }
+        Ok(())
+    }
 
-                let version = buf[10];
-                let supported_compression_set = buf[11].into();
-                let lane = match buf[12] {
-                    0xFF => None,
-                    v => Some(v),
-                };
-                let pubkey = ClientPublicKey(*array_ref!(buf, 13, 20));
-
-                Ok(Some(HandshakeFrame::HandshakeRequest {
-                    version,
-                    supported_compression_set,
-                    resume_lane: lane,
-                    pubkey,
-                }))
-            },
-            FrameTag::HandshakeResponse => {
-                let buf = self.buffer.split_to(size_hint);
-                let lane = buf[1];
-                let pubkey = NodePublicKey(*array_ref!(buf, 2, 96));
-                let nonce = u64::from_be_bytes(*array_ref!(buf, 98, 8));
-
-                Ok(Some(HandshakeFrame::HandshakeResponse {
-                    pubkey,
-                    nonce,
-                    lane,
-                }))
-            },
-            FrameTag::HandshakeResponseUnlock => {
-                let buf = self.buffer.split_to(size_hint);
-                let lane = buf[1];
-                let pubkey = NodePublicKey(*array_ref!(buf, 2, 96));
-                let nonce = u64::from_be_bytes(*array_ref!(buf, 98, 8));
-                let last_service_id = u32::from_be_bytes(*array_ref!(buf, 106, 4));
-                let last_bytes = u64::from_be_bytes(*array_ref!(buf, 110, 8));
-                let last_signature = *array_ref!(buf, 118, 96);
-
-                Ok(Some(HandshakeFrame::HandshakeResponseUnlock {
-                    pubkey,
-                    nonce,
-                    lane,
-                    last_service_id,
-                    last_bytes,
-                    last_signature,
-                }))
-            },
-            FrameTag::DeliveryAcknowledgement => {
-                let buf = self.buffer.split_to(size_hint);
-                let _signature = *array_ref!(buf, 1, 96);
-
-                // TODO: get size for client signature in fleek-crypto
-                Ok(Some(HandshakeFrame::DeliveryAcknowledgement {
-                    signature: ClientSignature,
-                }))
-            },
-            FrameTag::ServiceRequest => {
-                let buf = self.buffer.split_to(size_hint);
-                let service_id = u32::from_be_bytes(*array_ref!(buf, 1, 4));
+    /// Read a single message, returning `None` if the connection was closed cleanly.
+    pub async fn read_message(&mut self) -> std::io::Result<Option<Bytes>> {
+        if let Some(message) = self.queued.pop_front() {
+            return Ok(Some(message));
+        }
+
+        loop {
+            match self.read_item().await? {
+                Some(ServiceItem::Message(message)) => return Ok(Some(message)),
+                Some(ServiceItem::Control(frame)) => self.handle_control(frame).await?,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Write a control frame between the messages.
+    async fn write_control(&mut self, frame: HandshakeFrame) -> std::io::Result<()> {
+        let mut buf = BytesMut::with_capacity(SERVICE_CONTROL_PREFIX.len() + frame.size_hint());
+        buf.put_slice(&SERVICE_CONTROL_PREFIX);
+        encode_frame(&frame, &NETWORK, &mut buf);
+        self.writer.write_all(&buf).await
+    }
 
-                Ok(Some(HandshakeFrame::ServiceRequest { service_id }))
+    /// Handle a control frame that is not the acknowledgement of our own renegotiation.
+    async fn handle_control(&mut self, frame: HandshakeFrame) -> std::io::Result<()> {
+        match frame {
+            HandshakeFrame::RenegotiateCompression {
+                algorithm,
+                ack: false,
+            } => {
+                // The other side does not write anything until it reads the acknowledgement, so
+                // all of the payloads after the request use the accepted algorithm.
+                if Self::supports_compression(algorithm) {
+                    self.recv_compression = algorithm;
+                }
+                self.write_control(HandshakeFrame::RenegotiateCompression {
+                    algorithm: self.recv_compression,
+                    ack: true,
+                })
+                .await
             },
-            FrameTag::TerminationSignal => {
-                let buf = self.buffer.split_to(size_hint);
+            frame => Err(HandshakeCodecError::UnexpectedFrame(frame.tag()).into()),
+        }
+    }
 
-                if let Some(reason) = Reason::from_u8(buf[0]) {
-                    Ok(Some(HandshakeFrame::TerminationSignal(reason)))
+    /// Read the next message or control frame, the messages are decompressed.
+    async fn read_item(&mut self) -> std::io::Result<Option<ServiceItem>> {
+        loop {
+            // If we have a full message or control frame, return it.
+            if self.buffer.starts_with(&SERVICE_CONTROL_PREFIX) {
+                if let Some(frame) = self.parse_control()? {
+                    return Ok(Some(ServiceItem::Control(frame)));
+                }
+            } else if let Some(message) = self.parse_message()? {
+                let message = decompress(self.recv_compression, &message, self.max_message_size)?;
+                return Ok(Some(ServiceItem::Message(message)));
+            }
+
+            if 0 == self.reader.read_buf(&mut self.buffer).await? {
+                // Handle connection closed. If there are bytes in the buffer, it means the
+                // connection was interrupted mid-transmission.
+                if self.buffer.is_empty() {
+                    return Ok(None);
                 } else {
-                    Err(HandshakeCodecError::InvalidReason(buf[0]).into())
+                    return Err(Error::new(
+                        ErrorKind::ConnectionReset,
+                        "Client disconnected",
+                    ));
                 }
-            },
+            }
         }
     }
 
-    /// Write a termination signal to the stream.
+    /// Parse the control frame at the front of the buffer, which starts with the
+    /// [`SERVICE_CONTROL_PREFIX`].
     #[inline(always)]
-    pub async fn termination_signal(&mut self, reason: Reason) -> std::io::Result<()> {
-        self.write_frame(HandshakeFrame::TerminationSignal(reason))
-            .await
+    fn parse_control(&mut self) -> std::io::Result<Option<HandshakeFrame>> {
+        let prefix_len = SERVICE_CONTROL_PREFIX.len();
+        if self.buffer.len() == prefix_len {
+            return Ok(None);
+        }
+
+        // Only renegotiation frames are sent between the messages.
+        let tag = self.buffer[prefix_len];
+        if tag != RENEGOTIATE_COMPRESSION_TAG {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                HandshakeCodecError::DisallowedTag(tag),
+            ));
+        }
+        if self.buffer.len() < prefix_len + FrameTag::RenegotiateCompression.size_hint() {
+            return Ok(None);
+        }
+
+        self.buffer.advance(prefix_len);
+        decode_frame(
+            &mut self.buffer,
+            &NETWORK,
+            Some(RENEGOTIATE_COMPRESSION_TAG),
+        )
+        .map_err(|error| Error::new(ErrorKind::InvalidData, error))
     }
 
-    /// Finish the connection, consuming the struct and returning the reader and writer.
+    #[inline(always)]
+    fn parse_message(&mut self) -> std::io::Result<Option<Bytes>> {
+        let mut len = 0usize;
+        let mut prefix_len = 0;
+        loop {
+            if prefix_len == self.buffer.len() {
+                // The length prefix is incomplete.
+                return Ok(None);
+            }
+            if prefix_len == MAX_VARINT_LEN {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Invalid message length prefix",
+                ));
+            }
+
+            let byte = self.buffer[prefix_len];
+            len |= ((byte & 0x7F) as usize) << (7 * prefix_len);
+            prefix_len += 1;
+
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+
+        // Reject oversized messages before buffering any of the payload.
+        if len > self.max_message_size {
+            return Err(HandshakeCodecError::MessageTooLarge(len).into());
+        }
+
+        // If we need more bytes for the message, make room for all of them at once.
+        let frame_len = prefix_len + len;
+        if self.buffer.len() < frame_len {
+            self.buffer.reserve(frame_len - self.buffer.len());
+            return Ok(None);
+        }
+
+        self.buffer.advance(prefix_len);
+        Ok(Some(self.buffer.split_to(len).freeze()))
+    }
+
+    /// Finish the framer, consuming the struct and returning the reader and writer.
     pub fn finish(self) -> (R, W) {
         (self.reader, self.writer)
     }
 }
 
+/// Compress a service message payload with the given algorithm.
+fn compress(algorithm: CompressionAlgorithm, payload: &[u8]) -> std::io::Result<Vec<u8>> {
+    match algorithm {
+        CompressionAlgorithm::Uncompressed => Ok(payload.to_vec()),
+        CompressionAlgorithm::Gzip => {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(payload)?;
+            encoder.finish()
+        },
+        algorithm => Err(unsupported_compression(algorithm)),
+    }
+}
+
+/// Decompress a service message payload, rejecting payloads that decompress to more than the
+/// maximum message size.
+fn decompress(
+    algorithm: CompressionAlgorithm,
+    payload: &Bytes,
+    max_message_size: usize,
+) -> std::io::Result<Bytes> {
+    match algorithm {
+        CompressionAlgorithm::Uncompressed => Ok(payload.clone()),
+        CompressionAlgorithm::Gzip => {
+            let mut message = Vec::new();
+            flate2::read::GzDecoder::new(&payload[..])
+                .take(max_message_size as u64 + 1)
+                .read_to_end(&mut message)?;
+            if message.len() > max_message_size {
+                return Err(HandshakeCodecError::MessageTooLarge(message.len()).into());
+            }
+            Ok(message.into())
+        },
+        algorithm => Err(unsupported_compression(algorithm)),
+    }
+}
+
+fn unsupported_compression(algorithm: CompressionAlgorithm) -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        format!("unsupported compression algorithm: {algorithm:?}"),
+    )
+}
+
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use tokio::{
+        io::BufWriter,
         net::{TcpListener, TcpStream},
         sync::mpsc::channel,
     };
@@ -491,6 +1208,141 @@ fn is_termination_signal() {
         }
     }
 
+    /// Returns frames of every variant, covering the optional and variable length fields.
+    fn all_frames() -> Vec<HandshakeFrame> {
+        let mut frames = Vec::new();
+        for (i, lane) in [0u8, 1, 23, 0xFE].into_iter().enumerate() {
+            for resume_lane in [None, Some(lane)] {
+                frames.push(HandshakeFrame::HandshakeRequest {
+                    version: i as u8,
+                    supported_compression_set: CompressionAlgoSet::from(lane),
+                    resume_lane,
+                    pubkey: ClientPublicKey([lane; 20]),
+                    features: FeatureFlags::from_bits_truncate(lane as u32),
+                });
+            }
+            for address_hint in [
+                None,
+                Some(InternetAddress::Ipv4([lane; 4])),
+                Some(InternetAddress::Ipv6([lane; 16])),
+            ] {
+                frames.push(HandshakeFrame::HandshakeResponse {
+                    lane,
+                    pubkey: NodePublicKey([lane; 96]),
+                    nonce: u64::MAX >> i,
+                    address_hint,
+                    features: FeatureFlags::from_bits_truncate(lane as u32),
+                });
+            }
+            frames.push(HandshakeFrame::HandshakeResponseUnlock {
+                pubkey: NodePublicKey([lane; 96]),
+                nonce: u64::MAX >> i,
+                lane,
+                last_bytes: (lane as u64) << 40,
+                last_service_id: u32::MAX >> i,
+                last_signature: [lane; 96],
+            });
+            frames.push(HandshakeFrame::ServiceRequest {
+                service_id: u32::MAX >> i,
+            });
+        }
+        frames.push(HandshakeFrame::DeliveryAcknowledgement {
+            signature: ClientSignature,
+        });
+        for count in [0, 1, 4, MAX_BATCHED_ACKS] {
+            frames.push(HandshakeFrame::BatchedDeliveryAcknowledgement {
+                acks: (0..count as u64)
+                    .map(|i| (i * 16..(i + 1) * 16, ClientSignature))
+                    .collect(),
+            });
+        }
+        for algorithm in [
+            CompressionAlgorithm::Uncompressed,
+            CompressionAlgorithm::Gzip,
+        ] {
+            for ack in [false, true] {
+                frames.push(HandshakeFrame::RenegotiateCompression { algorithm, ack });
+            }
+        }
+        for reason in [
+            Reason::CodecViolation,
+            Reason::OutOfLanes,
+            Reason::ServiceNotFound,
+            Reason::InsufficientBalance,
+            Reason::WrongNetwork,
+            Reason::RateLimited,
+            Reason::Unknown,
+        ] {
+            frames.push(HandshakeFrame::TerminationSignal(reason));
+        }
+        frames
+    }
+
+    #[test]
+    fn codec_round_trip() {
+        for frame in all_frames() {
+            let mut buf = BytesMut::new();
+            encode_frame(&frame, &NETWORK, &mut buf);
+            assert_eq!(buf.len(), frame.size_hint());
+
+            // every strict prefix of the frame is incomplete
+            for len in 0..buf.len() {
+                let mut partial = BytesMut::from(&buf[..len]);
+                assert!(
+                    decode_frame(&mut partial, &NETWORK, None)
+                        .unwrap()
+                        .is_none()
+                );
+                assert_eq!(partial.len(), len);
+            }
+
+            // the full frame decodes to the same frame, leaving the following bytes alone
+            buf.put_u8(0xAA);
+            let decoded = decode_frame(&mut buf, &NETWORK, None).unwrap();
+            assert_eq!(decoded, Some(frame));
+            assert_eq!(&buf[..], &[0xAA]);
+        }
+    }
+
+    #[test]
+    fn codec_rejects_invalid_frames() {
+        let mut buf = BytesMut::new();
+        encode_frame(
+            &HandshakeFrame::ServiceRequest { service_id: 1 },
+            &NETWORK,
+            &mut buf,
+        );
+        assert!(matches!(
+            decode_frame(&mut buf, &NETWORK, Some(HANDSHAKE_REQ_TAG)),
+            Err(HandshakeCodecError::DisallowedTag(SERVICE_REQ_TAG))
+        ));
+
+        let mut buf = BytesMut::from(&[0x2a][..]);
+        assert!(matches!(
+            decode_frame(&mut buf, &NETWORK, None),
+            Err(HandshakeCodecError::InvalidTag(0x2a))
+        ));
+
+        let mut buf = BytesMut::new();
+        encode_frame(&all_frames()[0], &NETWORK, &mut buf);
+        assert!(matches!(
+            decode_frame(&mut buf, b"TESTNET01", None),
+            Err(HandshakeCodecError::InvalidNetwork)
+        ));
+
+        let mut buf = BytesMut::from(&[BATCHED_DELIVERY_ACK_TAG, MAX_BATCHED_ACKS as u8 + 1][..]);
+        assert!(matches!(
+            decode_frame(&mut buf, &NETWORK, None),
+            Err(HandshakeCodecError::MessageTooLarge(_))
+        ));
+
+        let mut buf = BytesMut::from(&[RENEGOTIATE_COMPRESSION_TAG, 0, 0x03][..]);
+        assert!(matches!(
+            decode_frame(&mut buf, &NETWORK, None),
+            Err(HandshakeCodecError::InvalidCompression(0x03))
+        ));
+    }
+
     async fn encode_decode(frame: HandshakeFrame) -> TResult {
         let listener = TcpListener::bind("127.0.0.1:0").await?;
         let addr = listener.local_addr()?;
@@ -527,16 +1379,110 @@ async fn handshake_req() -> TResult {
             supported_compression_set: CompressionAlgoSet::new(),
             resume_lane: None,
             pubkey: ClientPublicKey([1u8; 20]),
+            features: FeatureFlags::ALL,
         })
         .await
     }
 
+    #[tokio::test]
+    async fn handshake_req_wrong_network() -> TResult {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        // accept a single connection
+        let (tx, mut rx) = channel(1);
+        tokio::task::spawn(async move {
+            let (s, _) = listener.accept().await.unwrap();
+            tx.send(s).await.unwrap();
+        });
+
+        // create streams
+        let mut alice_stream = TcpStream::connect(addr).await?;
+        let mut bob_stream = rx.recv().await.unwrap();
+
+        // alice and bob are on different networks
+        let (r, w) = alice_stream.split();
+        let mut alice = HandshakeConnection::new(r, w).with_network(*b"TESTNET01");
+        let (r, w) = bob_stream.split();
+        let mut bob = HandshakeConnection::new(r, w);
+
+        let request = HandshakeFrame::HandshakeRequest {
+            version: 0,
+            supported_compression_set: CompressionAlgoSet::new(),
+            resume_lane: None,
+            pubkey: ClientPublicKey([1u8; 20]),
+            features: FeatureFlags::empty(),
+        };
+
+        // bob rejects the request from alice, and alice is told why
+        alice.write_frame(request.clone()).await?;
+        assert!(bob.read_frame(None).await.is_err());
+        assert_eq!(
+            alice.read_frame(None).await?,
+            Some(HandshakeFrame::TerminationSignal(Reason::WrongNetwork))
+        );
+
+        // and the other way around
+        bob.write_frame(request).await?;
+        assert!(alice.read_frame(None).await.is_err());
+        assert_eq!(
+            bob.read_frame(None).await?,
+            Some(HandshakeFrame::TerminationSignal(Reason::WrongNetwork))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shutdown_after_buffered_frames() -> TResult {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        // accept a single connection
+        let (tx, mut rx) = channel(1);
+        tokio::task::spawn(async move {
+            let (s, _) = listener.accept().await.unwrap();
+            tx.send(s).await.unwrap();
+        });
+
+        // create streams, buffering the writes of alice
+        let mut alice_stream = TcpStream::connect(addr).await?;
+        let mut bob_stream = rx.recv().await.unwrap();
+        let (r, w) = alice_stream.split();
+        let mut alice = HandshakeConnection::new(r, BufWriter::new(w));
+        let (r, w) = bob_stream.split();
+        let mut bob = HandshakeConnection::new(r, w);
+
+        let frames = [
+            HandshakeFrame::ServiceRequest { service_id: 1 },
+            HandshakeFrame::ServiceRequest { service_id: 2 },
+        ];
+        for frame in frames.iter().cloned() {
+            alice.write_frame(frame).await?;
+        }
+        alice.shutdown(Reason::OutOfLanes).await?;
+
+        // bob reads every frame, then the reason, then the end of the stream
+        for frame in frames {
+            assert_eq!(bob.read_frame(None).await?, Some(frame));
+        }
+        assert_eq!(
+            bob.read_frame(None).await?,
+            Some(HandshakeFrame::TerminationSignal(Reason::OutOfLanes))
+        );
+        assert_eq!(bob.read_frame(None).await?, None);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn handshake_res() -> TResult {
         encode_decode(HandshakeFrame::HandshakeResponse {
             lane: 0,
             nonce: 1000,
             pubkey: NodePublicKey([1; 96]),
+            address_hint: None,
+            features: FeatureFlags::BATCHED_DELIVERY_ACKS,
         })
         .await?;
 
@@ -551,6 +1497,82 @@ async fn handshake_res() -> TResult {
         .await
     }
 
+    #[tokio::test]
+    async fn handshake_res_address_hint() -> TResult {
+        for address_hint in [
+            None,
+            Some(InternetAddress::Ipv4([127, 0, 0, 1])),
+            Some(InternetAddress::Ipv6([
+                0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1,
+            ])),
+        ] {
+            let frame = HandshakeFrame::HandshakeResponse {
+                lane: 3,
+                nonce: 1000,
+                pubkey: NodePublicKey([1; 96]),
+                address_hint,
+                features: FeatureFlags::ALL,
+            };
+            encode_decode(frame).await?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn handshake_res_size_hint() {
+        let frame = |address_hint| HandshakeFrame::HandshakeResponse {
+            lane: 0,
+            nonce: 0,
+            pubkey: NodePublicKey([0; 96]),
+            address_hint,
+            features: FeatureFlags::empty(),
+        };
+        assert_eq!(frame(None).size_hint(), 111);
+        assert_eq!(frame(Some(InternetAddress::Ipv4([0; 4]))).size_hint(), 115);
+        assert_eq!(frame(Some(InternetAddress::Ipv6([0; 16]))).size_hint(), 127);
+    }
+
+    #[test]
+    fn feature_flags_ignore_unknown_bits() {
+        let request = HandshakeFrame::HandshakeRequest {
+            version: 0,
+            supported_compression_set: CompressionAlgoSet::new(),
+            resume_lane: None,
+            pubkey: ClientPublicKey([1u8; 20]),
+            features: FeatureFlags::COMPRESSION_RENEGOTIATION,
+        };
+        let mut buf = BytesMut::new();
+        encode_frame(&request, &NETWORK, &mut buf);
+
+        // a newer peer also advertises features we do not know about
+        buf[33..37].copy_from_slice(&((u32::MAX << 8) | 0x02).to_be_bytes());
+        assert_eq!(
+            decode_frame(&mut buf, &NETWORK, None).unwrap(),
+            Some(request)
+        );
+        assert!(buf.is_empty());
+
+        assert_eq!(
+            FeatureFlags::from_bits_truncate(u32::MAX),
+            FeatureFlags::ALL
+        );
+    }
+
+    #[test]
+    fn feature_flags_intersection() {
+        let client = FeatureFlags::ALL;
+        let node = FeatureFlags::BATCHED_DELIVERY_ACKS;
+        let negotiated = client.intersection(node);
+        assert_eq!(negotiated, FeatureFlags::BATCHED_DELIVERY_ACKS);
+        assert!(negotiated.contains(FeatureFlags::BATCHED_DELIVERY_ACKS));
+        assert!(!negotiated.contains(FeatureFlags::COMPRESSION_RENEGOTIATION));
+        assert_eq!(
+            FeatureFlags::empty().intersection(FeatureFlags::ALL),
+            FeatureFlags::empty()
+        );
+    }
+
     #[tokio::test]
     async fn service_req() -> TResult {
         encode_decode(HandshakeFrame::ServiceRequest { service_id: 0 }).await
@@ -564,6 +1586,18 @@ async fn decryption_key_req() -> TResult {
         .await
     }
 
+    #[tokio::test]
+    async fn batched_delivery_ack() -> TResult {
+        encode_decode(HandshakeFrame::BatchedDeliveryAcknowledgement {
+            acks: vec![
+                (0..4, ClientSignature),
+                (4..5, ClientSignature),
+                (10..42, ClientSignature),
+            ],
+        })
+        .await
+    }
+
     #[tokio::test]
     async fn termination_signal() -> TResult {
         encode_decode(HandshakeFrame::TerminationSignal(Reason::OutOfLanes)).await?;
@@ -571,4 +1605,213 @@ async fn termination_signal() -> TResult {
         encode_decode(HandshakeFrame::TerminationSignal(Reason::ServiceNotFound)).await?;
         encode_decode(HandshakeFrame::TerminationSignal(Reason::Unknown)).await
     }
+
+    async fn service_round_trip(payloads: &[Vec<u8>]) -> TResult {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        let (tx, mut rx) = channel(1);
+        tokio::task::spawn(async move {
+            let (s, _) = listener.accept().await.unwrap();
+            tx.send(s).await.unwrap();
+        });
+
+        let mut alice_stream = TcpStream::connect(addr).await?;
+        let mut bob_stream = rx.recv().await.unwrap();
+
+        // start from a finished handshake connection, like a service would
+        let (r, w) = alice_stream.split();
+        let mut alice = ServiceFramer::from_parts(HandshakeConnection::new(r, w).finish());
+        let (r, w) = bob_stream.split();
+        let mut bob = ServiceFramer::from_parts(HandshakeConnection::new(r, w).finish());
+
+        // write and read concurrently, so large messages can't fill up the socket buffers
+        let write = async {
+            for payload in payloads {
+                alice.write_message(payload).await?;
+            }
+            Ok::<_, HandshakeCodecError>(())
+        };
+        let read = async {
+            for payload in payloads {
+                let message = bob.read_message().await?.unwrap();
+                assert_eq!(&message[..], &payload[..]);
+            }
+            Ok::<_, HandshakeCodecError>(())
+        };
+        let (write, read) = tokio::join!(write, read);
+        write?;
+        read?;
+
+        // closing the stream should end the message stream cleanly
+        drop(alice);
+        drop(alice_stream);
+        assert!(bob.read_message().await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn service_message_below_capacity() -> TResult {
+        service_round_trip(&[vec![], vec![1], vec![2; SERVICE_BUFFER_CAPACITY - 1]]).await
+    }
+
+    #[tokio::test]
+    async fn service_message_at_capacity() -> TResult {
+        service_round_trip(&[vec![3; SERVICE_BUFFER_CAPACITY]]).await
+    }
+
+    #[tokio::test]
+    async fn service_message_above_capacity() -> TResult {
+        service_round_trip(&[
+            vec![4; SERVICE_BUFFER_CAPACITY + 1],
+            vec![5; 10 * SERVICE_BUFFER_CAPACITY],
+            vec![6; 16],
+        ])
+        .await
+    }
+
+    #[test]
+    fn codec_error_display() {
+        use std::error::Error as _;
+
+        assert_eq!(
+            HandshakeCodecError::MessageTooLarge(9).to_string(),
+            "message of 9 bytes is too large"
+        );
+        assert_eq!(
+            HandshakeCodecError::InvalidTag(0x2a).to_string(),
+            "invalid frame tag 0x2a"
+        );
+        assert_eq!(
+            HandshakeCodecError::UnexpectedFrame(FrameTag::HandshakeRequest).to_string(),
+            "unexpected HandshakeRequest frame"
+        );
+
+        // the io error is exposed as the source
+        let error: Box<dyn std::error::Error> = Box::new(HandshakeCodecError::from(Error::new(
+            ErrorKind::BrokenPipe,
+            "closed",
+        )));
+        assert_eq!(error.to_string(), "io error: closed");
+        assert_eq!(error.source().unwrap().to_string(), "closed");
+        assert!(HandshakeCodecError::OccupiedLane.source().is_none());
+
+        // other variants are kept as the inner error of an io error
+        let error = Error::from(HandshakeCodecError::ZeroLengthBlock);
+        assert_eq!(error.kind(), ErrorKind::Other);
+        assert_eq!(error.to_string(), "received a zero length block");
+    }
+
+    #[tokio::test]
+    async fn read_frame_deadline() -> TResult {
+        let (client, server) = tokio::io::duplex(1024);
+        let (client_r, client_w) = tokio::io::split(client);
+        let (server_r, server_w) = tokio::io::split(server);
+        let mut alice = HandshakeConnection::new(client_r, client_w);
+        let mut bob = HandshakeConnection::new(server_r, server_w);
+
+        // alice sends a frame every 150ms, well within a per-frame timeout of 200ms
+        let frame = HandshakeFrame::ServiceRequest { service_id: 1 };
+        tokio::spawn(async move {
+            for _ in 0..3 {
+                alice.write_frame(frame.clone()).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(150)).await;
+            }
+            alice
+        });
+
+        // but the exchange as a whole does not fit in 250ms
+        bob.with_deadline(Instant::now() + Duration::from_millis(250));
+        for _ in 0..2 {
+            let res = tokio::time::timeout(Duration::from_millis(200), bob.read_frame(None)).await;
+            assert_eq!(
+                res.expect("within the per-frame timeout")?,
+                Some(frame.clone())
+            );
+        }
+        let err = bob.read_frame(None).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+
+        // once the deadline is cleared, the last frame can still be read
+        bob.clear_deadline();
+        assert_eq!(bob.read_frame(None).await?, Some(frame));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn service_message_too_large() -> TResult {
+        let (r, w) = tokio::io::duplex(64);
+        let mut framer = ServiceFramer::new(r, w).with_max_message_size(8);
+        assert!(framer.write_message(&[0; 9]).await.is_err());
+
+        // a peer announcing an oversized message is rejected before the payload arrives
+        let (client, server) = tokio::io::duplex(64);
+        let (_, mut client_w) = tokio::io::split(client);
+        let (server_r, server_w) = tokio::io::split(server);
+        let mut framer = ServiceFramer::new(server_r, server_w).with_max_message_size(8);
+        client_w.write_all(&[9]).await?;
+        assert!(framer.read_message().await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn renegotiate_compression() -> TResult {
+        let first = vec![7; 4096];
+        let second = vec![8; 4096];
+        let mut ack = BytesMut::new();
+        ack.put_slice(&SERVICE_CONTROL_PREFIX);
+        encode_frame(
+            &HandshakeFrame::RenegotiateCompression {
+                algorithm: CompressionAlgorithm::Uncompressed,
+                ack: true,
+            },
+            &NETWORK,
+            &mut ack,
+        );
+
+        // alice switches from gzip to uncompressed between two messages, bob acknowledges it
+        let mut alice =
+            ServiceFramer::new(&ack[..], Vec::new()).with_compression(CompressionAlgorithm::Gzip);
+        alice.write_message(&first).await?;
+        assert_eq!(
+            alice
+                .renegotiate_compression(CompressionAlgorithm::Uncompressed)
+                .await?,
+            CompressionAlgorithm::Uncompressed
+        );
+        assert_eq!(alice.send_compression(), CompressionAlgorithm::Uncompressed);
+        alice.write_message(&second).await?;
+        let (_, sent) = alice.finish();
+
+        // the compressed first message is followed by the request, and then by the second message
+        // as is, with its 2 byte length prefix
+        let request = [
+            &SERVICE_CONTROL_PREFIX[..],
+            &[RENEGOTIATE_COMPRESSION_TAG, 0, 0],
+        ]
+        .concat();
+        let second_offset = sent.len() - second.len();
+        let request_offset = second_offset - 2 - request.len();
+        assert!(request_offset < first.len());
+        assert_eq!(&sent[request_offset..second_offset - 2], &request[..]);
+        assert_eq!(&sent[second_offset..], &second[..]);
+
+        // bob decodes the first message with gzip and the second one as is
+        let mut bob =
+            ServiceFramer::new(&sent[..], Vec::new()).with_compression(CompressionAlgorithm::Gzip);
+        assert_eq!(bob.read_message().await?.unwrap(), first);
+        assert_eq!(bob.recv_compression(), CompressionAlgorithm::Gzip);
+        assert_eq!(bob.read_message().await?.unwrap(), second);
+        assert_eq!(bob.recv_compression(), CompressionAlgorithm::Uncompressed);
+        assert!(bob.read_message().await?.is_none());
+
+        // and bob's only write is the acknowledgement
+        let (_, written) = bob.finish();
+        assert_eq!(written, ack);
+
+        Ok(())
+    }
 }
@@ -1,7 +1,9 @@
 use std::{
+    collections::BTreeSet,
     net::SocketAddr,
     str::FromStr,
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 use anyhow::{anyhow, Result};
@@ -9,9 +11,11 @@
 use dashmap::DashMap;
 use fleek_crypto::{ClientPublicKey, NodePublicKey};
 use lightning_interfaces::{
-    handshake::HandshakeInterface, types::CompressionAlgoSet, ConfigConsumer, ConnectionInterface,
-    WithStartAndShutdown,
+    handshake::HandshakeInterface,
+    types::{CompressionAlgoSet, ServiceId},
+    ConfigConsumer, ConnectionInterface, WithStartAndShutdown,
 };
+use rand::{rngs::OsRng, RngCore};
 use serde::{Deserialize, Serialize};
 use tokio::{
     io::{AsyncRead, AsyncWrite},
@@ -24,9 +28,12 @@
     task,
 };
 
-use crate::connection::{
-    consts::{DELIVERY_ACK_TAG, HANDSHAKE_REQ_TAG, SERVICE_REQ_TAG},
-    HandshakeConnection, HandshakeFrame, Reason,
+use crate::{
+    connection::{
+        consts::{DELIVERY_ACK_TAG, HANDSHAKE_REQ_TAG, SERVICE_REQ_TAG},
+        FeatureFlags, HandshakeConnection, HandshakeFrame, Reason,
+    },
+    types::Nonce,
 };
 
 /// Generic listener to accept new connection streams with.
@@ -64,13 +71,145 @@ async fn accept(&self) -> Result<Option<(Self::Reader, Self::Writer)>> {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct HandshakeServerConfig {
     pub listen_addr: SocketAddr,
+    /// Limit the rate of handshakes per client, no limit is enforced if this is not set.
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// The ids of the services to accept requests for, requests for any service are accepted if
+    /// this is not set.
+    #[serde(default)]
+    pub services: Option<BTreeSet<ServiceId>>,
 }
 
 impl Default for HandshakeServerConfig {
     fn default() -> Self {
         Self {
             listen_addr: SocketAddr::from_str("0.0.0.0:6969").unwrap(),
+            rate_limit: None,
+            services: None,
+        }
+    }
+}
+
+/// The limit on the rate of handshakes a single client can make, enforced with a token bucket.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitConfig {
+    /// The number of handshakes a client can make in a burst.
+    pub burst: u32,
+    /// The number of handshakes per second a client can make in the long run.
+    pub per_second: f64,
+}
+
+/// Default maximum number of clients the [`RateLimiter`] keeps a bucket for.
+pub const DEFAULT_MAX_RATE_LIMITED_CLIENTS: usize = 100_000;
+
+/// Minimum time between two sweeps of the buckets of the [`RateLimiter`].
+const RATE_LIMITER_PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A token bucket per client, a handshake takes a token from the bucket of its client.
+///
+/// The client keys are not authenticated yet when a handshake is rate limited, so the number of
+/// buckets is capped. Buckets that refilled completely are dropped, since a missing bucket is
+/// the same as a full one, and while the map is at the cap new clients are rate limited.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: DashMap<ClientPublicKey, (f64, Instant)>,
+    max_clients: usize,
+    /// The time of the last sweep of the buckets.
+    last_prune: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: DashMap::new(),
+            max_clients: DEFAULT_MAX_RATE_LIMITED_CLIENTS,
+            last_prune: Mutex::new(None),
+        }
+    }
+
+    /// Set the maximum number of clients a bucket is kept for, instead of
+    /// [`DEFAULT_MAX_RATE_LIMITED_CLIENTS`].
+    pub fn with_max_clients(mut self, max_clients: usize) -> Self {
+        self.max_clients = max_clients;
+        self
+    }
+
+    /// Returns the number of clients a bucket is kept for.
+    pub fn len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    /// Returns true if no bucket is kept.
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// Take a token from the bucket of the client, returns false if the bucket is empty.
+    pub fn try_acquire(&self, client: ClientPublicKey) -> bool {
+        let now = Instant::now();
+        self.prune(now);
+
+        // The guard of an entry can not be held while the map is inspected.
+        if !self.buckets.contains_key(&client) && self.buckets.len() >= self.max_clients {
+            return false;
+        }
+
+        let burst = self.config.burst as f64;
+        let mut bucket = self.buckets.entry(client).or_insert((burst, now));
+        let (tokens, last_refill) = *bucket;
+        let elapsed = now.duration_since(last_refill).as_secs_f64();
+        let tokens = (tokens + elapsed * self.config.per_second).min(burst);
+        if tokens < 1.0 {
+            *bucket = (tokens, now);
+            return false;
         }
+        *bucket = (tokens - 1.0, now);
+        true
+    }
+
+    /// Drop the full buckets, at most once every [`RATE_LIMITER_PRUNE_INTERVAL`] so the sweep
+    /// is amortized over the handshakes.
+    fn prune(&self, now: Instant) {
+        {
+            let mut last_prune = self.last_prune.lock().unwrap();
+            if last_prune.map_or(false, |last| {
+                now.duration_since(last) < RATE_LIMITER_PRUNE_INTERVAL
+            }) {
+                return;
+            }
+            *last_prune = Some(now);
+        }
+        self.drop_full_buckets(now);
+    }
+
+    /// Drop the buckets that are full at the given time.
+    fn drop_full_buckets(&self, now: Instant) {
+        let burst = self.config.burst as f64;
+        let per_second = self.config.per_second;
+        self.buckets.retain(|_, (tokens, last_refill)| {
+            let elapsed = now.duration_since(*last_refill).as_secs_f64();
+            *tokens + elapsed * per_second < burst
+        });
+    }
+}
+
+/// The source of the nonces the node hands out in its handshake responses.
+///
+/// The nonces must be unpredictable, otherwise a client can prepare the signatures for a future
+/// session and replay them.
+pub trait NonceSource: Send + Sync {
+    fn next_nonce(&self) -> Nonce;
+}
+
+/// The default [`NonceSource`], which draws the nonces from the entropy source of the operating
+/// system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OsNonceSource;
+
+impl NonceSource for OsNonceSource {
+    fn next_nonce(&self) -> Nonce {
+        OsRng.next_u64()
     }
 }
 
@@ -93,6 +232,10 @@ pub struct HandshakeServer<L: StreamProvider> {
 #[derive(Clone)]
 pub struct HandshakeServerInner {
     lanes: Arc<DashMap<ClientPublicKey, [LaneState; 24]>>,
+    services: Option<BTreeSet<ServiceId>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    nonces: Arc<dyn NonceSource>,
+    features: FeatureFlags,
 }
 
 impl<L: StreamProvider> ConfigConsumer for HandshakeServer<L> {
@@ -152,21 +295,67 @@ impl<
     type Connection = RawLaneConnection<L::Reader, L::Writer>;
 
     async fn init(config: Self::Config) -> anyhow::Result<Self> {
+        let mut inner = match config.services {
+            Some(services) => HandshakeServerInner::with_services(services).await,
+            None => HandshakeServerInner::new().await,
+        };
+        if let Some(rate_limit) = config.rate_limit {
+            inner = inner.with_rate_limit(rate_limit);
+        }
         Ok(Self {
             listener: L::new(config.listen_addr).await?.into(),
-            inner: Arc::new(HandshakeServerInner::new().await),
+            inner: Arc::new(inner),
             shutdown_channel: Mutex::new(None).into(),
         })
     }
 }
 
 impl HandshakeServerInner {
+    /// Create the server state, accepting requests for any service.
     pub async fn new() -> HandshakeServerInner {
         Self {
             lanes: DashMap::new().into(),
+            services: None,
+            rate_limiter: None,
+            nonces: Arc::new(OsNonceSource),
+            features: FeatureFlags::ALL,
+        }
+    }
+
+    /// Create the server state, only accepting requests for the services with the given ids.
+    pub async fn with_services(
+        services: impl IntoIterator<Item = ServiceId>,
+    ) -> HandshakeServerInner {
+        Self {
+            lanes: DashMap::new().into(),
+            services: Some(services.into_iter().collect()),
+            rate_limiter: None,
+            nonces: Arc::new(OsNonceSource),
+            features: FeatureFlags::ALL,
         }
     }
 
+    /// Limit the rate of handshakes per client, the handshakes over the limit are terminated.
+    pub fn with_rate_limit(mut self, config: RateLimitConfig) -> Self {
+        self.rate_limiter = Some(Arc::new(RateLimiter::new(config)));
+        self
+    }
+
+    /// Use the given source for the nonces of the handshake responses instead of the entropy
+    /// source of the operating system.
+    pub fn with_nonce_source(mut self, nonces: impl NonceSource + 'static) -> Self {
+        self.nonces = Arc::new(nonces);
+        self
+    }
+
+    /// Only enable the given optional features on connections, instead of every feature known to
+    /// the codec. The features of a connection are the intersection of these and the features
+    /// advertised by the client.
+    pub fn with_features(mut self, features: FeatureFlags) -> Self {
+        self.features = features;
+        self
+    }
+
     pub async fn handle<
         R: AsyncRead + Unpin + Send + Sync + 'static,
         W: AsyncWrite + Unpin + Send + Sync + 'static,
@@ -180,8 +369,16 @@ pub async fn handle<
                 resume_lane,
                 pubkey,
                 supported_compression_set: _,
+                features,
                 ..
             }) => {
+                if let Some(rate_limiter) = &inner.rate_limiter {
+                    if !rate_limiter.try_acquire(pubkey) {
+                        conn.termination_signal(Reason::RateLimited).await.ok();
+                        return Err(anyhow!("rate limited"));
+                    }
+                }
+
                 let mut user_lanes = inner.lanes.entry(pubkey).or_default();
 
                 // find or resume a lane
@@ -192,7 +389,7 @@ pub async fn handle<
                             // 2. send response w last info
                             conn.write_frame(HandshakeFrame::HandshakeResponseUnlock {
                                 pubkey: NodePublicKey([0u8; 96]),
-                                nonce: 1000,
+                                nonce: inner.nonces.next_nonce(),
                                 lane,
                                 // TODO: When exiting, services should return if the session was
                                 // pending a delivery acknowledgement.
@@ -224,8 +421,10 @@ pub async fn handle<
                         {
                             conn.write_frame(HandshakeFrame::HandshakeResponse {
                                 pubkey: NodePublicKey([0u8; 96]),
-                                nonce: 1000,
+                                nonce: inner.nonces.next_nonce(),
                                 lane,
+                                address_hint: None,
+                                features: features.intersection(inner.features),
                             })
                             .await?;
 
@@ -239,7 +438,16 @@ pub async fn handle<
 
                 // wait for a service request
                 match conn.read_frame(Some(SERVICE_REQ_TAG)).await? {
-                    Some(HandshakeFrame::ServiceRequest { .. }) => {
+                    Some(HandshakeFrame::ServiceRequest { service_id }) => {
+                        if inner
+                            .services
+                            .as_ref()
+                            .map_or(false, |services| !services.contains(&service_id))
+                        {
+                            conn.termination_signal(Reason::ServiceNotFound).await.ok();
+                            return Err(anyhow!("service not found"));
+                        }
+
                         // TODO(qti3e): Bring these back when the handshake interface has a way to
                         // direct a connection to a service.
                         //
@@ -332,6 +540,273 @@ fn get_compression_set(&self) -> CompressionAlgoSet {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpStream;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn service_request_unregistered_service() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        // only service 0 is registered on the server
+        let inner = Arc::new(HandshakeServerInner::with_services([0]).await);
+
+        let server = task::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (r, w) = stream.into_split();
+            HandshakeServerInner::handle(inner, HandshakeConnection::new(r, w)).await
+        });
+
+        let (r, w) = TcpStream::connect(addr).await?.into_split();
+        let mut client = HandshakeConnection::new(r, w);
+        client
+            .write_frame(HandshakeFrame::HandshakeRequest {
+                version: 0,
+                supported_compression_set: CompressionAlgoSet::new(),
+                resume_lane: None,
+                pubkey: ClientPublicKey([1u8; 20]),
+                features: FeatureFlags::ALL,
+            })
+            .await?;
+        assert!(matches!(
+            client.read_frame(None).await?,
+            Some(HandshakeFrame::HandshakeResponse { .. })
+        ));
+
+        // requesting an unknown service terminates the session
+        client
+            .write_frame(HandshakeFrame::ServiceRequest { service_id: 1 })
+            .await?;
+        assert_eq!(
+            client.read_frame(None).await?,
+            Some(HandshakeFrame::TerminationSignal(Reason::ServiceNotFound))
+        );
+        assert!(server.await?.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn service_request_without_registry() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        // no registry is configured, so every service is accepted
+        let inner = Arc::new(HandshakeServerInner::new().await);
+        let server = task::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (r, w) = stream.into_split();
+            HandshakeServerInner::handle(inner, HandshakeConnection::new(r, w)).await
+        });
+
+        let (r, w) = TcpStream::connect(addr).await?.into_split();
+        let mut client = HandshakeConnection::new(r, w);
+        client
+            .write_frame(HandshakeFrame::HandshakeRequest {
+                version: 0,
+                supported_compression_set: CompressionAlgoSet::new(),
+                resume_lane: None,
+                pubkey: ClientPublicKey([1u8; 20]),
+                features: FeatureFlags::ALL,
+            })
+            .await?;
+        assert!(matches!(
+            client.read_frame(None).await?,
+            Some(HandshakeFrame::HandshakeResponse { .. })
+        ));
+        client
+            .write_frame(HandshakeFrame::ServiceRequest { service_id: 7 })
+            .await?;
+        assert!(server.await?.is_ok());
+
+        Ok(())
+    }
+
+    /// Serve every connection to a local listener with the given server state, and return the
+    /// address of the listener.
+    async fn spawn_server(inner: HandshakeServerInner) -> Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let inner = Arc::new(inner);
+        task::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (r, w) = stream.into_split();
+                let inner = inner.clone();
+                task::spawn(HandshakeServerInner::handle(
+                    inner,
+                    HandshakeConnection::new(r, w),
+                ));
+            }
+        });
+        Ok(addr)
+    }
+
+    async fn request_handshake(
+        addr: SocketAddr,
+        pubkey: ClientPublicKey,
+    ) -> Result<Option<HandshakeFrame>> {
+        request_handshake_with_features(addr, pubkey, FeatureFlags::ALL).await
+    }
+
+    async fn request_handshake_with_features(
+        addr: SocketAddr,
+        pubkey: ClientPublicKey,
+        features: FeatureFlags,
+    ) -> Result<Option<HandshakeFrame>> {
+        let (r, w) = TcpStream::connect(addr).await?.into_split();
+        let mut client = HandshakeConnection::new(r, w);
+        client
+            .write_frame(HandshakeFrame::HandshakeRequest {
+                version: 0,
+                supported_compression_set: CompressionAlgoSet::new(),
+                resume_lane: None,
+                pubkey,
+                features,
+            })
+            .await?;
+        client.read_frame(None).await
+    }
+
+    #[tokio::test]
+    async fn handshake_rate_limited_per_client() -> Result<()> {
+        // every client can make two handshakes, and practically never gets a new one
+        let addr = spawn_server(HandshakeServerInner::new().await.with_rate_limit(
+            RateLimitConfig {
+                burst: 2,
+                per_second: 0.001,
+            },
+        ))
+        .await?;
+
+        let greedy = ClientPublicKey([1u8; 20]);
+        for _ in 0..2 {
+            assert!(matches!(
+                request_handshake(addr, greedy).await?,
+                Some(HandshakeFrame::HandshakeResponse { .. })
+            ));
+        }
+        assert_eq!(
+            request_handshake(addr, greedy).await?,
+            Some(HandshakeFrame::TerminationSignal(Reason::RateLimited))
+        );
+
+        // other clients are not affected
+        let compliant = ClientPublicKey([2u8; 20]);
+        assert!(matches!(
+            request_handshake(addr, compliant).await?,
+            Some(HandshakeFrame::HandshakeResponse { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn rate_limiter_caps_the_number_of_clients() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            burst: 1,
+            per_second: 0.001,
+        })
+        .with_max_clients(2);
+
+        assert!(limiter.try_acquire(ClientPublicKey([1u8; 20])));
+        assert!(limiter.try_acquire(ClientPublicKey([2u8; 20])));
+        assert_eq!(limiter.len(), 2);
+
+        // no bucket is full yet, so there is no room for another client
+        assert!(!limiter.try_acquire(ClientPublicKey([3u8; 20])));
+        assert_eq!(limiter.len(), 2);
+    }
+
+    #[test]
+    fn rate_limiter_drops_full_buckets() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            burst: 2,
+            per_second: 1.0,
+        });
+        assert!(limiter.try_acquire(ClientPublicKey([1u8; 20])));
+        assert!(limiter.try_acquire(ClientPublicKey([2u8; 20])));
+        assert!(limiter.try_acquire(ClientPublicKey([2u8; 20])));
+
+        // the buckets are not full right away
+        limiter.drop_full_buckets(Instant::now());
+        assert_eq!(limiter.len(), 2);
+
+        // but every bucket refilled after a few seconds
+        limiter.drop_full_buckets(Instant::now() + Duration::from_secs(5));
+        assert!(limiter.is_empty());
+    }
+
+    /// Hands out consecutive nonces starting from one.
+    struct CountingNonceSource(std::sync::atomic::AtomicU64);
+
+    impl NonceSource for CountingNonceSource {
+        fn next_nonce(&self) -> Nonce {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1
+        }
+    }
+
+    #[tokio::test]
+    async fn handshake_response_uses_nonce_source() -> Result<()> {
+        let addr = spawn_server(
+            HandshakeServerInner::new()
+                .await
+                .with_nonce_source(CountingNonceSource(Default::default())),
+        )
+        .await?;
+
+        for expected in 1..=2 {
+            match request_handshake(addr, ClientPublicKey([1u8; 20])).await? {
+                Some(HandshakeFrame::HandshakeResponse { nonce, .. }) => {
+                    assert_eq!(nonce, expected)
+                },
+                frame => panic!("unexpected frame: {frame:?}"),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handshake_response_negotiates_features() -> Result<()> {
+        // the node only supports batched delivery acknowledgements
+        let addr = spawn_server(
+            HandshakeServerInner::new()
+                .await
+                .with_features(FeatureFlags::BATCHED_DELIVERY_ACKS),
+        )
+        .await?;
+
+        for (client, expected) in [
+            (FeatureFlags::ALL, FeatureFlags::BATCHED_DELIVERY_ACKS),
+            (
+                FeatureFlags::COMPRESSION_RENEGOTIATION,
+                FeatureFlags::empty(),
+            ),
+            (FeatureFlags::empty(), FeatureFlags::empty()),
+        ] {
+            match request_handshake_with_features(addr, ClientPublicKey([1u8; 20]), client).await? {
+                Some(HandshakeFrame::HandshakeResponse { features, .. }) => {
+                    assert_eq!(features, expected)
+                },
+                frame => panic!("unexpected frame: {frame:?}"),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn os_nonce_source_is_unpredictable() {
+        let nonces = (0..8)
+            .map(|_| OsNonceSource.next_nonce())
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(nonces.len(), 8);
+    }
+}
+
 // TODO(qti3e): Bring these tests back to life after we have more things in the mock crate.
 //
 // #[cfg(test)]
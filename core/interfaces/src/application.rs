@@ -2,16 +2,17 @@
 
 use affair::Socket;
 use async_trait::async_trait;
-use fleek_crypto::{ClientPublicKey, EthAddress, NodePublicKey};
+use fleek_crypto::{ClientPublicKey, EthAddress, NodePublicKey, NodeSignature, TransactionSender};
 use hp_fixed::unsigned::HpUfixed;
 
 use crate::{
     common::WithStartAndShutdown,
     config::ConfigConsumer,
     types::{
-        Block, BlockExecutionResponse, Epoch, EpochInfo, NodeInfo, NodeServed, ProtocolParams,
-        ReportedReputationMeasurements, Service, ServiceId, TotalServed, TransactionResponse,
-        UpdateRequest,
+        Block, BlockExecutionResponse, Epoch, EpochInfo, EpochProgress, NodeIndex, NodeInfo,
+        NodeServed, Participation, Proposal, ProposalId, ProtocolParams, Receipt,
+        ReportedReputationMeasurements, ReputationMeasurements, Service, ServiceId,
+        SimulatedOutcome, SupplySnapshot, TotalServed, TransactionResponse, UpdateRequest,
     },
 };
 
@@ -88,6 +89,10 @@ pub trait SyncQueryRunnerInterface: Clone + Send + Sync + 'static {
     /// Returns the global reputation of a node.
     fn get_reputation(&self, node: &NodePublicKey) -> Option<u8>;
 
+    /// Returns the stake-weighted consensus of the measurements that were reported for a node
+    /// during the last epoch.
+    fn get_reputation_consensus(&self, node: &NodePublicKey) -> Option<ReputationMeasurements>;
+
     /// Returns the relative score between two nodes, this score should measure how much two
     /// nodes `n1` and `n2` trust each other. Of course in real world a direct measurement
     /// between any two node might not exits, but there does exits a path from `n1` to `n2`
@@ -100,10 +105,32 @@ pub trait SyncQueryRunnerInterface: Clone + Send + Sync + 'static {
     /// Returns information about a single node.
     fn get_node_info(&self, id: &NodePublicKey) -> Option<NodeInfo>;
 
+    /// Returns true if the signature over the digest is valid for the key the node registered
+    /// with. Returns false if the node is not registered.
+    fn verify_node_signature(
+        &self,
+        node: &NodePublicKey,
+        digest: &[u8; 32],
+        signature: &NodeSignature,
+    ) -> bool;
+
     /// Returns a full copy of the entire node-registry, but only contains the nodes that
     /// are still a valid node and have enough stake.
+    ///
+    /// On large networks prefer [`get_node_registry_page`](Self::get_node_registry_page), which
+    /// only copies the requested part of the registry.
     fn get_node_registry(&self) -> Vec<NodeInfo>;
 
+    /// Returns at most `limit` nodes of the node-registry, starting at `offset`. Returns an empty
+    /// list if the offset is past the end of the registry.
+    ///
+    /// The nodes are in the same order as in [`get_node_registry`](Self::get_node_registry). The
+    /// order only changes with the state, so the pages read from the same state line up.
+    fn get_node_registry_page(&self, offset: usize, limit: usize) -> Vec<NodeInfo>;
+
+    /// Returns the number of nodes in the node-registry.
+    fn node_registry_len(&self) -> usize;
+
     /// Returns true if the node is a valid node in the network, with enough stake.
     fn is_valid_node(&self, id: &NodePublicKey) -> bool;
 
@@ -116,12 +143,42 @@ pub trait SyncQueryRunnerInterface: Clone + Send + Sync + 'static {
     /// Returns the committee members of the current epoch.
     fn get_committee_members(&self) -> Vec<NodePublicKey>;
 
+    /// Returns how close the committee of the current epoch is to changing the epoch: the number
+    /// of members that signaled they are ready, the size of the committee, and whether enough
+    /// members signaled for the epoch to change.
+    fn epoch_change_readiness(&self) -> (usize, usize, bool);
+
+    /// Returns the index of the node in the committee of the current epoch, which is its position
+    /// in the list returned by [`get_committee_members`](Self::get_committee_members).
+    fn committee_position(&self, node: &NodePublicKey) -> Option<usize>;
+
+    /// Returns the rank of the node among the valid nodes, in the order in which they are
+    /// considered for the committee, along with its score. The highest scoring node has rank 0.
+    /// Returns `None` if the node is not a valid node.
+    fn get_node_selection_rank(&self, node: &NodePublicKey) -> Option<(usize, u128)>;
+
+    /// Returns the lowest score that still ranks within the committee size, so a candidate can
+    /// see how far it is from a seat. Returns `None` if there are no more valid nodes than seats.
+    fn committee_cutoff_score(&self) -> Option<u128>;
+
+    /// Returns the committee members of the next epoch, or an empty list if that committee has
+    /// not been determined yet.
+    fn get_next_committee_members(&self) -> Vec<NodePublicKey>;
+
     /// Returns just the current epoch
     fn get_epoch(&self) -> Epoch;
 
     /// Returns all the information on the current epoch that Narwhal needs to run
     fn get_epoch_info(&self) -> EpochInfo;
 
+    /// Returns the committee and timing of the given epoch, or `None` if the state has no record
+    /// of that epoch. The node information of the committee members is the most recent one.
+    fn get_epoch_info_at(&self, epoch: Epoch) -> Option<EpochInfo>;
+
+    /// Returns how far the current epoch has progressed at the given time, in milliseconds since
+    /// the unix epoch, based on the start and the end of the epoch.
+    fn epoch_progress(&self, now: u64) -> EpochProgress;
+
     /// Returns total served for all commodities from the state for a given epoch
     fn get_total_served(&self, epoch: Epoch) -> TotalServed;
 
@@ -137,21 +194,47 @@ pub trait SyncQueryRunnerInterface: Clone + Send + Sync + 'static {
     /// Return the foundation address where protocol fund goes to
     fn get_protocol_fund_address(&self) -> EthAddress;
 
+    /// Returns the total, year start and circulating supply along with the balance of the
+    /// protocol fund, all read from the same state.
+    fn get_supply_snapshot(&self) -> SupplySnapshot;
+
     /// Returns the passed in protocol parameter
     fn get_protocol_params(&self, param: ProtocolParams) -> u128;
 
     /// Validates the passed in transaction
     fn validate_txn(&self, txn: UpdateRequest) -> TransactionResponse;
 
+    /// Runs the passed in transaction against the current state without committing it, returning
+    /// the response along with the writes it would make.
+    fn simulate_txn(&self, txn: UpdateRequest) -> SimulatedOutcome;
+
+    /// Returns the receipt of the transaction from the sender whose payload has the given digest,
+    /// if it was executed in one of the recent blocks.
+    fn get_receipt(&self, sender: &TransactionSender, digest: [u8; 32]) -> Option<Receipt>;
+
+    /// Returns the participation of the node across the epochs that have ended, or the default
+    /// for a node that never participated.
+    fn get_participation(&self, node: &NodePublicKey) -> Participation;
+
+    /// Returns the governance proposals that can still be voted on in the current epoch, ordered
+    /// by their id.
+    fn get_open_proposals(&self) -> Vec<Proposal>;
+
+    /// Returns the governance proposal with the given id, including the ones whose deadline has
+    /// passed.
+    fn get_proposal(&self, id: ProposalId) -> Option<Proposal>;
+
     /// Return all latencies measurements for the current epoch.
     fn get_latencies(&self) -> HashMap<(NodePublicKey, NodePublicKey), Duration>;
 
     /// returns the service information for a given [`ServiceId`]
     fn get_service_info(&self, service_id: ServiceId) -> Service;
 
-    fn pubkey_to_index(&self, node: NodePublicKey) -> Option<u32>;
+    /// Returns the index of the given node, if it is registered.
+    fn pubkey_to_index(&self, node: NodePublicKey) -> Option<NodeIndex>;
 
-    fn index_to_pubkey(&self, node_index: u32) -> Option<NodePublicKey>;
+    /// Returns the public key of the node with the given index, if it is registered.
+    fn index_to_pubkey(&self, node_index: NodeIndex) -> Option<NodePublicKey>;
 }
 
 #[derive(Clone, Debug)]
@@ -2,6 +2,7 @@
 
 use async_trait::async_trait;
 use thiserror::Error;
+use tokio::io::AsyncRead;
 
 use crate::{
     config::ConfigConsumer,
@@ -122,6 +123,18 @@ async fn get(
 
     /// Create a putter that can be used to write a content into the block store.
     fn put(&self, cid: Option<Blake3Hash>) -> Self::Put;
+
+    /// Store the content of a `blake3-stream` encoded stream, such as the one sent by the
+    /// `Encoder` of a peer, and return the root of the stored content.
+    ///
+    /// Every block is verified against the given root as it is read, and the chunks are stored
+    /// along with the tree. Nothing is stored if the stream is invalid or ends before all of the
+    /// content was read.
+    async fn put_verified_stream<R: AsyncRead + Unpin + Send>(
+        &self,
+        root: Blake3Hash,
+        reader: R,
+    ) -> anyhow::Result<Blake3Hash>;
 }
 
 /// The interface for the writer to a [`BlockStoreInterface`].
@@ -160,6 +173,8 @@ pub enum PutWriteError {
     InvalidContent,
     #[error("The provided content could not be decompressed.")]
     DecompressionFailure,
+    #[error("The put was cancelled.")]
+    Cancelled,
 }
 
 #[derive(Error, Debug)]
@@ -168,4 +183,6 @@ pub enum PutFinalizeError {
     PartialContent,
     #[error("The final CID does not match the CID which was expected.")]
     InvalidCID,
+    #[error("The put was cancelled.")]
+    Cancelled,
 }
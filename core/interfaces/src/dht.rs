@@ -6,7 +6,9 @@
 use ink_quill::TranscriptBuilder;
 use serde::{Deserialize, Serialize};
 
-use crate::{SignerInterface, ToDigest, TopologyInterface, WithStartAndShutdown};
+use crate::{
+    BlockStoreInterface, SignerInterface, ToDigest, TopologyInterface, WithStartAndShutdown,
+};
 
 const FN_DHT_ENTRY_DOMAIN: &str = "FLEEK_NETWORK_DHT_ENTRY";
 
@@ -17,12 +19,17 @@ pub trait DhtInterface: WithStartAndShutdown + Sized {
 
     type Topology: TopologyInterface;
 
+    /// The block store holding the content of values that are too large to be sent in a single
+    /// message. The content is served to the nodes that get such values.
+    type BlockStore: BlockStoreInterface;
+
     // -- BOUNDED TYPES
     // empty
 
     async fn init<S: SignerInterface>(
         signer: &S,
         topology: Arc<Self::Topology>,
+        blockstore: Self::BlockStore,
     ) -> anyhow::Result<Self>;
 
     /// Put a key-value pair into the distributed hash table.
@@ -30,13 +37,23 @@ async fn init<S: SignerInterface>(
 
     /// Return one value associated with the given key.
     async fn get(&self, prefix: KeyPrefix, key: &[u8]) -> Option<TableEntry>;
+
+    /// Return one value for each of the given keys, in the same order as the keys. The keys are
+    /// looked up concurrently, which is much faster than getting them one by one.
+    async fn get_batch(&self, prefix: KeyPrefix, keys: &[Vec<u8>]) -> Vec<Option<TableEntry>>;
+
+    /// Return all the values associated with the given key, at most one per source. This is used
+    /// for keys that map to a set of values, such as the providers of some content.
+    async fn get_all(&self, prefix: KeyPrefix, key: &[u8]) -> Vec<TableEntry>;
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(u8)]
 pub enum KeyPrefix {
     /// The content registry keys.
     ContentRegistry,
+    /// The node registry keys.
+    NodeRegistry,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -3,7 +3,8 @@
 use affair::Socket;
 use async_trait::async_trait;
 use fleek_crypto::{
-    NodeNetworkingPublicKey, NodeNetworkingSecretKey, NodePublicKey, NodeSecretKey, NodeSignature,
+    NodeNetworkingPublicKey, NodeNetworkingSecretKey, NodeNetworkingSignature, NodePublicKey,
+    NodeSecretKey, NodeSignature,
 };
 use tokio::sync::Notify;
 
@@ -70,4 +71,15 @@ pub trait SignerInterface: ConfigConsumer + WithStartAndShutdown + Sized + Send
     /// this function is responsible for signing arbitrary messages from other parts of
     /// the system.
     fn sign_raw_digest(&self, digest: &[u8; 32]) -> NodeSignature;
+
+    /// Sign the provided raw digest with the `Ed25519` (network) key of the node, the signature
+    /// can be verified against [`SignerInterface::get_ed25519_pk`].
+    ///
+    /// This is meant for networking-layer messages. Transactions submitted through the socket
+    /// are always signed with the `BLS` key.
+    ///
+    /// # Safety
+    ///
+    /// The same care as [`SignerInterface::sign_raw_digest`] applies.
+    fn sign_with_network_key(&self, digest: &[u8; 32]) -> NodeNetworkingSignature;
 }
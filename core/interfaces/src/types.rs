@@ -7,8 +7,10 @@
 mod pod;
 mod reputation;
 mod response;
+mod service;
 mod state;
 mod transaction;
+mod wire;
 
 pub use application::*;
 pub use bridge::*;
@@ -17,8 +19,10 @@
 pub use pod::*;
 pub use reputation::*;
 pub use response::*;
+pub use service::*;
 pub use state::*;
 pub use transaction::*;
+pub use wire::*;
 
 /// The physical address of a node where it can be reached, the port numbers are
 /// omitted since each node is responsible to open the standard port numbers for
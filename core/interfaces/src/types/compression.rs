@@ -1,6 +1,9 @@
-#[derive(Hash, Debug, PartialEq, Eq, Clone, Copy)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum CompressionAlgorithm {
+    #[default]
     Uncompressed = 0,
     Snappy = 0x01 << 0,
     Gzip = 0x01 << 1,
@@ -39,6 +42,21 @@ pub fn contains(&self, algo: CompressionAlgorithm) -> bool {
     pub fn intersect(&self, other: &Self) -> Self {
         Self(self.0 & other.0)
     }
+
+    /// Create a new set containing the provided algorithms.
+    pub fn from_algos(algos: &[CompressionAlgorithm]) -> Self {
+        algos.iter().copied().collect()
+    }
+}
+
+impl FromIterator<CompressionAlgorithm> for CompressionAlgoSet {
+    fn from_iter<T: IntoIterator<Item = CompressionAlgorithm>>(iter: T) -> Self {
+        let mut set = Self::new();
+        for algo in iter {
+            set.insert(algo);
+        }
+        set
+    }
 }
 
 impl From<u8> for CompressionAlgoSet {
@@ -100,4 +118,27 @@ fn test_compression_set() {
         assert!(!set.contains(CompressionAlgorithm::Lz4));
         assert!(!set.contains(CompressionAlgorithm::Lzma));
     }
+
+    #[test]
+    fn test_compression_set_from_iter() {
+        let mut expected = CompressionAlgoSet::new();
+        expected.insert(CompressionAlgorithm::Uncompressed);
+        expected.insert(CompressionAlgorithm::Snappy);
+        expected.insert(CompressionAlgorithm::Lz4);
+
+        let algos = [
+            CompressionAlgorithm::Uncompressed,
+            CompressionAlgorithm::Snappy,
+            CompressionAlgorithm::Lz4,
+        ];
+        let set: CompressionAlgoSet = algos.into_iter().collect();
+        assert_eq!(set, expected);
+        assert_eq!(CompressionAlgoSet::from_algos(&algos), expected);
+
+        // Uncompressed is always contained, even in a set built from no algorithms.
+        let set: CompressionAlgoSet = std::iter::empty().collect();
+        assert_eq!(set, CompressionAlgoSet::new());
+        assert!(set.contains(CompressionAlgorithm::Uncompressed));
+        assert!(!set.contains(CompressionAlgorithm::Snappy));
+    }
 }
@@ -1,9 +1,97 @@
 //! Types related to the proof of misbehavior.
 
+use fleek_crypto::{NodePublicKey, NodeSignature, PublicKey};
+use ink_quill::TranscriptBuilder;
 use serde::{Deserialize, Serialize};
 
+use super::Epoch;
+use crate::ToDigest;
+
+const FN_CONSENSUS_VOTE_DOMAIN: &str = "FLEEK_NETWORK_CONSENSUS_VOTE";
+const FN_MISBEHAVIOR_DOMAIN: &str = "FLEEK_NETWORK_MISBEHAVIOR";
+
 /// Placeholder
 /// This is the proof presented to the slashing function that proves a node misbehaved and should be
 /// slashed
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, Hash)]
 pub enum ProofOfMisbehavior {}
+
+/// A vote of a node for the block with the given digest in a round of consensus.
+#[derive(Clone, Debug, Serialize, Deserialize, Hash, PartialEq, Eq)]
+pub struct SignedVote {
+    pub epoch: Epoch,
+    pub round: u64,
+    pub digest: [u8; 32],
+    /// The signature of the node over the digest of this vote.
+    pub signature: NodeSignature,
+}
+
+impl ToDigest for SignedVote {
+    /// Computes the digest signed by the voter, which covers everything but the signature.
+    fn to_digest(&self) -> [u8; 32] {
+        TranscriptBuilder::empty(FN_CONSENSUS_VOTE_DOMAIN)
+            .with("epoch", &self.epoch)
+            .with("round", &self.round)
+            .with("digest", &self.digest)
+            .hash()
+    }
+}
+
+/// Evidence that a node misbehaved, which anyone can submit to have the node slashed.
+///
+/// The evidence is self-contained: it is checked against the public key of the node alone, so the
+/// submitter does not have to be trusted.
+#[derive(Clone, Debug, Serialize, Deserialize, Hash, PartialEq, Eq)]
+pub enum MisbehaviorEvidence {
+    /// The node voted for two different blocks in the same round.
+    Equivocation {
+        first: SignedVote,
+        second: SignedVote,
+    },
+}
+
+impl MisbehaviorEvidence {
+    /// Returns true if this evidence proves that `node` misbehaved.
+    pub fn verify(&self, node: &NodePublicKey) -> bool {
+        match self {
+            MisbehaviorEvidence::Equivocation { first, second } => {
+                first.epoch == second.epoch
+                    && first.round == second.round
+                    && first.digest != second.digest
+                    && node.verify(&first.signature, &first.to_digest())
+                    && node.verify(&second.signature, &second.to_digest())
+            },
+        }
+    }
+
+    /// Returns an identifier of the offence this evidence is about, so that a node can only be
+    /// slashed once for it. Different evidence for the same offence, such as the same two votes in
+    /// the opposite order, has the same identifier.
+    pub fn offence_id(&self) -> [u8; 32] {
+        match self {
+            MisbehaviorEvidence::Equivocation { first, .. } => {
+                TranscriptBuilder::empty(FN_MISBEHAVIOR_DOMAIN)
+                    .with("offence", &"equivocation")
+                    .with("epoch", &first.epoch)
+                    .with("round", &first.round)
+                    .hash()
+            },
+        }
+    }
+}
+
+impl ToDigest for MisbehaviorEvidence {
+    fn to_digest(&self) -> [u8; 32] {
+        match self {
+            MisbehaviorEvidence::Equivocation { first, second } => {
+                TranscriptBuilder::empty(FN_MISBEHAVIOR_DOMAIN)
+                    .with("offence", &"equivocation")
+                    .with("first", &first.to_digest())
+                    .with("first_signature", &first.signature.0)
+                    .with("second", &second.to_digest())
+                    .with("second_signature", &second.signature.0)
+                    .hash()
+            },
+        }
+    }
+}
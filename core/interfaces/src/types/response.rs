@@ -1,3 +1,6 @@
+use std::time::Duration;
+
+use hp_fixed::unsigned::HpUfixed;
 use serde::{Deserialize, Serialize};
 
 use super::{Epoch, NodeInfo};
@@ -13,12 +16,72 @@ pub struct EpochInfo {
     pub epoch_end: u64,
 }
 
+/// The progress of the current epoch against the wall clock.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EpochProgress {
+    /// Time passed since the epoch started
+    pub elapsed: Duration,
+    /// Time left until the epoch ends, zero once the end is due
+    pub remaining: Duration,
+    /// The elapsed fraction of the epoch, in the range `[0, 1]`
+    pub fraction: f64,
+}
+
+/// The supply of FLK tokens, taken from a single consistent view of the state.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SupplySnapshot {
+    /// The current total supply
+    pub total_supply: HpUfixed<18>,
+    /// The total supply at the start of the year, used for inflation
+    pub year_start_supply: HpUfixed<18>,
+    /// The tokens staked or locked by all of the nodes
+    pub total_staked: HpUfixed<18>,
+    /// The total supply minus the staked and locked tokens
+    pub circulating: HpUfixed<18>,
+    /// The balance of the protocol fund address
+    pub protocol_fund_balance: HpUfixed<18>,
+}
+
+/// The outcome of a transaction that was executed in a block, stored under the
+/// [`receipt_key`](super::receipt_key) of its sender and payload.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    /// The response of the transaction
+    pub response: TransactionResponse,
+    /// The epoch in which the transaction was executed
+    pub epoch: Epoch,
+    /// The number of the block that contained the transaction
+    pub block_number: u64,
+    /// The position of the transaction in the block
+    pub index: u32,
+}
+
 #[derive(Clone, Debug, PartialEq, PartialOrd, Hash, Eq, Serialize, Deserialize)]
 pub enum TransactionResponse {
     Success(ExecutionData),
     Revert(ExecutionError),
 }
 
+/// The outcome of running a transaction against the current state without committing it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SimulatedOutcome {
+    /// The response the transaction would get if it was executed now.
+    pub response: TransactionResponse,
+    /// The final value of every entry the transaction would write, ordered by table and key.
+    pub deltas: Vec<StateDelta>,
+}
+
+/// A write to one entry of the application state.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateDelta {
+    /// The name of the table.
+    pub table: String,
+    /// The serialized key of the entry.
+    pub key: Vec<u8>,
+    /// The serialized new value of the entry, or `None` if the entry is removed.
+    pub value: Option<Vec<u8>>,
+}
+
 #[derive(Clone, Debug, PartialEq, PartialOrd, Hash, Eq, Serialize, Deserialize)]
 pub enum ExecutionData {
     None,
@@ -53,4 +116,10 @@ pub enum ExecutionError {
     LockedTokensUnstakeForbidden,
     EpochAlreadyChanged,
     EpochHasNotStarted,
+    BlockWeightExceeded,
+    AlreadySlashed,
+    ProposalDoesNotExist,
+    ProposalExpired,
+    AlreadyVoted,
+    NestedBundle,
 }
@@ -0,0 +1,79 @@
+use std::{
+    collections::{btree_map, BTreeMap},
+    ops::Index,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Service, ServiceId};
+
+/// The set of services known to a node, ordered by their [`ServiceId`].
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ServiceRegistry {
+    services: BTreeMap<ServiceId, Service>,
+}
+
+impl ServiceRegistry {
+    /// Returns an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a service, returning the previous metadata if the id was already registered.
+    pub fn insert(&mut self, id: ServiceId, service: Service) -> Option<Service> {
+        self.services.insert(id, service)
+    }
+
+    /// Returns true if a service with the given id is registered.
+    pub fn contains(&self, id: ServiceId) -> bool {
+        self.services.contains_key(&id)
+    }
+
+    /// Returns the metadata of the service with the given id.
+    pub fn get(&self, id: ServiceId) -> Option<&Service> {
+        self.services.get(&id)
+    }
+
+    /// Returns the number of registered services.
+    pub fn len(&self) -> usize {
+        self.services.len()
+    }
+
+    /// Returns true if no service is registered.
+    pub fn is_empty(&self) -> bool {
+        self.services.is_empty()
+    }
+
+    /// Iterate over the registered services in the order of their ids.
+    pub fn iter(&self) -> btree_map::Iter<'_, ServiceId, Service> {
+        self.services.iter()
+    }
+}
+
+impl Index<ServiceId> for ServiceRegistry {
+    type Output = Service;
+
+    /// # Panics
+    ///
+    /// If no service with the given id is registered.
+    fn index(&self, id: ServiceId) -> &Self::Output {
+        self.get(id).expect("Service is not registered.")
+    }
+}
+
+impl FromIterator<(ServiceId, Service)> for ServiceRegistry {
+    fn from_iter<T: IntoIterator<Item = (ServiceId, Service)>>(iter: T) -> Self {
+        Self {
+            services: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a ServiceRegistry {
+    type Item = (&'a ServiceId, &'a Service);
+    type IntoIter = btree_map::Iter<'a, ServiceId, Service>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
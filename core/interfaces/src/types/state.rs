@@ -1,5 +1,7 @@
 //! The data types used in the application state.
 
+use std::collections::BTreeMap;
+
 use fleek_crypto::{EthAddress, NodeNetworkingPublicKey, NodePublicKey};
 use hp_fixed::unsigned::HpUfixed;
 use ink_quill::TranscriptBuilderInput;
@@ -7,7 +9,7 @@
 use num_derive::FromPrimitive;
 use serde::{Deserialize, Serialize};
 
-use super::ReputationMeasurements;
+use super::{ReputationMeasurements, UpdateMethod};
 
 /// The Id of a Service
 pub type ServiceId = u32;
@@ -15,6 +17,40 @@
 /// Application epoch number
 pub type Epoch = u64;
 
+/// The Id of a governance proposal, assigned by the application when the proposal is submitted.
+pub type ProposalId = u64;
+
+/// The index of a node, assigned by the application when the node is registered.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+pub struct NodeIndex(pub u32);
+
+impl NodeIndex {
+    /// Returns the index that is assigned to the node registered after this one.
+    pub fn next(self) -> Self {
+        Self(self.0 + 1)
+    }
+}
+
+impl From<u32> for NodeIndex {
+    fn from(index: u32) -> Self {
+        Self(index)
+    }
+}
+
+impl From<NodeIndex> for u32 {
+    fn from(index: NodeIndex) -> Self {
+        index.0
+    }
+}
+
+impl std::fmt::Display for NodeIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 #[derive(Serialize, Deserialize, Hash, Debug, Clone)]
 pub enum Tokens {
     USDC,
@@ -35,6 +71,20 @@ pub struct TotalServed {
 
 pub type ServiceRevenue = HpUfixed<6>;
 
+/// The participation of a node across all of the epochs that have ended, updated at every epoch
+/// change.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct Participation {
+    /// The number of epochs in which the node served commodities or, as a committee member,
+    /// signaled the epoch change.
+    pub epochs_active: u64,
+    /// The number of epochs in which the node was a member of the committee.
+    pub epochs_in_committee: u64,
+    /// The number of epochs in which the node was a member of the committee but was not active,
+    /// so these never overlap with the active epochs.
+    pub missed_epochs: u64,
+}
+
 /// This is commodity served by each of the commodity types
 type CommodityServed = Vec<u128>;
 
@@ -50,8 +100,7 @@ pub enum CommodityTypes {
 
 #[derive(Clone, Debug, Hash, Serialize, Deserialize)]
 pub struct ReportedReputationMeasurements {
-    // TODO: Use NodeIndex instead.
-    pub reporting_node: NodePublicKey,
+    pub reporting_node: NodeIndex,
     pub measurements: ReputationMeasurements,
 }
 
@@ -64,6 +113,9 @@ pub enum Metadata {
     ProtocolFundAddress,
     NextNodeIndex,
     GovernanceAddress,
+    BlockNumber,
+    NextProposalId,
+    EpochStart,
 }
 
 /// The Value enum is a data type used to represent values in a key-value pair for a metadata table
@@ -73,7 +125,10 @@ pub enum Value {
     String(String),
     HpUfixed(HpUfixed<18>),
     AccountPublicKey(EthAddress),
-    NextNodeIndex(u32),
+    NextNodeIndex(NodeIndex),
+    BlockNumber(u64),
+    NextProposalId(ProposalId),
+    Timestamp(u64),
 }
 
 /// Adjustable parameters that are stored in the blockchain
@@ -102,6 +157,13 @@ pub enum ProtocolParams {
     MaxBoost = 9,
     /// The max amount of time tokens can be locked
     MaxStakeLockTime = 10,
+    /// The max total weight of the transactions executed in a single block
+    MaxBlockWeight = 11,
+    /// The max deviation of a reported measurement from the stake-weighted median of all reports
+    /// for the same node, in percent of the median, before it is discarded as an outlier
+    MaxMeasurementDeviation = 12,
+    /// The percentage of its stake a node loses when it is slashed for misbehaving
+    SlashingPercentage = 13,
 }
 
 #[derive(Debug, Hash, PartialEq, PartialOrd, Ord, Eq, Serialize, Deserialize, Clone)]
@@ -169,6 +231,32 @@ pub struct Committee {
     pub epoch_end_timestamp: u64,
 }
 
+/// A governance proposal, voted on by the members of the committee until its deadline.
+#[derive(Debug, Hash, Clone, Serialize, Deserialize)]
+pub struct Proposal {
+    /// The id of the proposal
+    pub id: ProposalId,
+    /// The method the proposal is about
+    pub method: UpdateMethod,
+    /// The vote of every node that voted, true if the node is in favor of the proposal
+    pub votes: BTreeMap<NodePublicKey, bool>,
+    /// The epoch at which voting ends, the proposal is open during the epochs before it
+    pub deadline: Epoch,
+}
+
+impl Proposal {
+    /// Returns true if the proposal can still be voted on in the given epoch.
+    pub fn is_open(&self, epoch: Epoch) -> bool {
+        epoch < self.deadline
+    }
+
+    /// Returns the number of votes in favor of and against the proposal.
+    pub fn tally(&self) -> (usize, usize) {
+        let yes = self.votes.values().filter(|approve| **approve).count();
+        (yes, self.votes.len() - yes)
+    }
+}
+
 impl TranscriptBuilderInput for Service {
     const TYPE: &'static str = "service";
 
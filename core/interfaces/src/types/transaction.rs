@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::{cell::Cell, collections::BTreeMap};
 
 use fleek_crypto::{
     EthAddress, NodeNetworkingPublicKey, NodePublicKey, PublicKey, TransactionSender,
@@ -7,16 +7,17 @@
 use hp_fixed::unsigned::HpUfixed;
 use ink_quill::{TranscriptBuilder, TranscriptBuilderInput};
 use num_bigint::BigUint;
-use serde::{Deserialize, Serialize};
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
 
 use super::{
-    DeliveryAcknowledgment, Epoch, ProofOfConsensus, ProofOfMisbehavior, ProtocolParams,
-    ReputationMeasurements, Service, ServiceId, Tokens,
+    DeliveryAcknowledgment, Epoch, MisbehaviorEvidence, ProofOfConsensus, ProofOfMisbehavior,
+    ProposalId, ProtocolParams, ReputationMeasurements, Service, ServiceId, Tokens,
 };
 use crate::ToDigest;
 
 // TODO: Change this to capital and non-abrv version.
 const FN_TXN_PAYLOAD_DOMAIN: &str = "fleek_network_txn_payload";
+const FN_TXN_RECEIPT_DOMAIN: &str = "fleek_network_txn_receipt";
 
 /// A block of transactions, which is a list of update requests each signed by a user,
 /// the block is the atomic view into the network, meaning that queries do not view
@@ -40,6 +41,25 @@ pub struct UpdateRequest {
     pub payload: UpdatePayload,
 }
 
+impl UpdateRequest {
+    /// Returns the key under which the receipt of this request is stored.
+    pub fn receipt_key(&self) -> [u8; 32] {
+        receipt_key(&self.sender, &self.payload.to_digest())
+    }
+}
+
+/// Returns the key under which the receipt of the transaction with the given sender and payload
+/// digest is stored. The payload does not say who sent it, so the key also commits to the sender
+/// to keep two senders that sign the same payload from sharing a receipt.
+pub fn receipt_key(sender: &TransactionSender, digest: &[u8; 32]) -> [u8; 32] {
+    let transcript_builder = TranscriptBuilder::empty(FN_TXN_RECEIPT_DOMAIN);
+    let transcript_builder = match sender {
+        TransactionSender::Node(node) => transcript_builder.with("node", &node.0),
+        TransactionSender::AccountOwner(address) => transcript_builder.with("account", &address.0),
+    };
+    transcript_builder.with("payload", digest).hash()
+}
+
 /// The payload data of an update request.
 #[derive(Debug, Hash, Clone, Serialize, Deserialize)]
 pub struct UpdatePayload {
@@ -49,6 +69,44 @@ pub struct UpdatePayload {
     pub method: UpdateMethod,
 }
 
+/// A builder that assembles a signed [`UpdateRequest`] from a method and a nonce.
+///
+/// The signature is always computed over the digest of the exact [`UpdatePayload`] that ends up
+/// in the request, so the sender, signature and payload can not get out of sync.
+#[derive(Debug, Clone)]
+pub struct UpdateRequestBuilder {
+    payload: UpdatePayload,
+}
+
+impl UpdateRequestBuilder {
+    /// Create a new builder for the given method and nonce.
+    pub fn new(method: UpdateMethod, nonce: u64) -> Self {
+        Self {
+            payload: UpdatePayload { nonce, method },
+        }
+    }
+
+    /// Returns the payload that is going to be signed.
+    pub fn payload(&self) -> &UpdatePayload {
+        &self.payload
+    }
+
+    /// Sign the payload digest using the provided closure and assemble the update request
+    /// on behalf of the given sender.
+    pub fn build<F, S>(self, sender: impl Into<TransactionSender>, sign: F) -> UpdateRequest
+    where
+        F: FnOnce(&[u8; 32]) -> S,
+        S: Into<TransactionSignature>,
+    {
+        let digest = self.payload.to_digest();
+        UpdateRequest {
+            sender: sender.into(),
+            signature: sign(&digest).into(),
+            payload: self.payload,
+        }
+    }
+}
+
 /// All of the update functions in our logic, along their parameters.
 #[derive(Debug, Hash, Clone, Serialize, Deserialize)]
 pub enum UpdateMethod {
@@ -149,6 +207,131 @@ pub enum UpdateMethod {
         param: ProtocolParams,
         value: u128,
     },
+    /// Execute several methods on behalf of the same sender, atomically: either all of them
+    /// succeed, or the transaction reverts with the error of the first method that failed and
+    /// none of their changes are applied. A bundle can not contain another bundle.
+    Bundle {
+        #[serde(deserialize_with = "deserialize_bundle_methods")]
+        methods: Vec<UpdateMethod>,
+    },
+    /// Submit evidence that a node misbehaved, which slashes a fraction of its stake if the
+    /// evidence is valid
+    SubmitMisbehaviorEvidence {
+        /// The public key of the node that misbehaved
+        node: NodePublicKey,
+        evidence: MisbehaviorEvidence,
+    },
+    /// Submit a governance proposal that the committee can vote on until the deadline epoch. Can
+    /// only be sent by the governance address
+    SubmitProposal {
+        /// The method the proposal is about
+        method: Box<UpdateMethod>,
+        /// The epoch at which voting ends
+        deadline: Epoch,
+    },
+    /// Sent by a committee member to vote on an open governance proposal
+    VoteOnProposal {
+        proposal_id: ProposalId,
+        /// True to vote in favor of the proposal
+        approve: bool,
+    },
+}
+
+/// The kind of an [`UpdateMethod`], without any of its parameters.
+///
+/// The discriminant values are part of the protocol and must never be reordered or reused, new
+/// kinds are only ever appended.
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum UpdateMethodKind {
+    SubmitDeliveryAcknowledgmentAggregation = 0,
+    Withdraw = 1,
+    Deposit = 2,
+    Stake = 3,
+    StakeLock = 4,
+    Unstake = 5,
+    WithdrawUnstaked = 6,
+    ChangeEpoch = 7,
+    AddService = 8,
+    RemoveService = 9,
+    Slash = 10,
+    SubmitReputationMeasurements = 11,
+    ChangeProtocolParam = 12,
+    Bundle = 13,
+    SubmitMisbehaviorEvidence = 14,
+    SubmitProposal = 15,
+    VoteOnProposal = 16,
+}
+
+impl UpdateMethod {
+    /// Returns the kind of this update method.
+    pub fn kind(&self) -> UpdateMethodKind {
+        match self {
+            UpdateMethod::SubmitDeliveryAcknowledgmentAggregation { .. } => {
+                UpdateMethodKind::SubmitDeliveryAcknowledgmentAggregation
+            },
+            UpdateMethod::Withdraw { .. } => UpdateMethodKind::Withdraw,
+            UpdateMethod::Deposit { .. } => UpdateMethodKind::Deposit,
+            UpdateMethod::Stake { .. } => UpdateMethodKind::Stake,
+            UpdateMethod::StakeLock { .. } => UpdateMethodKind::StakeLock,
+            UpdateMethod::Unstake { .. } => UpdateMethodKind::Unstake,
+            UpdateMethod::WithdrawUnstaked { .. } => UpdateMethodKind::WithdrawUnstaked,
+            UpdateMethod::ChangeEpoch { .. } => UpdateMethodKind::ChangeEpoch,
+            UpdateMethod::AddService { .. } => UpdateMethodKind::AddService,
+            UpdateMethod::RemoveService { .. } => UpdateMethodKind::RemoveService,
+            UpdateMethod::Slash { .. } => UpdateMethodKind::Slash,
+            UpdateMethod::SubmitReputationMeasurements { .. } => {
+                UpdateMethodKind::SubmitReputationMeasurements
+            },
+            UpdateMethod::ChangeProtocolParam { .. } => UpdateMethodKind::ChangeProtocolParam,
+            UpdateMethod::Bundle { .. } => UpdateMethodKind::Bundle,
+            UpdateMethod::SubmitMisbehaviorEvidence { .. } => {
+                UpdateMethodKind::SubmitMisbehaviorEvidence
+            },
+            UpdateMethod::SubmitProposal { .. } => UpdateMethodKind::SubmitProposal,
+            UpdateMethod::VoteOnProposal { .. } => UpdateMethodKind::VoteOnProposal,
+        }
+    }
+
+    /// Returns the weight of this update method, which is an estimate of the cost of executing
+    /// it. The total weight of the transactions in a block is bounded by
+    /// [`ProtocolParams::MaxBlockWeight`].
+    ///
+    /// [`ProtocolParams::MaxBlockWeight`]: crate::types::ProtocolParams::MaxBlockWeight
+    pub fn weight(&self) -> u64 {
+        match self {
+            UpdateMethod::SubmitDeliveryAcknowledgmentAggregation { proofs, .. } => {
+                10 + proofs.len() as u64
+            },
+            UpdateMethod::SubmitReputationMeasurements { measurements } => {
+                10 + measurements.len() as u64
+            },
+            // The last signal computes the reputation scores and distributes the rewards.
+            UpdateMethod::ChangeEpoch { .. } => 1_000,
+            UpdateMethod::Bundle { methods } => {
+                10.max(methods.iter().map(UpdateMethod::weight).sum())
+            },
+            _ => 10,
+        }
+    }
+}
+
+thread_local! {
+    /// Whether the methods of a bundle are being deserialized on this thread.
+    static IN_BUNDLE: Cell<bool> = Cell::new(false);
+}
+
+/// Deserialize the methods of a bundle, refusing any bundle found inside of them so that a
+/// malicious transaction can not make the deserialization recurse without bound.
+fn deserialize_bundle_methods<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<UpdateMethod>, D::Error> {
+    if IN_BUNDLE.with(|in_bundle| in_bundle.replace(true)) {
+        return Err(D::Error::custom("a bundle can not contain another bundle"));
+    }
+    let methods = Vec::deserialize(deserializer);
+    IN_BUNDLE.with(|in_bundle| in_bundle.set(false));
+    methods
 }
 
 impl ToDigest for UpdatePayload {
@@ -312,6 +495,58 @@ impl ToDigest for UpdatePayload {
                     .with("param", &(param.clone() as u8))
                     .with("value", value);
             },
+            UpdateMethod::Bundle { methods } => {
+                transcript_builder = transcript_builder.with("transaction_name", &"bundle");
+                for (index, method) in methods.iter().enumerate() {
+                    // A nested bundle always reverts, so only the fact that it is there is
+                    // committed to, which keeps the digest from recursing.
+                    if let UpdateMethod::Bundle { .. } = method {
+                        transcript_builder = transcript_builder
+                            .with_prefix(index.to_string())
+                            .with("method", &"nested_bundle");
+                        continue;
+                    }
+                    // Every method is committed to by the digest of its own payload.
+                    let digest = UpdatePayload {
+                        nonce: self.nonce,
+                        method: method.clone(),
+                    }
+                    .to_digest();
+                    transcript_builder = transcript_builder
+                        .with_prefix(index.to_string())
+                        .with("method", &digest);
+                }
+            },
+            UpdateMethod::SubmitMisbehaviorEvidence { node, evidence } => {
+                transcript_builder = transcript_builder
+                    .with("transaction_name", &"submit_misbehavior_evidence")
+                    .with_prefix("input".to_owned())
+                    .with("node", &node.0)
+                    .with("evidence", &evidence.to_digest());
+            },
+            UpdateMethod::SubmitProposal { method, deadline } => {
+                // The proposed method is committed to by the digest of its own payload.
+                let digest = UpdatePayload {
+                    nonce: self.nonce,
+                    method: (**method).clone(),
+                }
+                .to_digest();
+                transcript_builder = transcript_builder
+                    .with("transaction_name", &"submit_proposal")
+                    .with_prefix("input".to_owned())
+                    .with("method", &digest)
+                    .with("deadline", deadline);
+            },
+            UpdateMethod::VoteOnProposal {
+                proposal_id,
+                approve,
+            } => {
+                transcript_builder = transcript_builder
+                    .with("transaction_name", &"vote_on_proposal")
+                    .with_prefix("input".to_owned())
+                    .with("proposal_id", proposal_id)
+                    .with("approve", &(*approve as u8));
+            },
         }
 
         transcript_builder.hash()
@@ -343,3 +578,183 @@ fn to_transcript_builder_input(&self) -> Vec<u8> {
         input
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use fleek_crypto::NodeSignature;
+
+    use super::*;
+    use crate::types::{CommodityTypes, SignedVote};
+
+    #[test]
+    fn test_update_method_kind() {
+        let node = NodePublicKey([0; 96]);
+        let address = EthAddress([0; 20]);
+        let vote = SignedVote {
+            epoch: 0,
+            round: 0,
+            digest: [0; 32],
+            signature: NodeSignature([0; 48]),
+        };
+
+        // `Slash` can't be constructed until `ProofOfMisbehavior` is inhabited.
+        let methods = vec![
+            (
+                UpdateMethod::SubmitDeliveryAcknowledgmentAggregation {
+                    commodity: 0,
+                    service_id: 0,
+                    proofs: vec![],
+                    metadata: None,
+                },
+                0,
+            ),
+            (
+                UpdateMethod::Withdraw {
+                    amount: 0_u64.into(),
+                    token: Tokens::FLK,
+                    receiving_address: address,
+                },
+                1,
+            ),
+            (
+                UpdateMethod::Deposit {
+                    proof: ProofOfConsensus {},
+                    token: Tokens::FLK,
+                    amount: 0_u64.into(),
+                },
+                2,
+            ),
+            (
+                UpdateMethod::Stake {
+                    amount: 0_u64.into(),
+                    node_public_key: node,
+                    node_network_key: None,
+                    node_domain: None,
+                    worker_public_key: None,
+                    worker_domain: None,
+                    worker_mempool_address: None,
+                },
+                3,
+            ),
+            (
+                UpdateMethod::StakeLock {
+                    node,
+                    locked_for: 0,
+                },
+                4,
+            ),
+            (
+                UpdateMethod::Unstake {
+                    amount: 0_u64.into(),
+                    node,
+                },
+                5,
+            ),
+            (
+                UpdateMethod::WithdrawUnstaked {
+                    node,
+                    recipient: None,
+                },
+                6,
+            ),
+            (UpdateMethod::ChangeEpoch { epoch: 0 }, 7),
+            (
+                UpdateMethod::AddService {
+                    service: Service {
+                        owner: address,
+                        commodity_type: CommodityTypes::Bandwidth,
+                        slashing: (),
+                    },
+                    service_id: 0,
+                },
+                8,
+            ),
+            (UpdateMethod::RemoveService { service_id: 0 }, 9),
+            (
+                UpdateMethod::SubmitReputationMeasurements {
+                    measurements: BTreeMap::new(),
+                },
+                11,
+            ),
+            (
+                UpdateMethod::ChangeProtocolParam {
+                    param: ProtocolParams::EpochTime,
+                    value: 0,
+                },
+                12,
+            ),
+            (UpdateMethod::Bundle { methods: vec![] }, 13),
+            (
+                UpdateMethod::SubmitMisbehaviorEvidence {
+                    node,
+                    evidence: MisbehaviorEvidence::Equivocation {
+                        first: vote.clone(),
+                        second: vote,
+                    },
+                },
+                14,
+            ),
+            (
+                UpdateMethod::SubmitProposal {
+                    method: Box::new(UpdateMethod::ChangeEpoch { epoch: 0 }),
+                    deadline: 0,
+                },
+                15,
+            ),
+            (
+                UpdateMethod::VoteOnProposal {
+                    proposal_id: 0,
+                    approve: true,
+                },
+                16,
+            ),
+        ];
+
+        let mut seen = HashSet::new();
+        for (method, expected) in methods {
+            let kind = method.kind();
+            assert_eq!(kind as u8, expected, "{method:?} changed its kind");
+            assert!(
+                seen.insert(kind),
+                "{kind:?} is used by more than one method"
+            );
+        }
+        assert_eq!(UpdateMethodKind::Slash as u8, 10);
+    }
+
+    #[test]
+    fn test_nested_bundle_is_not_deserialized() {
+        let bundle = UpdateMethod::Bundle {
+            methods: vec![UpdateMethod::ChangeEpoch { epoch: 0 }],
+        };
+        let bytes = bincode::serialize(&bundle).unwrap();
+        let method: UpdateMethod = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(bincode::serialize(&method).unwrap(), bytes);
+
+        let nested = UpdateMethod::Bundle {
+            methods: vec![bundle.clone()],
+        };
+        let bytes = bincode::serialize(&nested).unwrap();
+        assert!(bincode::deserialize::<UpdateMethod>(&bytes).is_err());
+
+        // A failed deserialization does not leave the next bundle refused.
+        let bytes = bincode::serialize(&bundle).unwrap();
+        assert!(bincode::deserialize::<UpdateMethod>(&bytes).is_ok());
+    }
+
+    #[test]
+    fn test_nested_bundle_digest_does_not_recurse() {
+        let nested = |epoch| UpdatePayload {
+            nonce: 0,
+            method: UpdateMethod::Bundle {
+                methods: vec![UpdateMethod::Bundle {
+                    methods: vec![UpdateMethod::ChangeEpoch { epoch }],
+                }],
+            },
+        };
+        // The content of a nested bundle is never executed, so it is not committed to.
+        assert_eq!(nested(0).to_digest(), nested(1).to_digest());
+    }
+}
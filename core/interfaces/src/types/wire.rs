@@ -0,0 +1,256 @@
+use fleek_crypto::{
+    AccountOwnerSignature, EthAddress, NodePublicKey, NodeSignature, TransactionSender,
+    TransactionSignature,
+};
+use thiserror::Error;
+
+use super::{UpdateMethod, UpdatePayload, UpdateRequest};
+use crate::ToDigest;
+
+/// The version of the wire encoding of an [`UpdateRequest`] produced by
+/// [`UpdateRequest::encode`].
+///
+/// The encoding starts with the version byte, followed by the fields of the request in a fixed
+/// order, each prefixed with its length as a big endian `u32`:
+///
+/// - Version 0: sender, signature, nonce and method.
+/// - Version 1: adds the digest of the payload, which is checked against the decoded payload.
+///
+/// New versions only ever append fields. A blob of an older version decodes with defaults for the
+/// fields it is missing, and the fields a newer version appended are skipped, so nodes running
+/// different versions can exchange requests during a rolling upgrade.
+pub const UPDATE_REQUEST_WIRE_VERSION: u8 = 1;
+
+const SENDER_NODE: u8 = 0;
+const SENDER_ACCOUNT_OWNER: u8 = 1;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum WireDecodeError {
+    #[error("The encoded request ended before all of its fields were read.")]
+    Truncated,
+    #[error("The {0} field of the encoded request is invalid.")]
+    InvalidField(&'static str),
+    #[error("The digest of the decoded payload does not match the encoded digest.")]
+    DigestMismatch,
+}
+
+impl UpdateRequest {
+    /// Encode the request for the wire with the current [`UPDATE_REQUEST_WIRE_VERSION`].
+    pub fn encode(&self) -> Vec<u8> {
+        self.encode_version(UPDATE_REQUEST_WIRE_VERSION)
+    }
+
+    /// Decode a request that was encoded with [`UpdateRequest::encode`] by any version.
+    pub fn decode(bytes: &[u8]) -> Result<Self, WireDecodeError> {
+        let (_version, mut fields) = bytes.split_first().ok_or(WireDecodeError::Truncated)?;
+
+        let sender = decode_sender(next_field(&mut fields)?.ok_or(WireDecodeError::Truncated)?)?;
+        let signature =
+            decode_signature(next_field(&mut fields)?.ok_or(WireDecodeError::Truncated)?)?;
+        let nonce = next_field(&mut fields)?
+            .ok_or(WireDecodeError::Truncated)?
+            .try_into()
+            .map(u64::from_be_bytes)
+            .map_err(|_| WireDecodeError::InvalidField("nonce"))?;
+        let method = bincode::deserialize::<UpdateMethod>(
+            next_field(&mut fields)?.ok_or(WireDecodeError::Truncated)?,
+        )
+        .map_err(|_| WireDecodeError::InvalidField("method"))?;
+        let payload = UpdatePayload { nonce, method };
+
+        // Added in version 1, there is nothing to check when it is missing.
+        if let Some(digest) = next_field(&mut fields)? {
+            if digest != payload.to_digest() {
+                return Err(WireDecodeError::DigestMismatch);
+            }
+        }
+
+        // Fields appended by newer versions are skipped.
+        while next_field(&mut fields)?.is_some() {}
+
+        Ok(Self {
+            sender,
+            signature,
+            payload,
+        })
+    }
+
+    fn encode_version(&self, version: u8) -> Vec<u8> {
+        let mut out = vec![version];
+
+        let mut sender = Vec::with_capacity(97);
+        match &self.sender {
+            TransactionSender::Node(key) => {
+                sender.push(SENDER_NODE);
+                sender.extend_from_slice(&key.0);
+            },
+            TransactionSender::AccountOwner(address) => {
+                sender.push(SENDER_ACCOUNT_OWNER);
+                sender.extend_from_slice(&address.0);
+            },
+        }
+        push_field(&mut out, &sender);
+
+        let mut signature = Vec::with_capacity(66);
+        match &self.signature {
+            TransactionSignature::Node(signature_bytes) => {
+                signature.push(SENDER_NODE);
+                signature.extend_from_slice(&signature_bytes.0);
+            },
+            TransactionSignature::AccountOwner(signature_bytes) => {
+                signature.push(SENDER_ACCOUNT_OWNER);
+                signature.extend_from_slice(&signature_bytes.0);
+            },
+        }
+        push_field(&mut out, &signature);
+
+        push_field(&mut out, &self.payload.nonce.to_be_bytes());
+        let method =
+            bincode::serialize(&self.payload.method).expect("Serializing a method to succeed");
+        push_field(&mut out, &method);
+
+        if version >= 1 {
+            push_field(&mut out, &self.payload.to_digest());
+        }
+
+        out
+    }
+}
+
+fn push_field(out: &mut Vec<u8>, field: &[u8]) {
+    out.extend_from_slice(&(field.len() as u32).to_be_bytes());
+    out.extend_from_slice(field);
+}
+
+/// Read the next length-prefixed field, returns `None` once all of the fields were read.
+fn next_field<'a>(bytes: &mut &'a [u8]) -> Result<Option<&'a [u8]>, WireDecodeError> {
+    if bytes.is_empty() {
+        return Ok(None);
+    }
+    if bytes.len() < 4 {
+        return Err(WireDecodeError::Truncated);
+    }
+    let (len, rest) = bytes.split_at(4);
+    let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+    if rest.len() < len {
+        return Err(WireDecodeError::Truncated);
+    }
+    let (field, rest) = rest.split_at(len);
+    *bytes = rest;
+    Ok(Some(field))
+}
+
+fn decode_sender(field: &[u8]) -> Result<TransactionSender, WireDecodeError> {
+    let invalid = WireDecodeError::InvalidField("sender");
+    match field.split_first() {
+        Some((&SENDER_NODE, key)) => key
+            .try_into()
+            .map(|key| TransactionSender::Node(NodePublicKey(key)))
+            .map_err(|_| invalid),
+        Some((&SENDER_ACCOUNT_OWNER, address)) => address
+            .try_into()
+            .map(|address| TransactionSender::AccountOwner(EthAddress(address)))
+            .map_err(|_| invalid),
+        _ => Err(invalid),
+    }
+}
+
+fn decode_signature(field: &[u8]) -> Result<TransactionSignature, WireDecodeError> {
+    let invalid = WireDecodeError::InvalidField("signature");
+    match field.split_first() {
+        Some((&SENDER_NODE, signature)) => signature
+            .try_into()
+            .map(|signature| TransactionSignature::Node(NodeSignature(signature)))
+            .map_err(|_| invalid),
+        Some((&SENDER_ACCOUNT_OWNER, signature)) => signature
+            .try_into()
+            .map(|signature| TransactionSignature::AccountOwner(AccountOwnerSignature(signature)))
+            .map_err(|_| invalid),
+        _ => Err(invalid),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> UpdateRequest {
+        UpdateRequest {
+            sender: TransactionSender::Node(NodePublicKey([1; 96])),
+            signature: TransactionSignature::Node(NodeSignature([2; 48])),
+            payload: UpdatePayload {
+                nonce: 7,
+                method: UpdateMethod::ChangeEpoch { epoch: 3 },
+            },
+        }
+    }
+
+    fn assert_same(a: &UpdateRequest, b: &UpdateRequest) {
+        assert_eq!(a.sender, b.sender);
+        assert_eq!(a.signature, b.signature);
+        assert_eq!(a.payload.to_digest(), b.payload.to_digest());
+    }
+
+    #[test]
+    fn test_encode_decode_round_trip() {
+        let request = request();
+        let encoded = request.encode();
+        assert_eq!(encoded[0], UPDATE_REQUEST_WIRE_VERSION);
+        assert_same(&UpdateRequest::decode(&encoded).unwrap(), &request);
+
+        let account = UpdateRequest {
+            sender: TransactionSender::AccountOwner(EthAddress([3; 20])),
+            signature: TransactionSignature::AccountOwner(AccountOwnerSignature([4; 65])),
+            ..request
+        };
+        assert_same(&UpdateRequest::decode(&account.encode()).unwrap(), &account);
+    }
+
+    #[test]
+    fn test_decode_v0_defaults_missing_fields() {
+        let request = request();
+        let encoded = request.encode_version(0);
+        assert_eq!(encoded[0], 0);
+        assert!(encoded.len() < request.encode().len());
+        assert_same(&UpdateRequest::decode(&encoded).unwrap(), &request);
+    }
+
+    #[test]
+    fn test_decode_skips_fields_of_newer_versions() {
+        let request = request();
+        let mut encoded = request.encode();
+        encoded[0] = UPDATE_REQUEST_WIRE_VERSION + 1;
+        push_field(&mut encoded, b"future");
+        assert_same(&UpdateRequest::decode(&encoded).unwrap(), &request);
+    }
+
+    #[test]
+    fn test_decode_rejects_invalid_blobs() {
+        let request = request();
+        let encoded = request.encode();
+        assert_eq!(
+            UpdateRequest::decode(&encoded[..encoded.len() - 1]).unwrap_err(),
+            WireDecodeError::Truncated
+        );
+        assert_eq!(
+            UpdateRequest::decode(&[]).unwrap_err(),
+            WireDecodeError::Truncated
+        );
+
+        // A digest that does not match the payload.
+        let mut tampered = encoded.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            UpdateRequest::decode(&tampered).unwrap_err(),
+            WireDecodeError::DigestMismatch
+        );
+
+        // An unknown sender kind.
+        let mut tampered = encoded;
+        tampered[5] = 9;
+        assert_eq!(
+            UpdateRequest::decode(&tampered).unwrap_err(),
+            WireDecodeError::InvalidField("sender")
+        );
+    }
+}
@@ -269,6 +269,7 @@ impl AutoImplSerde for TestFrame {}
             lightning_application::app::Application::init(lightning_application::config::Config {
                 mode: Mode::Test,
                 genesis: None,
+                dev_single_node: None,
             })
             .await?;
         let query_runner = application.sync_query();
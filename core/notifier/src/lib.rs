@@ -79,6 +79,7 @@ async fn init_app(epoch_time: u64) -> (ExecutionEngineSocket, QueryRunner) {
         let config = Config {
             genesis: Some(genesis),
             mode: Mode::Test,
+            dev_single_node: None,
         };
 
         let app = Application::init(config).await.unwrap();
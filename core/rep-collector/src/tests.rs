@@ -95,6 +95,7 @@ async fn test_query() {
     let app = Application::init(AppConfig {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     })
     .await
     .unwrap();
@@ -207,6 +208,7 @@ async fn test_submit_measurements() {
     let app = Application::init(AppConfig {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     })
     .await
     .unwrap();
@@ -273,7 +275,10 @@ async fn test_submit_measurements() {
                     // Make sure that the reported measurements were submitted to the application
                     // state.
                     assert_eq!(measurements.len(), 1);
-                    assert_eq!(measurements[0].reporting_node, public_key);
+                    assert_eq!(
+                        Some(measurements[0].reporting_node),
+                        query_runner.pubkey_to_index(public_key)
+                    );
                     assert_eq!(
                         measurements[0].measurements.latency,
                         Some(Duration::from_millis(200))
@@ -362,6 +367,7 @@ async fn test_reputation_calculation_and_query() {
     let app = Application::init(AppConfig {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     })
     .await
     .unwrap();
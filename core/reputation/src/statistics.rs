@@ -151,11 +151,60 @@ pub fn calculate_z_normalized_weighted_mean<T>(mut values: Vec<T>) -> Option<HpF
     calculate_weighted_mean(&values)
 }
 
+/// Returns the weighted consensus of the given `(value, weight)` pairs.
+///
+/// Values that deviate from the weighted median by more than `max_deviation` times the median
+/// are discarded as outliers, and the weighted mean of the remaining values is returned. Values
+/// without a positive weight are ignored. Returns `None` if no value is left.
+pub fn weighted_consensus(mut values: Vec<(f64, f64)>, max_deviation: f64) -> Option<f64> {
+    values.retain(|(value, weight)| value.is_finite() && *weight > 0.0);
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|(lhs, _), (rhs, _)| lhs.total_cmp(rhs));
+
+    let total_weight: f64 = values.iter().map(|(_, weight)| weight).sum();
+    let mut cumulative_weight = 0.0;
+    let median = values
+        .iter()
+        .find(|(_, weight)| {
+            cumulative_weight += weight;
+            cumulative_weight >= total_weight / 2.0
+        })
+        .map(|(value, _)| *value)?;
+
+    let max_distance = median.abs() * max_deviation;
+    let (sum, weight) = values
+        .iter()
+        .filter(|(value, _)| (value - median).abs() <= max_distance)
+        .fold((0.0, 0.0), |(sum, total), (value, weight)| {
+            (sum + value * weight, total + weight)
+        });
+    // The median itself is always within the allowed deviation, so the weight is positive.
+    Some(sum / weight)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::types::WeightedFloat;
 
+    #[test]
+    fn test_weighted_consensus() {
+        // The heavier reporters pull the result towards their values.
+        let values = vec![(100.0, 1.0), (110.0, 2.0), (120.0, 3.0)];
+        let consensus = weighted_consensus(values, 0.5).unwrap();
+        assert!((consensus - 680.0 / 6.0).abs() < EPSILON);
+
+        // An outlier beyond the deviation is discarded, no matter its weight.
+        let values = vec![(100.0, 1.0), (110.0, 2.0), (120.0, 3.0), (1000.0, 2.0)];
+        let consensus = weighted_consensus(values, 0.5).unwrap();
+        assert!((consensus - 680.0 / 6.0).abs() < EPSILON);
+
+        assert_eq!(weighted_consensus(vec![(1.0, 0.0)], 0.5), None);
+        assert_eq!(weighted_consensus(vec![], 0.5), None);
+    }
+
     #[test]
     fn test_mean_basic() {
         let values = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0];
@@ -153,6 +153,7 @@ async fn test_rpc_get_flk_balance() -> Result<()> {
     let app = Application::init(AppConfig {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     })
     .await
     .unwrap();
@@ -211,6 +212,7 @@ async fn test_rpc_get_reputation() -> Result<()> {
     let app = Application::init(AppConfig {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     })
     .await
     .unwrap();
@@ -297,6 +299,7 @@ async fn test_rpc_get_staked() -> Result<()> {
     let app = Application::init(AppConfig {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     })
     .await
     .unwrap();
@@ -359,6 +362,7 @@ async fn test_rpc_get_stables_balance() -> Result<()> {
     let app = Application::init(AppConfig {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     })
     .await
     .unwrap();
@@ -445,6 +449,7 @@ async fn test_rpc_get_stake_locked_until() -> Result<()> {
     let app = Application::init(AppConfig {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     })
     .await
     .unwrap();
@@ -531,6 +536,7 @@ async fn test_rpc_get_locked_time() -> Result<()> {
     let app = Application::init(AppConfig {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     })
     .await
     .unwrap();
@@ -617,6 +623,7 @@ async fn test_rpc_get_locked() -> Result<()> {
     let app = Application::init(AppConfig {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     })
     .await
     .unwrap();
@@ -681,6 +688,7 @@ async fn test_rpc_get_bandwidth_balance() -> Result<()> {
     let app = Application::init(AppConfig {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     })
     .await
     .unwrap();
@@ -767,6 +775,7 @@ async fn test_rpc_get_node_info() -> Result<()> {
     let app = Application::init(AppConfig {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     })
     .await
     .unwrap();
@@ -1210,6 +1219,7 @@ async fn test_rpc_get_total_served() -> Result<()> {
     let app = Application::init(AppConfig {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     })
     .await
     .unwrap();
@@ -1273,6 +1283,7 @@ async fn test_rpc_get_node_served() -> Result<()> {
     let app = Application::init(AppConfig {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     })
     .await
     .unwrap();
@@ -1359,6 +1370,7 @@ async fn test_rpc_is_valid_node() -> Result<()> {
     let app = Application::init(AppConfig {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     })
     .await
     .unwrap();
@@ -1447,6 +1459,7 @@ async fn test_rpc_get_node_registry() -> Result<()> {
     let app = Application::init(AppConfig {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     })
     .await
     .unwrap();
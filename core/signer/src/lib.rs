@@ -13,15 +13,15 @@
 use async_trait::async_trait;
 pub use config::Config;
 use fleek_crypto::{
-    NodeNetworkingPublicKey, NodeNetworkingSecretKey, NodePublicKey, NodeSecretKey, NodeSignature,
-    SecretKey, TransactionSender,
+    NodeNetworkingPublicKey, NodeNetworkingSecretKey, NodeNetworkingSignature, NodePublicKey,
+    NodeSecretKey, NodeSignature, SecretKey,
 };
 use lightning_application::query_runner::QueryRunner;
 use lightning_interfaces::{
-    common::{ToDigest, WithStartAndShutdown},
+    common::WithStartAndShutdown,
     config::ConfigConsumer,
     signer::{SignerInterface, SubmitTxSocket},
-    types::{TransactionResponse, UpdateMethod, UpdatePayload, UpdateRequest},
+    types::{TransactionResponse, UpdateMethod, UpdateRequest, UpdateRequestBuilder},
     MempoolSocket, SyncQueryRunnerInterface,
 };
 use log::warn;
@@ -169,9 +169,28 @@ fn get_socket(&self) -> SubmitTxSocket {
     fn sign_raw_digest(&self, digest: &[u8; 32]) -> NodeSignature {
         self.inner.node_secret_key.sign(digest)
     }
+
+    /// Sign the provided raw digest with the network key and return a signature.
+    fn sign_with_network_key(&self, digest: &[u8; 32]) -> NodeNetworkingSignature {
+        self.inner.network_secret_key.sign(digest)
+    }
 }
 
 impl Signer {
+    /// Returns a summary of the nonce synchronization with the application, for monitoring.
+    pub fn health(&self) -> SignerHealth {
+        let status = *self.inner.status.lock().unwrap();
+        SignerHealth {
+            is_running: self.is_running(),
+            base_nonce: status.base_nonce,
+            next_nonce: status.next_nonce,
+            pending_count: status.pending_count,
+            oldest_pending_age: status
+                .oldest_pending
+                .map(|timestamp| timestamp.elapsed().unwrap_or_default()),
+        }
+    }
+
     fn get_mempool_socket(&self) -> MempoolSocket {
         self.mempool_socket
             .lock()
@@ -197,11 +216,45 @@ fn get_new_block_notify(&self) -> Arc<Notify> {
     }
 }
 
+/// A summary of the state of the signer, see [`Signer::health`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SignerHealth {
+    /// Whether the signer is running.
+    pub is_running: bool,
+    /// The nonce of the node on the application, as last observed by the signer.
+    pub base_nonce: u64,
+    /// The nonce that will be assigned to the next transaction.
+    pub next_nonce: u64,
+    /// The number of transactions that were sent to the mempool but not ordered yet.
+    pub pending_count: usize,
+    /// How long the oldest pending transaction has been waiting to be ordered.
+    pub oldest_pending_age: Option<Duration>,
+}
+
+impl SignerHealth {
+    /// Returns true if the signer is running and no pending transaction has been waiting for
+    /// longer than the timeout after which transactions are resent.
+    pub fn is_healthy(&self) -> bool {
+        self.is_running && self.oldest_pending_age.map_or(true, |age| age < TIMEOUT)
+    }
+}
+
 struct SignerInner {
     node_secret_key: NodeSecretKey,
     node_public_key: NodePublicKey,
     network_secret_key: NodeNetworkingSecretKey,
     network_public_key: NodeNetworkingPublicKey,
+    // A snapshot of the nonce state, which is owned by the `handle` loop. It is updated after
+    // every event so that `Signer::health` can read it without waiting on the loop.
+    status: Mutex<NonceStatus>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct NonceStatus {
+    base_nonce: u64,
+    next_nonce: u64,
+    pending_count: usize,
+    oldest_pending: Option<SystemTime>,
 }
 
 impl SignerInner {
@@ -257,6 +310,7 @@ fn new(config: Config) -> Self {
             node_public_key,
             network_secret_key,
             network_public_key,
+            status: Mutex::new(NonceStatus::default()),
         }
     }
 
@@ -278,20 +332,15 @@ async fn handle(
             };
         let mut base_nonce = application_nonce;
         let mut next_nonce = application_nonce + 1;
+        self.update_status(base_nonce, next_nonce, &pending_transactions);
         loop {
             tokio::select! {
                 task = rx.recv() => {
                     let task = task.expect("Failed to receive UpdateMethod.");
                     let update_method = task.request.clone();
                     task.respond(next_nonce);
-                    let update_payload = UpdatePayload { method: update_method, nonce: next_nonce };
-                    let digest = update_payload.to_digest();
-                    let signature = self.node_secret_key.sign(&digest);
-                    let update_request = UpdateRequest {
-                        sender:  TransactionSender::Node(self.node_public_key),
-                        signature: signature.into(),
-                        payload: update_payload,
-                    };
+                    let update_request = UpdateRequestBuilder::new(update_method, next_nonce)
+                        .build(self.node_public_key, |digest| self.node_secret_key.sign(digest));
                     mempool_socket.run(update_request.clone())
                         .await
                         .map_err(|r| anyhow::anyhow!(format!("{r:?}")))
@@ -322,9 +371,24 @@ async fn handle(
                 }
                 _ = shutdown_notify.notified() => break,
             }
+            self.update_status(base_nonce, next_nonce, &pending_transactions);
         }
     }
 
+    fn update_status(
+        &self,
+        base_nonce: u64,
+        next_nonce: u64,
+        pending_transactions: &VecDeque<PendingTransaction>,
+    ) {
+        *self.status.lock().unwrap() = NonceStatus {
+            base_nonce,
+            next_nonce,
+            pending_count: pending_transactions.len(),
+            oldest_pending: pending_transactions.iter().map(|tx| tx.timestamp).min(),
+        };
+    }
+
     async fn sync_with_application(
         node_public_key: NodePublicKey,
         query_runner: &QueryRunner,
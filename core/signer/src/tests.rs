@@ -3,19 +3,38 @@
     time::Duration,
 };
 
-use fleek_crypto::{AccountOwnerSecretKey, PublicKey, SecretKey};
+use fleek_crypto::{
+    AccountOwnerSecretKey, PublicKey, SecretKey, TransactionSender, TransactionSignature,
+};
 use lightning_application::{
     app::Application,
     config::{Config as AppConfig, Mode},
     genesis::{Genesis, GenesisCommittee},
 };
 use lightning_interfaces::{
-    application::ApplicationInterface, common::WithStartAndShutdown, consensus::ConsensusInterface,
-    signer::SignerInterface, types::UpdateMethod, SyncQueryRunnerInterface,
+    application::ApplicationInterface,
+    common::{ToDigest, WithStartAndShutdown},
+    consensus::ConsensusInterface,
+    signer::SignerInterface,
+    types::{UpdateMethod, UpdateRequestBuilder},
+    SyncQueryRunnerInterface,
 };
 use lightning_test_utils::consensus::{Config as ConsensusConfig, MockConsensus, MockPubSub};
 
-use crate::{config::Config, Signer};
+use crate::{config::Config, Signer, TIMEOUT};
+
+#[tokio::test]
+async fn test_sign_with_network_key() {
+    let app = Application::init(AppConfig::default()).await.unwrap();
+    let signer = Signer::init(Config::test(), app.sync_query())
+        .await
+        .unwrap();
+
+    let digest = [7; 32];
+    let signature = signer.sign_with_network_key(&digest);
+    assert!(signer.get_ed25519_pk().verify(&signature, &digest));
+    assert!(!signer.get_ed25519_pk().verify(&signature, &[8; 32]));
+}
 
 #[tokio::test]
 async fn test_send_two_txs_in_a_row() {
@@ -42,6 +61,7 @@ async fn test_send_two_txs_in_a_row() {
     let app = Application::init(AppConfig {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     })
     .await
     .unwrap();
@@ -122,6 +142,7 @@ async fn test_retry_send() {
     let app = Application::init(AppConfig {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     })
     .await
     .unwrap();
@@ -234,3 +255,109 @@ async fn test_sign_raw_digest() {
     let public_key = signer.get_bls_pk();
     assert!(public_key.verify(&signature, &digest));
 }
+
+#[tokio::test]
+async fn test_update_request_builder_verifies() {
+    let app = Application::init(AppConfig::default()).await.unwrap();
+    let query_runner = app.sync_query();
+    let signer = Signer::init(Config::default(), query_runner.clone())
+        .await
+        .unwrap();
+
+    let update_method = UpdateMethod::ChangeEpoch { epoch: 0 };
+    let public_key = signer.get_bls_pk();
+    let request = UpdateRequestBuilder::new(update_method, 1)
+        .build(public_key, |digest| signer.sign_raw_digest(digest));
+
+    assert!(matches!(request.sender, TransactionSender::Node(pk) if pk == public_key));
+    let TransactionSignature::Node(signature) = request.signature else {
+        panic!("Expected a node signature.");
+    };
+    assert!(public_key.verify(&signature, &request.payload.to_digest()));
+}
+
+#[tokio::test]
+async fn test_health_reports_stuck_transaction() {
+    let signer_config = Config::test();
+    let (secret_key, network_secret_key) = signer_config.load_test_keys();
+
+    let mut genesis = Genesis::load().unwrap();
+    let public_key = secret_key.to_pk();
+    let network_public_key = network_secret_key.to_pk();
+    let owner_secret_key = AccountOwnerSecretKey::generate();
+    let owner_public_key = owner_secret_key.to_pk();
+
+    genesis.committee.push(GenesisCommittee::new(
+        owner_public_key.to_base64(),
+        public_key.to_base64(),
+        "/ip4/127.0.0.1/udp/48000".to_owned(),
+        network_public_key.to_base64(),
+        "/ip4/127.0.0.1/udp/48101/http".to_owned(),
+        network_public_key.to_base64(),
+        "/ip4/127.0.0.1/tcp/48102/http".to_owned(),
+        None,
+    ));
+
+    let app = Application::init(AppConfig {
+        genesis: Some(genesis),
+        mode: Mode::Test,
+        dev_single_node: None,
+    })
+    .await
+    .unwrap();
+    app.start().await;
+
+    let (update_socket, query_runner) = (app.transaction_executor(), app.sync_query());
+
+    let mut signer = Signer::init(signer_config, query_runner.clone())
+        .await
+        .unwrap();
+    let signer_socket = signer.get_socket();
+
+    let consensus_config = ConsensusConfig {
+        min_ordering_time: 0,
+        max_ordering_time: 1,
+        probability_txn_lost: 0.0,
+        transactions_to_lose: HashSet::from([2]), // drop the 2nd transaction arriving
+        // Don't let a new block trigger the resending of the lost transaction.
+        new_block_interval: Duration::from_secs(60),
+    };
+    let consensus = MockConsensus::init(
+        consensus_config,
+        &signer,
+        update_socket.clone(),
+        query_runner.clone(),
+        MockPubSub {},
+    )
+    .await
+    .unwrap();
+
+    signer.provide_mempool(consensus.mempool());
+    signer.provide_new_block_notify(consensus.new_block_notifier());
+    assert!(!signer.health().is_healthy());
+    signer.start().await;
+    consensus.start().await;
+
+    // The first transaction gets ordered in time.
+    let update_method = UpdateMethod::SubmitReputationMeasurements {
+        measurements: BTreeMap::new(),
+    };
+    signer_socket.run(update_method).await.unwrap();
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let health = signer.health();
+    assert!(health.is_healthy());
+    assert_eq!(health.next_nonce, 2);
+
+    // The second transaction gets lost, and is not resent until the next block arrives.
+    let update_method = UpdateMethod::SubmitReputationMeasurements {
+        measurements: BTreeMap::new(),
+    };
+    signer_socket.run(update_method).await.unwrap();
+    tokio::time::sleep(TIMEOUT + Duration::from_secs(1)).await;
+    let health = signer.health();
+    assert!(!health.is_healthy());
+    assert!(health.is_running);
+    assert_eq!(health.next_nonce, 3);
+    assert!(health.pending_count >= 1);
+    assert!(health.oldest_pending_age.unwrap() >= TIMEOUT);
+}
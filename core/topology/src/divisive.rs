@@ -1,4 +1,7 @@
-use std::{collections::BTreeMap, fmt::Display};
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt::Display,
+};
 
 use ndarray::Array2;
 use rand::Rng;
@@ -17,6 +20,10 @@ pub enum DivisiveHierarchy {
         // TODO: dont store this and instead traverse the tree at each depth for collecting cluster
         // assignments
         nodes: Vec<Node>,
+        /// The pairs of nodes connected across the children, with their dissimilarity. Only
+        /// retained if the hierarchy was created with [`DivisiveHierarchy::new_with_edges`].
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        edges: Vec<(usize, usize, i32)>,
     },
     Cluster {
         id: String,
@@ -63,6 +70,25 @@ fn add_connection(indeces: &mut [Node], depth: usize, i: usize, j: usize) {
     right.connections.entry(depth).or_default().push(iid);
 }
 
+// check if there is a path between two nodes in the graph
+fn is_reachable(adjacency: &[BTreeSet<usize>], from: usize, to: usize) -> bool {
+    let mut visited = vec![false; adjacency.len()];
+    let mut queue = VecDeque::from([from]);
+    visited[from] = true;
+    while let Some(current) = queue.pop_front() {
+        if current == to {
+            return true;
+        }
+        for &next in &adjacency[current] {
+            if !visited[next] {
+                visited[next] = true;
+                queue.push_back(next);
+            }
+        }
+    }
+    false
+}
+
 impl DivisiveHierarchy {
     /// Create a new divisive hierarchy using constrained fasterpam and selecting random medoids.
     /// For deterministic results, a fast seedable rng source such as ChaCha is recommended.
@@ -70,6 +96,21 @@ impl DivisiveHierarchy {
     /// anymore, and finally divides the last superclusters into an optimal number of final
     /// clusters with k nodes in them.
     pub fn new<R: Rng>(rng: &mut R, dissim_matrix: &Array2<i32>, k: usize) -> Self {
+        Self::new_root(rng, dissim_matrix, k, false)
+    }
+
+    /// Like [`DivisiveHierarchy::new`], but also retains the pairing edges between clusters along
+    /// with their dissimilarity, see [`DivisiveHierarchy::edges_with_weights`].
+    pub fn new_with_edges<R: Rng>(rng: &mut R, dissim_matrix: &Array2<i32>, k: usize) -> Self {
+        Self::new_root(rng, dissim_matrix, k, true)
+    }
+
+    fn new_root<R: Rng>(
+        rng: &mut R,
+        dissim_matrix: &Array2<i32>,
+        k: usize,
+        retain_edges: bool,
+    ) -> Self {
         let indeces: Vec<_> = (0..dissim_matrix.nrows())
             .map(|i| Node {
                 id: i,
@@ -77,7 +118,14 @@ pub fn new<R: Rng>(rng: &mut R, dissim_matrix: &Array2<i32>, k: usize) -> Self {
             })
             .collect();
 
-        Self::new_inner(rng, dissim_matrix, indeces, &HierarchyPath::root(), k)
+        Self::new_inner(
+            rng,
+            dissim_matrix,
+            indeces,
+            &HierarchyPath::root(),
+            k,
+            retain_edges,
+        )
     }
 
     /// Recursive function for each depth.
@@ -87,6 +135,7 @@ fn new_inner<R: Rng>(
         mut indeces: Vec<Node>,
         current_path: &HierarchyPath,
         k: usize,
+        retain_edges: bool,
     ) -> Self {
         // calculate the number of clusters
         let depth = current_path.depth();
@@ -133,11 +182,15 @@ fn new_inner<R: Rng>(
             }
 
             // greedily pair nodes together
+            let mut edges = Vec::new();
             for a in 0..clusters.len() {
                 for b in a + 1..clusters.len() {
                     let pairs = greedy_pairs(dissim_matrix, &clusters[&a], &clusters[&b]);
                     for (i, j) in pairs {
                         add_connection(&mut indeces, depth, i, j);
+                        if retain_edges {
+                            edges.push((indeces[i].id, indeces[j].id, dissim_matrix[(i, j)]));
+                        }
                     }
                 }
             }
@@ -161,7 +214,7 @@ fn new_inner<R: Rng>(
                 let mut path = current_path.clone();
                 path.0.push(path_index as u8);
                 let nodes: Vec<_> = new_indeces.iter().map(|&i| indeces[i].clone()).collect();
-                let child = Self::new_inner(rng, &child_matrix, nodes, &path, k);
+                let child = Self::new_inner(rng, &child_matrix, nodes, &path, k, retain_edges);
                 children.push(child);
             }
 
@@ -170,6 +223,7 @@ fn new_inner<R: Rng>(
                 total: indeces.len(),
                 children,
                 nodes: indeces,
+                edges,
             }
         }
     }
@@ -182,6 +236,29 @@ pub fn n_nodes(&self) -> usize {
         }
     }
 
+    /// Collect the pairing edges between clusters at all depths of the hierarchy, as the indices
+    /// of the two nodes in the dissimilarity matrix and their dissimilarity.
+    ///
+    /// The edges are only retained if the hierarchy was created with
+    /// [`DivisiveHierarchy::new_with_edges`], otherwise this is empty.
+    pub fn edges_with_weights(&self) -> Vec<(usize, usize, i32)> {
+        fn inner(item: &DivisiveHierarchy, data: &mut Vec<(usize, usize, i32)>) {
+            if let DivisiveHierarchy::SuperCluster {
+                children, edges, ..
+            } = item
+            {
+                data.extend_from_slice(edges);
+                for child in children {
+                    inner(child, data);
+                }
+            }
+        }
+
+        let mut data = Vec::new();
+        inner(self, &mut data);
+        data
+    }
+
     /// Collect connections for each node at all depths of the hierarchy.
     pub fn connections(&self) -> Vec<Vec<Vec<usize>>> {
         fn inner(item: &DivisiveHierarchy, data: &mut Vec<Vec<Vec<usize>>>) {
@@ -205,6 +282,58 @@ fn inner(item: &DivisiveHierarchy, data: &mut Vec<Vec<Vec<usize>>>) {
         data
     }
 
+    /// Collect connections for each node at all depths of the hierarchy, capping the total number
+    /// of connections for each node to `max_degree`.
+    ///
+    /// Excess edges are pruned starting from the pairs with the highest dissimilarity, so that
+    /// nodes keep their closest peers. An edge is never pruned if that would disconnect the graph,
+    /// so a node may still exceed the cap if all of its remaining edges are required to keep the
+    /// graph connected.
+    pub fn connections_with_max_degree(
+        &self,
+        dissim_matrix: &Array2<i32>,
+        max_degree: usize,
+    ) -> Vec<Vec<Vec<usize>>> {
+        let mut connections = self.connections();
+
+        // build the set of peers for each node across all depths
+        let mut adjacency: Vec<BTreeSet<usize>> = connections
+            .iter()
+            .map(|depths| depths.iter().flatten().copied().collect())
+            .collect();
+
+        // collect every edge once, sorted from the furthest to the closest pair
+        let mut edges: Vec<_> = adjacency
+            .iter()
+            .enumerate()
+            .flat_map(|(i, peers)| peers.range(i + 1..).map(move |&j| (i, j)))
+            .collect();
+        edges.sort_by_key(|&(i, j)| std::cmp::Reverse(dissim_matrix[(i, j)]));
+
+        for (i, j) in edges {
+            if adjacency[i].len() <= max_degree && adjacency[j].len() <= max_degree {
+                continue;
+            }
+
+            adjacency[i].remove(&j);
+            adjacency[j].remove(&i);
+
+            if !is_reachable(&adjacency, i, j) {
+                // the edge is a bridge, put it back to keep the graph connected
+                adjacency[i].insert(j);
+                adjacency[j].insert(i);
+            }
+        }
+
+        for (i, depths) in connections.iter_mut().enumerate() {
+            for conns in depths.iter_mut() {
+                conns.retain(|j| adjacency[i].contains(j));
+            }
+        }
+
+        connections
+    }
+
     /// Collect assignments for each node at each depth of the hierarchy. The last vec of
     /// assignments is the final tree depth.
     pub fn assignments(&self) -> Vec<Vec<usize>> {
@@ -244,3 +373,85 @@ fn inner(
         data.into_values().map(|v| v.1).collect()
     }
 }
+
+#[test]
+fn test_connections_with_max_degree() {
+    use rand::SeedableRng;
+
+    const MAX_DEGREE: usize = 10;
+
+    // sample 8 groups of 8 points, and use the squared distance as the dissimilarity
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+    let points: Vec<(i32, i32)> = (0..64)
+        .map(|i| {
+            let (x, y) = ((i / 8) % 4 * 500, (i / 8) / 4 * 500);
+            (x + rng.gen_range(0..100), y + rng.gen_range(0..100))
+        })
+        .collect();
+    let mut dissim_matrix = Array2::zeros((points.len(), points.len()));
+    for (i, a) in points.iter().enumerate() {
+        for (j, b) in points.iter().enumerate() {
+            dissim_matrix[(i, j)] = (a.0 - b.0).pow(2) + (a.1 - b.1).pow(2);
+        }
+    }
+
+    let hierarchy = DivisiveHierarchy::new(&mut rng, &dissim_matrix, 8);
+    let connections = hierarchy.connections_with_max_degree(&dissim_matrix, MAX_DEGREE);
+
+    let adjacency: Vec<BTreeSet<usize>> = connections
+        .iter()
+        .map(|depths| depths.iter().flatten().copied().collect())
+        .collect();
+
+    // no node should exceed the maximum degree
+    for peers in &adjacency {
+        assert!(peers.len() <= MAX_DEGREE);
+    }
+
+    // connections should be symmetric
+    for (i, peers) in adjacency.iter().enumerate() {
+        for &j in peers {
+            assert!(adjacency[j].contains(&i));
+        }
+    }
+
+    // the graph should remain connected
+    for i in 1..adjacency.len() {
+        assert!(is_reachable(&adjacency, 0, i));
+    }
+}
+
+#[test]
+fn test_edges_with_weights() {
+    use rand::SeedableRng;
+
+    // two groups of 4 points on a line, with a unique dissimilarity for every pair
+    let points = [0, 1, 3, 6, 100, 110, 130, 160];
+    let mut dissim_matrix = Array2::zeros((points.len(), points.len()));
+    for (i, a) in points.iter().enumerate() {
+        for (j, b) in points.iter().enumerate() {
+            dissim_matrix[(i, j)] = (a - b).abs() * 10 + (i + j) as i32;
+        }
+    }
+
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+    let hierarchy = DivisiveHierarchy::new_with_edges(&mut rng, &dissim_matrix, 4);
+    let edges = hierarchy.edges_with_weights();
+    assert!(!edges.is_empty());
+
+    let assignments = hierarchy.assignments();
+    let connections = hierarchy.connections();
+    for &(i, j, weight) in &edges {
+        // the weight is the dissimilarity of the paired nodes
+        assert_eq!(weight, dissim_matrix[(i, j)]);
+        // pairs connect nodes of different clusters
+        assert_ne!(assignments[1][i], assignments[1][j]);
+        // and are part of the connections
+        assert!(connections[i].iter().flatten().any(|&peer| peer == j));
+    }
+
+    // edges are not retained by default
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+    let hierarchy = DivisiveHierarchy::new(&mut rng, &dissim_matrix, 4);
+    assert!(hierarchy.edges_with_weights().is_empty());
+}
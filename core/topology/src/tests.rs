@@ -109,6 +109,7 @@ async fn test_build_latency_matrix() {
     let app = Application::init(AppConfig {
         genesis: Some(genesis),
         mode: Mode::Test,
+        dev_single_node: None,
     })
     .await
     .unwrap();
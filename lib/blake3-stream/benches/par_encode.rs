@@ -0,0 +1,58 @@
+use std::io::Write;
+
+use blake3_stream::*;
+use blake3_tree::blake3::tree::{HashTree, HashTreeBuilder};
+use criterion::*;
+
+pub const SIZES: &[usize] = &[1, 2, 4, 8, 16, 32, 64, 128, 256, 512];
+pub const UNITS: &[(&str, usize)] = &[("MB", 1024 * 1024)];
+
+fn get_content_and_tree(len: usize) -> (Vec<u8>, HashTree) {
+    let content = vec![0x80; len];
+    let mut tree_builder = HashTreeBuilder::new();
+    tree_builder.update(&content);
+
+    (content, tree_builder.finalize())
+}
+
+fn bench(c: &mut Criterion) {
+    let mut g = c.benchmark_group("ParEncode");
+
+    for (name, unit) in UNITS {
+        for &size in SIZES {
+            let length = unit * size;
+            g.throughput(Throughput::Bytes(length as u64));
+            let (content, tree) = get_content_and_tree(length);
+
+            g.bench_with_input(
+                BenchmarkId::new(format!("sequential/{name}"), size),
+                &length,
+                |b, _| {
+                    b.iter(|| {
+                        let mut encoded_buffer = Vec::new();
+                        let mut encoder =
+                            Encoder::new(&mut encoded_buffer, length, tree.clone()).unwrap();
+                        encoder.write_all(&content).unwrap();
+                        encoder.flush().unwrap();
+                    })
+                },
+            );
+
+            g.bench_with_input(
+                BenchmarkId::new(format!("parallel/{name}"), size),
+                &length,
+                |b, _| {
+                    b.iter(|| {
+                        let mut encoded_buffer = Vec::new();
+                        par_encode(&mut encoded_buffer, &content, &tree).unwrap();
+                    })
+                },
+            );
+        }
+    }
+
+    g.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
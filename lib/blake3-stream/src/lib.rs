@@ -12,18 +12,33 @@
 //! ```text
 //! [ header (u64) . tree bytes . block bytes . block bytes . tree bytes . block bytes ... ]
 //! ```
+//!
+//! The frame sizing and verification state machine lives in [`state`], which only depends on
+//! `core` and `alloc` and operates on byte slices. The `std::io` based [`Encoder`] and
+//! [`VerifiedDecoder`] are available behind the default `std` feature, as are [`MultiEncoder`] and
+//! [`MultiDecoder`] which pack several streams into one behind a manifest (see [`multi`]).
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
+pub mod multi;
+pub mod state;
 
+#[cfg(feature = "std")]
 use std::{
     fmt::Debug,
     io::{self, Read, Write},
+    time::{Duration, Instant},
 };
 
-use arrayref::array_ref;
-use blake3_tree::{
-    blake3::tree::{BlockHasher, HashTree},
-    IncrementalVerifier, ProofBuf, ProofSizeEstimator,
-};
+#[cfg(feature = "std")]
+use blake3_tree::{blake3::tree::HashTree, ProofBuf};
+use blake3_tree::{IncrementalVerifier, ProofSizeEstimator};
+#[cfg(feature = "std")]
 use bytes::{BufMut, BytesMut};
+#[cfg(feature = "std")]
+pub use multi::{ManifestEntry, MultiDecoder, MultiEncoder};
+pub use state::{DecoderCore, DecoderError, DecoderState};
 
 pub const BLOCK_SIZE: usize = 256 * 1024;
 
@@ -31,7 +46,24 @@
 pub const BLOCK_TAG: u8 = 0x01;
 pub const SIZED_BLOCK_TAG: u8 = 0x02;
 
+/// The kind of a segment of a stream, as passed to the tap of an [`Encoder`].
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SegmentKind {
+    /// The u64 content length header.
+    Header,
+    /// The proof that precedes a block, which may be empty.
+    Proof,
+    /// A block of content.
+    Block,
+}
+
+/// Observes the segments written by an [`Encoder`], see [`Encoder::with_tap`].
+#[cfg(feature = "std")]
+type Tap = Box<dyn FnMut(SegmentKind, &[u8]) + Send>;
+
 /// Encoder for a blake3 stream of content
+#[cfg(feature = "std")]
 pub struct Encoder<W: Write> {
     writer: W,
     buffer: BytesMut,
@@ -39,8 +71,11 @@ pub struct Encoder<W: Write> {
     block: usize,
     num_blocks: usize,
     content_len: usize,
+    written: usize,
+    tap: Option<Tap>,
 }
 
+#[cfg(feature = "std")]
 impl<W: Write> Encoder<W> {
     /// Create a new proof encoder, immediately writing the u64 length header
     pub fn new(mut writer: W, content_len: usize, tree: HashTree) -> io::Result<Self> {
@@ -52,13 +87,78 @@ pub fn new(mut writer: W, content_len: usize, tree: HashTree) -> io::Result<Self
             content_len,
             buffer: BytesMut::new(),
             block: 0,
+            written: 0,
+            tap: None,
         })
     }
+
+    /// Pass every segment to the given tap as it is written, without changing the output. This is
+    /// meant for debugging and metrics, such as measuring the proof overhead of a stream.
+    ///
+    /// The header was already written by [`Encoder::new`], so it is passed to the tap right away.
+    /// After that every block is preceded by its proof segment, including the empty ones which
+    /// are not written, so the proof and block segments always alternate.
+    pub fn with_tap(mut self, tap: impl FnMut(SegmentKind, &[u8]) + Send + 'static) -> Self {
+        let mut tap: Tap = Box::new(tap);
+        tap(
+            SegmentKind::Header,
+            &(self.content_len as u64).to_be_bytes(),
+        );
+        self.tap = Some(tap);
+        self
+    }
+
+    /// Write a segment to the inner writer and pass it to the tap.
+    fn write_segment(&mut self, kind: SegmentKind, bytes: &[u8]) -> io::Result<()> {
+        if !bytes.is_empty() {
+            self.writer.write_all(bytes)?;
+        }
+        if let Some(tap) = &mut self.tap {
+            tap(kind, bytes);
+        }
+        Ok(())
+    }
+
+    /// Emit the remaining buffered content as the final block, with its proof, and return the
+    /// inner writer after flushing it. Unlike [`Write::flush`], this does not depend on how the
+    /// content was split across the calls to `write`.
+    ///
+    /// Returns an [`io::ErrorKind::InvalidInput`] error if the number of bytes written to the
+    /// encoder is not the content length it was created with.
+    pub fn finalize(mut self) -> io::Result<W> {
+        if self.written != self.content_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "expected {} bytes of content but {} were written",
+                    self.content_len, self.written
+                ),
+            ));
+        }
+
+        if !self.buffer.is_empty() {
+            let proof = if self.block == 0 {
+                ProofBuf::new(&self.tree.tree, 0)
+            } else {
+                ProofBuf::resume(&self.tree.tree, self.block)
+            };
+            self.write_segment(SegmentKind::Proof, proof.as_ref())?;
+
+            let bytes = self.buffer.split();
+            self.write_segment(SegmentKind::Block, bytes.as_ref())?;
+            self.block += 1;
+        }
+
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
 }
 
+#[cfg(feature = "std")]
 impl<W: Write> Write for Encoder<W> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         self.buffer.put(buf);
+        self.written += buf.len();
 
         let mut proof = if self.block == 0 {
             ProofBuf::new(&self.tree.tree, 0)
@@ -72,13 +172,11 @@ fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
                 || ((self.block == self.num_blocks - 1)
                     && self.buffer.len() == self.content_len % BLOCK_SIZE))
         {
-            if !proof.is_empty() {
-                self.writer.write_all(proof.as_ref())?;
-            };
+            self.write_segment(SegmentKind::Proof, proof.as_ref())?;
 
             let bytes = self.buffer.split_to(self.buffer.len().min(BLOCK_SIZE));
 
-            self.writer.write_all(bytes.as_ref())?;
+            self.write_segment(SegmentKind::Block, bytes.as_ref())?;
 
             self.block += 1;
             if self.block < self.num_blocks {
@@ -94,157 +192,215 @@ fn flush(&mut self) -> io::Result<()> {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
-pub enum DecoderState {
-    WaitingForHeader,
-    WaitingForProof(usize),
-    WaitingForBlock(usize),
-    Finished,
-}
+/// Encode `content` into `writer` as a blake3 stream, computing the proofs for all blocks in
+/// parallel.
+///
+/// The output is byte-identical to writing the whole content through an [`Encoder`], but since the
+/// tree is already known upfront the proof segments are independent and can be generated on the
+/// rayon thread pool before being written interleaved with the blocks.
+#[cfg(feature = "rayon")]
+pub fn par_encode<W: Write>(mut writer: W, content: &[u8], tree: &HashTree) -> io::Result<()> {
+    use rayon::prelude::*;
+
+    writer.write_all(&(content.len() as u64).to_be_bytes())?;
+
+    let num_blocks = (content.len() + BLOCK_SIZE - 1) / BLOCK_SIZE;
+    debug_assert!(num_blocks == 0 || num_blocks == (tree.tree.len() + 1) / 2);
+
+    let proofs: Vec<ProofBuf> = (0..num_blocks)
+        .into_par_iter()
+        .map(|block| {
+            if block == 0 {
+                ProofBuf::new(&tree.tree, 0)
+            } else {
+                ProofBuf::resume(&tree.tree, block)
+            }
+        })
+        .collect();
 
-impl DecoderState {
-    pub fn next_size(&self) -> Option<usize> {
-        match self {
-            DecoderState::WaitingForHeader => Some(8),
-            DecoderState::WaitingForProof(proof_len) => Some(*proof_len),
-            DecoderState::WaitingForBlock(block_len) => Some(*block_len),
-            DecoderState::Finished => None,
+    for (proof, block) in proofs.iter().zip(content.chunks(BLOCK_SIZE)) {
+        if !proof.is_empty() {
+            writer.write_all(proof.as_ref())?;
         }
+        writer.write_all(block)?;
     }
+
+    writer.flush()
+}
+
+/// Check a standalone proof for a block against the root hash without having the block content.
+///
+/// The `proof` is expected to be the initial proof for `block`, as produced by [`ProofBuf::new`],
+/// and `expected_block_hash` is the hash of the block as it appears in the tree. Returns `true`
+/// if the proof is consistent with both the root and the block hash.
+pub fn verify_proof(
+    root: [u8; 32],
+    block: usize,
+    proof: &[u8],
+    expected_block_hash: [u8; 32],
+) -> bool {
+    let mut verifier = IncrementalVerifier::new(root, block);
+    verifier.feed_proof(proof).is_ok() && verifier.verify_hash(&expected_block_hash).is_ok()
+}
+
+/// Returns the total number of proof bytes in the stream of content of the given length,
+/// excluding the length header and the content itself.
+///
+/// Every block is preceded by a proof segment. The first segment carries the path from the root
+/// to the first block, which is `ceil(log2(num_blocks)) + 1` hashes, while every following segment
+/// only carries the hashes the decoder does not know yet and is often empty. In total that is
+/// about one and a half 32 byte hashes per block, plus a sign byte for every 8 hashes in a
+/// segment, so the overhead grows linearly with the number of blocks while no single segment
+/// grows faster than logarithmically.
+pub fn proof_overhead(content_len: usize) -> usize {
+    let num_blocks = (content_len + BLOCK_SIZE - 1) / BLOCK_SIZE;
+    if num_blocks == 0 {
+        return 0;
+    }
+
+    ProofSizeEstimator::new(0, num_blocks).0
+        + (1..num_blocks)
+            .map(|block| ProofSizeEstimator::resume(block, num_blocks).0)
+            .sum::<usize>()
 }
 
 /// Decoder for a blake3 stream of content
 /// TODO:
 ///   - make verification optional
 ///   - return the tree for optional use after
+#[cfg(feature = "std")]
 pub struct VerifiedDecoder<R: Read> {
     reader: R,
-    iv: IncrementalVerifier,
+    core: DecoderCore,
+    policy: BufferPolicy,
     read_buffer: BytesMut,
     out_buffer: BytesMut,
-    block: usize,
-    num_blocks: usize,
-    remaining: usize,
-    state: DecoderState,
+    stats: DecodeStats,
+}
+
+/// A summary of the work done by a [`VerifiedDecoder`], see [`VerifiedDecoder::stats`].
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DecodeStats {
+    /// The number of blocks that passed the verification.
+    pub blocks: usize,
+    /// The number of proof bytes consumed.
+    pub proof_bytes: u64,
+    /// The number of content bytes that passed the verification, including the ones that are
+    /// buffered and were not returned by a read yet.
+    pub content_bytes: u64,
+    /// The time spent verifying the proofs and the blocks.
+    pub verification_time: Duration,
+}
+
+/// How a [`VerifiedDecoder`] allocates the buffer it reads the stream into.
+///
+/// Preallocating a full block means the buffer never has to be reallocated, but every decoder
+/// holds on to [`BLOCK_SIZE`] bytes from the start, which adds up when decoding many streams at
+/// once. Growing on demand keeps idle decoders and short streams small, at the cost of a few
+/// reallocations and smaller reads until the buffer reaches the size of a block.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BufferPolicy {
+    /// Allocate a full block up front, and read as much as a block at a time.
+    #[default]
+    Preallocate,
+    /// Start with the given capacity, and only read what is needed for the next frame, growing
+    /// the buffer as larger frames arrive.
+    Grow { initial: usize },
 }
 
+#[cfg(feature = "std")]
 impl<R: Read> VerifiedDecoder<R> {
     /// Create a new stream decoder
     pub fn new(reader: R, root_hash: [u8; 32]) -> Self {
+        Self::with_buffer_policy(reader, root_hash, BufferPolicy::Preallocate)
+    }
+
+    /// Create a new stream decoder that allocates its read buffer using the given policy.
+    pub fn with_buffer_policy(reader: R, root_hash: [u8; 32], policy: BufferPolicy) -> Self {
+        let capacity = match policy {
+            BufferPolicy::Preallocate => BLOCK_SIZE,
+            BufferPolicy::Grow { initial } => initial,
+        };
         Self {
             reader,
-            iv: IncrementalVerifier::new(root_hash, 0),
-            read_buffer: BytesMut::with_capacity(BLOCK_SIZE),
+            core: DecoderCore::new(root_hash),
+            policy,
+            read_buffer: BytesMut::with_capacity(capacity),
             out_buffer: BytesMut::new(),
-            block: 0,
-            num_blocks: 0,
-            remaining: 0,
-            state: DecoderState::WaitingForHeader,
+            stats: DecodeStats::default(),
         }
     }
+
+    /// Returns the statistics of the stream decoded so far.
+    pub fn stats(&self) -> DecodeStats {
+        self.stats
+    }
 }
 
+#[cfg(feature = "std")]
 impl<R: Read + Debug> Read for VerifiedDecoder<R> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        if self.iv.is_done() {
-            return Ok(0);
-        }
-
         if !self.out_buffer.is_empty() {
             let take = self.out_buffer.len().min(buf.len());
             buf[..take].copy_from_slice(&self.out_buffer.split_to(take));
             return Ok(take);
         }
 
+        if self.core.is_done() {
+            return Ok(0);
+        }
+
         loop {
-            if let Some(size) = self.state.next_size() {
+            if let Some(size) = self.core.next_size() {
                 if self.read_buffer.len() >= size {
-                    match self.state {
-                        DecoderState::WaitingForHeader => {
-                            let bytes = self.read_buffer.split_to(size);
-
-                            // read a u64 content length header
-                            self.remaining = u64::from_be_bytes(*array_ref!(bytes, 0, 8)) as usize;
-                            self.num_blocks = (self.remaining + BLOCK_SIZE - 1) / BLOCK_SIZE;
-                            let proof_len = ProofSizeEstimator::new(0, self.num_blocks).0;
-                            self.state = DecoderState::WaitingForProof(proof_len);
-                        },
-                        DecoderState::WaitingForProof(_) => {
-                            if size != 0 {
-                                let bytes = self.read_buffer.split_to(size);
-
-                                self.iv
-                                    .feed_proof(&bytes)
-                                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-                            }
-
-                            let block_len = if self.block < self.num_blocks - 1 {
-                                BLOCK_SIZE
-                            } else {
-                                // final block
-                                let mut len = self.remaining % BLOCK_SIZE;
-                                if len == 0 {
-                                    len = BLOCK_SIZE;
-                                }
-                                len
-                            };
-                            self.state = DecoderState::WaitingForBlock(block_len);
-                        },
-                        DecoderState::WaitingForBlock(_) => {
-                            // we have enough bytes to parse the next item
-                            let mut bytes = self.read_buffer.split_to(size);
-
-                            // verify block
-                            let mut hasher = BlockHasher::new();
-                            hasher.set_block(self.block);
-                            hasher.update(&bytes);
-
-                            self.iv
-                                .verify(hasher)
-                                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-
-                            // setup state for the next block
-                            self.block += 1;
-                            if self.block < self.num_blocks {
-                                let proof_len =
-                                    ProofSizeEstimator::resume(self.block, self.num_blocks).0;
-                                self.state = DecoderState::WaitingForProof(proof_len);
-                            } else {
-                                self.state = DecoderState::Finished;
-                            }
-
-                            if bytes.len() > buf.len() {
-                                // We have to write more bytes than the buffer has available
-                                let take = bytes.len() - buf.len();
-                                buf[..take].copy_from_slice(&bytes.split_to(take));
-                                self.out_buffer.put(bytes);
-                                break Ok(take);
-                            } else {
-                                // or, write the entire content
-                                let take = bytes.len().min(buf.len());
-                                buf[..take].copy_from_slice(&bytes.split_to(take));
-                                break Ok(take);
-                            }
-                        },
-                        DecoderState::Finished => break Ok(0),
-                    };
+                    // we have enough bytes to parse the next item
+                    let bytes = self.read_buffer.split_to(size);
+                    let is_proof = matches!(self.core.state(), DecoderState::WaitingForProof(_));
+                    let start = Instant::now();
+                    let block = self.core.feed(&bytes);
+                    self.stats.verification_time += start.elapsed();
+                    let block = block.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+                    if is_proof {
+                        self.stats.proof_bytes += size as u64;
+                    }
+
+                    if let Some(block) = block {
+                        self.stats.blocks += 1;
+                        self.stats.content_bytes += block.len() as u64;
+
+                        // write as much as the buffer can hold and keep the rest for later
+                        let take = block.len().min(buf.len());
+                        buf[..take].copy_from_slice(&block[..take]);
+                        self.out_buffer.put(&block[take..]);
+                        break Ok(take);
+                    }
                 } else {
                     // We don't have enough bytes, get some more from the reader
-                    let mut buf = [0; BLOCK_SIZE];
-                    match self.reader.read(&mut buf)? {
-                        0 => {
-                            break if !self.read_buffer.is_empty() {
-                                // If the buffer contains anything, the connection was
-                                // interrupted
-                                // while transferring data.
-                                Err(io::Error::from(io::ErrorKind::ConnectionReset))
-                            } else {
-                                Ok(0)
-                            };
-                        },
-                        len => {
-                            self.read_buffer.extend_from_slice(&buf[0..len]);
+                    let start = self.read_buffer.len();
+                    let want = match self.policy {
+                        BufferPolicy::Preallocate => BLOCK_SIZE,
+                        BufferPolicy::Grow { .. } => size - start,
+                    };
+                    self.read_buffer.resize(start + want, 0);
+                    let len = match self.reader.read(&mut self.read_buffer[start..]) {
+                        Ok(len) => len,
+                        Err(e) => {
+                            self.read_buffer.truncate(start);
+                            break Err(e);
                         },
+                    };
+                    self.read_buffer.truncate(start + len);
+
+                    if len == 0 {
+                        break if !self.read_buffer.is_empty() {
+                            // If the buffer contains anything, the connection was interrupted
+                            // while transferring data.
+                            Err(io::Error::from(io::ErrorKind::ConnectionReset))
+                        } else {
+                            Ok(0)
+                        };
                     }
                 }
             } else {
@@ -255,14 +411,146 @@ fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use std::io::{Read, Write};
+/// A reader that counts the bytes read through it.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+struct CountingReader<R> {
+    reader: R,
+    count: u64,
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.reader.read(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+}
+
+/// A [`VerifiedDecoder`] that keeps track of how many bytes of content it returned and how many
+/// bytes of the stream it read, to tell the goodput apart from the throughput.
+///
+/// The difference between the two counters is the verification overhead of the stream, the
+/// length header and the proofs, plus whatever the decoder read ahead and did not decode yet.
+#[cfg(feature = "std")]
+pub struct CountingDecoder<R: Read> {
+    decoder: VerifiedDecoder<CountingReader<R>>,
+    content_bytes: u64,
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> CountingDecoder<R> {
+    /// Create a new counting stream decoder.
+    pub fn new(reader: R, root_hash: [u8; 32]) -> Self {
+        Self::with_buffer_policy(reader, root_hash, BufferPolicy::Preallocate)
+    }
+
+    /// Create a new counting stream decoder that allocates its read buffer using the given
+    /// policy.
+    pub fn with_buffer_policy(reader: R, root_hash: [u8; 32], policy: BufferPolicy) -> Self {
+        let reader = CountingReader { reader, count: 0 };
+        Self {
+            decoder: VerifiedDecoder::with_buffer_policy(reader, root_hash, policy),
+            content_bytes: 0,
+        }
+    }
+
+    /// Returns the number of verified content bytes returned so far.
+    pub fn content_bytes_read(&self) -> u64 {
+        self.content_bytes
+    }
+
+    /// Returns the number of bytes read from the underlying stream so far.
+    pub fn stream_bytes_read(&self) -> u64 {
+        self.decoder.reader.count
+    }
+
+    /// Returns the statistics of the stream decoded so far, see [`VerifiedDecoder::stats`].
+    pub fn stats(&self) -> DecodeStats {
+        self.decoder.stats()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read + Debug> Read for CountingDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.decoder.read(buf)?;
+        self.content_bytes += len as u64;
+        Ok(len)
+    }
+}
+
+/// The result of a [`repair_scan`].
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RepairScan {
+    /// The indices of the blocks that failed the verification, in order.
+    pub corrupt_blocks: Vec<usize>,
+    /// The block at which the scan had to stop, if it could not get through the entire stream.
+    /// This block and every block after it are unverified and must be fetched again.
+    pub stopped_at: Option<usize>,
+}
 
-    use blake3_tree::blake3::tree::{HashTree, HashTreeBuilder};
+/// Decode and verify an entire stream, reporting the index of every block that fails the
+/// verification instead of aborting on the first one.
+///
+/// The framing of the stream only depends on the length header, so a corrupt block can be
+/// skipped and the scan continues with the next one. The scan can not continue past:
+///
+/// - A proof that fails the verification, since the hashes for the remaining blocks can no longer
+///   be trusted. This is also how a corrupt length header shows up.
+/// - The end of the stream before the last block, since there is nothing left to read.
+///
+/// In both cases [`RepairScan::stopped_at`] is set to the block the scan stopped at. Errors from
+/// the reader itself are returned as is.
+#[cfg(feature = "std")]
+pub fn repair_scan<R: Read>(mut reader: R, root_hash: [u8; 32]) -> io::Result<RepairScan> {
+    let mut core = DecoderCore::new(root_hash);
+    let mut scan = RepairScan::default();
+    let mut frame = Vec::with_capacity(BLOCK_SIZE);
+
+    while let Some(size) = core.next_size() {
+        frame.resize(size, 0);
+        if let Err(e) = reader.read_exact(&mut frame) {
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                scan.stopped_at = Some(core.block());
+                return Ok(scan);
+            }
+            return Err(e);
+        }
+
+        let is_block = matches!(core.state(), DecoderState::WaitingForBlock(_));
+        if core.feed(&frame).is_err() {
+            if !is_block || core.skip_block().is_err() {
+                scan.stopped_at = Some(core.block());
+                return Ok(scan);
+            }
+            scan.corrupt_blocks.push(core.block() - 1);
+        }
+    }
+
+    Ok(scan)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        sync::{Arc, Mutex},
+    };
+
+    use blake3_tree::{
+        blake3::tree::{BlockHasher, HashTree, HashTreeBuilder},
+        ProofBuf, ProofSizeEstimator,
+    };
     use bytes::BytesMut;
 
-    use crate::{Encoder, VerifiedDecoder, BLOCK_SIZE};
+    use crate::{
+        proof_overhead, repair_scan, verify_proof, BufferPolicy, CountingDecoder, DecodeStats,
+        Encoder, ManifestEntry, MultiDecoder, MultiEncoder, RepairScan, SegmentKind,
+        VerifiedDecoder, BLOCK_SIZE,
+    };
 
     pub const TEST_CASES: &[usize] = &[
         BLOCK_SIZE - 1,
@@ -313,6 +601,87 @@ fn encode_and_decode() -> std::io::Result<()> {
         Ok(())
     }
 
+    #[test]
+    fn proof_overhead_matches_encoder() -> std::io::Result<()> {
+        for &content_len in TEST_CASES {
+            let (content, tree) = get_content_and_tree(content_len);
+
+            let mut encoded_buffer = Vec::new();
+            let mut encoder = Encoder::new(&mut encoded_buffer, content.len(), tree)?;
+            encoder.write_all(&content)?;
+            encoder.flush()?;
+
+            assert_eq!(
+                proof_overhead(content_len),
+                encoded_buffer.len() - 8 - content_len
+            );
+        }
+        assert_eq!(proof_overhead(0), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn proof_overhead_growth() {
+        let mut previous_first_segment = 0;
+        for num_blocks in (0..=10).map(|i| 1 << i) {
+            // The largest segment, for the first block, grows by a hash per doubling.
+            let first_segment = ProofSizeEstimator::new(0, num_blocks).0;
+            if num_blocks > 2 {
+                assert!(first_segment > previous_first_segment);
+                assert!(first_segment <= previous_first_segment + 33);
+            }
+            previous_first_segment = first_segment;
+
+            // While the total averages out to less than two hashes per block.
+            let overhead = proof_overhead(num_blocks * BLOCK_SIZE);
+            assert!(overhead >= first_segment);
+            assert!(overhead <= num_blocks * 2 * 33);
+        }
+    }
+
+    #[test]
+    fn encode_and_decode_multiple() -> std::io::Result<()> {
+        let entries: Vec<_> = [10, BLOCK_SIZE + 1, 3 * BLOCK_SIZE - 1]
+            .into_iter()
+            .enumerate()
+            .map(|(i, len)| {
+                // Use different content for each entry so they all have a different root.
+                let content = vec![i as u8; len];
+                let mut tree_builder = HashTreeBuilder::new();
+                tree_builder.update(&content);
+                (content, tree_builder.finalize())
+            })
+            .collect();
+        let manifest: Vec<_> = entries
+            .iter()
+            .map(|(content, tree)| ManifestEntry {
+                root: tree.hash.into(),
+                len: content.len() as u64,
+            })
+            .collect();
+
+        let mut encoder = MultiEncoder::new(Vec::new(), manifest.clone())?;
+        for (content, tree) in &entries {
+            let mut entry = encoder.next_entry(tree.clone())?;
+            entry.write_all(content)?;
+            entry.flush()?;
+        }
+        let encoded_buffer = encoder.finish()?;
+
+        let mut decoder = MultiDecoder::new(encoded_buffer.as_slice())?;
+        assert_eq!(decoder.manifest(), manifest.as_slice());
+        for (content, _) in &entries {
+            let mut entry = decoder.next_entry().expect("missing entry");
+            let mut decoded_buffer = Vec::with_capacity(content.len());
+            entry.read_to_end(&mut decoded_buffer)?;
+            assert_eq!(content, &decoded_buffer);
+        }
+        assert!(decoder.next_entry().is_none());
+
+        Ok(())
+    }
+
     #[test]
     fn encode_incrementally_and_decode() -> std::io::Result<()> {
         for &content_len in TEST_CASES {
@@ -336,4 +705,289 @@ fn encode_incrementally_and_decode() -> std::io::Result<()> {
 
         Ok(())
     }
+
+    #[test]
+    fn encode_odd_chunks_and_finalize() -> std::io::Result<()> {
+        for &content_len in TEST_CASES {
+            let (content, tree) = get_content_and_tree(content_len);
+
+            let mut encoder = Encoder::new(Vec::new(), content.len(), tree.clone())?;
+            for (i, chunk) in content.chunks(BLOCK_SIZE / 3 + 7).enumerate() {
+                // Split every other chunk once more so the writes never line up with a block.
+                if i % 2 == 0 {
+                    let (a, b) = chunk.split_at(chunk.len() / 2);
+                    encoder.write_all(a)?;
+                    encoder.write_all(b)?;
+                } else {
+                    encoder.write_all(chunk)?;
+                }
+            }
+            let encoded_buffer = encoder.finalize()?;
+
+            let mut decoder = VerifiedDecoder::new(encoded_buffer.as_slice(), tree.hash.into());
+            let mut decoded_buffer = Vec::with_capacity(content_len);
+            decoder.read_to_end(&mut decoded_buffer)?;
+            assert_eq!(content, decoded_buffer);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn tap_observes_segments_in_order() -> std::io::Result<()> {
+        for &content_len in TEST_CASES {
+            let (content, tree) = get_content_and_tree(content_len);
+            let num_blocks = (content_len + BLOCK_SIZE - 1) / BLOCK_SIZE;
+
+            let segments = Arc::new(Mutex::new(Vec::new()));
+            let mut encoder = Encoder::new(Vec::new(), content.len(), tree.clone())?.with_tap({
+                let segments = segments.clone();
+                move |kind, bytes: &[u8]| segments.lock().unwrap().push((kind, bytes.to_vec()))
+            });
+            encoder.write_all(&content)?;
+            let encoded_buffer = encoder.finalize()?;
+
+            let segments = segments.lock().unwrap();
+            assert_eq!(segments.len(), 1 + 2 * num_blocks);
+            assert_eq!(segments[0].0, SegmentKind::Header);
+            assert_eq!(segments[0].1, (content_len as u64).to_be_bytes());
+            for (block, pair) in segments[1..].chunks(2).enumerate() {
+                assert_eq!(pair[0].0, SegmentKind::Proof);
+                assert_eq!(
+                    pair[0].1.len(),
+                    if block == 0 {
+                        ProofSizeEstimator::new(0, num_blocks).0
+                    } else {
+                        ProofSizeEstimator::resume(block, num_blocks).0
+                    }
+                );
+                assert_eq!(pair[1].0, SegmentKind::Block);
+                assert_eq!(
+                    pair[1].1.len(),
+                    BLOCK_SIZE.min(content_len - block * BLOCK_SIZE)
+                );
+            }
+
+            // The tap sees exactly the bytes of the stream, and does not change them.
+            let tapped: Vec<u8> = segments
+                .iter()
+                .flat_map(|(_, bytes)| bytes.clone())
+                .collect();
+            assert_eq!(tapped, encoded_buffer);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn finalize_rejects_wrong_length() -> std::io::Result<()> {
+        let (content, tree) = get_content_and_tree(2 * BLOCK_SIZE + 1);
+
+        let mut encoder = Encoder::new(Vec::new(), content.len(), tree)?;
+        encoder.write_all(&content[..content.len() - 1])?;
+        let error = encoder.finalize().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+
+        Ok(())
+    }
+
+    #[test]
+    fn decode_with_growing_buffer() -> std::io::Result<()> {
+        for &content_len in TEST_CASES {
+            let (content, tree) = get_content_and_tree(content_len);
+
+            let mut encoded_buffer = Vec::new();
+            let mut encoder = Encoder::new(&mut encoded_buffer, content.len(), tree.clone())?;
+            encoder.write_all(&content)?;
+            encoder.flush()?;
+
+            // The buffer can not even hold the length header, let alone a block.
+            let mut decoder = VerifiedDecoder::with_buffer_policy(
+                encoded_buffer.as_slice(),
+                tree.hash.into(),
+                BufferPolicy::Grow { initial: 4 },
+            );
+            let mut decoded_buffer = Vec::with_capacity(content_len);
+            decoder.read_to_end(&mut decoded_buffer)?;
+            assert_eq!(content, decoded_buffer);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn counting_decoder_tracks_content_and_stream_bytes() -> std::io::Result<()> {
+        for &content_len in TEST_CASES {
+            let (content, tree) = get_content_and_tree(content_len);
+
+            let mut encoded_buffer = Vec::new();
+            let mut encoder = Encoder::new(&mut encoded_buffer, content.len(), tree.clone())?;
+            encoder.write_all(&content)?;
+            encoder.flush()?;
+
+            let mut decoder = CountingDecoder::new(encoded_buffer.as_slice(), tree.hash.into());
+            assert_eq!(decoder.content_bytes_read(), 0);
+            assert_eq!(decoder.stream_bytes_read(), 0);
+
+            // Read in small pieces so the counters are updated across many calls.
+            let mut decoded_buffer = Vec::with_capacity(content_len);
+            let mut chunk = [0; 4096];
+            loop {
+                let len = decoder.read(&mut chunk)?;
+                if len == 0 {
+                    break;
+                }
+                decoded_buffer.extend_from_slice(&chunk[..len]);
+                assert_eq!(decoder.content_bytes_read(), decoded_buffer.len() as u64);
+                assert!(decoder.stream_bytes_read() > decoder.content_bytes_read());
+            }
+
+            assert_eq!(content, decoded_buffer);
+            assert_eq!(decoder.content_bytes_read(), content_len as u64);
+            assert_eq!(decoder.stream_bytes_read(), encoded_buffer.len() as u64);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn decoder_stats_match_input() -> std::io::Result<()> {
+        for &content_len in TEST_CASES {
+            let (content, tree) = get_content_and_tree(content_len);
+
+            let mut encoded_buffer = Vec::new();
+            let mut encoder = Encoder::new(&mut encoded_buffer, content.len(), tree.clone())?;
+            encoder.write_all(&content)?;
+            encoder.flush()?;
+
+            let mut decoder = VerifiedDecoder::new(encoded_buffer.as_slice(), tree.hash.into());
+            assert_eq!(decoder.stats(), DecodeStats::default());
+
+            let mut decoded_buffer = Vec::with_capacity(content_len);
+            decoder.read_to_end(&mut decoded_buffer)?;
+            assert_eq!(content, decoded_buffer);
+
+            let stats = decoder.stats();
+            assert_eq!(stats.blocks, (content_len + BLOCK_SIZE - 1) / BLOCK_SIZE);
+            assert_eq!(stats.content_bytes, content_len as u64);
+            assert_eq!(stats.proof_bytes, proof_overhead(content_len) as u64);
+        }
+
+        Ok(())
+    }
+
+    fn block_hash(content: &[u8], block: usize, num_blocks: usize) -> [u8; 32] {
+        let start = block * BLOCK_SIZE;
+        let end = (start + BLOCK_SIZE).min(content.len());
+        let mut hasher = BlockHasher::new();
+        hasher.set_block(block);
+        hasher.update(&content[start..end]);
+        hasher.finalize(num_blocks == 1)
+    }
+
+    #[test]
+    fn verify_valid_proof() {
+        for &content_len in TEST_CASES {
+            let (content, tree) = get_content_and_tree(content_len);
+            let num_blocks = (content_len + BLOCK_SIZE - 1) / BLOCK_SIZE;
+
+            for block in 0..num_blocks {
+                let proof = ProofBuf::new(&tree.tree, block);
+                let hash = block_hash(&content, block, num_blocks);
+                assert!(verify_proof(
+                    tree.hash.into(),
+                    block,
+                    proof.as_slice(),
+                    hash
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn verify_proof_for_wrong_block() {
+        let (content, tree) = get_content_and_tree(4 * BLOCK_SIZE);
+
+        for block in 0..4 {
+            let hash = block_hash(&content, block, 4);
+            for other in (0..4).filter(|other| *other != block) {
+                // A proof served for another block must not be accepted for this block.
+                let proof = ProofBuf::new(&tree.tree, other);
+                assert!(!verify_proof(
+                    tree.hash.into(),
+                    block,
+                    proof.as_slice(),
+                    hash
+                ));
+            }
+        }
+    }
+
+    fn encode_corrupted(content_len: usize, corrupt: &[usize]) -> (Vec<u8>, HashTree) {
+        let (mut content, tree) = get_content_and_tree(content_len);
+        for block in corrupt {
+            content[block * BLOCK_SIZE] ^= 0xff;
+        }
+
+        let mut encoded_buffer = Vec::new();
+        let mut encoder = Encoder::new(&mut encoded_buffer, content.len(), tree.clone()).unwrap();
+        encoder.write_all(&content).unwrap();
+        encoder.flush().unwrap();
+        (encoded_buffer, tree)
+    }
+
+    #[test]
+    fn repair_scan_reports_corrupt_block() -> std::io::Result<()> {
+        let (encoded, tree) = encode_corrupted(3 * BLOCK_SIZE, &[1]);
+        let scan = repair_scan(encoded.as_slice(), tree.hash.into())?;
+        assert_eq!(
+            scan,
+            RepairScan {
+                corrupt_blocks: vec![1],
+                stopped_at: None,
+            }
+        );
+
+        let (encoded, tree) = encode_corrupted(4 * BLOCK_SIZE + 1, &[0, 2, 4]);
+        let scan = repair_scan(encoded.as_slice(), tree.hash.into())?;
+        assert_eq!(scan.corrupt_blocks, vec![0, 2, 4]);
+        assert_eq!(scan.stopped_at, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn repair_scan_stops_on_truncated_stream() -> std::io::Result<()> {
+        let (encoded, tree) = encode_corrupted(3 * BLOCK_SIZE, &[0]);
+        let scan = repair_scan(&encoded[..encoded.len() - 1], tree.hash.into())?;
+        assert_eq!(
+            scan,
+            RepairScan {
+                corrupt_blocks: vec![0],
+                stopped_at: Some(2),
+            }
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_encode_matches_encoder() -> std::io::Result<()> {
+        for &content_len in TEST_CASES {
+            let (content, tree) = get_content_and_tree(content_len);
+
+            let mut expected = Vec::new();
+            let mut encoder = Encoder::new(&mut expected, content.len(), tree.clone())?;
+            encoder.write_all(&content)?;
+            encoder.flush()?;
+
+            let mut encoded_buffer = Vec::new();
+            crate::par_encode(&mut encoded_buffer, &content, &tree)?;
+
+            assert_eq!(expected, encoded_buffer);
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,148 @@
+//! Multiple streams packed into a single combined stream.
+//!
+//! The combined stream starts with a manifest listing the root hash and the content length of
+//! every entry, followed by the encoded stream of each entry in the same order:
+//!
+//! ```text
+//! [ count (u32) . root (32 bytes) . length (u64) ... . stream . stream ... ]
+//! ```
+//!
+//! Every entry is a complete stream as written by [`Encoder`], so it is verified against its own
+//! root independently of the other entries.
+
+use std::io::{self, Read, Write};
+
+use blake3_tree::blake3::tree::HashTree;
+
+use crate::{BufferPolicy, Encoder, VerifiedDecoder, BLOCK_SIZE};
+
+/// An entry in the manifest of a combined stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// The root hash of the content of the entry.
+    pub root: [u8; 32],
+    /// The length of the content of the entry.
+    pub len: u64,
+}
+
+impl ManifestEntry {
+    /// The size of an encoded manifest entry.
+    pub const SIZE: usize = 40;
+}
+
+/// Encoder for a combined stream of multiple entries.
+pub struct MultiEncoder<W: Write> {
+    writer: W,
+    manifest: Vec<ManifestEntry>,
+    next: usize,
+}
+
+impl<W: Write> MultiEncoder<W> {
+    /// Create a new encoder for the given entries, immediately writing the manifest.
+    pub fn new(mut writer: W, manifest: Vec<ManifestEntry>) -> io::Result<Self> {
+        let count = u32::try_from(manifest.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many entries"))?;
+        writer.write_all(&count.to_be_bytes())?;
+        for entry in &manifest {
+            writer.write_all(&entry.root)?;
+            writer.write_all(&entry.len.to_be_bytes())?;
+        }
+        Ok(Self {
+            writer,
+            manifest,
+            next: 0,
+        })
+    }
+
+    /// Returns the encoder for the next entry in the manifest. The entire content of the entry
+    /// must be written to it before moving on to the next entry.
+    ///
+    /// Returns an error if all of the entries were already written, or if the tree does not belong
+    /// to the next entry.
+    pub fn next_entry(&mut self, tree: HashTree) -> io::Result<Encoder<&mut W>> {
+        let Some(entry) = self.manifest.get(self.next) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "all of the entries were already written",
+            ));
+        };
+        if entry.root != <[u8; 32]>::from(tree.hash) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the tree does not match the root of the next entry",
+            ));
+        }
+        self.next += 1;
+        Encoder::new(&mut self.writer, entry.len as usize, tree)
+    }
+
+    /// Flush the combined stream and return the underlying writer.
+    ///
+    /// Returns an error if not all of the entries in the manifest were written.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.next < self.manifest.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not all of the entries were written",
+            ));
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Decoder for a combined stream of multiple entries.
+pub struct MultiDecoder<R: Read> {
+    reader: R,
+    manifest: Vec<ManifestEntry>,
+    next: usize,
+}
+
+impl<R: Read> MultiDecoder<R> {
+    /// Create a new decoder, immediately reading the manifest.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut count = [0; 4];
+        reader.read_exact(&mut count)?;
+        let count = u32::from_be_bytes(count) as usize;
+
+        // The count is not trusted, so it is not used to size the allocation up front.
+        let mut manifest = Vec::with_capacity(count.min(BLOCK_SIZE / ManifestEntry::SIZE));
+        let mut bytes = [0; ManifestEntry::SIZE];
+        for _ in 0..count {
+            reader.read_exact(&mut bytes)?;
+            manifest.push(ManifestEntry {
+                root: *arrayref::array_ref!(bytes, 0, 32),
+                len: u64::from_be_bytes(*arrayref::array_ref!(bytes, 32, 8)),
+            });
+        }
+
+        Ok(Self {
+            reader,
+            manifest,
+            next: 0,
+        })
+    }
+
+    /// Returns the manifest of the combined stream.
+    pub fn manifest(&self) -> &[ManifestEntry] {
+        &self.manifest
+    }
+
+    /// Returns the decoder for the next entry, which verifies the content against the root of the
+    /// entry in the manifest, or `None` once all of the entries were returned. The decoder must be
+    /// read to the end before moving on to the next entry.
+    ///
+    /// The length in the manifest is not verified, only the content is.
+    pub fn next_entry(&mut self) -> Option<VerifiedDecoder<&mut R>> {
+        let entry = self.manifest.get(self.next)?;
+        self.next += 1;
+        // The decoder must not read past the end of the entry, which belongs to the next one.
+        Some(VerifiedDecoder::with_buffer_policy(
+            &mut self.reader,
+            entry.root,
+            BufferPolicy::Grow {
+                initial: BLOCK_SIZE,
+            },
+        ))
+    }
+}
@@ -0,0 +1,338 @@
+//! The decoder state machine.
+//!
+//! This module only depends on `core` and `alloc` and operates on byte slices, so it can be used
+//! to verify a stream on targets without `std`, as long as they have an allocator. The caller is
+//! responsible for buffering the input and feeding the decoder exactly [`DecoderCore::next_size`]
+//! bytes at a time.
+
+use core::fmt;
+
+use arrayref::array_ref;
+use blake3_tree::{
+    blake3::tree::BlockHasher, IncrementalVerifier, IncrementalVerifierError, ProofSizeEstimator,
+};
+
+use crate::BLOCK_SIZE;
+
+#[derive(Clone, Copy, Debug)]
+pub enum DecoderState {
+    WaitingForHeader,
+    WaitingForProof(usize),
+    WaitingForBlock(usize),
+    Finished,
+}
+
+impl DecoderState {
+    pub fn next_size(&self) -> Option<usize> {
+        match self {
+            DecoderState::WaitingForHeader => Some(8),
+            DecoderState::WaitingForProof(proof_len) => Some(*proof_len),
+            DecoderState::WaitingForBlock(block_len) => Some(*block_len),
+            DecoderState::Finished => None,
+        }
+    }
+}
+
+/// An error returned by [`DecoderCore::feed`].
+#[derive(Debug)]
+pub enum DecoderError {
+    /// A proof or a block failed the verification against the root hash.
+    Verifier(IncrementalVerifierError),
+    /// The content length in the header does not match the number of blocks in the tree.
+    LengthMismatch,
+}
+
+impl From<IncrementalVerifierError> for DecoderError {
+    fn from(value: IncrementalVerifierError) -> Self {
+        Self::Verifier(value)
+    }
+}
+
+impl fmt::Display for DecoderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecoderError::Verifier(e) => write!(f, "{e}"),
+            DecoderError::LengthMismatch => {
+                write!(f, "The content length header does not match the tree.")
+            },
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecoderError {}
+
+/// The sans-io core of the stream decoder, tracking the frame sizes and verifying the proofs and
+/// blocks that are fed into it.
+pub struct DecoderCore {
+    iv: IncrementalVerifier,
+    block: usize,
+    num_blocks: usize,
+    remaining: usize,
+    state: DecoderState,
+}
+
+impl DecoderCore {
+    /// Create a new decoder core for a stream with the given root hash.
+    pub fn new(root_hash: [u8; 32]) -> Self {
+        Self {
+            iv: IncrementalVerifier::new(root_hash, 0),
+            block: 0,
+            num_blocks: 0,
+            remaining: 0,
+            state: DecoderState::WaitingForHeader,
+        }
+    }
+
+    /// Returns the current state of the decoder.
+    #[inline]
+    pub fn state(&self) -> DecoderState {
+        self.state
+    }
+
+    /// Returns the number of bytes that must be passed to the next call to [`DecoderCore::feed`],
+    /// or `None` if the stream is finished.
+    #[inline]
+    pub fn next_size(&self) -> Option<usize> {
+        self.state.next_size()
+    }
+
+    /// Returns true if the entire content has been verified.
+    #[inline]
+    pub fn is_done(&self) -> bool {
+        self.iv.is_done()
+    }
+
+    /// Feed the next frame to the decoder. The length of `bytes` must be equal to the value
+    /// returned by [`DecoderCore::next_size`].
+    ///
+    /// Returns the verified content once a block is consumed, and `None` for the header and the
+    /// proof frames.
+    ///
+    /// The root hash commits to the true length of the content, so a header that lies about the
+    /// length is detected once the blocks run out before the tree does, or the other way around,
+    /// and [`DecoderError::LengthMismatch`] is returned.
+    ///
+    /// # Panics
+    ///
+    /// If the stream is already finished or the length of `bytes` is not the expected size.
+    pub fn feed<'a>(&mut self, bytes: &'a [u8]) -> Result<Option<&'a [u8]>, DecoderError> {
+        assert_eq!(
+            Some(bytes.len()),
+            self.next_size(),
+            "Unexpected frame size for the decoder state."
+        );
+
+        match self.state {
+            DecoderState::WaitingForHeader => {
+                // read a u64 content length header
+                self.remaining = u64::from_be_bytes(*array_ref!(bytes, 0, 8)) as usize;
+                self.num_blocks = (self.remaining + BLOCK_SIZE - 1) / BLOCK_SIZE;
+                let proof_len = ProofSizeEstimator::new(0, self.num_blocks).0;
+                self.state = DecoderState::WaitingForProof(proof_len);
+                Ok(None)
+            },
+            DecoderState::WaitingForProof(_) => {
+                if !bytes.is_empty() {
+                    self.iv.feed_proof(bytes)?;
+                }
+
+                let block_len = if self.block + 1 < self.num_blocks {
+                    BLOCK_SIZE
+                } else {
+                    // final block
+                    let mut len = self.remaining % BLOCK_SIZE;
+                    if len == 0 {
+                        len = BLOCK_SIZE;
+                    }
+                    len
+                };
+                self.state = DecoderState::WaitingForBlock(block_len);
+                Ok(None)
+            },
+            DecoderState::WaitingForBlock(_) => {
+                // verify block
+                let mut hasher = BlockHasher::new();
+                hasher.set_block(self.block);
+                hasher.update(bytes);
+                self.iv.verify(hasher)?;
+                // The tree has to end exactly at the last block announced by the header.
+                if self.iv.is_done() != (self.block + 1 == self.num_blocks) {
+                    return Err(DecoderError::LengthMismatch);
+                }
+                self.next_block();
+
+                Ok(Some(bytes))
+            },
+            DecoderState::Finished => unreachable!(),
+        }
+    }
+
+    /// Returns the index of the current block.
+    #[inline]
+    pub fn block(&self) -> usize {
+        self.block
+    }
+
+    /// Skip the current block after it failed the verification in [`DecoderCore::feed`], so
+    /// the rest of the stream can still be verified.
+    ///
+    /// # Panics
+    ///
+    /// If the decoder is not waiting for a block.
+    pub fn skip_block(&mut self) -> Result<(), IncrementalVerifierError> {
+        assert!(
+            matches!(self.state, DecoderState::WaitingForBlock(_)),
+            "Decoder is not waiting for a block."
+        );
+        self.iv.skip()?;
+        self.next_block();
+        Ok(())
+    }
+
+    /// Setup the state for the next block.
+    fn next_block(&mut self) {
+        self.block += 1;
+        if self.block < self.num_blocks {
+            let proof_len = ProofSizeEstimator::resume(self.block, self.num_blocks).0;
+            self.state = DecoderState::WaitingForProof(proof_len);
+        } else {
+            self.state = DecoderState::Finished;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use alloc::{vec, vec::Vec};
+
+    use blake3_tree::{blake3::tree::HashTreeBuilder, ProofBuf};
+
+    use super::{DecoderCore, DecoderError};
+    use crate::BLOCK_SIZE;
+
+    /// Build an encoded stream by hand, without going through the `std::io` encoder.
+    fn encode(content: &[u8]) -> ([u8; 32], Vec<u8>) {
+        let mut tree_builder = HashTreeBuilder::new();
+        tree_builder.update(content);
+        let tree = tree_builder.finalize();
+
+        let mut encoded = Vec::new();
+        encoded.extend_from_slice(&(content.len() as u64).to_be_bytes());
+        for (block, bytes) in content.chunks(BLOCK_SIZE).enumerate() {
+            let proof = if block == 0 {
+                ProofBuf::new(&tree.tree, 0)
+            } else {
+                ProofBuf::resume(&tree.tree, block)
+            };
+            encoded.extend_from_slice(proof.as_ref());
+            encoded.extend_from_slice(bytes);
+        }
+
+        (tree.hash.into(), encoded)
+    }
+
+    #[test]
+    fn decode_slices() {
+        for content_len in [
+            BLOCK_SIZE - 1,
+            BLOCK_SIZE,
+            BLOCK_SIZE + 1,
+            3 * BLOCK_SIZE,
+            4 * BLOCK_SIZE + 1,
+        ] {
+            let content = vec![0x80; content_len];
+            let (root, encoded) = encode(&content);
+
+            let mut decoder = DecoderCore::new(root);
+            let mut input = encoded.as_slice();
+            let mut decoded = Vec::with_capacity(content_len);
+            while let Some(size) = decoder.next_size() {
+                let (frame, rest) = input.split_at(size);
+                input = rest;
+                if let Some(block) = decoder.feed(frame).unwrap() {
+                    decoded.extend_from_slice(block);
+                }
+            }
+
+            assert!(input.is_empty());
+            assert!(decoder.is_done());
+            assert_eq!(content, decoded);
+        }
+    }
+
+    #[test]
+    fn decode_slices_invalid_block() {
+        let content = vec![0x80; 2 * BLOCK_SIZE];
+        let (root, mut encoded) = encode(&content);
+        let last = encoded.len() - 1;
+        encoded[last] ^= 0xff;
+
+        let mut decoder = DecoderCore::new(root);
+        let mut input = encoded.as_slice();
+        let mut result = Ok(None);
+        while let Some(size) = decoder.next_size() {
+            let (frame, rest) = input.split_at(size);
+            input = rest;
+            result = decoder.feed(frame);
+            if result.is_err() {
+                break;
+            }
+        }
+
+        assert!(result.is_err());
+        assert!(!decoder.is_done());
+    }
+
+    #[test]
+    fn decode_slices_forged_length() {
+        for (content_len, forged_len) in [
+            // The header announces fewer blocks than there are in the tree.
+            (4 * BLOCK_SIZE, 3 * BLOCK_SIZE),
+            // The header announces more blocks than there are in the tree.
+            (3 * BLOCK_SIZE, 3 * BLOCK_SIZE + 1),
+        ] {
+            let content = vec![0x80; content_len];
+            let (root, mut encoded) = encode(&content);
+            encoded[..8].copy_from_slice(&(forged_len as u64).to_be_bytes());
+
+            let mut decoder = DecoderCore::new(root);
+            let mut input = encoded.as_slice();
+            let mut result = Ok(None);
+            while let Some(size) = decoder.next_size() {
+                let (frame, rest) = input.split_at(size.min(input.len()));
+                if frame.len() < size {
+                    break;
+                }
+                input = rest;
+                result = decoder.feed(frame);
+                if result.is_err() {
+                    break;
+                }
+            }
+
+            // The stream is rejected before the decoder runs out of blocks to verify.
+            assert!(result.is_err());
+            assert!(decoder.next_size().is_some());
+            assert!(!decoder.is_done());
+        }
+    }
+
+    #[test]
+    fn decode_slices_tree_ends_before_header() {
+        let content = vec![0x80; BLOCK_SIZE];
+        let (root, encoded) = encode(&content);
+
+        // Pretend the header announced one more block than the tree has once the header is read,
+        // so the framing of the only block is still correct.
+        let mut decoder = DecoderCore::new(root);
+        decoder.feed(&encoded[..8]).unwrap();
+        decoder.num_blocks += 1;
+        decoder.feed(&[]).unwrap();
+        let result = decoder.feed(&encoded[8..]);
+
+        assert!(matches!(result, Err(DecoderError::LengthMismatch)));
+    }
+}
@@ -1,12 +1,21 @@
-use std::{borrow::Borrow, cmp::Ordering, fmt::Debug, ptr};
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    borrow::Borrow,
+    cmp::Ordering,
+    fmt::{self, Debug},
+    ptr,
+};
 
 use arrayref::array_ref;
 use arrayvec::ArrayVec;
 pub use fleek_blake3 as blake3;
-use thiserror::Error;
 
 // Debug only code for testing against memory leaks.
-#[cfg(debug_assertions)]
+#[cfg(all(debug_assertions, feature = "std"))]
 thread_local! {
     /// Number of pointers that `IncrementalVerifierTreeNode` has allocated.
     static POINTERS: std::cell::RefCell<usize> = std::cell::RefCell::new(0);
@@ -37,16 +46,33 @@ pub struct IncrementalVerifier {
     next_head: *mut IncrementalVerifierTreeNode,
 }
 
-#[derive(Error, Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum IncrementalVerifierError {
-    #[error("The proof provided to the verifier does not have a valid length.")]
     InvalidProofSize,
-    #[error("The proof provided did not belong to the tree.")]
     HashMismatch,
-    #[error("Verifier has already finished its job.")]
     VerifierTerminated,
 }
 
+impl fmt::Display for IncrementalVerifierError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IncrementalVerifierError::InvalidProofSize => write!(
+                f,
+                "The proof provided to the verifier does not have a valid length."
+            ),
+            IncrementalVerifierError::HashMismatch => {
+                write!(f, "The proof provided did not belong to the tree.")
+            },
+            IncrementalVerifierError::VerifierTerminated => {
+                write!(f, "Verifier has already finished its job.")
+            },
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for IncrementalVerifierError {}
+
 struct IncrementalVerifierTreeNode {
     /// A non-owning pointer to the parent node in the tree, can be null if
     /// the node is in pending state (part of stack), or is the root node.
@@ -118,13 +144,7 @@ fn finish(&mut self) {
             return Err(IncrementalVerifierError::HashMismatch);
         }
 
-        self.move_to_next();
-        self.block_counter += 1;
-
-        if self.is_root() {
-            self.finish();
-        }
-
+        self.advance();
         Ok(())
     }
 
@@ -141,6 +161,29 @@ pub fn verify(
         self.verify_hash(&hash)
     }
 
+    /// Skip the current block without verifying it, moving on to the next block.
+    ///
+    /// This can be used to keep verifying the rest of a stream after a block failed the
+    /// verification, the proofs for the following blocks are fed as usual.
+    pub fn skip(&mut self) -> Result<(), IncrementalVerifierError> {
+        if self.is_done() {
+            return Err(IncrementalVerifierError::VerifierTerminated);
+        }
+
+        self.advance();
+        Ok(())
+    }
+
+    /// Move on to the next block, finishing the verifier if the current block was the last one.
+    fn advance(&mut self) {
+        self.move_to_next();
+        self.block_counter += 1;
+
+        if self.is_root() {
+            self.finish();
+        }
+    }
+
     /// Go to the next element in the tree.
     fn move_to_next(&mut self) {
         debug_assert!(!self.cursor.is_null());
@@ -477,7 +520,7 @@ fn merge_stack(&mut self, is_root: bool) {
 impl IncrementalVerifierTreeNode {
     #[inline(always)]
     pub fn new(left: *mut Self, right: *mut Self, hash: [u8; 32]) -> *mut Self {
-        #[cfg(debug_assertions)]
+        #[cfg(all(debug_assertions, feature = "std"))]
         POINTERS.with(|c| {
             *c.borrow_mut() += 1;
         });
@@ -499,7 +542,7 @@ impl IncrementalVerifierTreeNode {
     pub unsafe fn free(ptr: *mut Self) {
         debug_assert!(!ptr.is_null(), "Attempted to free null pointer.");
 
-        #[cfg(debug_assertions)]
+        #[cfg(all(debug_assertions, feature = "std"))]
         POINTERS.with(|c| {
             let mut pointers_mut = c.borrow_mut();
             if *pointers_mut == 0 {
@@ -610,8 +653,8 @@ fn borrow(&self) -> &[u8] {
 }
 
 impl Debug for ProofBuf {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        std::fmt::Debug::fmt(self.as_slice(), f)
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Debug::fmt(self.as_slice(), f)
     }
 }
 
@@ -963,7 +1006,7 @@ mod tests {
     use super::*;
 
     fn assert_no_leak() {
-        #[cfg(debug_assertions)]
+        #[cfg(all(debug_assertions, feature = "std"))]
         POINTERS.with(|c| {
             let n = *c.borrow();
             assert_eq!(n, 0, "Memory leak detected.");
@@ -4,6 +4,7 @@
 mod listen;
 mod log;
 mod spawn;
+mod stimulus;
 mod storage;
 mod time;
 
@@ -16,5 +17,6 @@
 pub use listen::*;
 pub use log::*;
 pub use spawn::*;
+pub use stimulus::*;
 pub use storage::*;
 pub use time::*;
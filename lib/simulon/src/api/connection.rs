@@ -58,11 +58,20 @@ pub async fn recv<T>(&mut self) -> Option<T>
 
     /// Send a message through the connection.
     pub fn write<T>(&mut self, message: &T)
+    where
+        T: Serialize,
+    {
+        self.write_with_priority(message, 0)
+    }
+
+    /// Send a message through the connection with the given priority. If several messages are
+    /// due at the receiver at the same time, the ones with a higher priority are delivered first.
+    pub fn write_with_priority<T>(&mut self, message: &T, priority: u8)
     where
         T: Serialize,
     {
         let bytes = bincode::serialize(message).expect("Serialization failed.");
-        with_node(|n| n.send(self.remote, self.remote_rid, bytes))
+        with_node(|n| n.send(self.remote, self.remote_rid, bytes, priority))
     }
 }
 
@@ -118,10 +127,26 @@ pub fn is_closed(&self) -> bool {
 
     /// Send a message through the connection.
     pub fn write<T>(&mut self, message: &T)
+    where
+        T: Serialize,
+    {
+        self.write_with_priority(message, 0)
+    }
+
+    /// Send a message through the connection with the given priority. If several messages are
+    /// due at the receiver at the same time, the ones with a higher priority are delivered first.
+    pub fn write_with_priority<T>(&mut self, message: &T, priority: u8)
     where
         T: Serialize,
     {
         let bytes = bincode::serialize(message).expect("Serialization failed.");
-        with_node(|n| n.send(self.connection.remote, self.connection.remote_rid, bytes))
+        with_node(|n| {
+            n.send(
+                self.connection.remote,
+                self.connection.remote_rid,
+                bytes,
+                priority,
+            )
+        })
     }
 }
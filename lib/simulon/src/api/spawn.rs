@@ -8,8 +8,12 @@ pub fn spawn<F>(future: F)
 where
     F: Future<Output = ()> + 'static,
 {
-    let owned: LocalBoxFuture<()> = Box::pin(future);
+    let owned: LocalBoxFuture<()> = Box::pin(async move {
+        future.await;
+        with_node(|n| n.tasks -= 1);
+    });
     with_node(|n| {
+        n.tasks += 1;
         let future = LocalFutureObj::new(owned);
         n.spawn_pool
             .spawner()
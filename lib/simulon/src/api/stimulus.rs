@@ -0,0 +1,14 @@
+use std::any::Any;
+
+use crate::state::with_node;
+
+/// Returns the next stimulus event delivered to this node. Stimuli are scheduled from outside of
+/// the executor using [`crate::simulation::SimulationBuilder::schedule`], and are delivered in the
+/// order of their scheduled time.
+///
+/// # Panics
+///
+/// If another call to `next_stimulus` is still pending on this node.
+pub async fn next_stimulus() -> Box<dyn Any + Send> {
+    with_node(|n| n.next_stimulus()).await
+}
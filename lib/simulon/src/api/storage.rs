@@ -13,3 +13,13 @@ pub fn with_state<F, T: Any, U>(closure: F) -> U
 {
     with_node(|n| n.storage.with(closure))
 }
+
+/// Publish a value of type `T` as part of the final state of this node, replacing any value of
+/// the same type that was published before. The published values of every node are returned by
+/// [`crate::simulation::Simulation::run_with_state`] once the simulation is over.
+///
+/// The value is dropped outside of the simulation, so it should be plain data and not hold on to
+/// any resource such as a connection.
+pub fn publish<T: Any>(value: T) {
+    with_node(|n| n.published.set(value))
+}
@@ -7,6 +7,12 @@ pub fn now() -> u128 {
     with_node(|n| n.now())
 }
 
+/// Simulate spending the provided duration of cpu time. The clock of the node advances by the
+/// duration, and it is counted in the `cpu_time` metric of the node.
+pub fn consume_cpu(time: Duration) {
+    with_node(|n| n.consume_cpu(time.as_nanos()));
+}
+
 /// Returns a future that will be resolved after the provided duration is passed.
 pub async fn sleep(time: Duration) {
     with_node(|n| n.sleep(time.as_nanos())).await;
@@ -2,8 +2,10 @@
 
 pub use constant::ConstLatencyProvider;
 pub use ping::PingDataLatencyProvider;
+pub use time_varying::TimeVaryingLatency;
 
 mod constant;
+mod time_varying;
 
 /// A latency data provider from real world data.
 pub mod ping;
@@ -19,6 +21,20 @@ fn init(&mut self, _number_of_nodes: usize) {}
 
     /// Return a latency between two nodes from the provided global indices.
     fn get(&mut self, a: usize, b: usize) -> Duration;
+
+    /// Return a latency between two nodes for a message sent at the given simulated time. This is
+    /// what the simulation calls, so providers can model latency that changes over time.
+    ///
+    /// The default implementation ignores the time and calls [`Self::get`].
+    fn get_at(&mut self, a: usize, b: usize, _now: Duration) -> Duration {
+        self.get(a, b)
+    }
+
+    /// Reset the random number generators of this provider to the given seed, so the latencies
+    /// sampled after this call are the same every time it is called with the same seed.
+    ///
+    /// The default implementation does nothing, which is correct for deterministic providers.
+    fn reset_seed(&mut self, _seed: u64) {}
 }
 
 pub type DefaultLatencyProvider = PingDataLatencyProvider;
@@ -1,10 +1,14 @@
-use std::{ops::Add, time::Duration};
+use std::{
+    collections::HashSet,
+    ops::{Add, RangeInclusive},
+    time::Duration,
+};
 
 use arrayref::array_ref;
 use num::integer::Roots;
 use rand::Rng;
 use rand_chacha::ChaCha8Rng;
-use rand_core::SeedableRng;
+use rand_core::{RngCore, SeedableRng};
 use rand_distr::{Distribution, Normal};
 
 use super::LatencyProvider;
@@ -12,6 +16,13 @@
 const PING_DATA: &[u8] = include_bytes!("../ping.bin");
 const COUNT: usize = 222;
 
+/// The default one-way latency between two co-located nodes.
+const CO_LOCATED_LATENCY: Duration = Duration::from_micros(5);
+
+/// The default range of the one-way latency for a node sending a message to itself.
+const LOOPBACK_LATENCY: RangeInclusive<Duration> =
+    Duration::from_micros(7)..=Duration::from_micros(12);
+
 #[inline(always)]
 fn read(i: usize, j: usize) -> PingStat {
     let index = 16 * (i * COUNT + j);
@@ -32,11 +43,23 @@ pub trait RegionToRegionDistribution: From<PingStat> {
 }
 
 /// A latency provider with pre-filled real world ping data.
-pub struct PingDataLatencyProvider<T: RegionToRegionDistribution = ClampNormalDistribution> {
-    rng: ChaCha8Rng,
-    rng2: ChaCha8Rng,
+///
+/// The random number generator used to sample the latencies is `R`, which defaults to
+/// [`ChaCha8Rng`] so the samples are reproducible across platforms and versions. Any seedable rng
+/// can be used instead, e.g. to compare the influence of the rng or to trade quality for speed.
+pub struct PingDataLatencyProvider<
+    T: RegionToRegionDistribution = ClampNormalDistribution,
+    R: RngCore + SeedableRng = ChaCha8Rng,
+> {
+    rng: R,
+    /// The rng used to sample the loopback latency, kept apart from the inter-node rng so
+    /// self-messages don't change the inter-node samples.
+    rng2: R,
+    loopback_latency: RangeInclusive<Duration>,
     node_to_region: Vec<usize>,
     region_to_region: Vec<T>,
+    co_located: HashSet<(usize, usize)>,
+    co_located_latency: Duration,
 }
 
 /// A normal distribution which clamps the ping data to the min and max.
@@ -68,20 +91,74 @@ fn from(value: PingStat) -> Self {
     }
 }
 
-impl<T: RegionToRegionDistribution> Default for PingDataLatencyProvider<T> {
+/// Create an rng from a seed with every byte set to the given value.
+fn seeded<R: SeedableRng>(byte: u8) -> R {
+    let mut seed = R::Seed::default();
+    seed.as_mut().fill(byte);
+    R::from_seed(seed)
+}
+
+impl<T: RegionToRegionDistribution, R: RngCore + SeedableRng> Default
+    for PingDataLatencyProvider<T, R>
+{
     fn default() -> Self {
         Self {
-            rng: ChaCha8Rng::from_seed([17; 32]),
-            rng2: ChaCha8Rng::from_seed([11; 32]),
+            rng: seeded(17),
+            rng2: seeded(11),
+            loopback_latency: LOOPBACK_LATENCY,
             node_to_region: Vec::new(),
             region_to_region: Vec::new(),
+            co_located: HashSet::new(),
+            co_located_latency: CO_LOCATED_LATENCY,
         }
     }
 }
 
-impl<T: RegionToRegionDistribution> LatencyProvider for PingDataLatencyProvider<T> {
+impl<T: RegionToRegionDistribution, R: RngCore + SeedableRng> PingDataLatencyProvider<T, R> {
+    /// Mark two nodes as running on the same physical host. The latency between them will be a
+    /// small fixed value instead of a sample from the region model.
+    pub fn co_locate(&mut self, a: usize, b: usize) {
+        self.co_located.insert((a.min(b), a.max(b)));
+    }
+
+    /// Like [`Self::co_locate`] but consumes and returns the provider.
+    pub fn with_co_located(mut self, a: usize, b: usize) -> Self {
+        self.co_locate(a, b);
+        self
+    }
+
+    /// Set the latency used between co-located nodes. Defaults to 5 microseconds.
+    pub fn with_co_located_latency(mut self, latency: Duration) -> Self {
+        assert!(!latency.is_zero(), "Latency must be non-zero.");
+        self.co_located_latency = latency;
+        self
+    }
+
+    /// Set a fixed latency for a node sending a message to itself.
+    pub fn with_loopback_latency(self, latency: Duration) -> Self {
+        self.with_loopback_latency_range(latency..=latency)
+    }
+
+    /// Set the range the latency of a node sending a message to itself is uniformly sampled
+    /// from. Defaults to 7 to 12 microseconds.
+    pub fn with_loopback_latency_range(mut self, range: RangeInclusive<Duration>) -> Self {
+        assert!(!range.start().is_zero(), "Latency must be non-zero.");
+        assert!(range.start() <= range.end(), "Range must not be empty.");
+        self.loopback_latency = range;
+        self
+    }
+
+    /// Returns true if the two nodes were marked as co-located.
+    pub fn is_co_located(&self, a: usize, b: usize) -> bool {
+        self.co_located.contains(&(a.min(b), a.max(b)))
+    }
+}
+
+impl<T: RegionToRegionDistribution, R: RngCore + SeedableRng> LatencyProvider
+    for PingDataLatencyProvider<T, R>
+{
     fn init(&mut self, number_of_nodes: usize) {
-        let mut rng = ChaCha8Rng::from_seed([0; 32]);
+        let mut rng = seeded::<R>(0);
 
         self.node_to_region = Vec::with_capacity(number_of_nodes);
         self.node_to_region
@@ -97,8 +174,13 @@ fn init(&mut self, number_of_nodes: usize) {
 
     fn get(&mut self, a: usize, b: usize) -> Duration {
         if a == b {
-            let micro = self.rng2.gen_range(14..25);
-            return Duration::from_micros(micro / 2);
+            let start = self.loopback_latency.start().as_nanos() as u64;
+            let end = self.loopback_latency.end().as_nanos() as u64;
+            return Duration::from_nanos(self.rng2.gen_range(start..=end));
+        }
+
+        if self.is_co_located(a, b) {
+            return self.co_located_latency;
         }
 
         let region_a = self.node_to_region[a];
@@ -108,6 +190,12 @@ fn get(&mut self, a: usize, b: usize) -> Duration {
         let sample = entry.next(&mut self.rng);
         Duration::from_micros((sample / 2) as u64)
     }
+
+    fn reset_seed(&mut self, seed: u64) {
+        self.rng = R::seed_from_u64(seed);
+        // Derive a different stream for the loopback latency so the two stay independent.
+        self.rng2 = R::seed_from_u64(!seed);
+    }
 }
 
 /// The statistic data for a ping measurement between two servers.
@@ -214,3 +302,117 @@ fn test_data_count() {
     let count = (PING_DATA.len() / 16).sqrt();
     assert_eq!(COUNT, count);
 }
+
+#[test]
+fn test_co_located_latency() {
+    let mut provider =
+        PingDataLatencyProvider::<ClampNormalDistribution>::default().with_co_located(3, 40);
+    provider.init(64);
+
+    let co_located = provider.get(3, 40);
+    assert_eq!(co_located, CO_LOCATED_LATENCY);
+    // Co-located links are symmetric and jitter-free.
+    for _ in 0..10 {
+        assert_eq!(provider.get(40, 3), co_located);
+    }
+
+    for a in 0..64 {
+        for b in 0..64 {
+            if provider.is_co_located(a, b)
+                || provider.node_to_region[a] == provider.node_to_region[b]
+            {
+                continue;
+            }
+            assert!(co_located < provider.get(a, b));
+        }
+    }
+}
+
+#[test]
+fn test_loopback_latency() {
+    let latency = Duration::from_micros(3);
+    let mut provider = PingDataLatencyProvider::<ClampNormalDistribution>::default()
+        .with_loopback_latency(latency);
+    provider.init(8);
+
+    for a in 0..8 {
+        assert_eq!(provider.get(a, a), latency);
+    }
+
+    let mut default = PingDataLatencyProvider::<ClampNormalDistribution>::default();
+    default.init(8);
+    for a in 0..8 {
+        assert!(LOOPBACK_LATENCY.contains(&default.get(a, a)));
+    }
+}
+
+#[test]
+fn test_loopback_does_not_perturb_inter_node_latency() {
+    let mut a = PingDataLatencyProvider::<ClampNormalDistribution>::default();
+    let mut b = PingDataLatencyProvider::<ClampNormalDistribution>::default();
+    a.init(8);
+    b.init(8);
+
+    for i in 0..8 {
+        // Only one of the providers samples self-messages in between.
+        a.get(i, i);
+        assert_eq!(a.get(i, (i + 1) % 8), b.get(i, (i + 1) % 8));
+    }
+}
+
+#[test]
+fn test_reset_seed() {
+    let mut provider = PingDataLatencyProvider::<ClampNormalDistribution>::default();
+    provider.init(8);
+
+    let mut sweep = |seed| {
+        provider.reset_seed(seed);
+        (0..8)
+            .flat_map(|a| (0..8).map(move |b| (a, b)))
+            .map(|(a, b)| provider.get(a, b))
+            .collect::<Vec<_>>()
+    };
+
+    let first = sweep(42);
+    assert_eq!(first, sweep(42));
+    assert_ne!(first, sweep(7));
+}
+
+#[test]
+fn test_alternative_rng() {
+    use rand::rngs::StdRng;
+
+    type Provider = PingDataLatencyProvider<ClampNormalDistribution, StdRng>;
+
+    let sweep = |provider: &mut Provider| {
+        (0..8)
+            .flat_map(|a| (0..8).map(move |b| (a, b)))
+            .map(|(a, b)| provider.get(a, b))
+            .collect::<Vec<_>>()
+    };
+
+    let mut a = Provider::default();
+    let mut b = Provider::default();
+    a.init(8);
+    b.init(8);
+    let first = sweep(&mut a);
+    assert_eq!(first, sweep(&mut b));
+
+    // Seeding resets the alternative rng just like the default one.
+    a.reset_seed(42);
+    b.reset_seed(42);
+    let seeded = sweep(&mut a);
+    assert_eq!(seeded, sweep(&mut b));
+    a.reset_seed(42);
+    assert_eq!(seeded, sweep(&mut a));
+
+    // The samples come from a different rng than the default provider.
+    let mut chacha = PingDataLatencyProvider::<ClampNormalDistribution>::default();
+    chacha.init(8);
+    chacha.reset_seed(42);
+    let chacha = (0..8)
+        .flat_map(|a| (0..8).map(move |b| (a, b)))
+        .map(|(a, b)| chacha.get(a, b))
+        .collect::<Vec<_>>();
+    assert_ne!(seeded, chacha);
+}
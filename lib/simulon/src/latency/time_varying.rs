@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use super::LatencyProvider;
+
+/// A latency provider that scales the latency of an inner provider by a factor that depends on
+/// the simulated time, to model congestion that follows the time of the day.
+pub struct TimeVaryingLatency<P> {
+    inner: P,
+    factor: Box<dyn Fn(Duration) -> f64 + Send + Sync>,
+}
+
+impl<P> TimeVaryingLatency<P> {
+    /// Create a provider that multiplies the latency returned by `inner` by `factor(now)`, where
+    /// `now` is the simulated time at which the message is sent.
+    ///
+    /// The factor must be positive.
+    pub fn new<F>(inner: P, factor: F) -> Self
+    where
+        F: Fn(Duration) -> f64 + Send + Sync + 'static,
+    {
+        Self {
+            inner,
+            factor: Box::new(factor),
+        }
+    }
+}
+
+impl<P: Default> Default for TimeVaryingLatency<P> {
+    fn default() -> Self {
+        Self::new(P::default(), |_| 1.0)
+    }
+}
+
+impl<P: LatencyProvider> LatencyProvider for TimeVaryingLatency<P> {
+    fn init(&mut self, number_of_nodes: usize) {
+        self.inner.init(number_of_nodes);
+    }
+
+    /// Returns the latency at the start of the simulation.
+    fn get(&mut self, a: usize, b: usize) -> Duration {
+        self.get_at(a, b, Duration::ZERO)
+    }
+
+    fn get_at(&mut self, a: usize, b: usize, now: Duration) -> Duration {
+        let factor = (self.factor)(now);
+        debug_assert!(factor > 0.0, "The latency factor must be positive.");
+        self.inner.get_at(a, b, now).mul_f64(factor)
+    }
+
+    fn reset_seed(&mut self, seed: u64) {
+        self.inner.reset_seed(seed);
+    }
+}
+
+#[test]
+fn test_peak_latency() {
+    use crate::latency::ConstLatencyProvider;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    // Latency triples between 8:00 and 18:00 of every simulated day.
+    let mut provider = TimeVaryingLatency::new(
+        ConstLatencyProvider(Duration::from_millis(10)),
+        |now: Duration| {
+            let hour = now.as_secs() / HOUR.as_secs() % 24;
+            if (8..18).contains(&hour) { 3.0 } else { 1.0 }
+        },
+    );
+    provider.init(2);
+
+    let off_peak = provider.get_at(0, 1, 2 * HOUR);
+    let peak = provider.get_at(0, 1, 12 * HOUR);
+    assert_eq!(off_peak, Duration::from_millis(10));
+    assert_eq!(peak, Duration::from_millis(30));
+    assert!(peak > off_peak);
+
+    // The pattern repeats on the next day.
+    assert_eq!(provider.get_at(0, 1, 36 * HOUR), peak);
+    assert_eq!(provider.get_at(0, 1, 20 * HOUR), off_peak);
+}
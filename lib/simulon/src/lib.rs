@@ -19,7 +19,10 @@
 mod future;
 mod message;
 mod state;
-mod storage;
+mod transfer;
+
+/// The storage used to share state with the nodes and to collect the state they publish.
+pub mod storage;
 
 /// How many frames is one millisecond?
 pub const FRAME_TO_MS: u64 = 4;
@@ -1,4 +1,4 @@
-use std::cmp::Reverse;
+use std::{any::Any, cmp::Reverse};
 
 use derive_more::{Deref, DerefMut};
 
@@ -10,6 +10,8 @@ pub struct Message {
     // Also we reverse it since we use a max heap but we want the messages with smaller
     // time to come first.
     pub time: Reverse<u128>,
+    // Messages with a higher priority come first if they are due at the same time.
+    pub priority: u8,
     pub sender: RemoteAddr,
     pub receiver: RemoteAddr,
     pub detail: MessageDetail,
@@ -38,6 +40,51 @@ pub enum MessageDetail {
     WakeUp {
         waker: Ignored<DeferredFutureWaker<()>>,
     },
+    Stimulus {
+        event: Ignored<Box<dyn Any + Send>>,
+    },
+    /// Wakes a node that was added to a running simulation at the time it joins.
+    Join,
+}
+
+impl Message {
+    /// Clone this message, returns `None` for a wake up or a stimulus message since the waker
+    /// and the event can not be cloned.
+    pub fn try_clone(&self) -> Option<Self> {
+        let detail = match &self.detail {
+            MessageDetail::Connect { port, rid } => MessageDetail::Connect {
+                port: *port,
+                rid: *rid,
+            },
+            MessageDetail::ConnectionAccepted {
+                sender_rid,
+                receiver_rid,
+            } => MessageDetail::ConnectionAccepted {
+                sender_rid: *sender_rid,
+                receiver_rid: *receiver_rid,
+            },
+            MessageDetail::ConnectionRefused { receiver_rid } => MessageDetail::ConnectionRefused {
+                receiver_rid: *receiver_rid,
+            },
+            MessageDetail::ConnectionClosed { receiver_rid } => MessageDetail::ConnectionClosed {
+                receiver_rid: *receiver_rid,
+            },
+            MessageDetail::Data { receiver_rid, data } => MessageDetail::Data {
+                receiver_rid: *receiver_rid,
+                data: data.clone(),
+            },
+            MessageDetail::Join => MessageDetail::Join,
+            MessageDetail::WakeUp { .. } | MessageDetail::Stimulus { .. } => return None,
+        };
+
+        Some(Self {
+            time: self.time,
+            priority: self.priority,
+            sender: self.sender,
+            receiver: self.receiver,
+            detail,
+        })
+    }
 }
 
 #[derive(Deref, DerefMut)]
@@ -77,6 +124,7 @@ mod tests {
     fn test_ordering() {
         let m1 = Message {
             time: Reverse(0),
+            priority: 0,
             sender: RemoteAddr(1),
             receiver: RemoteAddr(2),
             detail: super::MessageDetail::ConnectionClosed {
@@ -86,6 +134,7 @@ fn test_ordering() {
 
         let m2 = Message {
             time: Reverse(17),
+            priority: 0,
             sender: RemoteAddr(1),
             receiver: RemoteAddr(2),
             detail: super::MessageDetail::ConnectionClosed {
@@ -95,6 +144,7 @@ fn test_ordering() {
 
         let m3 = Message {
             time: Reverse(3),
+            priority: 0,
             sender: RemoteAddr(0),
             receiver: RemoteAddr(0),
             detail: super::MessageDetail::ConnectionClosed {
@@ -104,6 +154,7 @@ fn test_ordering() {
 
         let m4 = Message {
             time: Reverse(5),
+            priority: 0,
             sender: RemoteAddr(0),
             receiver: RemoteAddr(0),
             detail: super::MessageDetail::ConnectionClosed {
@@ -113,6 +164,7 @@ fn test_ordering() {
 
         let m5 = Message {
             time: Reverse(2),
+            priority: 0,
             sender: RemoteAddr(18),
             receiver: RemoteAddr(0),
             detail: super::MessageDetail::ConnectionClosed {
@@ -134,4 +186,27 @@ fn test_ordering() {
         assert_eq!(set.pop().unwrap().time.0, 5);
         assert_eq!(set.pop().unwrap().time.0, 17);
     }
+
+    #[test]
+    fn test_priority_ordering() {
+        let message = |time, priority, sender| Message {
+            time: Reverse(time),
+            priority,
+            sender: RemoteAddr(sender),
+            receiver: RemoteAddr(0),
+            detail: super::MessageDetail::ConnectionClosed {
+                receiver_rid: ResourceId(0),
+            },
+        };
+
+        let mut set: BinaryHeap<Message> = BinaryHeap::new();
+        set.push(message(3, 0, 2));
+        set.push(message(3, 1, 1));
+        set.push(message(4, 2, 3));
+
+        // The priority only breaks ties between messages that are due at the same time.
+        assert_eq!(set.pop().unwrap().sender.0, 1);
+        assert_eq!(set.pop().unwrap().sender.0, 2);
+        assert_eq!(set.pop().unwrap().sender.0, 3);
+    }
 }
@@ -1,11 +1,14 @@
-use std::ops::{Add, Deref, DerefMut};
+use std::{
+    ops::{Add, Deref, DerefMut},
+    time::Duration,
+};
 
 use derive_more::{Add, AddAssign};
 use fxhash::FxHashMap;
 use replace_with::replace_with_or_abort;
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Default, Serialize, Add)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Add)]
 pub struct Report {
     /// The number of simulated frames.
     pub frames: u64,
@@ -19,9 +22,25 @@ pub struct Report {
     pub timeline: Timeline,
     /// Metrics for each node. During execution this must be empty.
     pub node: VecWithAdd<NodeMetrics>,
+    /// The busy and spin time of each worker thread, only collected when worker profiling is
+    /// enabled. During execution this must be empty.
+    pub workers: VecWithAdd<WorkerProfile>,
+    /// The total number of pending messages across all of the nodes per each 'n' frame, only
+    /// collected when queue depth sampling is enabled. During execution this must be empty.
+    pub queue_depth: QueueDepth,
+    /// The distribution of the network latency of every message that was sent.
+    pub latency: LatencyHistogram,
+    /// The data messages sent by the nodes per each frame, only collected when message capture
+    /// is enabled. During execution this must be empty.
+    pub messages: CapturedMessages,
+    /// The graph of which nodes exchanged data messages with each other, only collected when
+    /// communication graph recording is enabled. During execution this must be empty.
+    pub comm_graph: CommGraph,
+    /// The nodes whose executor panicked, ordered by the frame of the failure.
+    pub failures: Failures,
 }
 
-#[derive(Clone, Debug, Default, Serialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
 pub struct Log {
     pub emitted: FxHashMap<String, FxHashMap<u128, u32>>,
 }
@@ -35,7 +54,7 @@ fn add(self, rhs: Self) -> Self::Output {
     }
 }
 
-#[derive(Clone, Debug, Default, Serialize, Deserialize, Add)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, Add)]
 pub struct NodeMetrics {
     /// The total metrics during the entire execution.
     pub total: Metrics,
@@ -43,7 +62,7 @@ pub struct NodeMetrics {
     pub timeline: Timeline,
 }
 
-#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, Add, AddAssign)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, Add, AddAssign)]
 pub struct Metrics {
     /// Amount of CPU processing time spent in nanoseconds.
     pub cpu_time: u128,
@@ -73,6 +92,402 @@ pub struct Metrics {
     pub connections_failed: u16,
 }
 
+/// The wall clock time a worker thread spent executing nodes versus waiting for the other
+/// workers to finish a frame.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct WorkerProfile {
+    /// The total time during the entire execution.
+    pub total: WorkerTime,
+    /// The time per each 'n' frame.
+    pub timeline: FxHashMap<usize, WorkerTime>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, Add, AddAssign)]
+pub struct WorkerTime {
+    /// Time spent executing nodes in nanoseconds.
+    pub busy: u128,
+    /// Time spent spinning while waiting for the next frame in nanoseconds.
+    pub spin: u128,
+}
+
+impl WorkerProfile {
+    #[inline(always)]
+    pub fn insert(&mut self, key: Option<usize>, time: WorkerTime) {
+        self.total += time;
+        if let Some(key) = key {
+            *self.timeline.entry(key).or_default() += time;
+        }
+    }
+}
+
+impl Add for WorkerProfile {
+    type Output = Self;
+
+    fn add(mut self, rhs: Self) -> Self::Output {
+        self.total += rhs.total;
+        for (key, time) in rhs.timeline {
+            *self.timeline.entry(key).or_default() += time;
+        }
+        self
+    }
+}
+
+/// The number of pending messages sampled at the end of the last executed frame of each global
+/// report period. Periods in which no frame was executed are absent.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct QueueDepth(pub FxHashMap<usize, usize>);
+
+impl Add for QueueDepth {
+    type Output = Self;
+
+    fn add(mut self, rhs: Self) -> Self::Output {
+        self.0.extend(rhs.0);
+        self
+    }
+}
+
+impl Deref for QueueDepth {
+    type Target = FxHashMap<usize, usize>;
+
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// The nodes that failed during the simulation.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Failures(pub Vec<NodeFailure>);
+
+/// A node whose executor panicked.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeFailure {
+    /// The global index of the node.
+    pub node: usize,
+    /// The frame in which the executor panicked.
+    pub frame: usize,
+    /// The panic message.
+    pub message: String,
+}
+
+impl Add for Failures {
+    type Output = Self;
+
+    fn add(mut self, rhs: Self) -> Self::Output {
+        self.0.extend(rhs.0);
+        self
+    }
+}
+
+impl Deref for Failures {
+    type Target = Vec<NodeFailure>;
+
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// The data messages that the nodes sent out, by the frame in which they were sent. Frames in
+/// which no message was sent are absent.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CapturedMessages(pub FxHashMap<usize, Vec<CapturedMessage>>);
+
+/// A data message that was sent from one node to another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CapturedMessage {
+    /// The global index of the sender.
+    pub sender: usize,
+    /// The global index of the receiver.
+    pub receiver: usize,
+    /// The size of the payload in bytes.
+    pub size: usize,
+}
+
+impl CapturedMessages {
+    /// Returns the messages that were sent during the given frame.
+    pub fn sent_at(&self, frame: usize) -> &[CapturedMessage] {
+        self.0.get(&frame).map(Vec::as_slice).unwrap_or_default()
+    }
+}
+
+impl Add for CapturedMessages {
+    type Output = Self;
+
+    fn add(mut self, rhs: Self) -> Self::Output {
+        self.0.extend(rhs.0);
+        self
+    }
+}
+
+impl Deref for CapturedMessages {
+    type Target = FxHashMap<usize, Vec<CapturedMessage>>;
+
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Number of linear sub-buckets per power of two in a [`LatencyHistogram`], which bounds the
+/// relative error of a recorded value to `1 / SUB_BUCKETS`.
+const SUB_BUCKETS: u64 = 64;
+const SUB_BUCKET_BITS: u32 = SUB_BUCKETS.trailing_zeros();
+
+/// The effective topology of a simulation: for every sender the receivers it sent data messages
+/// to, with the number of messages and bytes sent over each directed edge.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CommGraph(pub FxHashMap<usize, FxHashMap<usize, CommEdge>>);
+
+/// The traffic sent over a directed edge of a [`CommGraph`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Add, AddAssign)]
+pub struct CommEdge {
+    /// The number of data messages sent.
+    pub count: u64,
+    /// The total size of the payloads in bytes.
+    pub bytes: u64,
+}
+
+impl CommGraph {
+    /// Record a data message of the given size from `sender` to `receiver`.
+    pub fn record(&mut self, sender: usize, receiver: usize, size: usize) {
+        *self
+            .0
+            .entry(sender)
+            .or_default()
+            .entry(receiver)
+            .or_default() += CommEdge {
+            count: 1,
+            bytes: size as u64,
+        };
+    }
+
+    /// Returns the traffic from `sender` to `receiver`, if any message was sent over that edge.
+    pub fn edge(&self, sender: usize, receiver: usize) -> Option<CommEdge> {
+        self.0.get(&sender)?.get(&receiver).copied()
+    }
+
+    /// Returns every edge of the graph as `(sender, receiver, edge)`, sorted by sender and then
+    /// by receiver.
+    pub fn edges(&self) -> Vec<(usize, usize, CommEdge)> {
+        let mut edges = self
+            .0
+            .iter()
+            .flat_map(|(sender, receivers)| {
+                receivers
+                    .iter()
+                    .map(move |(receiver, edge)| (*sender, *receiver, *edge))
+            })
+            .collect::<Vec<_>>();
+        edges.sort_by_key(|(sender, receiver, _)| (*sender, *receiver));
+        edges
+    }
+
+    /// Render the graph in the Graphviz DOT format with the message count and bytes as the label
+    /// of each edge.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph simulation {\n");
+        for (sender, receiver, edge) in self.edges() {
+            out.push_str(&format!(
+                "    {sender} -> {receiver} [label=\"{} msgs, {} bytes\"];\n",
+                edge.count, edge.bytes
+            ));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Render the graph as an adjacency list with one line per sender in the form
+    /// `sender: receiver(count, bytes) ...`.
+    pub fn to_adjacency_list(&self) -> String {
+        let mut out = String::new();
+        let mut senders = self.0.keys().copied().collect::<Vec<_>>();
+        senders.sort_unstable();
+        for sender in senders {
+            let mut receivers = self.0[&sender].iter().collect::<Vec<_>>();
+            receivers.sort_unstable_by_key(|(receiver, _)| **receiver);
+            out.push_str(&sender.to_string());
+            out.push(':');
+            for (receiver, edge) in receivers {
+                out.push_str(&format!(" {receiver}({}, {})", edge.count, edge.bytes));
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+impl Add for CommGraph {
+    type Output = Self;
+
+    fn add(mut self, rhs: Self) -> Self::Output {
+        for (sender, receivers) in rhs.0 {
+            let entry = self.0.entry(sender).or_default();
+            for (receiver, edge) in receivers {
+                *entry.entry(receiver).or_default() += edge;
+            }
+        }
+        self
+    }
+}
+
+impl Deref for CommGraph {
+    type Target = FxHashMap<usize, FxHashMap<usize, CommEdge>>;
+
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// A histogram of latencies in nanoseconds with a bounded memory usage.
+///
+/// Values are counted in logarithmic buckets, each split into [`SUB_BUCKETS`] linear sub-buckets,
+/// similar to HdrHistogram. Values below [`SUB_BUCKETS`] nanoseconds are exact, and every other
+/// value is reported with a relative error of less than 1%.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LatencyHistogram {
+    counts: Vec<u64>,
+    count: u64,
+    sum: u128,
+    min: u64,
+    max: u64,
+}
+
+impl LatencyHistogram {
+    /// Record a single latency value in nanoseconds.
+    pub fn record(&mut self, nanos: u64) {
+        let index = bucket_index(nanos);
+        if index >= self.counts.len() {
+            self.counts.resize(index + 1, 0);
+        }
+        self.counts[index] += 1;
+        self.min = if self.count == 0 {
+            nanos
+        } else {
+            self.min.min(nanos)
+        };
+        self.max = self.max.max(nanos);
+        self.count += 1;
+        self.sum += nanos as u128;
+    }
+
+    /// Returns the number of recorded values.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns true if no value has been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Returns the smallest recorded value.
+    pub fn min(&self) -> Option<Duration> {
+        (!self.is_empty()).then(|| Duration::from_nanos(self.min))
+    }
+
+    /// Returns the largest recorded value.
+    pub fn max(&self) -> Option<Duration> {
+        (!self.is_empty()).then(|| Duration::from_nanos(self.max))
+    }
+
+    /// Returns the mean of the recorded values.
+    pub fn mean(&self) -> Option<Duration> {
+        (!self.is_empty()).then(|| Duration::from_nanos((self.sum / self.count as u128) as u64))
+    }
+
+    /// Returns the value below which the given percentage of the recorded values fall.
+    ///
+    /// # Panics
+    ///
+    /// If the percentile is not in the range `0.0..=100.0`.
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        assert!(
+            (0.0..=100.0).contains(&percentile),
+            "Percentile must be in the range 0..=100."
+        );
+        if self.is_empty() {
+            return None;
+        }
+
+        let rank = ((percentile / 100.0 * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let nanos = bucket_value(index).clamp(self.min, self.max);
+                return Some(Duration::from_nanos(nanos));
+            }
+        }
+        unreachable!("The counts add up to the total count.")
+    }
+
+    /// Returns the median of the recorded values.
+    pub fn p50(&self) -> Option<Duration> {
+        self.percentile(50.0)
+    }
+
+    /// Returns the 95th percentile of the recorded values.
+    pub fn p95(&self) -> Option<Duration> {
+        self.percentile(95.0)
+    }
+
+    /// Returns the 99th percentile of the recorded values.
+    pub fn p99(&self) -> Option<Duration> {
+        self.percentile(99.0)
+    }
+}
+
+impl Add for LatencyHistogram {
+    type Output = Self;
+
+    fn add(mut self, rhs: Self) -> Self::Output {
+        if rhs.is_empty() {
+            return self;
+        }
+        if self.is_empty() {
+            return rhs;
+        }
+        if self.counts.len() < rhs.counts.len() {
+            self.counts.resize(rhs.counts.len(), 0);
+        }
+        for (count, other) in self.counts.iter_mut().zip(rhs.counts) {
+            *count += other;
+        }
+        self.count += rhs.count;
+        self.sum += rhs.sum;
+        self.min = self.min.min(rhs.min);
+        self.max = self.max.max(rhs.max);
+        self
+    }
+}
+
+/// Returns the index of the bucket the value is counted in.
+#[inline(always)]
+fn bucket_index(nanos: u64) -> usize {
+    if nanos < SUB_BUCKETS {
+        return nanos as usize;
+    }
+    let exponent = 63 - nanos.leading_zeros();
+    let shift = exponent - SUB_BUCKET_BITS;
+    let sub_bucket = (nanos >> shift) & (SUB_BUCKETS - 1);
+    ((shift as u64 + 1) * SUB_BUCKETS + sub_bucket) as usize
+}
+
+/// Returns the value in the middle of the range of values counted in the bucket.
+#[inline(always)]
+fn bucket_value(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return index;
+    }
+    let shift = index / SUB_BUCKETS - 1;
+    let sub_bucket = index % SUB_BUCKETS;
+    let lower = (SUB_BUCKETS + sub_bucket) << shift;
+    lower + ((1 << shift) >> 1)
+}
+
 impl NodeMetrics {
     #[inline(always)]
     pub fn insert(&mut self, key: Option<usize>, metric: Metrics) {
@@ -109,7 +524,7 @@ pub fn is_empty(&self) -> bool {
     }
 }
 
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Timeline(pub FxHashMap<usize, Metrics>);
 
 impl Timeline {
@@ -161,7 +576,7 @@ fn deref_mut(&mut self) -> &mut Self::Target {
 }
 
 /// A [`Vec`] wrapper that implements pairwise addition.
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct VecWithAdd<T>(pub Vec<T>);
 
 impl<T> Add for VecWithAdd<T>
@@ -200,3 +615,59 @@ fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_percentiles() {
+        // Uniformly distributed latencies between 1us and 10ms.
+        let mut histogram = LatencyHistogram::default();
+        for micros in 1..=10_000 {
+            histogram.record(micros * 1_000);
+        }
+
+        let within_tolerance = |actual: Duration, expected: Duration| {
+            let error = actual.as_secs_f64() - expected.as_secs_f64();
+            error.abs() <= expected.as_secs_f64() * 0.01
+        };
+        assert_eq!(histogram.count(), 10_000);
+        assert_eq!(histogram.min(), Some(Duration::from_micros(1)));
+        assert_eq!(histogram.max(), Some(Duration::from_millis(10)));
+        assert!(within_tolerance(
+            histogram.p50().unwrap(),
+            Duration::from_millis(5)
+        ));
+        assert!(within_tolerance(
+            histogram.p95().unwrap(),
+            Duration::from_micros(9_500)
+        ));
+        assert!(within_tolerance(
+            histogram.p99().unwrap(),
+            Duration::from_micros(9_900)
+        ));
+
+        // Merging two halves gives the same histogram.
+        let (mut lower, mut upper) = (LatencyHistogram::default(), LatencyHistogram::default());
+        for micros in 1..=10_000 {
+            if micros <= 5_000 {
+                lower.record(micros * 1_000);
+            } else {
+                upper.record(micros * 1_000);
+            }
+        }
+        assert_eq!(upper + lower, histogram);
+    }
+
+    #[test]
+    fn test_latency_histogram_exact_small_values() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.p50(), None);
+        for nanos in 0..SUB_BUCKETS {
+            histogram.record(nanos);
+        }
+        assert_eq!(histogram.p50(), Some(Duration::from_nanos(31)));
+        assert_eq!(histogram.percentile(100.0), Some(Duration::from_nanos(63)));
+    }
+}
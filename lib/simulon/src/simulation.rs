@@ -1,40 +1,69 @@
 use std::{
     any::Any,
     cell::UnsafeCell,
+    ops::{Range, RangeFrom, RangeInclusive},
+    panic::{self, AssertUnwindSafe},
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc,
     },
     thread::JoinHandle,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+use fxhash::FxHashMap;
 use indicatif::ProgressBar;
+use rand::seq::SliceRandom;
+use rand_chacha::ChaCha8Rng;
+use rand_core::SeedableRng;
 
 use crate::{
     latency::{DefaultLatencyProvider, LatencyProvider},
-    message::Message,
-    report::{Metrics, Report},
-    state::{hook_node, with_node, NodeState},
+    message::{Message, MessageDetail},
+    report::{
+        CapturedMessage, CapturedMessages, CommGraph, LatencyHistogram, Metrics, NodeFailure,
+        QueueDepth, Report, WorkerProfile, WorkerTime,
+    },
+    state::{hook_node, with_node, NodeCheckpoint, NodeState},
     storage::TypedStorage,
+    transfer::Transfers,
     FRAME_DURATION, FRAME_TO_MS,
 };
 
 /// Constructor for a simulation which allows you to set the parameters of a simulation.
 pub struct SimulationBuilder<L = DefaultLatencyProvider> {
     executor: Box<dyn Fn() + Send + Sync>,
+    executors: Vec<(Box<dyn NodeSelector>, Box<dyn Fn() + Send + Sync>)>,
     num_workers: Option<usize>,
     num_nodes: Option<usize>,
     frame_per_node_report: usize,
+    frame_per_node_report_overrides: FxHashMap<usize, usize>,
     frame_per_global_report: usize,
     storage: TypedStorage,
     latency_provider: Option<L>,
     show_progress: bool,
+    profile_workers: bool,
+    deterministic_cpu: bool,
+    sample_queue_depth: bool,
+    capture_messages: bool,
+    record_comm_graph: bool,
+    realtime_factor: Option<f64>,
+    checkpoint: Option<SimulationState>,
+    stimuli: Vec<(Duration, usize, Box<dyn Any + Send>)>,
+    joining: Vec<(Duration, Box<dyn Fn() + Send + Sync>)>,
+    connection_setup: Option<ConnectionSetupCost>,
+    bandwidth: Option<Bandwidth>,
+    scheduling: Scheduling,
+    shuffle_seed: Option<u64>,
 }
 
 pub struct Simulation<L: LatencyProvider = DefaultLatencyProvider> {
     /// The current time in nanoseconds.
     now: u128,
+    /// The frame at which the last call to `run_for` stopped.
+    end_frame: usize,
+    /// Whether the latency provider has been initialized.
+    initialized: bool,
     /// The shared state between us and the workers.
     state: Arc<SharedState>,
     /// The owned array of nodes in their actual order.
@@ -45,6 +74,98 @@ pub struct Simulation<L: LatencyProvider = DefaultLatencyProvider> {
     latency_provider: L,
     /// Show progress bar or not.
     show_progress: bool,
+    /// The sampled queue depth, if sampling is enabled.
+    queue_depth: Option<QueueDepth>,
+    /// The captured data messages, if message capture is enabled.
+    messages: Option<CapturedMessages>,
+    /// The communication graph, if recording it is enabled.
+    comm_graph: Option<CommGraph>,
+    /// The network latency of every message sent so far.
+    latency: LatencyHistogram,
+    /// How many times faster than the wall clock the simulation runs, `None` to run flat-out.
+    realtime_factor: Option<f64>,
+    /// The cost of the first contact between two nodes, if it is modeled.
+    connection_setup: Option<ConnectionSetupCost>,
+    /// For every pair of nodes that has been in contact, the time at which the connection
+    /// between them is established.
+    established: FxHashMap<(usize, usize), u128>,
+    /// The transfers in flight, if the bandwidth is modeled.
+    transfers: Option<Transfers>,
+    /// The logical position of each node by its global index, which is what the latency
+    /// provider sees. This is the identity unless the nodes are shuffled.
+    positions: Box<[usize]>,
+}
+
+/// The cost of establishing a connection between two nodes, such as a TCP and TLS handshake. It
+/// is paid by the first message between the two nodes, see
+/// [`SimulationBuilder::set_connection_setup_cost`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionSetupCost {
+    /// A fixed delay.
+    Fixed(Duration),
+    /// The given number of round trips over the link between the two nodes.
+    RoundTrips(u32),
+}
+
+/// The bandwidth of the nodes, see [`SimulationBuilder::set_bandwidth`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bandwidth {
+    /// The number of bytes per second that can be sent.
+    pub bytes_per_second: u64,
+    /// Which transfers share the bandwidth.
+    pub contention: Contention,
+}
+
+/// Which transfers contend for the same bandwidth.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Contention {
+    /// Every link from a sender to a receiver has the full bandwidth, shared by the transfers
+    /// over that link.
+    PerLink,
+    /// Every node has the bandwidth as its egress, shared by all of the transfers it sends
+    /// regardless of the receiver.
+    PerNodeEgress,
+}
+
+/// How the nodes are distributed over the workers, see [`SimulationBuilder::set_scheduling`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Scheduling {
+    /// Every worker grabs the next node that has to run, which balances the load between the
+    /// workers, but a node can run on a different worker every frame.
+    #[default]
+    WorkStealing,
+    /// Node `i` always runs on worker `i % num_workers`, which keeps the state of a node in the
+    /// caches of the same core at the cost of balancing the load.
+    Static,
+}
+
+/// The result of a single [`Simulation::step`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepResult {
+    /// The frame with the given index was executed.
+    Executed(usize),
+    /// There are no more events to process, so no frame was executed.
+    Idle,
+}
+
+/// A snapshot of a simulation taken using [`Simulation::checkpoint`] that can be resumed any number
+/// of times using [`SimulationBuilder::from_checkpoint`].
+///
+/// The state of the futures spawned by the executors can not be captured, so a checkpoint can only
+/// be taken when every node is at a stall boundary: all of the tasks spawned on the node have
+/// completed, and the node has no open connection or listener. The state of the latency provider
+/// is not captured either.
+#[derive(Clone)]
+pub struct SimulationState {
+    now: u128,
+    frame: usize,
+    end_frame: usize,
+    nodes: Vec<NodeCheckpoint>,
+    metrics: Report,
+    storage: Arc<TypedStorage>,
+    executors: Arc<[Box<dyn Fn() + Send + Sync>]>,
+    node_executor: Arc<[usize]>,
+    established: FxHashMap<(usize, usize), u128>,
 }
 
 #[derive(Default)]
@@ -53,15 +174,61 @@ struct WorkerState {
     outgoing: Vec<Message>,
     /// The collected metrics on this worker.
     metrics: Report,
+    /// The busy and spin time of this worker, if profiling is enabled.
+    profile: WorkerProfile,
+}
+
+/// A set of node indices which can be assigned a dedicated executor using
+/// [`SimulationBuilder::with_executor_for`].
+///
+/// This is implemented for ranges of node indices and for any `Fn(usize) -> bool` predicate.
+pub trait NodeSelector: Send + Sync + 'static {
+    /// Returns true if the node with the given global index is part of this selection.
+    fn contains(&self, index: usize) -> bool;
+}
+
+impl NodeSelector for Range<usize> {
+    fn contains(&self, index: usize) -> bool {
+        Range::contains(self, &index)
+    }
+}
+
+impl NodeSelector for RangeInclusive<usize> {
+    fn contains(&self, index: usize) -> bool {
+        RangeInclusive::contains(self, &index)
+    }
+}
+
+impl NodeSelector for RangeFrom<usize> {
+    fn contains(&self, index: usize) -> bool {
+        RangeFrom::contains(self, &index)
+    }
+}
+
+impl<F> NodeSelector for F
+where
+    F: Fn(usize) -> bool + Send + Sync + 'static,
+{
+    fn contains(&self, index: usize) -> bool {
+        self(index)
+    }
 }
 
 struct SharedState {
-    /// The executor function for each task.
-    executor: Box<dyn Fn() + Send + Sync>,
-    /// Number of frames for each report on each node.
-    frame_per_node_report: usize,
+    /// The executor functions. The first item is always the default executor.
+    executors: Arc<[Box<dyn Fn() + Send + Sync>]>,
+    /// The index of the executor in `executors` for each node by its global index.
+    node_executor: Arc<[usize]>,
+    /// Number of frames for each report on each node, by its global index.
+    frame_per_node_report: Box<[usize]>,
     /// Number of frames for each global report.
     frame_per_global_report: usize,
+    /// Whether to record the busy and spin time of each worker.
+    profile_workers: bool,
+    /// Whether to only count the cpu time consumed with [`crate::api::consume_cpu`].
+    deterministic_cpu: bool,
+    /// How the nodes are distributed over the workers.
+    scheduling: Scheduling,
     /// The state for each worker. We use an `UnsafeCell` instead of a Mutex since we know
     /// that our synchronization strategy already guarantees that the worker state is either:
     ///
@@ -72,6 +239,9 @@ struct SharedState {
     workers: Box<[UnsafeCell<WorkerState>]>,
     /// Nodes sorted by their event time.
     nodes: Box<[*mut NodeState]>,
+    /// The start of the array of nodes in their actual order, so the global index of a node can
+    /// be told from its pointer without accessing it.
+    first_node: *const NodeState,
     /// The current frame.
     frame: AtomicUsize,
     /// The current node that is being processed.
@@ -96,30 +266,223 @@ pub fn new<E>(executor: E) -> Self
     {
         Self {
             executor: Box::new(executor),
+            executors: Vec::new(),
             num_workers: None,
             num_nodes: None,
             frame_per_node_report: (FRAME_TO_MS * 10) as usize,
+            frame_per_node_report_overrides: FxHashMap::default(),
             frame_per_global_report: FRAME_TO_MS as usize,
             storage: TypedStorage::default(),
             latency_provider: None,
             show_progress: false,
+            profile_workers: false,
+            deterministic_cpu: false,
+            sample_queue_depth: false,
+            capture_messages: false,
+            record_comm_graph: false,
+            realtime_factor: None,
+            checkpoint: None,
+            stimuli: Vec::new(),
+            joining: Vec::new(),
+            connection_setup: None,
+            bandwidth: None,
+            scheduling: Scheduling::default(),
+            shuffle_seed: None,
         }
     }
+
+    /// Creates a new simulation builder that resumes the simulation from the given checkpoint.
+    ///
+    /// The executors, number of nodes and the injected state are taken from the checkpoint,
+    /// other parameters such as the latency provider or number of workers can be changed.
+    pub fn from_checkpoint(state: SimulationState) -> Self {
+        let mut builder = Self::new(|| {});
+        builder.num_nodes = Some(state.nodes.len());
+        builder.checkpoint = Some(state);
+        builder
+    }
 }
 
 impl<L> SimulationBuilder<L> {
     /// Inject the given value as shared state value for the executor to access.
+    ///
+    /// # Panics
+    ///
+    /// If the simulation is being resumed from a checkpoint.
     pub fn with_state<T: Any>(mut self, data: T) -> Self {
+        assert!(
+            self.checkpoint.is_none(),
+            "Can not inject state when resuming from a checkpoint."
+        );
         self.storage.insert(data);
         self
     }
 
+    /// Use a different executor function for the nodes matched by the given selector, the
+    /// selector can either be a range of node indices or a predicate over the node index.
+    ///
+    /// This allows simulating heterogeneous networks where different nodes run different
+    /// logic, for example a few client nodes and many server nodes. Nodes that are not matched
+    /// by any selector run the default executor passed to [`SimulationBuilder::new`]. If more
+    /// than one selector matches a node, the one that was added last is used.
+    pub fn with_executor_for<S, E>(mut self, selector: S, executor: E) -> Self
+    where
+        S: NodeSelector,
+        E: Fn() + Send + Sync + 'static,
+    {
+        self.executors
+            .push((Box::new(selector), Box::new(executor)));
+        self
+    }
+
+    /// Schedule an event to be delivered to the node with the given global index at the given
+    /// simulated time. The executor of the node receives the event using
+    /// [`crate::api::next_stimulus`].
+    ///
+    /// This allows scripting a scenario (e.g. a client issuing a request at a certain time)
+    /// without baking the timing into the executor.
+    pub fn schedule(mut self, at: Duration, node: usize, event: Box<dyn Any + Send>) -> Self {
+        self.stimuli.push((at, node, event));
+        self
+    }
+
+    /// Add a new node that joins the simulation at the given simulated time, rounded up to the
+    /// next frame, and runs the given executor from that point on. The added nodes get the global
+    /// indices after the nodes set by [`SimulationBuilder::with_nodes`], in the order they were
+    /// added.
+    ///
+    /// Until it joins, the node has no metrics and refuses every connection as if it was down.
+    /// The node is already counted in [`crate::api::NodeArray`] and by the latency provider from
+    /// the start, so the network can be grown without reassigning any of the existing nodes.
+    pub fn add_node_at<E>(mut self, at: Duration, executor: E) -> Self
+    where
+        E: Fn() + Send + Sync + 'static,
+    {
+        self.joining.push((at, Box::new(executor)));
+        self
+    }
+
+    /// Model the cost of establishing a connection between two nodes. The first message between
+    /// a pair of nodes, in either direction, is held back by the given cost before it goes over
+    /// the link, and so is every message between them that is sent before the connection is
+    /// established. Messages sent after that only pay the link latency.
+    ///
+    /// # Default
+    ///
+    /// By default messages flow immediately at the link latency.
+    pub fn set_connection_setup_cost(mut self, cost: ConnectionSetupCost) -> Self {
+        self.connection_setup = Some(cost);
+        self
+    }
+
+    /// Limit the bandwidth of the nodes. The data sent over a connection takes the time needed to
+    /// transfer it at the bandwidth before it goes over the link latency, and the transfers that
+    /// are in flight at the same time share the bandwidth fairly, see [`Contention`]. The
+    /// messages of a single connection are transferred one after the other, so they still arrive
+    /// in order.
+    ///
+    /// # Default
+    ///
+    /// By default the bandwidth is unlimited and messages only pay the link latency.
+    pub fn set_bandwidth(mut self, bandwidth: Bandwidth) -> Self {
+        self.bandwidth = Some(bandwidth);
+        self
+    }
+
+    /// Set how the nodes are distributed over the workers in every frame.
+    ///
+    /// # Default
+    ///
+    /// By default the workers steal work from each other, see [`Scheduling::WorkStealing`].
+    pub fn set_scheduling(mut self, scheduling: Scheduling) -> Self {
+        self.scheduling = scheduling;
+        self
+    }
+
+    /// Shuffle the logical order of the nodes with the given seed, so the global index of a node
+    /// is not correlated with the order in which the nodes are processed or with the position
+    /// the latency provider sees, which for example decides the region of a node in the
+    /// [`crate::latency::PingDataLatencyProvider`]. The same seed always results in the same
+    /// order.
+    ///
+    /// The order is not captured by a checkpoint, so a simulation has to be resumed with the same
+    /// seed to behave the same.
+    ///
+    /// # Default
+    ///
+    /// By default the nodes are not shuffled.
+    pub fn shuffle_nodes(mut self, seed: u64) -> Self {
+        self.shuffle_seed = Some(seed);
+        self
+    }
+
     /// Show a progress bar when running the simulation.
     pub fn enable_progress_bar(mut self) -> Self {
         self.show_progress = true;
         self
     }
 
+    /// Record the time each worker spends executing nodes versus spinning while it waits for the
+    /// other workers, for every frame. The result is available in [`Report::workers`] and can be
+    /// used to detect load imbalance across workers.
+    pub fn enable_worker_profiling(mut self) -> Self {
+        self.profile_workers = true;
+        self
+    }
+
+    /// Only count the cpu time that the nodes explicitly consume with [`crate::api::consume_cpu`]
+    /// in the metrics, instead of also measuring the wall clock time spent executing them. This
+    /// makes the `cpu_time` of the [`Report`] independent of the machine, so two runs of the
+    /// same simulation produce identical reports.
+    pub fn deterministic_cpu(mut self) -> Self {
+        self.deterministic_cpu = true;
+        self
+    }
+
+    /// Record the total number of pending messages across all of the nodes once per global report,
+    /// see [`SimulationBuilder::set_global_metrics_rate`]. The result is available in
+    /// [`Report::queue_depth`] and can be used to detect protocols that build up a backlog.
+    pub fn enable_queue_depth_sampling(mut self) -> Self {
+        self.sample_queue_depth = true;
+        self
+    }
+
+    /// Record every data message that the nodes send, with its sender, receiver and size, keyed
+    /// by the frame in which it was sent. The result is available in [`Report::messages`] and
+    /// can be used to assert on the exact messages a protocol produces in response to an input.
+    pub fn capture_messages(mut self) -> Self {
+        self.capture_messages = true;
+        self
+    }
+
+    /// Record which nodes actually exchanged data messages, with the number of messages and bytes
+    /// sent over each directed edge. The result is available in [`Report::comm_graph`] and can be
+    /// exported with [`CommGraph::to_dot`] or [`CommGraph::to_adjacency_list`].
+    pub fn record_comm_graph(mut self) -> Self {
+        self.record_comm_graph = true;
+        self
+    }
+
+    /// Pace the simulation against the wall clock so that it runs `factor` times faster than real
+    /// time, for example a factor of `0.1` runs the simulation ten times slower than real time.
+    /// This only affects how fast the frames are executed and not the result of the simulation.
+    ///
+    /// # Panics
+    ///
+    /// If the factor is not a positive finite number.
+    ///
+    /// # Default
+    ///
+    /// By default the simulation runs as fast as possible.
+    pub fn with_realtime_factor(mut self, factor: f64) -> Self {
+        assert!(
+            factor.is_finite() && factor > 0.0,
+            "Realtime factor must be a positive number."
+        );
+        self.realtime_factor = Some(factor);
+        self
+    }
+
     /// Determines the number of workers that we should use to run this simulation.
     ///
     /// # Panics
@@ -163,6 +526,23 @@ pub fn set_node_metrics_rate(mut self, duration: Duration) -> Self {
         self
     }
 
+    /// Overrides the compaction rate of the collected metrics for the given nodes, so that a few
+    /// nodes of interest can be observed in detail while the rest of the nodes use a coarser rate,
+    /// see [`SimulationBuilder::set_node_metrics_rate`].
+    ///
+    /// # Panics
+    ///
+    /// When the simulation is built, if any of the nodes does not exist.
+    pub fn set_node_metrics_rate_for(mut self, nodes: &[usize], duration: Duration) -> Self {
+        let rate = duration.as_nanos() / FRAME_DURATION.as_nanos();
+        assert!(rate < (usize::MAX as u128));
+        for node in nodes {
+            self.frame_per_node_report_overrides
+                .insert(*node, rate as usize);
+        }
+        self
+    }
+
     /// Sets the compaction rate of the globally aggregated collected metrics collect per-frame
     /// metric data on each node.
     ///
@@ -180,13 +560,28 @@ pub fn set_global_metrics_rate(mut self, duration: Duration) -> Self {
     pub fn set_latency_provider<T: LatencyProvider>(self, provider: T) -> SimulationBuilder<T> {
         SimulationBuilder {
             executor: self.executor,
+            executors: self.executors,
             num_workers: self.num_workers,
             num_nodes: self.num_nodes,
             frame_per_node_report: self.frame_per_node_report,
+            frame_per_node_report_overrides: self.frame_per_node_report_overrides,
             frame_per_global_report: self.frame_per_global_report,
             storage: self.storage,
             latency_provider: Some(provider),
             show_progress: self.show_progress,
+            profile_workers: self.profile_workers,
+            deterministic_cpu: self.deterministic_cpu,
+            sample_queue_depth: self.sample_queue_depth,
+            capture_messages: self.capture_messages,
+            record_comm_graph: self.record_comm_graph,
+            realtime_factor: self.realtime_factor,
+            checkpoint: self.checkpoint,
+            stimuli: self.stimuli,
+            joining: self.joining,
+            connection_setup: self.connection_setup,
+            bandwidth: self.bandwidth,
+            scheduling: self.scheduling,
+            shuffle_seed: self.shuffle_seed,
         }
     }
 
@@ -198,22 +593,95 @@ pub fn build(self) -> Simulation<L>
             .num_workers
             .unwrap_or_else(|| num_cpus::get_physical() - 1)
             .max(1);
-        let num_nodes = self.num_nodes.unwrap_or(num_workers * 4);
+        let num_initial_nodes = self.num_nodes.unwrap_or(num_workers * 4);
+        let num_nodes = num_initial_nodes + self.joining.len();
+
+        if let Some(checkpoint) = &self.checkpoint {
+            assert_eq!(
+                num_nodes,
+                checkpoint.nodes.len(),
+                "Can not change the number of nodes when resuming from a checkpoint."
+            );
+        }
 
         // Cap the number of workers to the number of nodes.
         let num_workers = num_workers.min(num_nodes);
-        let storage = Arc::new(self.storage);
-        let nodes = (0..num_nodes)
+        let storage = match &self.checkpoint {
+            Some(checkpoint) => checkpoint.storage.clone(),
+            None => Arc::new(self.storage),
+        };
+        let mut nodes = (0..num_nodes)
             .map(|i| NodeState::new(storage.clone(), num_nodes, i))
             .collect::<Vec<_>>()
             .into_boxed_slice();
+        if self.checkpoint.is_none() {
+            for (node, (at, _)) in nodes[num_initial_nodes..].iter_mut().zip(&self.joining) {
+                node.join_at(ceil_div(at.as_nanos(), FRAME_DURATION.as_nanos()) as usize);
+            }
+        }
 
         let ptr = nodes.as_ptr();
 
+        // The global indices of the nodes in their logical order.
+        let mut order = (0..num_nodes).collect::<Vec<_>>();
+        if let Some(seed) = self.shuffle_seed {
+            order.shuffle(&mut ChaCha8Rng::seed_from_u64(seed));
+        }
+        let mut positions = vec![0; num_nodes].into_boxed_slice();
+        for (position, index) in order.iter().enumerate() {
+            positions[*index] = position;
+        }
+
+        let (executors, node_executor) = match &self.checkpoint {
+            Some(checkpoint) => (
+                checkpoint.executors.clone(),
+                checkpoint.node_executor.clone(),
+            ),
+            None => {
+                let num_executors = 1 + self.executors.len();
+                let node_executor = (0..num_initial_nodes)
+                    .map(|i| {
+                        self.executors
+                            .iter()
+                            .rposition(|(selector, _)| selector.contains(i))
+                            .map_or(0, |index| index + 1)
+                    })
+                    .chain(num_executors..num_executors + self.joining.len())
+                    .collect();
+
+                let executors = std::iter::once(self.executor)
+                    .chain(self.executors.into_iter().map(|(_, executor)| executor))
+                    .chain(self.joining.into_iter().map(|(_, executor)| executor))
+                    .collect();
+
+                (executors, node_executor)
+            },
+        };
+
+        if let Some(node) = self
+            .frame_per_node_report_overrides
+            .keys()
+            .find(|node| **node >= num_nodes)
+        {
+            panic!("Metrics rate set for unknown node {node}.");
+        }
+        let frame_per_node_report = (0..num_nodes)
+            .map(|i| {
+                self.frame_per_node_report_overrides
+                    .get(&i)
+                    .copied()
+                    .unwrap_or(self.frame_per_node_report)
+            })
+            .collect();
+
         let state = SharedState {
-            executor: self.executor,
-            frame_per_node_report: self.frame_per_node_report,
+            executors,
+            node_executor,
+            frame_per_node_report,
             frame_per_global_report: self.frame_per_global_report,
+            profile_workers: self.profile_workers,
+            deterministic_cpu: self.deterministic_cpu,
+            scheduling: self.scheduling,
             workers: (0..num_workers)
                 .map(|_| {
                     let mut worker = WorkerState::default();
@@ -222,25 +690,52 @@ pub fn build(self) -> Simulation<L>
                 })
                 .collect::<Vec<_>>()
                 .into_boxed_slice(),
-            nodes: nodes
+            // Nodes with events at the same time keep their relative order when they are sorted,
+            // so they are processed in their logical order.
+            nodes: order
                 .iter()
-                .enumerate()
-                .map(|(i, _)| unsafe { ptr.add(i) as *mut NodeState })
+                .map(|i| unsafe { ptr.add(*i) as *mut NodeState })
                 .collect::<Vec<_>>()
                 .into_boxed_slice(),
+            first_node: ptr,
             frame: AtomicUsize::new(0),
             cursor: AtomicUsize::new(0),
             ready_workers: AtomicUsize::new(0),
         };
 
-        Simulation {
+        let mut simulation = Simulation {
             now: 0,
+            end_frame: 0,
+            initialized: false,
             state: Arc::new(state),
             nodes,
             workers: Vec::with_capacity(num_workers),
             latency_provider: self.latency_provider.unwrap_or_default(),
             show_progress: self.show_progress,
+            queue_depth: self.sample_queue_depth.then(QueueDepth::default),
+            messages: self.capture_messages.then(CapturedMessages::default),
+            comm_graph: self.record_comm_graph.then(CommGraph::default),
+            latency: LatencyHistogram::default(),
+            realtime_factor: self.realtime_factor,
+            connection_setup: self.connection_setup,
+            established: FxHashMap::default(),
+            transfers: self.bandwidth.map(Transfers::new),
+            positions,
+        };
+
+        if let Some(checkpoint) = self.checkpoint {
+            simulation.restore(checkpoint);
+        }
+
+        for (at, node, event) in self.stimuli {
+            assert!(
+                node < num_nodes,
+                "Stimulus scheduled for unknown node {node}."
+            );
+            simulation.nodes[node].schedule_stimulus(at.as_nanos(), event);
         }
+
+        simulation
     }
 
     /// Build and run the simulation.
@@ -250,48 +745,84 @@ pub fn run(self, duration: Duration) -> Report
     {
         self.build().run(duration)
     }
+
+    /// Build and run the simulation, see [`Simulation::run_with_state`].
+    pub fn run_with_state(self, duration: Duration) -> (Report, Vec<TypedStorage>)
+    where
+        L: LatencyProvider,
+    {
+        self.build().run_with_state(duration)
+    }
 }
 
 impl<L: LatencyProvider> Simulation<L> {
     pub fn run(mut self, duration: Duration) -> Report {
-        // Initialize the latency provider.
-        self.latency_provider.init(self.nodes.len());
+        self.run_for(duration);
+        self.finish()
+    }
 
-        self.start_threads();
+    /// Like [`Simulation::run`] but also returns the state each node published using
+    /// [`crate::api::publish`], indexed by the node index.
+    pub fn run_with_state(mut self, duration: Duration) -> (Report, Vec<TypedStorage>) {
+        self.run_for(duration);
+        let published = self
+            .nodes
+            .iter_mut()
+            .map(|node| std::mem::take(&mut node.published))
+            .collect();
+        (self.finish(), published)
+    }
 
-        let mut n = duration.as_nanos() / FRAME_DURATION.as_nanos();
-        let pb = self.show_progress.then(|| ProgressBar::new(n as u64));
+    /// Run the simulation for the given duration. Unlike [`Simulation::run`] this does not consume
+    /// the simulation and can be called again to continue the simulation from where it was
+    /// stopped.
+    pub fn run_for(&mut self, duration: Duration) {
+        self.initialize();
 
-        // Run frame zero regardless that the event queue is empty.
-        wait_for_workers(&self.state);
-        self.state.ready_workers.store(0, Ordering::Relaxed);
-        self.state.frame.fetch_add(1, Ordering::Relaxed);
-        if let Some(pb) = pb.as_ref() {
-            pb.inc(1);
+        let n = (duration.as_nanos() / FRAME_DURATION.as_nanos()) as usize;
+        let end_frame = self.end_frame + n;
+        self.end_frame = end_frame;
+
+        let mut frame = self.state.frame.load(Ordering::Relaxed);
+        if frame >= end_frame {
+            return;
         }
 
-        while n > 1 {
-            // wait for the workers to become online.
-            wait_for_workers(&self.state);
+        self.start_threads();
 
-            // reset the work stealing state.
-            self.state.ready_workers.store(0, Ordering::Relaxed);
-            self.state.cursor.store(0, Ordering::Relaxed);
+        let pb = self.show_progress.then(|| ProgressBar::new(n as u64));
+        let pacing = self
+            .realtime_factor
+            .map(|factor| Pacing::new(frame, factor));
+
+        if frame == 0 {
+            // Run frame zero regardless that the event queue is empty.
+            self.sync_workers();
+            self.state.frame.fetch_add(1, Ordering::Relaxed);
+            frame += 1;
+            if let Some(pb) = pb.as_ref() {
+                pb.inc(1);
+            }
+        }
+
+        loop {
+            self.sync_workers();
 
             // Run the post executing tasks and figure out how many frames we should move forward.
             if let Some(skip) = self.run_post_frame() {
-                if n <= skip as u128 {
+                if frame + skip > end_frame {
                     break;
                 }
 
                 debug_assert!(skip >= 1);
 
-                // Move the clock to `skip` frames forward.
-                self.now += skip as u128 * FRAME_DURATION.as_nanos();
+                // Hold the workers back until the frame is due on the wall clock.
+                if let Some(pacing) = &pacing {
+                    pacing.wait_for(frame + skip);
+                }
 
-                // Update the loop counter and move to the frame.
-                n -= skip as u128;
-                self.state.frame.fetch_add(skip, Ordering::Relaxed);
+                self.advance(skip);
+                frame += skip;
                 if let Some(pb) = pb.as_ref() {
                     pb.inc(skip as u64);
                 }
@@ -302,13 +833,106 @@ pub fn run(mut self, duration: Duration) -> Report {
         }
 
         if let Some(pb) = pb.as_ref() {
-            pb.inc(n as u64);
+            pb.inc((end_frame - frame) as u64);
+        }
+
+        // The simulated time still passes when there are no more events.
+        if let Some(pacing) = &pacing {
+            pacing.wait_for(end_frame);
         }
 
         // wait for threads one last time.
         self.stop_threads();
+    }
 
-        self.finish()
+    /// Execute the next frame that has an event to process, skipping over the frames without any
+    /// event, and return the index of the executed frame. The first call always executes frame
+    /// zero, where the executors are started.
+    ///
+    /// This is an alternative to [`Simulation::run_for`] that allows inspecting the state of the
+    /// simulation between frames. The end of the last call to `run_for` is moved forward to the
+    /// executed frame.
+    pub fn step(&mut self) -> StepResult {
+        self.initialize();
+        self.start_threads();
+
+        let frame = self.state.frame.load(Ordering::Relaxed);
+        let result = if frame == 0 {
+            self.sync_workers();
+            self.state.frame.fetch_add(1, Ordering::Relaxed);
+            StepResult::Executed(0)
+        } else {
+            self.sync_workers();
+            match self.run_post_frame() {
+                Some(skip) => {
+                    self.advance(skip);
+                    StepResult::Executed(frame + skip - 1)
+                },
+                None => StepResult::Idle,
+            }
+        };
+
+        // Wait for the workers to finish the frame.
+        self.stop_threads();
+
+        let frame = self.state.frame.load(Ordering::Relaxed);
+        self.end_frame = self.end_frame.max(frame);
+        result
+    }
+
+    /// Reset the latency provider to the given seed, see [`LatencyProvider::reset_seed`]. Calling
+    /// this between the iterations of a parameter sweep that reuses the simulation makes each
+    /// iteration start from the same sequence of latencies.
+    pub fn reset_latency_seed(&mut self, seed: u64) {
+        self.latency_provider.reset_seed(seed);
+    }
+
+    /// Capture the current state of the simulation, which can be used to resume the simulation
+    /// later using [`SimulationBuilder::from_checkpoint`].
+    ///
+    /// # Panics
+    ///
+    /// If any of the nodes is not at a stall boundary, see [`SimulationState`], or if there are
+    /// transfers in flight when the bandwidth is modeled.
+    pub fn checkpoint(&self) -> SimulationState {
+        assert!(
+            self.transfers.as_ref().map_or(true, Transfers::is_empty),
+            "Can not checkpoint while transfers are in flight."
+        );
+        let mut metrics = self
+            .state
+            .workers
+            .iter()
+            .map(|v| unsafe { &*v.get() })
+            .map(|s| s.metrics.clone())
+            .fold(Report::default(), |a, b| a + b);
+        metrics.latency = self.latency.clone();
+
+        SimulationState {
+            now: self.now,
+            frame: self.state.frame.load(Ordering::Relaxed),
+            end_frame: self.end_frame,
+            nodes: self.nodes.iter().map(|n| n.checkpoint()).collect(),
+            metrics,
+            storage: self.nodes[0].storage.clone(),
+            executors: self.state.executors.clone(),
+            node_executor: self.state.node_executor.clone(),
+            established: self.established.clone(),
+        }
+    }
+
+    fn restore(&mut self, mut checkpoint: SimulationState) {
+        self.now = checkpoint.now;
+        self.end_frame = checkpoint.end_frame;
+        self.state.frame.store(checkpoint.frame, Ordering::Relaxed);
+
+        for (node, state) in self.nodes.iter_mut().zip(checkpoint.nodes) {
+            node.restore(state);
+        }
+
+        self.established = checkpoint.established;
+        self.latency = std::mem::take(&mut checkpoint.metrics.latency);
+        unsafe { &mut *self.state.workers[0].get() }.metrics = checkpoint.metrics;
     }
 
     fn finish(mut self) -> Report {
@@ -334,10 +958,59 @@ fn finish(mut self) -> Report {
             report.node.push(std::mem::take(&mut node.metrics));
         }
 
+        if self.state.profile_workers {
+            for worker in self.state.workers.iter() {
+                let worker = unsafe { &mut *worker.get() };
+                report.workers.push(std::mem::take(&mut worker.profile));
+            }
+        }
+
+        if let Some(queue_depth) = self.queue_depth.take() {
+            report.queue_depth = queue_depth;
+        }
+
+        if let Some(messages) = self.messages.take() {
+            report.messages = messages;
+        }
+
+        if let Some(comm_graph) = self.comm_graph.take() {
+            report.comm_graph = comm_graph;
+        }
+
+        report.latency = std::mem::take(&mut self.latency);
         report
+            .failures
+            .0
+            .sort_by_key(|failure| (failure.frame, failure.node));
+
+        report
+    }
+
+    fn initialize(&mut self) {
+        // Initialize the latency provider.
+        if !self.initialized {
+            self.latency_provider.init(self.nodes.len());
+            self.initialized = true;
+        }
+    }
+
+    /// Wait for the workers to finish the current frame and reset the work stealing state.
+    fn sync_workers(&self) {
+        wait_for_workers(&self.state);
+        self.state.ready_workers.store(0, Ordering::Relaxed);
+        self.state.cursor.store(0, Ordering::Relaxed);
+    }
+
+    /// Move the clock `skip` frames forward and let the workers execute that frame.
+    fn advance(&mut self, skip: usize) {
+        self.now += skip as u128 * FRAME_DURATION.as_nanos();
+        self.state.frame.fetch_add(skip, Ordering::Relaxed);
     }
 
     fn run_post_frame(&mut self) -> Option<usize> {
+        // The frame that was just executed by the workers.
+        let frame = self.state.frame.load(Ordering::Relaxed).saturating_sub(1);
+
         // Move the messages generated by each worker to each of the destinations.
         for messages in self
             .state
@@ -346,19 +1019,97 @@ fn run_post_frame(&mut self) -> Option<usize> {
             .map(|s| &mut unsafe { &mut *s.get() }.outgoing)
         {
             for mut msg in messages.drain(..) {
+                if let (Some(captured), MessageDetail::Data { data, .. }) =
+                    (&mut self.messages, &msg.detail)
+                {
+                    captured.0.entry(frame).or_default().push(CapturedMessage {
+                        sender: msg.sender.0,
+                        receiver: msg.receiver.0,
+                        size: data.len(),
+                    });
+                }
+
+                if let (Some(graph), MessageDetail::Data { data, .. }) =
+                    (&mut self.comm_graph, &msg.detail)
+                {
+                    graph.record(msg.sender.0, msg.receiver.0, data.len());
+                }
+
                 let node_id = msg.receiver.0;
+                if self.nodes[node_id].failed {
+                    continue;
+                }
+
                 let latency = self
                     .latency_provider
-                    .get(msg.sender.0, msg.receiver.0)
+                    .get_at(
+                        self.positions[msg.sender.0],
+                        self.positions[msg.receiver.0],
+                        Duration::from_nanos(msg.time.0 as u64),
+                    )
                     .as_nanos();
 
                 debug_assert!(latency > 0);
-                msg.time.0 += latency;
+                let sent = msg.time.0;
+                let departure = match self.connection_setup {
+                    Some(cost) => {
+                        let cost = match cost {
+                            ConnectionSetupCost::Fixed(duration) => duration.as_nanos(),
+                            ConnectionSetupCost::RoundTrips(n) => 2 * latency * n as u128,
+                        };
+                        let (a, b) = (msg.sender.0, msg.receiver.0);
+                        let pair = (a.min(b), a.max(b));
+                        let established = *self.established.entry(pair).or_insert(sent + cost);
+                        sent.max(established)
+                    },
+                    None => sent,
+                };
+                if let Some(transfers) = &mut self.transfers {
+                    if Transfers::is_transfer(&msg) {
+                        transfers.push(departure, latency, msg);
+                        continue;
+                    }
+                }
+
+                msg.time.0 = departure + latency;
+                self.latency.record((msg.time.0 - sent) as u64);
 
                 self.nodes[node_id].received.push(msg);
             }
         }
 
+        if let Some(transfers) = &mut self.transfers {
+            // No message is sent before the next frame, so the transfers can make progress up to
+            // that frame and deliver the ones that complete by then.
+            let next = self
+                .nodes
+                .iter()
+                .filter_map(|node| node.received.peek().map(|msg| msg.time.0))
+                .chain(transfers.next_event())
+                .min();
+            if let Some(next) = next {
+                let duration = FRAME_DURATION.as_nanos();
+                let until = self.now + ceil_div(next - self.now, duration).max(1) * duration;
+                transfers.advance(until, |completed, mut transfer| {
+                    transfer.msg.time.0 = completed + transfer.latency;
+                    self.latency
+                        .record((transfer.msg.time.0 - transfer.sent) as u64);
+
+                    let node = &mut self.nodes[transfer.msg.receiver.0];
+                    if !node.failed {
+                        node.received.push(transfer.msg);
+                    }
+                });
+            }
+        }
+
+        if let Some(queue_depth) = &mut self.queue_depth {
+            if let Some(key) = frame.checked_div(self.state.frame_per_global_report) {
+                let depth = self.nodes.iter().map(NodeState::pending_messages).sum();
+                queue_depth.0.insert(key, depth);
+            }
+        }
+
         let ptr = self.state.nodes.as_ptr();
         let slice = unsafe {
             std::slice::from_raw_parts_mut(ptr as *mut *mut NodeState, self.state.nodes.len())
@@ -369,8 +1120,13 @@ fn run_post_frame(&mut self) -> Option<usize> {
 
         // Figure out how many frames to move forward.
         let first = unsafe { &*self.state.nodes[0] };
-        let msg = first.received.peek()?;
-        let time = msg.time.0;
+        let time = first
+            .received
+            .peek()
+            .map(|msg| msg.time.0)
+            .into_iter()
+            .chain(self.transfers.as_ref().and_then(Transfers::next_event))
+            .min()?;
 
         debug_assert!(time > self.now);
         Some(ceil_div(time - self.now, FRAME_DURATION.as_nanos()).max(1) as usize)
@@ -382,7 +1138,8 @@ fn start_threads(&mut self) {
         let num_workers = self.state.workers.len();
         for i in 0..num_workers {
             let state = self.state.clone();
-            std::thread::spawn(move || worker_loop(i, state));
+            let handle = std::thread::spawn(move || worker_loop(i, state));
+            self.workers.push(handle);
         }
     }
 
@@ -406,6 +1163,8 @@ fn worker_loop(worker_index: usize, state: Arc<SharedState>) {
     let worker_state = unsafe { &mut *state.workers[worker_index].get() };
 
     loop {
+        let spin_start = state.profile_workers.then(std::time::Instant::now);
+
         // Signal to everyone that we're ready to move to the next frame.
         state.ready_workers.fetch_add(1, Ordering::Relaxed);
 
@@ -416,18 +1175,50 @@ fn worker_loop(worker_index: usize, state: Arc<SharedState>) {
             break;
         }
 
-        loop {
-            let index = state.cursor.fetch_add(1, Ordering::Relaxed);
+        let busy_start = spin_start.map(|_| std::time::Instant::now());
 
-            if index >= state.nodes.len() {
-                break;
-            }
+        match state.scheduling {
+            Scheduling::WorkStealing => loop {
+                let index = state.cursor.fetch_add(1, Ordering::Relaxed);
 
-            if execute_node(&state, worker_state, current_frame - 1, index) {
-                break;
-            }
+                if index >= state.nodes.len() {
+                    break;
+                }
+
+                if execute_node(&state, worker_state, current_frame - 1, index) {
+                    break;
+                }
+
+                hook_node(std::ptr::null_mut());
+            },
+            Scheduling::Static => {
+                let num_workers = state.workers.len();
+                for (index, ptr) in state.nodes.iter().enumerate() {
+                    // Safety: Every pointer points into the array that starts at `first_node`.
+                    let node_id =
+                        unsafe { (*ptr as *const NodeState).offset_from(state.first_node) };
+                    if node_id as usize % num_workers != worker_index {
+                        continue;
+                    }
+
+                    if execute_node(&state, worker_state, current_frame - 1, index) {
+                        break;
+                    }
+
+                    hook_node(std::ptr::null_mut());
+                }
+            },
+        }
 
-            hook_node(std::ptr::null_mut());
+        if let (Some(spin_start), Some(busy_start)) = (spin_start, busy_start) {
+            let time = WorkerTime {
+                busy: busy_start.elapsed().as_nanos(),
+                spin: (busy_start - spin_start).as_nanos(),
+            };
+            let frame = current_frame - 1;
+            worker_state
+                .profile
+                .insert(frame.checked_div(state.frame_per_global_report), time);
         }
     }
 }
@@ -445,24 +1236,39 @@ fn execute_node(
     hook_node(ptr);
 
     // update the time on the node.
-    let is_stalled = with_node(|n| {
+    let (node_id, is_stalled, join_frame) = with_node(|n| {
         n.time = (frame as u128) * FRAME_DURATION.as_nanos();
-        n.is_stalled()
+        (n.node_id, n.is_stalled(), n.join_frame)
     });
 
     if is_stalled && frame > 0 {
         return true;
     }
 
-    let started = std::time::Instant::now();
-    if frame == 0 {
-        (state.executor)();
-    }
+    // A panic in the executor only takes down this node, the rest of the simulation goes on.
+    let started = (!state.deterministic_cpu).then(std::time::Instant::now);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        if frame == join_frame {
+            (state.executors[state.node_executor[node_id]])();
+        }
+        with_node(|n| n.run_until_stalled());
+    }));
 
     with_node(|n| {
-        n.run_until_stalled();
-        let elapsed = started.elapsed();
-        n.current_metrics.cpu_time += elapsed.as_nanos();
+        if let Err(payload) = result {
+            n.failed = true;
+            n.received.clear();
+            worker_state.metrics.failures.0.push(NodeFailure {
+                node: node_id,
+                frame,
+                message: panic_message(payload.as_ref()),
+            });
+        }
+
+        // The cpu time consumed through the api is already counted.
+        if let Some(started) = started {
+            n.current_metrics.cpu_time += started.elapsed().as_nanos();
+        }
 
         // Move the outgoing messages that this node generated to the worker's
         // outgoing message set.
@@ -470,7 +1276,7 @@ fn execute_node(
 
         // Push the metrics for this frame to the reporter and clear the data.
         n.metrics.insert(
-            frame.checked_div(state.frame_per_node_report),
+            frame.checked_div(state.frame_per_node_report[node_id]),
             n.current_metrics,
         );
 
@@ -485,6 +1291,17 @@ fn execute_node(
     false
 }
 
+/// Returns the message of a panic payload.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
 fn wait_for_next_frame(state: &Arc<SharedState>, current_frame: usize) -> Option<usize> {
     loop {
         let frame = state.frame.load(Ordering::Relaxed);
@@ -515,7 +1332,785 @@ fn wait_for_workers(state: &Arc<SharedState>) {
     }
 }
 
+/// Keeps the frames of a simulation in step with the wall clock.
+struct Pacing {
+    /// The wall clock time at which the pacing started.
+    start: Instant,
+    /// The frame at which the pacing started.
+    start_frame: usize,
+    factor: f64,
+}
+
+impl Pacing {
+    fn new(start_frame: usize, factor: f64) -> Self {
+        Self {
+            start: Instant::now(),
+            start_frame,
+            factor,
+        }
+    }
+
+    /// Block the current thread until the given frame is due.
+    fn wait_for(&self, frame: usize) {
+        let frames = frame.saturating_sub(self.start_frame) as f64;
+        let due = self.start + FRAME_DURATION.mul_f64(frames / self.factor);
+        let now = Instant::now();
+        if due > now {
+            std::thread::sleep(due - now);
+        }
+    }
+}
+
 #[inline(always)]
-fn ceil_div(a: u128, b: u128) -> u128 {
+pub(crate) fn ceil_div(a: u128, b: u128) -> u128 {
     (a + b - 1) / b
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{api, latency::ConstLatencyProvider};
+
+    const CLIENTS: usize = 3;
+    const SERVERS: usize = 20;
+
+    async fn run_client() {
+        let mut listener = api::listen(80);
+        while let Some(conn) = listener.accept().await {
+            api::spawn(async move {
+                let (mut reader, _writer) = conn.split();
+                while reader.recv::<u64>().await.is_some() {}
+            });
+        }
+    }
+
+    async fn run_server() {
+        let index = *api::RemoteAddr::whoami();
+        let addr = api::RemoteAddr::from_global_index(index % CLIENTS);
+        let mut conn = api::connect(addr, 80).await.expect("Could not connect.");
+        conn.write(&(index as u64));
+    }
+
+    #[test]
+    fn test_heterogeneous_executors() {
+        let report = SimulationBuilder::new(|| api::spawn(run_client()))
+            .with_executor_for(CLIENTS.., || api::spawn(run_server()))
+            .with_nodes(CLIENTS + SERVERS)
+            .with_workers(2)
+            .set_latency_provider(ConstLatencyProvider(Duration::from_millis(1)))
+            .run(Duration::from_secs(1));
+
+        // Every message takes exactly the constant latency.
+        assert!(!report.latency.is_empty());
+        assert_eq!(report.latency.p50(), Some(Duration::from_millis(1)));
+        assert_eq!(report.latency.p99(), Some(Duration::from_millis(1)));
+
+        for (index, node) in report.node.iter().enumerate() {
+            if index < CLIENTS {
+                assert_eq!(node.total.msg_sent, 0);
+                assert!(node.total.msg_received > 0);
+            } else {
+                assert_eq!(node.total.msg_sent, 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_record_comm_graph_star() {
+        // Every leaf sends a single message to the hub at node 0 and nothing else is sent.
+        let leaf = || {
+            api::spawn(async {
+                let index = *api::RemoteAddr::whoami();
+                let addr = api::RemoteAddr::from_global_index(0);
+                let mut conn = api::connect(addr, 80).await.expect("Could not connect.");
+                conn.write(&(index as u64));
+            })
+        };
+        let hub = || {
+            api::spawn(async {
+                let mut listener = api::listen(80);
+                while let Some(conn) = listener.accept().await {
+                    api::spawn(async move {
+                        let (mut reader, _writer) = conn.split();
+                        while reader.recv::<u64>().await.is_some() {}
+                    });
+                }
+            })
+        };
+
+        let leaves = 5;
+        let report = SimulationBuilder::new(leaf)
+            .with_executor_for(0..1, hub)
+            .with_nodes(leaves + 1)
+            .with_workers(2)
+            .record_comm_graph()
+            .set_latency_provider(ConstLatencyProvider(Duration::from_millis(1)))
+            .run(Duration::from_millis(100));
+
+        let graph = &report.comm_graph;
+        let edges = graph.edges();
+        assert_eq!(edges.len(), leaves);
+        for (i, (sender, receiver, edge)) in edges.into_iter().enumerate() {
+            assert_eq!((sender, receiver), (i + 1, 0));
+            assert_eq!(edge.count, 1);
+            assert_eq!(edge.bytes, report.node[sender].total.bytes_sent);
+        }
+        assert!(graph.edge(0, 1).is_none());
+
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph simulation {"));
+        assert_eq!(dot.matches("-> 0 ").count(), leaves);
+        assert_eq!(graph.to_adjacency_list().lines().count(), leaves);
+    }
+
+    #[test]
+    fn test_add_node_at() {
+        let server = || {
+            api::spawn(async {
+                let mut listener = api::listen(80);
+                while let Some(mut conn) = listener.accept().await {
+                    api::spawn(async move {
+                        while let Some(n) = conn.recv::<u64>().await {
+                            conn.write(&n);
+                        }
+                    });
+                }
+            })
+        };
+        let client = || {
+            api::spawn(async {
+                let addr = api::RemoteAddr::from_global_index(0);
+                let mut conn = api::connect(addr, 80).await.expect("Could not connect.");
+                api::emit("joined");
+                loop {
+                    conn.write(&0u64);
+                    conn.recv::<u64>().await.expect("Expected a response.");
+                    api::sleep(Duration::from_millis(100)).await;
+                }
+            })
+        };
+
+        // The added node is connected to before it is part of the network.
+        let early = || {
+            api::spawn(async {
+                let addr = api::RemoteAddr::from_global_index(2);
+                assert!(api::connect(addr, 80).await.is_err());
+                api::emit("refused");
+            })
+        };
+
+        let report = SimulationBuilder::new(client)
+            .with_executor_for(0..1, server)
+            .with_executor_for(1..2, early)
+            .with_nodes(2)
+            .add_node_at(Duration::from_secs(1), client)
+            .with_workers(2)
+            .set_latency_provider(ConstLatencyProvider(Duration::from_millis(1)))
+            .run(Duration::from_secs(2));
+
+        assert_eq!(report.node.len(), 3);
+        assert_eq!(report.log.emitted["refused"].len(), 1);
+
+        // The added node starts at T=1s and then keeps talking to the server.
+        let joined = &report.log.emitted["joined"];
+        assert_eq!(joined.len(), 1);
+        assert!(joined.keys().all(|time| *time >= 1000));
+        let metrics = &report.node[2];
+        assert!(metrics.total.msg_sent > 1);
+
+        // Nothing is recorded for the added node before it joins, not even the refused
+        // connection. With the default rate of 10ms per node report, T=1s is report 100.
+        assert!(!metrics.timeline.is_empty());
+        assert!(metrics.timeline.keys().all(|key| *key >= 100));
+        assert_eq!(metrics.total.connections_refused, 0);
+    }
+
+    /// Every node sends a single message to the next node after a delay and then stops.
+    fn exchange_once() {
+        api::spawn(async {
+            let mut listener = api::listen(80);
+            let conn = listener.accept().await.unwrap();
+            let (mut reader, _writer) = conn.split();
+            reader.recv::<u64>().await.unwrap();
+        });
+
+        api::spawn(async {
+            let index = *api::RemoteAddr::whoami();
+            let n = api::NodeArray::new().len();
+            api::sleep(Duration::from_millis(index as u64 * 10)).await;
+            let addr = api::RemoteAddr::from_global_index((index + 1) % n);
+            let mut conn = api::connect(addr, 80).await.expect("Could not connect.");
+            conn.write(&(index as u64));
+            api::emit("sent");
+        });
+    }
+
+    fn exchange_once_simulation() -> SimulationBuilder<ConstLatencyProvider> {
+        SimulationBuilder::new(exchange_once)
+            .with_nodes(8)
+            .with_workers(2)
+            .set_latency_provider(ConstLatencyProvider(Duration::from_millis(1)))
+    }
+
+    /// Remove the wall clock cpu time from the report so it can be compared.
+    fn without_cpu_time(mut report: Report) -> Report {
+        report.total.cpu_time = 0;
+        report.timeline.values_mut().for_each(|m| m.cpu_time = 0);
+        for node in report.node.iter_mut() {
+            node.total.cpu_time = 0;
+            node.timeline.values_mut().for_each(|m| m.cpu_time = 0);
+        }
+        report
+    }
+
+    #[test]
+    fn test_checkpoint_and_resume() {
+        let expected = exchange_once_simulation().run(Duration::from_secs(1));
+
+        let mut simulation = exchange_once_simulation().build();
+        simulation.run_for(Duration::from_millis(500));
+        let state = simulation.checkpoint();
+
+        // The same checkpoint can be resumed more than once.
+        for _ in 0..2 {
+            let report = SimulationBuilder::from_checkpoint(state.clone())
+                .with_workers(2)
+                .set_latency_provider(ConstLatencyProvider(Duration::from_millis(1)))
+                .run(Duration::from_millis(500));
+
+            assert_eq!(without_cpu_time(report), without_cpu_time(expected.clone()));
+        }
+    }
+
+    #[test]
+    fn test_published_state() {
+        struct Counter(usize);
+
+        let (_, published) = SimulationBuilder::new(|| {
+            api::spawn(async {
+                let index = *api::RemoteAddr::whoami();
+                for i in 1..=index {
+                    api::sleep(Duration::from_millis(1)).await;
+                    api::publish(Counter(i));
+                }
+            })
+        })
+        .with_nodes(5)
+        .with_workers(2)
+        .set_latency_provider(ConstLatencyProvider(Duration::from_millis(1)))
+        .run_with_state(Duration::from_millis(20));
+
+        assert_eq!(published.len(), 5);
+        assert!(published[0].get::<Counter>().is_none());
+        for (index, state) in published.iter().enumerate().skip(1) {
+            assert_eq!(state.get::<Counter>().unwrap().0, index);
+        }
+    }
+
+    #[test]
+    fn test_worker_profiling() {
+        let report = SimulationBuilder::new(|| {
+            api::spawn(async {
+                let heavy = *api::RemoteAddr::whoami() == 0;
+                for _ in 0..10 {
+                    if heavy {
+                        std::thread::sleep(Duration::from_millis(2));
+                    }
+                    api::sleep(Duration::from_millis(1)).await;
+                }
+            })
+        })
+        .with_nodes(2)
+        .with_workers(2)
+        .enable_worker_profiling()
+        .set_global_metrics_rate(FRAME_DURATION)
+        .set_latency_provider(ConstLatencyProvider(Duration::from_millis(1)))
+        .run(Duration::from_millis(20));
+
+        assert_eq!(report.workers.len(), 2);
+
+        // On the frames where the heavy node runs, whichever worker picks it up is busy while
+        // the other one spins.
+        let heavy = Duration::from_millis(2).as_nanos();
+        let imbalanced_frames = report.workers[0]
+            .timeline
+            .iter()
+            .filter_map(|(frame, a)| Some((a, report.workers[1].timeline.get(frame)?)))
+            .filter(|(a, b)| {
+                let (max, min) = (a.busy.max(b.busy), a.busy.min(b.busy));
+                max >= heavy && min < heavy / 2
+            })
+            .count();
+        assert!(imbalanced_frames > 0);
+        assert!(report.workers.iter().any(|w| w.total.spin > 0));
+    }
+
+    #[test]
+    fn test_queue_depth_sampling() {
+        // The producer sends two messages every millisecond while the consumer only reads one.
+        let producer = || {
+            api::spawn(async {
+                let addr = api::RemoteAddr::from_global_index(1);
+                let mut conn = api::connect(addr, 80).await.expect("Could not connect.");
+                loop {
+                    conn.write(&0u64);
+                    conn.write(&1u64);
+                    api::sleep(Duration::from_millis(1)).await;
+                }
+            })
+        };
+        let consumer = || {
+            api::spawn(async {
+                let mut listener = api::listen(80);
+                let mut conn = listener.accept().await.expect("Could not accept.");
+                loop {
+                    api::sleep(Duration::from_millis(1)).await;
+                    if conn.recv::<u64>().await.is_none() {
+                        break;
+                    }
+                }
+            })
+        };
+
+        let report = SimulationBuilder::new(producer)
+            .with_executor_for(1..2, consumer)
+            .with_nodes(2)
+            .with_workers(1)
+            .enable_queue_depth_sampling()
+            .set_latency_provider(ConstLatencyProvider(Duration::from_millis(1)))
+            .run(Duration::from_millis(50));
+
+        let mut samples = report.queue_depth.iter().collect::<Vec<_>>();
+        samples.sort();
+        assert!(samples.len() > 10);
+        assert!(samples.windows(2).all(|w| w[0].1 <= w[1].1));
+        assert!(samples.last().unwrap().1 > samples.first().unwrap().1 + 10);
+    }
+
+    #[test]
+    fn test_scheduled_stimulus() {
+        let report = SimulationBuilder::new(|| {
+            api::spawn(async {
+                let event = api::next_stimulus().await;
+                let value = event.downcast::<u32>().expect("Unexpected stimulus type.");
+                assert_eq!(*value, 42);
+                assert_eq!(*api::RemoteAddr::whoami(), 3);
+                api::emit("stimulus");
+            })
+        })
+        .with_nodes(8)
+        .with_workers(2)
+        .set_latency_provider(ConstLatencyProvider(Duration::from_millis(1)))
+        .schedule(Duration::from_secs(2), 3, Box::new(42u32))
+        .run(Duration::from_secs(3));
+
+        // Only the target node observes the stimulus, on the frame of T=2s.
+        let emitted = &report.log.emitted["stimulus"];
+        assert_eq!(emitted.len(), 1);
+        assert_eq!(emitted[&2000], 1);
+    }
+
+    #[test]
+    fn test_message_priority() {
+        let sender = |priority: u8| {
+            move || {
+                api::spawn(async move {
+                    let addr = api::RemoteAddr::from_global_index(0);
+                    let mut conn = api::connect(addr, 80).await.expect("Could not connect.");
+                    // Both senders write when the stimulus arrives, at the same time.
+                    api::next_stimulus().await;
+                    conn.write_with_priority(&(priority as u64), priority);
+                })
+            }
+        };
+        let receiver = || {
+            api::spawn(async {
+                let mut listener = api::listen(80);
+                let received = Rc::new(RefCell::new(Vec::new()));
+                for _ in 0..2 {
+                    let mut conn = listener.accept().await.expect("Could not accept.");
+                    let received = received.clone();
+                    api::spawn(async move {
+                        let value = conn.recv::<u64>().await.expect("Could not receive.");
+                        let mut received = received.borrow_mut();
+                        received.push(value);
+                        if *received == [1, 0] {
+                            api::emit("high-first");
+                        }
+                    });
+                }
+            })
+        };
+
+        // Both messages arrive at the same time. Without the priority the one of the sender with
+        // the higher index would be delivered first.
+        let report = SimulationBuilder::new(receiver)
+            .with_executor_for(1..2, sender(1))
+            .with_executor_for(2..3, sender(0))
+            .with_nodes(3)
+            .with_workers(1)
+            .set_latency_provider(ConstLatencyProvider(Duration::from_millis(1)))
+            .schedule(Duration::from_millis(5), 1, Box::new(()))
+            .schedule(Duration::from_millis(5), 2, Box::new(()))
+            .run(Duration::from_millis(10));
+
+        assert_eq!(report.log.emitted["high-first"].values().sum::<u32>(), 1);
+    }
+
+    #[test]
+    fn test_connection_setup_cost() {
+        let sender = || {
+            api::spawn(async {
+                let addr = api::RemoteAddr::from_global_index(1);
+                let mut conn = api::connect(addr, 80).await.expect("Could not connect.");
+                conn.write(&0u64);
+                api::sleep(Duration::from_millis(10)).await;
+                conn.write(&1u64);
+            })
+        };
+        let receiver = || {
+            api::spawn(async {
+                let mut listener = api::listen(80);
+                let mut conn = listener.accept().await.expect("Could not accept.");
+                while conn.recv::<u64>().await.is_some() {}
+            })
+        };
+
+        let report = SimulationBuilder::new(sender)
+            .with_executor_for(1..2, receiver)
+            .with_nodes(2)
+            .with_workers(1)
+            .set_latency_provider(ConstLatencyProvider(Duration::from_millis(1)))
+            .set_connection_setup_cost(ConnectionSetupCost::RoundTrips(1))
+            .run(Duration::from_millis(50));
+
+        // Only the first contact pays for the round trip of the setup on top of the latency,
+        // everything after that flows at the link latency.
+        assert_eq!(report.latency.max(), Some(Duration::from_millis(3)));
+        assert_eq!(report.latency.min(), Some(Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn test_executor_panic_is_reported() {
+        let report = SimulationBuilder::new(|| {
+            api::spawn(async {
+                api::sleep(Duration::from_millis(5)).await;
+                if *api::RemoteAddr::whoami() == 1 {
+                    panic!("node 1 gave up");
+                }
+                api::sleep(Duration::from_millis(5)).await;
+                api::emit("done");
+            })
+        })
+        .with_nodes(4)
+        .with_workers(2)
+        .set_latency_provider(ConstLatencyProvider(Duration::from_millis(1)))
+        .run(Duration::from_millis(20));
+
+        // Only the panicking node fails, the others run to completion.
+        assert_eq!(report.failures.len(), 1);
+        let failure = &report.failures[0];
+        assert_eq!(failure.node, 1);
+        assert_eq!(failure.frame, 5 * FRAME_TO_MS as usize);
+        assert_eq!(failure.message, "node 1 gave up");
+        assert_eq!(report.log.emitted["done"][&10], 3);
+    }
+
+    #[test]
+    fn test_capture_messages() {
+        let client = || {
+            api::spawn(async {
+                let addr = api::RemoteAddr::from_global_index(1);
+                let mut conn = api::connect(addr, 80).await.expect("Could not connect.");
+                conn.write(&7u64);
+                conn.recv::<u64>().await;
+            })
+        };
+        let server = || {
+            api::spawn(async {
+                let mut listener = api::listen(80);
+                let mut conn = listener.accept().await.expect("Could not accept.");
+                let request = conn.recv::<u64>().await.expect("Expected a request.");
+                conn.write(&(request + 1));
+            })
+        };
+
+        let report = SimulationBuilder::new(|| {})
+            .with_executor_for(0..1, client)
+            .with_executor_for(1..2, server)
+            .with_nodes(3)
+            .with_workers(1)
+            .capture_messages()
+            .set_latency_provider(ConstLatencyProvider(Duration::from_millis(1)))
+            .run(Duration::from_millis(10));
+
+        let mut frames = report.messages.keys().copied().collect::<Vec<_>>();
+        frames.sort();
+        assert_eq!(frames.len(), 2);
+
+        // The request goes out first and is answered by exactly one response on the frame
+        // in which it is received.
+        let request = report.messages.sent_at(frames[0]);
+        assert_eq!(request.len(), 1);
+        assert_eq!((request[0].sender, request[0].receiver), (0, 1));
+
+        let response = report.messages.sent_at(frames[1]);
+        assert_eq!(response.len(), 1);
+        assert_eq!((response[0].sender, response[0].receiver), (1, 0));
+        assert_eq!(response[0].size, request[0].size);
+    }
+
+    #[test]
+    #[should_panic(expected = "is not at a stall boundary")]
+    fn test_checkpoint_requires_stall_boundary() {
+        let mut simulation = exchange_once_simulation().build();
+        simulation.run_for(Duration::from_millis(5));
+        simulation.checkpoint();
+    }
+
+    #[test]
+    fn test_realtime_factor() {
+        let duration = Duration::from_millis(100);
+        let factor = 2.0;
+
+        let started = Instant::now();
+        SimulationBuilder::new(|| {})
+            .with_nodes(2)
+            .with_workers(1)
+            .with_realtime_factor(factor)
+            .run(duration);
+
+        assert!(started.elapsed() >= duration.div_f64(factor));
+    }
+
+    #[test]
+    fn test_step() {
+        let mut simulation = SimulationBuilder::new(|| {
+            api::spawn(async {
+                loop {
+                    let event = api::next_stimulus().await;
+                    let name = event.downcast::<&str>().expect("Unexpected stimulus type.");
+                    api::emit(*name);
+                }
+            })
+        })
+        .with_nodes(1)
+        .with_workers(1)
+        .schedule(Duration::from_millis(2), 0, Box::new("first"))
+        .schedule(Duration::from_millis(5), 0, Box::new("second"))
+        .build();
+
+        // The executor is started on frame zero, before any event.
+        assert_eq!(simulation.step(), StepResult::Executed(0));
+        assert!(simulation.nodes[0].emitted.is_empty());
+
+        // The frames without events are skipped.
+        let frame = 2 * FRAME_TO_MS as usize;
+        assert_eq!(simulation.step(), StepResult::Executed(frame));
+        assert_eq!(simulation.nodes[0].emitted["first"], 2_000_000);
+        assert!(!simulation.nodes[0].emitted.contains_key("second"));
+
+        let frame = 5 * FRAME_TO_MS as usize;
+        assert_eq!(simulation.step(), StepResult::Executed(frame));
+        assert_eq!(simulation.nodes[0].emitted["second"], 5_000_000);
+
+        // Nothing is left to process.
+        assert_eq!(simulation.step(), StepResult::Idle);
+        assert_eq!(simulation.step(), StepResult::Idle);
+    }
+
+    #[test]
+    fn test_deterministic_cpu() {
+        let run = || {
+            SimulationBuilder::new(|| {
+                api::spawn(async {
+                    let index = *api::RemoteAddr::whoami() as u64;
+                    loop {
+                        // Some real work that should not show up in the metrics.
+                        let _ = (0..1000).fold(index, |acc, i| acc.wrapping_mul(31) ^ i);
+                        api::consume_cpu(Duration::from_micros(10 * (index + 1)));
+                        api::sleep(Duration::from_millis(1)).await;
+                    }
+                })
+            })
+            .with_nodes(4)
+            .with_workers(2)
+            .deterministic_cpu()
+            .run(Duration::from_millis(50))
+        };
+
+        let first = run();
+        let second = run();
+        assert_eq!(first, second);
+
+        // Only the consumed time is counted, so the node that consumes more has a higher total.
+        assert!(first.node[0].total.cpu_time > 0);
+        assert!(first.node[3].total.cpu_time > first.node[0].total.cpu_time);
+    }
+
+    #[test]
+    fn test_node_metrics_rate_for() {
+        let report = SimulationBuilder::new(|| {
+            api::spawn(async {
+                loop {
+                    api::sleep(Duration::from_millis(1)).await;
+                }
+            })
+        })
+        .with_nodes(4)
+        .with_workers(2)
+        .set_node_metrics_rate(Duration::from_millis(10))
+        .set_node_metrics_rate_for(&[0], Duration::from_millis(1))
+        .run(Duration::from_millis(100));
+
+        let fine = report.node[0].timeline.len();
+        let coarse = report.node[1].timeline.len();
+        assert!(fine > coarse, "{fine} buckets should exceed {coarse}");
+        assert_eq!(coarse, report.node[3].timeline.len());
+    }
+
+    /// Returns the millisecond at which each of the receivers got a large message that the first
+    /// node sent to all of them at once.
+    fn simultaneous_transfers(receivers: usize, contention: Contention) -> Vec<u128> {
+        const SIZE: usize = 1 << 20;
+
+        let sender = move || {
+            api::spawn(async move {
+                let mut conns = Vec::new();
+                for index in 1..=receivers {
+                    let addr = api::RemoteAddr::from_global_index(index);
+                    conns.push(api::connect(addr, 80).await.expect("Could not connect."));
+                }
+                for conn in &mut conns {
+                    conn.write(&vec![0u8; SIZE]);
+                }
+                // Keep the connections open until the transfers are done.
+                api::sleep(Duration::from_secs(10)).await;
+            })
+        };
+        let receiver = || {
+            api::spawn(async {
+                let mut listener = api::listen(80);
+                let mut conn = listener.accept().await.expect("Could not accept.");
+                if conn.recv::<Vec<u8>>().await.is_some() {
+                    api::emit("received");
+                }
+            })
+        };
+
+        let report = SimulationBuilder::new(sender)
+            .with_executor_for(1.., receiver)
+            .with_nodes(1 + receivers)
+            .with_workers(1)
+            .set_latency_provider(ConstLatencyProvider(Duration::from_millis(1)))
+            .set_bandwidth(Bandwidth {
+                bytes_per_second: SIZE as u64,
+                contention,
+            })
+            .run(Duration::from_secs(5));
+
+        report.log.emitted["received"]
+            .iter()
+            .flat_map(|(ms, count)| std::iter::repeat(*ms).take(*count as usize))
+            .collect()
+    }
+
+    #[test]
+    fn test_bandwidth_contention() {
+        // The connections are set up after a round trip, then it takes about a second to send
+        // the message at the bandwidth.
+        let alone = simultaneous_transfers(1, Contention::PerNodeEgress);
+        assert_eq!(alone.len(), 1);
+        let alone = alone[0] - 2;
+        assert!((1000..1010).contains(&alone));
+
+        // Two transfers from the same node share its egress, so each takes twice as long.
+        let shared = simultaneous_transfers(2, Contention::PerNodeEgress);
+        assert_eq!(shared.len(), 2);
+        for ms in shared {
+            let ratio = (ms - 2) as f64 / alone as f64;
+            assert!((1.9..2.1).contains(&ratio), "ratio {ratio}");
+        }
+
+        // Transfers to different receivers are on different links.
+        let links = simultaneous_transfers(2, Contention::PerLink);
+        assert_eq!(links, vec![alone + 2; 2]);
+    }
+
+    #[test]
+    fn test_static_scheduling() {
+        use std::{collections::HashSet, sync::Mutex, thread::ThreadId};
+
+        // The thread every node ran on, every time it ran.
+        static RUNS: Mutex<Vec<(usize, ThreadId)>> = Mutex::new(Vec::new());
+
+        const NODES: usize = 12;
+        const WORKERS: usize = 4;
+
+        SimulationBuilder::new(|| {
+            api::spawn(async {
+                let index = *api::RemoteAddr::whoami();
+                for _ in 0..10 {
+                    RUNS.lock()
+                        .unwrap()
+                        .push((index, std::thread::current().id()));
+                    api::sleep(Duration::from_millis(1)).await;
+                }
+            })
+        })
+        .with_nodes(NODES)
+        .with_workers(WORKERS)
+        .set_scheduling(Scheduling::Static)
+        .set_latency_provider(ConstLatencyProvider(Duration::from_millis(1)))
+        .build()
+        .run(Duration::from_millis(20));
+
+        let runs = RUNS.lock().unwrap();
+        assert_eq!(runs.len(), NODES * 10);
+
+        // Every node always ran on the same worker.
+        let mut node_worker = FxHashMap::default();
+        for (node, thread) in runs.iter() {
+            assert_eq!(*node_worker.entry(*node).or_insert(*thread), *thread);
+        }
+        assert_eq!(node_worker.len(), NODES);
+
+        // Which is the worker at the index of the node modulo the number of workers.
+        for (node, thread) in &node_worker {
+            assert_eq!(node_worker[&(node % WORKERS)], *thread);
+        }
+        assert_eq!(node_worker.values().collect::<HashSet<_>>().len(), WORKERS);
+    }
+
+    /// The latency of a message grows with the position of its sender.
+    #[derive(Default)]
+    struct SenderLatencyProvider;
+
+    impl LatencyProvider for SenderLatencyProvider {
+        fn get(&mut self, a: usize, _b: usize) -> Duration {
+            Duration::from_millis(1 + a as u64)
+        }
+    }
+
+    #[test]
+    fn test_shuffle_nodes() {
+        let run = |seed: Option<u64>| {
+            let mut builder = SimulationBuilder::new(exchange_once)
+                .with_nodes(8)
+                .with_workers(2)
+                .set_node_metrics_rate(Duration::from_millis(1))
+                .set_latency_provider(SenderLatencyProvider);
+            if let Some(seed) = seed {
+                builder = builder.shuffle_nodes(seed);
+            }
+            without_cpu_time(builder.run(Duration::from_secs(1)))
+        };
+
+        let unshuffled = run(None);
+        let shuffled = run(Some(7));
+        assert_eq!(shuffled, run(Some(7)));
+        assert_ne!(shuffled, unshuffled);
+    }
+}
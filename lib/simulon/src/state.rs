@@ -1,4 +1,5 @@
 use std::{
+    any::Any,
     cell::RefCell,
     collections::{BinaryHeap, HashMap, VecDeque},
     sync::Arc,
@@ -13,6 +14,7 @@
     message::{Ignored, Message, MessageDetail},
     report::{Metrics, NodeMetrics},
     storage::TypedStorage,
+    FRAME_DURATION,
 };
 
 thread_local! {
@@ -71,6 +73,20 @@ pub struct NodeState {
     pub storage: Arc<TypedStorage>,
     /// The already emitted events.
     pub emitted: FxHashMap<String, u128>,
+    /// The state published by this node.
+    pub published: TypedStorage,
+    /// Number of spawned tasks that have not completed yet.
+    pub tasks: usize,
+    /// The stimuli which were delivered but are not yet consumed by the executor.
+    pub stimuli: VecDeque<Box<dyn Any + Send>>,
+    /// The current ongoing `next_stimulus` future.
+    pub stimulus: Option<DeferredFutureWaker<Box<dyn Any + Send>>>,
+    /// Whether the executor of this node panicked. A failed node is not executed anymore and the
+    /// messages sent to it are dropped.
+    pub failed: bool,
+    /// The frame at which the executor of this node is started. Nodes that are part of the
+    /// simulation from the start join at frame zero.
+    pub join_frame: usize,
     next_rid: usize,
     _clean_up: WithCleanUpDrop,
 }
@@ -119,6 +135,36 @@ pub struct AcceptResponse {
 #[derive(Debug, Clone, Copy, Hash, PartialEq, PartialOrd, Ord, Eq)]
 pub struct ResourceId(pub(crate) usize);
 
+/// The state of a node captured at a stall boundary. See [`NodeState::checkpoint`].
+pub struct NodeCheckpoint {
+    time: u128,
+    received: Vec<Message>,
+    metrics: NodeMetrics,
+    emitted: FxHashMap<String, u128>,
+    join_frame: usize,
+    next_rid: usize,
+}
+
+impl Clone for NodeCheckpoint {
+    fn clone(&self) -> Self {
+        Self {
+            time: self.time,
+            received: self
+                .received
+                .iter()
+                .map(|msg| {
+                    msg.try_clone()
+                        .expect("Checkpoint can not contain wake ups.")
+                })
+                .collect(),
+            metrics: self.metrics.clone(),
+            emitted: self.emitted.clone(),
+            join_frame: self.join_frame,
+            next_rid: self.next_rid,
+        }
+    }
+}
+
 pub enum Resource {
     PendingConnection {
         waker: DeferredFutureWaker<Result<ResourceId, ConnectError>>,
@@ -131,6 +177,20 @@ pub enum Resource {
 }
 
 impl NodeState {
+    /// Returns the number of messages pending on this node, which are the messages that are not
+    /// delivered yet and the data delivered to a connection that the executor has not read.
+    pub fn pending_messages(&self) -> usize {
+        let queued: usize = self
+            .resources
+            .values()
+            .map(|resource| match resource {
+                Resource::PendingConnection { queue, .. }
+                | Resource::EstablishedConnection { queue, .. } => queue.len(),
+            })
+            .sum();
+        self.received.len() + queued
+    }
+
     /// Create the empty state of a node.
     pub fn new(storage: Arc<TypedStorage>, count_nodes: usize, node_id: usize) -> Self {
         Self {
@@ -146,6 +206,12 @@ pub fn new(storage: Arc<TypedStorage>, count_nodes: usize, node_id: usize) -> Se
             current_metrics: Metrics::default(),
             storage,
             emitted: FxHashMap::default(),
+            published: TypedStorage::default(),
+            tasks: 0,
+            stimuli: VecDeque::new(),
+            stimulus: None,
+            failed: false,
+            join_frame: 0,
             next_rid: 0,
             _clean_up: WithCleanUpDrop,
         }
@@ -166,6 +232,12 @@ pub fn now(&mut self) -> u128 {
         now
     }
 
+    /// Account for the given cpu time spent by the node, advancing its clock.
+    pub fn consume_cpu(&mut self, duration: u128) {
+        self.time += duration;
+        self.current_metrics.cpu_time += duration;
+    }
+
     /// Send a request to establish a connection with the given peer on the provided port number.
     ///
     /// Returns a future that will be resolved when the connection is established.
@@ -185,6 +257,7 @@ pub fn connect(
             sender: RemoteAddr(self.node_id),
             receiver: remote,
             time: std::cmp::Reverse(self.now()),
+            priority: 0,
             detail: MessageDetail::Connect { port, rid },
         };
 
@@ -237,7 +310,7 @@ pub fn accept(&mut self, port: u16) -> DeferredFuture<Option<AcceptResponse>> {
         }
     }
 
-    pub fn send(&mut self, remote: RemoteAddr, rid: ResourceId, data: Vec<u8>) {
+    pub fn send(&mut self, remote: RemoteAddr, rid: ResourceId, data: Vec<u8>, priority: u8) {
         self.current_metrics.msg_sent += 1;
         self.current_metrics.bytes_sent += data.len() as u64;
 
@@ -245,6 +318,7 @@ pub fn send(&mut self, remote: RemoteAddr, rid: ResourceId, data: Vec<u8>) {
             sender: RemoteAddr(self.node_id),
             receiver: remote,
             time: std::cmp::Reverse(self.now()),
+            priority,
             detail: MessageDetail::Data {
                 receiver_rid: rid,
                 data,
@@ -285,6 +359,7 @@ pub fn close_connection(&mut self, local_rid: ResourceId, addr: RemoteAddr, rid:
             sender: RemoteAddr(self.node_id),
             receiver: addr,
             time: std::cmp::Reverse(self.now()),
+            priority: 0,
             detail: MessageDetail::ConnectionClosed { receiver_rid: rid },
         };
 
@@ -303,6 +378,7 @@ pub fn sleep(&mut self, duration: u128) -> DeferredFuture<()> {
             sender: RemoteAddr(self.node_id),
             receiver: RemoteAddr(self.node_id),
             time: std::cmp::Reverse(self.now() + duration),
+            priority: 0,
             detail: MessageDetail::WakeUp {
                 waker: Ignored(waker),
             },
@@ -312,6 +388,53 @@ pub fn sleep(&mut self, duration: u128) -> DeferredFuture<()> {
         future
     }
 
+    /// Make this node join the simulation at the given frame instead of at the start. The node
+    /// does not run its executor and refuses connections until then.
+    pub fn join_at(&mut self, frame: usize) {
+        self.join_frame = frame;
+        let message = Message {
+            sender: RemoteAddr(self.node_id),
+            receiver: RemoteAddr(self.node_id),
+            time: std::cmp::Reverse(frame as u128 * FRAME_DURATION.as_nanos()),
+            priority: 0,
+            detail: MessageDetail::Join,
+        };
+        self.received.push(message);
+    }
+
+    /// Returns true if this node has joined the simulation.
+    pub fn has_joined(&self) -> bool {
+        self.time >= self.join_frame as u128 * FRAME_DURATION.as_nanos()
+    }
+
+    /// Schedule the stimulus to be delivered to this node at the given time.
+    pub fn schedule_stimulus(&mut self, time: u128, event: Box<dyn Any + Send>) {
+        let message = Message {
+            sender: RemoteAddr(self.node_id),
+            receiver: RemoteAddr(self.node_id),
+            time: std::cmp::Reverse(time),
+            priority: 0,
+            detail: MessageDetail::Stimulus {
+                event: Ignored(event),
+            },
+        };
+        self.received.push(message);
+    }
+
+    pub fn next_stimulus(&mut self) -> DeferredFuture<Box<dyn Any + Send>> {
+        if let Some(event) = self.stimuli.pop_front() {
+            DeferredFuture::resolved(event)
+        } else {
+            assert!(
+                self.stimulus.is_none(),
+                "Another next_stimulus call is still on-going."
+            );
+            let future = DeferredFuture::new();
+            self.stimulus = Some(future.waker());
+            future
+        }
+    }
+
     pub fn emit(&mut self, key: String) {
         assert!(
             self.emitted.insert(key, self.time).is_none(),
@@ -374,6 +497,7 @@ fn accepted(&mut self, addr: RemoteAddr, remote_rid: ResourceId) -> AcceptRespon
             sender: RemoteAddr(self.node_id),
             receiver: addr,
             time: std::cmp::Reverse(self.now()),
+            priority: 0,
             detail: MessageDetail::ConnectionAccepted {
                 sender_rid: rid,
                 receiver_rid: remote_rid,
@@ -395,6 +519,7 @@ fn refuse_connection(&mut self, addr: RemoteAddr, remote_rid: ResourceId) {
             sender: RemoteAddr(self.node_id),
             receiver: addr,
             time: std::cmp::Reverse(self.now()),
+            priority: 0,
             detail: MessageDetail::ConnectionRefused {
                 receiver_rid: remote_rid,
             },
@@ -404,6 +529,11 @@ fn refuse_connection(&mut self, addr: RemoteAddr, remote_rid: ResourceId) {
     }
 
     fn maybe_accept_new_connection(&mut self, port: u16, addr: RemoteAddr, rid: ResourceId) {
+        // A node that has not joined yet is not part of the network, so it has no metrics.
+        if !self.has_joined() {
+            return self.refuse_connection(addr, rid);
+        }
+
         let listener_state = if let Some(s) = self.listening.get_mut(&port) {
             s
         } else {
@@ -442,44 +572,112 @@ fn resolve_connection(
         }
     }
 
+    /// Capture the state of this node so it can be restored later using [`NodeState::restore`].
+    ///
+    /// # Panics
+    ///
+    /// If the node is not at a stall boundary, which means it still has pending tasks, open
+    /// connections or active listeners. The state of the futures driving these can not be
+    /// captured. Stimuli that are scheduled or not yet consumed and published state can not be
+    /// captured either.
+    pub fn checkpoint(&self) -> NodeCheckpoint {
+        assert!(
+            self.tasks == 0 && self.resources.is_empty() && self.listening.is_empty(),
+            "Node {} is not at a stall boundary.",
+            self.node_id
+        );
+        assert!(
+            self.stimuli.is_empty()
+                && !self
+                    .received
+                    .iter()
+                    .any(|msg| matches!(msg.detail, MessageDetail::Stimulus { .. })),
+            "Node {} has pending stimuli.",
+            self.node_id
+        );
+        assert!(
+            self.published.is_empty(),
+            "Node {} has published state.",
+            self.node_id
+        );
+
+        NodeCheckpoint {
+            time: self.time,
+            // Wake ups with no pending task have nothing to wake.
+            received: self
+                .received
+                .iter()
+                .filter_map(Message::try_clone)
+                .collect(),
+            metrics: self.metrics.clone(),
+            emitted: self.emitted.clone(),
+            join_frame: self.join_frame,
+            next_rid: self.next_rid,
+        }
+    }
+
+    /// Restore the state of this node from a checkpoint.
+    pub fn restore(&mut self, checkpoint: NodeCheckpoint) {
+        self.time = checkpoint.time;
+        self.received = checkpoint.received.into();
+        self.metrics = checkpoint.metrics;
+        self.emitted = checkpoint.emitted;
+        self.join_frame = checkpoint.join_frame;
+        self.next_rid = checkpoint.next_rid;
+    }
+
     pub fn is_stalled(&self) -> bool {
         !matches!(self.received.peek(), Some(msg) if msg.time.0 <= self.time)
     }
 
     pub fn run_until_stalled(&mut self) {
+        // The messages are delivered in the order of their arrival time, the ones that arrive at
+        // the same time are delivered by their priority. See the ordering of `Message`.
         while !self.is_stalled() {
             let msg = self.received.pop().unwrap();
-
-            // eprintln!("\t>current {}: {:?}", self.node_id, msg);
-
-            match msg.detail {
-                MessageDetail::Connect { port, rid } => {
-                    self.maybe_accept_new_connection(port, msg.sender, rid);
-                },
-                MessageDetail::ConnectionAccepted {
-                    sender_rid,
-                    receiver_rid,
-                } => {
-                    self.resolve_connection(receiver_rid, Ok(sender_rid));
-                },
-                MessageDetail::ConnectionRefused { receiver_rid } => {
-                    self.current_metrics.connections_failed += 1;
-                    self.resolve_connection(receiver_rid, Err(ConnectError::RemoteIsDown));
-                },
-                MessageDetail::ConnectionClosed { receiver_rid: rid } => {
-                    self.close_local_connection(rid);
-                },
-                MessageDetail::Data { receiver_rid, data } => {
-                    self.current_metrics.msg_received += 1;
-                    self.current_metrics.bytes_received += data.len() as u64;
-                    self.process_message(receiver_rid, data);
-                },
-                MessageDetail::WakeUp { waker } => {
-                    waker.wake(());
-                },
-            }
+            self.deliver(msg);
         }
 
         self.spawn_pool.run_until_stalled();
     }
+
+    fn deliver(&mut self, msg: Message) {
+        // eprintln!("\t>current {}: {:?}", self.node_id, msg);
+
+        match msg.detail {
+            MessageDetail::Connect { port, rid } => {
+                self.maybe_accept_new_connection(port, msg.sender, rid);
+            },
+            MessageDetail::ConnectionAccepted {
+                sender_rid,
+                receiver_rid,
+            } => {
+                self.resolve_connection(receiver_rid, Ok(sender_rid));
+            },
+            MessageDetail::ConnectionRefused { receiver_rid } => {
+                self.current_metrics.connections_failed += 1;
+                self.resolve_connection(receiver_rid, Err(ConnectError::RemoteIsDown));
+            },
+            MessageDetail::ConnectionClosed { receiver_rid: rid } => {
+                self.close_local_connection(rid);
+            },
+            MessageDetail::Data { receiver_rid, data } => {
+                self.current_metrics.msg_received += 1;
+                self.current_metrics.bytes_received += data.len() as u64;
+                self.process_message(receiver_rid, data);
+            },
+            MessageDetail::WakeUp { waker } => {
+                waker.wake(());
+            },
+            MessageDetail::Stimulus { event } => {
+                if let Some(waker) = self.stimulus.take() {
+                    waker.wake(event.0);
+                } else {
+                    self.stimuli.push_back(event.0);
+                }
+            },
+            // The executor is started before the due messages are delivered.
+            MessageDetail::Join => {},
+        }
+    }
 }
@@ -8,6 +8,11 @@ pub struct TypedStorage {
 }
 
 impl TypedStorage {
+    /// Returns true if there are no values in the storage.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
     pub fn insert<T: Any>(&mut self, value: T) {
         let tid = TypeId::of::<T>();
         let item = Box::new(value);
@@ -19,6 +24,16 @@ pub fn insert<T: Any>(&mut self, value: T) {
         }
     }
 
+    /// Insert the value, replacing any previous value with the same type.
+    pub fn set<T: Any>(&mut self, value: T) {
+        self.data.insert(TypeId::of::<T>(), Box::new(value));
+    }
+
+    /// Returns a reference to the value with type `T` if there is one.
+    pub fn get<T: Any>(&self) -> Option<&T> {
+        self.data.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+
     pub fn with<F, T: Any, U>(&self, closure: F) -> U
     where
         F: FnOnce(&T) -> U,
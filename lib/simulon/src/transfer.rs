@@ -0,0 +1,203 @@
+use std::collections::VecDeque;
+
+use fxhash::FxHashMap;
+
+use crate::{
+    message::{Message, MessageDetail},
+    simulation::{ceil_div, Bandwidth, Contention},
+};
+
+/// The transfers in flight over the links of the simulation, when the bandwidth is modeled.
+///
+/// Transfers that contend for the same bandwidth are kept in a group, and the bandwidth of the
+/// group is shared fairly between its flows. A flow is one connection, its messages are sent one
+/// after the other so they arrive in order, and only the message at the front of each flow is
+/// using the bandwidth.
+pub struct Transfers {
+    bandwidth: Bandwidth,
+    groups: FxHashMap<(usize, usize), Group>,
+}
+
+/// A message waiting for or in the middle of its transfer.
+pub struct Transfer {
+    /// The time at which the message was sent.
+    pub sent: u128,
+    /// The time at which the message can start going over the link.
+    start: u128,
+    /// The latency of the link, paid once the message is transferred.
+    pub latency: u128,
+    /// The amount of data left to transfer, in bytes times 10^9 so the progress made at a
+    /// bandwidth in bytes per second over a number of nanoseconds is an integer.
+    remaining: u128,
+    pub msg: Message,
+}
+
+#[derive(Default)]
+struct Group {
+    /// The time up to which the transfers of this group have progressed.
+    time: u128,
+    flows: Vec<Flow>,
+}
+
+struct Flow {
+    /// The receiver and the resource id of the connection on the receiver.
+    key: (usize, usize),
+    queue: VecDeque<Transfer>,
+}
+
+impl Transfers {
+    pub fn new(bandwidth: Bandwidth) -> Self {
+        assert!(
+            bandwidth.bytes_per_second > 0,
+            "Bandwidth must be non-zero."
+        );
+        Self {
+            bandwidth,
+            groups: FxHashMap::default(),
+        }
+    }
+
+    /// Returns true if the message takes up bandwidth, and should be passed to [`Self::push`].
+    pub fn is_transfer(msg: &Message) -> bool {
+        // Closing the connection goes through the flow as well so it does not overtake the data.
+        matches!(
+            msg.detail,
+            MessageDetail::Data { .. } | MessageDetail::ConnectionClosed { .. }
+        )
+    }
+
+    /// Returns true if there is no transfer in flight.
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Queue the message on its flow. The transfer starts at `start`, which must not be before
+    /// the time the transfers were last advanced to.
+    pub fn push(&mut self, start: u128, latency: u128, msg: Message) {
+        let (size, rid) = match &msg.detail {
+            MessageDetail::Data { receiver_rid, data } => (data.len() as u128, receiver_rid.0),
+            MessageDetail::ConnectionClosed { receiver_rid } => (0, receiver_rid.0),
+            _ => unreachable!("Only data and close messages are transferred."),
+        };
+        let (sender, receiver) = (msg.sender.0, msg.receiver.0);
+        let group_key = match self.bandwidth.contention {
+            Contention::PerLink => (sender, receiver),
+            Contention::PerNodeEgress => (sender, usize::MAX),
+        };
+
+        let transfer = Transfer {
+            sent: msg.time.0,
+            start,
+            latency,
+            remaining: size * 1_000_000_000,
+            msg,
+        };
+
+        let group = self.groups.entry(group_key).or_default();
+        debug_assert!(start >= group.time);
+        let key = (receiver, rid);
+        match group.flows.iter_mut().find(|flow| flow.key == key) {
+            Some(flow) => flow.queue.push_back(transfer),
+            None => group.flows.push(Flow {
+                key,
+                queue: VecDeque::from([transfer]),
+            }),
+        }
+    }
+
+    /// Returns the time of the next transfer that starts or completes, if there is any.
+    pub fn next_event(&self) -> Option<u128> {
+        let bandwidth = self.bandwidth.bytes_per_second as u128;
+        self.groups
+            .values()
+            .filter_map(|group| group.next_event(bandwidth))
+            .min()
+    }
+
+    /// Make progress on every transfer up to the given time, calling `complete` with the time of
+    /// completion for every transfer that completes.
+    pub fn advance<F>(&mut self, until: u128, mut complete: F)
+    where
+        F: FnMut(u128, Transfer),
+    {
+        let bandwidth = self.bandwidth.bytes_per_second as u128;
+        self.groups.retain(|_, group| {
+            group.advance(until, bandwidth, &mut complete);
+            !group.flows.is_empty()
+        });
+    }
+}
+
+impl Group {
+    /// Returns the number of flows whose front transfer is using the bandwidth, and the least
+    /// amount of data left among them.
+    fn active(&self) -> (u128, Option<u128>) {
+        let mut count = 0;
+        let mut min = None;
+        for transfer in self.fronts().filter(|t| t.start <= self.time) {
+            count += 1;
+            min = Some(min.map_or(transfer.remaining, |min: u128| min.min(transfer.remaining)));
+        }
+        (count, min)
+    }
+
+    fn fronts(&self) -> impl Iterator<Item = &Transfer> {
+        self.flows.iter().filter_map(|flow| flow.queue.front())
+    }
+
+    fn next_event(&self, bandwidth: u128) -> Option<u128> {
+        let next_start = self
+            .fronts()
+            .map(|t| t.start)
+            .filter(|start| *start > self.time)
+            .min();
+        let next_finish = match self.active() {
+            (count, Some(min)) => Some(self.time + ceil_div(min * count, bandwidth)),
+            _ => None,
+        };
+        match (next_start, next_finish) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn advance<F>(&mut self, until: u128, bandwidth: u128, complete: &mut F)
+    where
+        F: FnMut(u128, Transfer),
+    {
+        while let Some(next) = self.next_event(bandwidth).filter(|next| *next <= until) {
+            self.progress(next, bandwidth);
+
+            for flow in &mut self.flows {
+                while flow
+                    .queue
+                    .front()
+                    .map_or(false, |t| t.start <= next && t.remaining == 0)
+                {
+                    complete(next, flow.queue.pop_front().unwrap());
+                }
+            }
+            self.flows.retain(|flow| !flow.queue.is_empty());
+        }
+
+        if until > self.time {
+            self.progress(until, bandwidth);
+        }
+    }
+
+    /// Share the bandwidth between the active flows from the current time of the group up to
+    /// the given time.
+    fn progress(&mut self, to: u128, bandwidth: u128) {
+        let (count, _) = self.active();
+        if count > 0 {
+            let progress = (to - self.time) * bandwidth / count;
+            let time = self.time;
+            for flow in &mut self.flows {
+                if let Some(transfer) = flow.queue.front_mut().filter(|t| t.start <= time) {
+                    transfer.remaining = transfer.remaining.saturating_sub(progress);
+                }
+            }
+        }
+        self.time = to;
+    }
+}
@@ -161,7 +161,8 @@ async fn setup_node(
         [u8; 32],
     )> {
         // setup blockstore with some content
-        let blockstore = MemoryBlockStore::init(lightning_test_utils::blockstore::Config {}).await?;
+        let blockstore =
+            MemoryBlockStore::init(lightning_test_utils::blockstore::Config::default()).await?;
         let content = create_content();
         let mut putter = blockstore.put(None);
         putter
@@ -184,7 +185,12 @@ async fn setup_node(
         );
 
         // finally, initialize the handshake server
-        let server = HandshakeServer::init(HandshakeServerConfig { listen_addr }).await?;
+        let server = HandshakeServer::init(HandshakeServerConfig {
+            listen_addr,
+            rate_limit: None,
+            services: None,
+        })
+        .await?;
 
         Ok((server, sdk, hash))
     }